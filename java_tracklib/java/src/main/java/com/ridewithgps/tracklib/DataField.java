@@ -6,10 +6,17 @@ public class DataField {
     private long numberValue;
     private double longFloatValue;
     private double shortFloatValue;
+    private double xorFloatValue;
     private String base64Value;
     private String stringValue;
     private boolean boolValue;
     private List<Long> idsValue;
+    private Map<String, DataField> mapValue;
+    private double latValue;
+    private double lngValue;
+    private List<Long> i64ArrayValue;
+    private List<Double> f64ArrayValue;
+    private long timestampValue;
     private Type type;
 
     public void setNumberValue(long v) {
@@ -27,6 +34,11 @@ public class DataField {
         this.type = Type.ShortFloat;
     }
 
+    public void setXorFloatValue(double v) {
+        this.xorFloatValue = v;
+        this.type = Type.XorFloat;
+    }
+
     public void setBase64Value(String v) {
         this.base64Value = v;
         this.type = Type.Base64;
@@ -47,6 +59,32 @@ public class DataField {
         this.type = Type.IDs;
     }
 
+    public void setMapValue(Map<String, DataField> v) {
+        this.mapValue = v;
+        this.type = Type.Map;
+    }
+
+    public void setGeoPointValue(double lat, double lng) {
+        this.latValue = lat;
+        this.lngValue = lng;
+        this.type = Type.GeoPoint;
+    }
+
+    public void setI64ArrayValue(List<Long> v) {
+        this.i64ArrayValue = v;
+        this.type = Type.I64Array;
+    }
+
+    public void setF64ArrayValue(List<Double> v) {
+        this.f64ArrayValue = v;
+        this.type = Type.F64Array;
+    }
+
+    public void setTimestampValue(long v) {
+        this.timestampValue = v;
+        this.type = Type.Timestamp;
+    }
+
     public boolean isNumber() {
         return Type.Number.equals(this.type);
     }
@@ -59,6 +97,10 @@ public class DataField {
         return Type.ShortFloat.equals(this.type);
     }
 
+    public boolean isXorFloat() {
+        return Type.XorFloat.equals(this.type);
+    }
+
     public boolean isBase64() {
         return Type.Base64.equals(this.type);
     }
@@ -75,6 +117,26 @@ public class DataField {
         return Type.IDs.equals(this.type);
     }
 
+    public boolean isMap() {
+        return Type.Map.equals(this.type);
+    }
+
+    public boolean isGeoPoint() {
+        return Type.GeoPoint.equals(this.type);
+    }
+
+    public boolean isI64Array() {
+        return Type.I64Array.equals(this.type);
+    }
+
+    public boolean isF64Array() {
+        return Type.F64Array.equals(this.type);
+    }
+
+    public boolean isTimestamp() {
+        return Type.Timestamp.equals(this.type);
+    }
+
     public long asNumber() {
         return this.numberValue;
     }
@@ -87,6 +149,10 @@ public class DataField {
         return this.shortFloatValue;
     }
 
+    public double asXorFloat() {
+        return this.xorFloatValue;
+    }
+
     public String asBase64() {
         return this.base64Value;
     }
@@ -103,6 +169,30 @@ public class DataField {
         return this.idsValue;
     }
 
+    public Map<String, DataField> asMap() {
+        return this.mapValue;
+    }
+
+    public double asLat() {
+        return this.latValue;
+    }
+
+    public double asLng() {
+        return this.lngValue;
+    }
+
+    public List<Long> asI64Array() {
+        return this.i64ArrayValue;
+    }
+
+    public List<Double> asF64Array() {
+        return this.f64ArrayValue;
+    }
+
+    public long asTimestamp() {
+        return this.timestampValue;
+    }
+
     public String toString() {
         if (isNumber()) {
             return String.format("Number(%s)", this.numberValue);
@@ -110,6 +200,8 @@ public class DataField {
             return String.format("LongFloat(%s)", this.longFloatValue);
         } else if (isShortFloat()) {
             return String.format("ShortFloat(%s)", this.shortFloatValue);
+        } else if (isXorFloat()) {
+            return String.format("XorFloat(%s)", this.xorFloatValue);
         } else if (isBase64()) {
             return String.format("Base64(%s)", this.base64Value);
         } else if (isString()) {
@@ -118,6 +210,16 @@ public class DataField {
             return String.format("Bool(%s)", this.boolValue);
         } else if (isIDs()) {
             return String.format("IDs(%s)", this.idsValue);
+        } else if (isMap()) {
+            return String.format("Map(%s)", this.mapValue);
+        } else if (isGeoPoint()) {
+            return String.format("GeoPoint(%s, %s)", this.latValue, this.lngValue);
+        } else if (isI64Array()) {
+            return String.format("I64Array(%s)", this.i64ArrayValue);
+        } else if (isF64Array()) {
+            return String.format("F64Array(%s)", this.f64ArrayValue);
+        } else if (isTimestamp()) {
+            return String.format("Timestamp(%s)", java.time.Instant.ofEpochMilli(this.timestampValue));
         } else {
             return "Null";
         }
@@ -127,9 +229,15 @@ public class DataField {
         Number,
         LongFloat,
         ShortFloat,
+        XorFloat,
         Base64,
         String,
         Bool,
-        IDs
+        IDs,
+        Map,
+        GeoPoint,
+        I64Array,
+        F64Array,
+        Timestamp
     }
 }
@@ -1,9 +1,11 @@
 package com.ridewithgps.tracklib;
 
+import java.io.InputStream;
 import java.util.*;
 
 public class RWTF {
     private static native List<Map<String, DataField>> parse_rwtf(byte[] input);
+    private static native List<Map<String, DataField>> parse_rwtf_stream(InputStream input);
     private List<Map<String, DataField>> data;
 
     static {
@@ -14,6 +16,13 @@ public class RWTF {
         this.data = parse_rwtf(input);
     }
 
+    // Parses input a section at a time as it's read, so only the section
+    // being decoded is held in memory besides the track points already
+    // read. An IOException from input is rethrown as it is.
+    public RWTF(InputStream input) {
+        this.data = parse_rwtf_stream(input);
+    }
+
     public Iterator<Map<String, DataField>> iterator() {
         return data.iterator();
     }
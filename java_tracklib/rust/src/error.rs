@@ -4,6 +4,8 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub enum Error {
     JNIError(jni::errors::Error),
     NomError,
+    ParseError(tracklib::ParseError),
+    ReadError(tracklib::RangeReaderError),
 }
 
 impl From<jni::errors::Error> for Error {
@@ -17,3 +19,15 @@ impl From<nom::Err<&[u8]>> for Error {
         Error::NomError
     }
 }
+
+impl From<tracklib::ParseError> for Error {
+    fn from(e: tracklib::ParseError) -> Self {
+        Error::ParseError(e)
+    }
+}
+
+impl From<tracklib::RangeReaderError> for Error {
+    fn from(e: tracklib::RangeReaderError) -> Self {
+        Error::ReadError(e)
+    }
+}
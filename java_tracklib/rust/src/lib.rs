@@ -1,19 +1,113 @@
+use std::io::{self, Read};
 use jni::objects::{JClass, JList, JMap, JObject, JValue};
 use jni::sys::{jbyteArray, jobject};
 use jni::JNIEnv;
-use tracklib::{parse_rwtf, Column};
+use tracklib::{parse_rwtf_with_options, Column, DataField, Location, ParseError, ParseOptions, Position, Section, SectionType, StreamReader};
 
 mod error;
 use crate::error::{Error, Result};
 
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// A java.io.InputStream as a Read, copying through one Java byte[] so that
+// StreamReader can pull the input a piece at a time
+struct JavaInputStream<'a, 'e> {
+    env: &'e JNIEnv<'a>,
+    input: JObject<'a>,
+    java_chunk: jbyteArray,
+    chunk: Vec<i8>,
+}
+
+impl Read for JavaInputStream<'_, '_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // a Java exception, e.g. an IOException from the input, stays
+        // pending and is rethrown by java_result
+        let java_error = |e: jni::errors::Error| io::Error::other(e.to_string());
+
+        let len = buf.len().min(CHUNK_SIZE);
+        if len == 0 {
+            return Ok(0);
+        }
+        let read = self.env.call_method(self.input,
+                                        "read",
+                                        "([BII)I",
+                                        &[JObject::from(self.java_chunk).into(), JValue::Int(0), JValue::Int(len as i32)])
+            .and_then(|v| v.i())
+            .map_err(java_error)?;
+        if read < 0 {
+            return Ok(0);
+        }
+        let read = (read as usize).min(len);
+        self.env.get_byte_array_region(self.java_chunk, 0, &mut self.chunk[..read]).map_err(java_error)?;
+        for (b, c) in buf.iter_mut().zip(&self.chunk[..read]) {
+            *b = *c as u8;
+        }
+        Ok(read)
+    }
+}
+
 fn java_parse_rwtf(env: &JNIEnv, input: jbyteArray) -> Result<jobject> {
-    // parse the input
     let bytes = env.convert_byte_array(input)?;
-    let (_, rwtf) = parse_rwtf(&bytes)?;
-    let track_points = rwtf.track_points;
+    let rwtf = parse_rwtf_with_options(&bytes, &ParseOptions::new())?;
+
+    let java_list = JList::from_env(env, env.new_object("java/util/ArrayList", "()V", &[])?)?;
+    add_track_points(env, &java_list, &rwtf.track_points)?;
+    Ok(java_list.into_inner())
+}
+
+// Parses the input a section at a time as it's read with StreamReader, so
+// only the section being decoded is held besides the rows already added to
+// the list. Continuation sections after the track points add more rows.
+fn java_parse_rwtf_stream(env: &JNIEnv, input: JObject) -> Result<jobject> {
+    let java_chunk = env.new_byte_array(CHUNK_SIZE as i32)?;
+    let stream = JavaInputStream{env, input, java_chunk, chunk: vec![0; CHUNK_SIZE]};
+    let mut reader = StreamReader::open(stream, ParseOptions::new())?;
 
-    // create a list in the jvm and start adding to it
     let java_list = JList::from_env(env, env.new_object("java/util/ArrayList", "()V", &[])?)?;
+    let mut last = None;
+    let mut seen_track_points = false;
+    while let Some((info, section)) = reader.next_section()? {
+        // the same layout checks parse_rwtf makes for the track points
+        let position = Position{location: Location::SectionHeader{section: info.index}, column: None, row: None};
+        match info.section_type {
+            SectionType::TrackPoints if seen_track_points => {
+                return Err(Error::from(ParseError::DuplicateSection{offset: info.offset, position}));
+            }
+            SectionType::Continuation if last.is_none() => {
+                return Err(Error::from(ParseError::MisplacedContinuation{offset: info.offset, position}));
+            }
+            SectionType::Continuation => {}
+            section_type => last = Some(section_type),
+        }
+        if last == Some(SectionType::TrackPoints) {
+            seen_track_points = true;
+            add_track_points(env, &java_list, &section)?;
+        }
+    }
+    env.delete_local_ref(JObject::from(java_chunk))?;
+
+    Ok(java_list.into_inner())
+}
+
+// A DataField for a value of a Map column, which is always a Number,
+// LongFloat, String or Bool
+fn java_map_value<'a>(env: &JNIEnv<'a>, v: &DataField) -> jni::errors::Result<JObject<'a>> {
+    let java_datafield = env.new_object("com/ridewithgps/tracklib/DataField", "()V", &[])?;
+    match v {
+        DataField::Number(v) => env.call_method(java_datafield, "setNumberValue", "(J)V", &[JValue::Long(*v)])?,
+        DataField::LongFloat(v) => env.call_method(java_datafield, "setLongFloatValue", "(D)V", &[JValue::Double(*v)])?,
+        DataField::String(v) => env.call_method(java_datafield,
+                                                "setStringValue",
+                                                "(Ljava/lang/String;)V",
+                                                &[env.new_string(v)?.into()])?,
+        DataField::Bool(v) => env.call_method(java_datafield, "setBoolValue", "(Z)V", &[JValue::Bool(*v as u8)])?,
+        _ => JValue::Void,
+    };
+    Ok(java_datafield)
+}
+
+// Adds a map to `java_list` for each row of `track_points`
+fn add_track_points(env: &JNIEnv, java_list: &JList, track_points: &Section) -> Result<()> {
     for i in 0..track_points.len() {
         let java_map = JMap::from_env(env, env.new_object("java/util/HashMap", "()V", &[])?)?;
 
@@ -39,6 +133,12 @@ fn java_parse_rwtf(env: &JNIEnv, input: jbyteArray) -> Result<jobject> {
                                     "(D)V",
                                     &[JValue::Double(*v)])
                 }),
+                Column::XorFloat(m) => m.get(&i).map(|v| {
+                    env.call_method(java_datafield,
+                                    "setXorFloatValue",
+                                    "(D)V",
+                                    &[JValue::Double(*v)])
+                }),
                 Column::Base64(m) => m.get(&i).map(|v| {
                     env.call_method(java_datafield,
                                     "setBase64Value",
@@ -51,7 +151,7 @@ fn java_parse_rwtf(env: &JNIEnv, input: jbyteArray) -> Result<jobject> {
                                     "(Ljava/lang/String;)V",
                                     &[env.new_string(v)?.into()])
                 }),
-                Column::Bool(m) => m.get(&i).map(|v| {
+                Column::Bool(m) | Column::PackedBool(m) => m.get(&i).map(|v| {
                     env.call_method(java_datafield,
                                     "setBoolValue",
                                     "(Z)V",
@@ -73,6 +173,54 @@ fn java_parse_rwtf(env: &JNIEnv, input: jbyteArray) -> Result<jobject> {
                                     "(Ljava/util/List;)V",
                                     &[ids_list.into()])
                 }),
+                Column::GeoPoint(m) => m.get(&i).map(|(lat, lng)| {
+                    env.call_method(java_datafield,
+                                    "setGeoPointValue",
+                                    "(DD)V",
+                                    &[JValue::Double(*lat), JValue::Double(*lng)])
+                }),
+                Column::I64Array(m) => m.get(&i).map(|v| {
+                    let values_list = JList::from_env(env, env.new_object("java/util/ArrayList", "()V", &[])?)?;
+
+                    for val in v {
+                        values_list.add(env.new_object("java/lang/Long", "(J)V", &[JValue::Long(*val)])?)?;
+                    }
+
+                    env.call_method(java_datafield,
+                                    "setI64ArrayValue",
+                                    "(Ljava/util/List;)V",
+                                    &[values_list.into()])
+                }),
+                Column::F64Array(m) => m.get(&i).map(|v| {
+                    let values_list = JList::from_env(env, env.new_object("java/util/ArrayList", "()V", &[])?)?;
+
+                    for val in v {
+                        values_list.add(env.new_object("java/lang/Double", "(D)V", &[JValue::Double(*val)])?)?;
+                    }
+
+                    env.call_method(java_datafield,
+                                    "setF64ArrayValue",
+                                    "(Ljava/util/List;)V",
+                                    &[values_list.into()])
+                }),
+                Column::Timestamp(m) => m.get(&i).map(|v| {
+                    env.call_method(java_datafield,
+                                    "setTimestampValue",
+                                    "(J)V",
+                                    &[JValue::Long(*v)])
+                }),
+                Column::Map(m) => m.get(&i).map(|v| {
+                    let entries = JMap::from_env(env, env.new_object("java/util/HashMap", "()V", &[])?)?;
+
+                    for (key, val) in v {
+                        entries.put(env.new_string(key)?.into(), java_map_value(env, val)?)?;
+                    }
+
+                    env.call_method(java_datafield,
+                                    "setMapValue",
+                                    "(Ljava/util/Map;)V",
+                                    &[entries.into()])
+                }),
             };
 
             match add_result {
@@ -93,18 +241,21 @@ fn java_parse_rwtf(env: &JNIEnv, input: jbyteArray) -> Result<jobject> {
         java_list.add(java_map.into())?;
     }
 
-    Ok(java_list.into_inner())
+    Ok(())
 }
 
-#[no_mangle]
-pub extern "C" fn Java_com_ridewithgps_tracklib_RWTF_parse_1rwtf(env: JNIEnv, _class: JClass, input: jbyteArray) -> jobject {
-    match java_parse_rwtf(&env, input) {
+// Throws a ParseException for `result` if it's an error, unless a Java
+// exception such as an IOException from the input is already pending
+fn java_result(env: &JNIEnv, result: Result<jobject>) -> jobject {
+    match result {
         Err(e) => {
             if !env.exception_check().expect("Failed to check exception status") {
                 env.throw_new("com/ridewithgps/tracklib/ParseException",
                               match e {
                                   error::Error::JNIError(jni_e) => jni_e.to_string(),
                                   error::Error::NomError => "RWTF Parse Error".to_string(),
+                                  error::Error::ParseError(parse_e) => parse_e.to_string(),
+                                  error::Error::ReadError(read_e) => read_e.to_string(),
                               })
                     .expect("Failed to create new ParseException");
             }
@@ -113,3 +264,13 @@ pub extern "C" fn Java_com_ridewithgps_tracklib_RWTF_parse_1rwtf(env: JNIEnv, _c
         Ok(obj) => obj
     }
 }
+
+#[no_mangle]
+pub extern "C" fn Java_com_ridewithgps_tracklib_RWTF_parse_1rwtf(env: JNIEnv, _class: JClass, input: jbyteArray) -> jobject {
+    java_result(&env, java_parse_rwtf(&env, input))
+}
+
+#[no_mangle]
+pub extern "C" fn Java_com_ridewithgps_tracklib_RWTF_parse_1rwtf_1stream(env: JNIEnv, _class: JClass, input: JObject) -> jobject {
+    java_result(&env, java_parse_rwtf_stream(&env, input))
+}
@@ -1,5 +1,6 @@
 require "tracklib/version"
 require "rutie"
+require "date"
 
 module Tracklib
   class UnknownFieldError < StandardError; end
@@ -11,4 +12,34 @@ class RWTFile
     puts "I am a ruby method"
     return 5
   end
+
+  class << self
+    alias_method :from_h_without_times, :from_h
+
+    # Time and DateTime values are written as seconds since the epoch, the
+    # way times are stored in Number columns like "t"
+    def from_h(data, *args)
+      data = data.transform_values do |points|
+        next points unless points.is_a?(Array)
+        points.map do |point|
+          point.transform_values do |v|
+            case v
+            when Time, DateTime then v.to_time.to_i
+            else v
+            end
+          end
+        end
+      end
+      from_h_without_times(data, *args)
+    end
+  end
+
+  alias_method :metadata_without_times, :metadata
+
+  # created_at as a Time rather than seconds since the epoch
+  def metadata
+    metadata_without_times.tap do |md|
+      md["created_at"] = Time.at(md["created_at"]).utc if md.key?("created_at")
+    end
+  end
 end
@@ -38,6 +38,13 @@ describe RWTFile do
                                                                    {},
                                                                    {"t"=>4}]})
     end
+    it "keeps points of interest" do
+      config = CONFIG.merge("points_of_interest"=>{"LongFloat"=>["x", "y"],
+                                                   "String"=>["N", "k"]})
+      orig_data = {"track_points"=>[{"t"=>1}],
+                   "points_of_interest"=>[{"x"=>-122.68, "y"=>45.52, "N"=>"Bike Shop", "k"=>"bike_shop"}]}
+      expect(roundtrip(orig_data, config)).to eq(orig_data)
+    end
     it "drops numbers that are too large to turn into primitives" do
       # integer
       orig_data = {"track_points"=>[{"x"=>10000000000000000000000000000000000, "y"=>7.2}]}
@@ -82,4 +89,18 @@ describe RWTFile do
       expect(roundtrip(orig_data, CONFIG)).to eq(orig_data)
     end
   end
+
+  context "times" do
+    it "writes Time and DateTime values as seconds since the epoch" do
+      orig_data = {"track_points"=>[{"t"=>Time.at(1600000000), "y"=>7.2},
+                                    {"t"=>DateTime.new(2020, 9, 13, 12, 26, 41), "y"=>7.3}]}
+      expect(roundtrip(orig_data, CONFIG)).to eq({"track_points"=>[{"t"=>1600000000, "y"=>7.2},
+                                                                   {"t"=>1600000001, "y"=>7.3}]})
+    end
+    it "reads created_at as a Time" do
+      rwtf = RWTFile::from_bytes(RWTFile::from_h({"track_points"=>[{"t"=>1}]}, CONFIG).to_bytes)
+      expect(rwtf.metadata["created_at"]).to be_a(Time)
+      expect(rwtf.metadata["created_at"]).to be_within(60).of(Time.now)
+    end
+  end
 end
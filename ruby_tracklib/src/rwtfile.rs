@@ -7,7 +7,7 @@ use rutie_serde::{ruby_class, rutie_serde_methods};
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::io::BufWriter;
-use tracklib::{parse_rwtf, DataField, RWTFMetadata, RWTFile, TrackType};
+use tracklib::{parse_rwtf_with_options, DataField, ParseOptions, RWTFMetadata, RWTFile, TrackType};
 use super::polyline;
 use super::surface;
 
@@ -61,6 +61,24 @@ fn any_to_ids(o: AnyObject) -> Vec<u64> {
         .collect()
 }
 
+fn any_to_i64_array(o: AnyObject) -> Vec<i64> {
+    o.try_convert_to::<Array>()
+        .map_err(|e| VM::raise_ex(e))
+        .unwrap()
+        .into_iter()
+        .map(any_to_int)
+        .collect()
+}
+
+fn any_to_f64_array(o: AnyObject) -> Vec<f64> {
+    o.try_convert_to::<Array>()
+        .map_err(|e| VM::raise_ex(e))
+        .unwrap()
+        .into_iter()
+        .map(any_to_float)
+        .collect()
+}
+
 #[derive(Debug, Copy, Clone)]
 enum ColumnType {
     Numbers,
@@ -70,6 +88,11 @@ enum ColumnType {
     String,
     Bool,
     IDs,
+    PackedBool,
+    XorFloat,
+    I64Array,
+    F64Array,
+    Timestamp,
 }
 
 impl ColumnType {
@@ -82,6 +105,11 @@ impl ColumnType {
             "String" => Some(ColumnType::String),
             "Bool" => Some(ColumnType::Bool),
             "IDs" => Some(ColumnType::IDs),
+            "PackedBool" => Some(ColumnType::PackedBool),
+            "XorFloat" => Some(ColumnType::XorFloat),
+            "I64Array" => Some(ColumnType::I64Array),
+            "F64Array" => Some(ColumnType::F64Array),
+            "Timestamp" => Some(ColumnType::Timestamp),
             _ => None,
         }
     }
@@ -102,11 +130,18 @@ impl ColumnType {
     }
 
     fn max_integer(&self) -> i64 {
-        2i64.pow(u32::from(self.exponent()))
+        match self {
+            // XorFloat stores the raw bits of any f64, and Timestamp any i64
+            ColumnType::XorFloat | ColumnType::Timestamp => std::i64::MAX,
+            _ => 2i64.pow(u32::from(self.exponent())),
+        }
     }
 
     fn max_float(&self) -> f64 {
-        2f64.powi(i32::from(self.exponent()))
+        match self {
+            ColumnType::XorFloat | ColumnType::Timestamp => std::f64::MAX,
+            _ => 2f64.powi(i32::from(self.exponent())),
+        }
     }
 }
 
@@ -245,6 +280,11 @@ fn add_points(
                                 ColumnType::String => DataField::String(any_to_str(v)),
                                 ColumnType::Bool => DataField::Bool(any_to_bool(v)),
                                 ColumnType::IDs => DataField::IDs(any_to_ids(v)),
+                                ColumnType::PackedBool => DataField::PackedBool(any_to_bool(v)),
+                                ColumnType::XorFloat => DataField::XorFloat(any_to_float(v)),
+                                ColumnType::I64Array => DataField::I64Array(any_to_i64_array(v)),
+                                ColumnType::F64Array => DataField::F64Array(any_to_f64_array(v)),
+                                ColumnType::Timestamp => DataField::Timestamp(any_to_int(v)),
                             };
 
                             callback(i, name, data);
@@ -275,7 +315,7 @@ methods!(
     itself,
     fn rwtf_from_bytes(bytes: RString) -> AnyObject {
         let source = bytes.map_err(|e| VM::raise_ex(e)).unwrap();
-        let (_, rwtf) = parse_rwtf(source.to_bytes_unchecked())
+        let rwtf = parse_rwtf_with_options(source.to_bytes_unchecked(), &ParseOptions::new())
             .map_err(|e| VM::raise(Class::from_existing("Exception"), &format!("{}", e)))
             .unwrap();
         let inner = Inner { inner: rwtf };
@@ -321,6 +361,12 @@ methods!(
                 .map_err(|e| VM::raise_ex(e))
                 .unwrap(),
         );
+        // optional, so configs from before points of interest existed still work
+        let points_of_interest_config = config
+            .at(&RString::new_utf8("points_of_interest"))
+            .try_convert_to::<Hash>()
+            .map(convert_config)
+            .unwrap_or_default();
 
         let mut rwtf = if let Some(md) = metadata.ok() {
             let tt_metadata = md
@@ -386,6 +432,17 @@ methods!(
             },
         );
 
+        add_points(
+            &source,
+            &points_of_interest_config,
+            "points_of_interest",
+            |i, name, data| {
+                rwtf.add_point_of_interest(i, name, data)
+                    .map_err(|e| VM::raise(Class::from_existing("Exception"), &format!("{}", e)))
+                    .unwrap();
+            },
+        );
+
         let inner = Inner { inner: rwtf };
         Class::from_existing("RWTFile").wrap_data(inner, &*INNER_WRAPPER)
     }
@@ -407,9 +464,11 @@ methods!(
         let rwtf = &itself.get_data(&*INNER_WRAPPER).inner;
 
         RString::new_utf8(&format!(
-            "RWTFile<track_points: {}, course_points: {}>",
+            "RWTFile<file_version: {}, track_points: {}, course_points: {}, points_of_interest: {}>",
+            rwtf.header().file_version(),
             rwtf.track_points.len(),
-            rwtf.course_points.len()
+            rwtf.course_points.len(),
+            rwtf.points_of_interest.len()
         ))
     }
 );
@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+// The first byte picks the column type and the second the number of rows,
+// the rest is the column's data
+fuzz_target!(|data: &[u8]| {
+    if let [tag, points, data @ ..] = data {
+        tracklib_fuzz::parse(&tracklib_fuzz::file_with_column(*tag, *points, data));
+    }
+});
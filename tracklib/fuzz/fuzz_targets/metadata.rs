@@ -0,0 +1,6 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    tracklib_fuzz::parse(&tracklib_fuzz::file_with_metadata(data));
+});
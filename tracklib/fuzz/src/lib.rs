@@ -0,0 +1,76 @@
+// Helpers which wrap fuzzer input in just enough of an RWTF file for a
+// target to reach the parser it's aimed at. CRCs aren't fixed up, so
+// everything is parsed with `CrcPolicy::Warn`.
+use std::convert::{TryFrom};
+use tracklib::{parse_raw_sections, parse_rwtf, parse_rwtf_with_options, CrcPolicy, ParseOptions, RWTFile};
+
+const HEADER_LEN: usize = 24;
+const METADATA_TABLE_OFFSET: usize = 16;
+const DATA_OFFSET: usize = 18;
+const TRAILER: [u8; 5] = [0xff, b'F', b'T', b'W', b'R'];
+
+// header + metadata table, ready for sections
+fn file_start() -> Vec<u8> {
+    let mut buf = vec![];
+    RWTFile::new().write(&mut buf).expect("Couldn't write an empty file");
+    buf.truncate(buf.len() - TRAILER.len());
+    buf
+}
+
+/// Parse `buf` both ways, and make sure whatever comes out can be written.
+pub fn parse(buf: &[u8]) {
+    let _ = parse_rwtf(buf);
+
+    let mut options = ParseOptions::new();
+    options.set_crc_policy(CrcPolicy::Warn);
+    let _ = parse_raw_sections(buf, &options);
+    if let Ok(rwtf) = parse_rwtf_with_options(buf, &options) {
+        let mut out = vec![];
+        let _ = rwtf.write(&mut out);
+    }
+}
+
+/// A file with `metadata` as its metadata table and no sections.
+pub fn file_with_metadata(metadata: &[u8]) -> Vec<u8> {
+    let mut buf = file_start();
+    buf.truncate(HEADER_LEN);
+
+    let metadata = &metadata[..metadata.len().min(usize::from(u16::MAX) - HEADER_LEN)];
+    buf.extend_from_slice(metadata);
+    let data_offset = u16::try_from(buf.len()).expect("metadata was truncated");
+    buf[DATA_OFFSET..DATA_OFFSET + 2].copy_from_slice(&data_offset.to_le_bytes());
+    buf[METADATA_TABLE_OFFSET..METADATA_TABLE_OFFSET + 2].copy_from_slice(&(HEADER_LEN as u16).to_le_bytes());
+
+    buf.extend_from_slice(&TRAILER);
+    buf
+}
+
+/// A file with `sections` in its data area, followed by the trailer.
+pub fn file_with_sections(sections: &[u8]) -> Vec<u8> {
+    let mut buf = file_start();
+    buf.extend_from_slice(sections);
+    buf.extend_from_slice(&TRAILER);
+    buf
+}
+
+/// A file with a single track points section holding a single column, of
+/// type `tag`, present in each of `points` rows and encoded as `data`.
+pub fn file_with_column(tag: u8, points: u8, data: &[u8]) -> Vec<u8> {
+    let points = points.max(1);
+    let mut section = vec![];
+
+    // types table
+    section.extend_from_slice(&[0x01, tag, 0x01, b'c', 0x00, 0x00]);
+    // flags
+    section.extend(std::iter::repeat(0x01).take(usize::from(points)));
+    section.extend_from_slice(data);
+    // data crc
+    section.extend_from_slice(&[0x00; 4]);
+
+    let mut header = vec![0x00, points, 0x00, 0x00];
+    header.extend_from_slice(&(12 + section.len() as u64).to_le_bytes());
+    header.extend_from_slice(&[0x00, 0x00]);
+
+    header.extend_from_slice(&section);
+    file_with_sections(&header)
+}
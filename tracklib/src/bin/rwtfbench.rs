@@ -0,0 +1,192 @@
+//! Reports how big and how fast a .rwtf file is in each of the encodings
+//! this version can write, and how much each column costs:
+//!
+//!     rwtfbench [--iterations N] <input.rwtf>
+//!
+//! Every encoding holds the same values. Encode and decode times are the
+//! mean over N runs, 10 by default. Column sizes are for the encoding the
+//! file was read in, see `Section::encoded_sizes`.
+use std::process;
+use std::time::{Duration, Instant};
+use tracklib::{DataField, RWTFile, Section};
+
+const USAGE: &str = "usage: rwtfbench [--iterations N] <input.rwtf>";
+
+type Encoding = Box<dyn Fn(&Section) -> Result<Section, String>>;
+
+fn parse_args(args: &[String]) -> Result<(usize, String), String> {
+    match args {
+        [input] => Ok((10, input.clone())),
+        [flag, n, input] if flag == "--iterations" => {
+            let n = n.parse::<usize>().ok().filter(|n| *n > 0)
+                .ok_or_else(|| format!("--iterations needs a number greater than 0, not {:?}", n))?;
+            Ok((n, input.clone()))
+        }
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+// The same values with every LongFloat and ShortFloat column stored as a
+// XorFloat column
+fn xor_floats(section: &Section) -> Result<Section, String> {
+    let mut out = Section::new(section.section_type());
+    out.set_run_length_encoding(section.run_length_encoding());
+    out.set_packed_ids(section.packed_ids());
+    for (name, column) in section.columns() {
+        for index in 0..section.len() {
+            let v = match column.get(index) {
+                Some(DataField::LongFloat(v)) | Some(DataField::ShortFloat(v)) => DataField::XorFloat(v),
+                Some(v) => v,
+                None => continue,
+            };
+            out.add(index, name, v).map_err(|e| format!("Couldn't convert {}: {}", name, e))?;
+        }
+    }
+
+    Ok(out)
+}
+
+fn encoding(file: &RWTFile, f: &dyn Fn(&Section) -> Result<Section, String>) -> Result<RWTFile, String> {
+    let mut out = RWTFile::new();
+    out.set_metadata(file.metadata().clone());
+    out.set_file_checksum(file.file_checksum());
+    for section in &[file.track_points(), file.course_points(), file.points_of_interest(), file.laps(), file.events()] {
+        out.set_section(f(section)?).map_err(|e| format!("Couldn't set section: {}", e))?;
+    }
+
+    Ok(out)
+}
+
+fn with(run_length_encoding: bool, packed_ids: bool) -> impl Fn(&Section) -> Result<Section, String> {
+    move |section| {
+        let mut out = section.clone();
+        out.set_run_length_encoding(run_length_encoding);
+        out.set_packed_ids(packed_ids);
+        Ok(out)
+    }
+}
+
+fn mean<F: FnMut() -> Result<(), String>>(iterations: usize, mut f: F) -> Result<Duration, String> {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f()?;
+    }
+
+    Ok(start.elapsed() / iterations as u32)
+}
+
+fn millis(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let (iterations, input) = parse_args(args)?;
+    let bytes = std::fs::read(&input).map_err(|e| format!("Couldn't read {}: {}", input, e))?;
+    let file = RWTFile::read(&bytes).map_err(|e| format!("Couldn't parse {}: {}", input, e))?;
+
+    let encodings: Vec<(&str, Encoding)> = vec![
+        ("as read", Box::new(|section: &Section| Ok(section.clone()))),
+        ("plain", Box::new(with(false, false))),
+        ("run-length", Box::new(with(true, false))),
+        ("packed-ids", Box::new(with(false, true))),
+        ("run-length+packed-ids", Box::new(with(true, true))),
+        ("xor-floats", Box::new(xor_floats)),
+    ];
+
+    println!("{}: {} bytes, {} track points", input, bytes.len(), file.track_points().len());
+    println!();
+    println!("{:<24}{:>12}{:>12}{:>12}", "encoding", "bytes", "encode ms", "decode ms");
+    for (name, f) in &encodings {
+        let converted = encoding(&file, f.as_ref())?;
+        let out = converted.to_bytes().map_err(|e| format!("Couldn't write {}: {}", name, e))?;
+        let encode = mean(iterations, || converted.to_bytes().map(|_| ()).map_err(|e| e.to_string()))?;
+        let decode = mean(iterations, || RWTFile::read(&out).map(|_| ()).map_err(|e| e.to_string()))?;
+        println!("{:<24}{:>12}{:>12.3}{:>12.3}", name, out.len(), millis(encode), millis(decode));
+    }
+
+    let sections = [file.track_points(), file.course_points(), file.points_of_interest(), file.laps(), file.events()];
+    for section in sections.iter().filter(|section| !section.columns().is_empty()) {
+        println!();
+        println!("{:<24}{:>12}{:>12}{:>12}", format!("{:?}", section.section_type()), "type", "bytes", "per row");
+        let sizes = section.encoded_sizes();
+        let rows = section.len().max(1) as f64;
+        for (name, field_type, size) in &sizes.columns {
+            println!("{:<24}{:>12}{:>12}{:>12.2}", name, format!("{:?}", field_type), size, *size as f64 / rows);
+        }
+        println!("{:<24}{:>12}{:>12}{:>12.2}", "(presence)", "", sizes.presence, sizes.presence as f64 / rows);
+        println!("{:<24}{:>12}{:>12}", "(overhead)", "", sizes.overhead);
+        println!("{:<24}{:>12}{:>12}", "(total)", "", sizes.total());
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if let Err(e) = run(&args) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracklib::{fields};
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn file() -> RWTFile {
+        let mut file = RWTFile::new();
+        for i in 0..5 {
+            file.add_track_point(i, fields::TIME.name, 1000 + i as i64).unwrap();
+            file.add_track_point(i, fields::LAT.name, DataField::LongFloat(45.0 + i as f64 / 1000.0)).unwrap();
+        }
+        file.add_track_point(2, fields::HEART_RATE.name, 140).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(parse_args(&strings(&["in.rwtf"])), Ok((10, "in.rwtf".to_string())));
+        assert_eq!(parse_args(&strings(&["--iterations", "3", "in.rwtf"])), Ok((3, "in.rwtf".to_string())));
+        assert_eq!(parse_args(&strings(&[])), Err(USAGE.to_string()));
+        assert_eq!(parse_args(&strings(&["--bogus", "3", "in.rwtf"])), Err(USAGE.to_string()));
+        assert!(parse_args(&strings(&["--iterations", "0", "in.rwtf"])).is_err());
+    }
+
+    #[test]
+    fn test_encodings_keep_values() {
+        let file = file();
+        let encodings: Vec<Encoding> = vec![Box::new(with(true, true)), Box::new(xor_floats)];
+        for f in &encodings {
+            let converted = encoding(&file, f.as_ref()).unwrap();
+            let read = RWTFile::read(&converted.to_bytes().unwrap()).unwrap();
+            let (before, after) = (file.track_points(), read.track_points());
+            assert_eq!(after.len(), before.len());
+            for (name, column) in before.columns() {
+                for i in 0..before.len() {
+                    let v = after.columns().get(name).and_then(|c| c.get(i));
+                    match column.get(i) {
+                        Some(DataField::LongFloat(x)) => assert!(matches!(v, Some(DataField::LongFloat(y)) | Some(DataField::XorFloat(y)) if y == x)),
+                        expected => assert_eq!(v, expected),
+                    }
+                }
+            }
+        }
+
+        let xor = xor_floats(file.track_points()).unwrap();
+        assert_eq!(xor.columns().get(fields::LAT.name).and_then(|c| c.get(0)), Some(DataField::XorFloat(45.0)));
+    }
+
+    #[test]
+    fn test_run() {
+        let input = std::env::temp_dir().join(format!("rwtfbench-{}.rwtf", process::id())).to_string_lossy().into_owned();
+        std::fs::write(&input, file().to_bytes().unwrap()).unwrap();
+        assert_eq!(run(&strings(&["--iterations", "1", &input])), Ok(()));
+        std::fs::remove_file(&input).unwrap();
+        assert!(run(&strings(&["missing.rwtf"])).unwrap_err().starts_with("Couldn't read"));
+    }
+}
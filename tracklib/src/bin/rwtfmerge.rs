@@ -0,0 +1,152 @@
+//! Joins several .rwtf files into one, e.g. the pieces of a ride a device
+//! recorded either side of a crash:
+//!
+//!     rwtfmerge [--rebase-times] [--metadata-from N] <output.rwtf> <input.rwtf>...
+//!
+//! The inputs are joined in the order given with `RWTFile::join`, so columns
+//! missing from some of them are kept. With `--rebase-times` each input's
+//! times are moved to start a second after the input before it ends, for a
+//! device whose clock was reset. The metadata is taken from the Nth input,
+//! counting from 1, or the first if `--metadata-from` isn't given.
+use std::process;
+use tracklib::{RWTFile, TrackPointReader};
+
+const USAGE: &str = "usage: rwtfmerge [--rebase-times] [--metadata-from N] <output.rwtf> <input.rwtf>...";
+
+struct Args {
+    output: String,
+    inputs: Vec<String>,
+    rebase_times: bool,
+    metadata_from: usize,
+}
+
+fn parse_args(args: &[String]) -> Result<Args, String> {
+    let mut rebase_times = false;
+    let mut metadata_from = 1;
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--rebase-times" => rebase_times = true,
+            "--metadata-from" => {
+                let value = args.next().ok_or_else(|| USAGE.to_string())?;
+                metadata_from = value.parse().ok().filter(|n| *n > 0)
+                    .ok_or_else(|| format!("--metadata-from needs a number greater than 0, not {:?}", value))?;
+            }
+            flag if flag.starts_with("--") => return Err(USAGE.to_string()),
+            path => paths.push(path.to_string()),
+        }
+    }
+    if paths.len() < 2 {
+        return Err(USAGE.to_string());
+    }
+    let output = paths.remove(0);
+    if metadata_from > paths.len() {
+        return Err(format!("--metadata-from {} is more than the {} inputs", metadata_from, paths.len()));
+    }
+
+    Ok(Args{output, inputs: paths, rebase_times, metadata_from})
+}
+
+fn times(file: &RWTFile) -> impl Iterator<Item = i64> + '_ {
+    let track = TrackPointReader::new(file.track_points());
+    (0..track.len()).filter_map(move |row| track.time(row))
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let args = parse_args(args)?;
+
+    let mut files = Vec::with_capacity(args.inputs.len());
+    let mut last_time: Option<i64> = None;
+    for input in &args.inputs {
+        let bytes = std::fs::read(input).map_err(|e| format!("Couldn't read {}: {}", input, e))?;
+        let mut file = RWTFile::read(&bytes).map_err(|e| format!("Couldn't parse {}: {}", input, e))?;
+        if args.rebase_times {
+            let first_time = times(&file).next();
+            if let (Some(last), Some(first)) = (last_time, first_time) {
+                let seconds = last.checked_add(1).and_then(|next| next.checked_sub(first))
+                    .ok_or_else(|| format!("Couldn't rebase {}: its times are too far from the input before it", input))?;
+                file = file.shift_times(seconds).map_err(|e| format!("Couldn't rebase {}: {}", input, e))?;
+            }
+        }
+        last_time = times(&file).max().or(last_time);
+        files.push(file);
+    }
+
+    let mut joined = RWTFile::join(&files.iter().collect::<Vec<_>>()).map_err(|e| format!("Couldn't merge: {}", e))?;
+    joined.set_metadata(files[args.metadata_from - 1].metadata().clone());
+
+    let out = joined.to_bytes().map_err(|e| format!("Couldn't write {}: {}", args.output, e))?;
+    std::fs::write(&args.output, out).map_err(|e| format!("Couldn't write {}: {}", args.output, e))?;
+    println!("{}: {} track points from {} files", args.output, joined.track_points().len(), files.len());
+
+    Ok(())
+}
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if let Err(e) = run(&args) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracklib::{fields};
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    // Writes a file with a track point at each of `times` into the temp
+    // directory, returning its path
+    fn write_input(name: &str, times: &[i64]) -> String {
+        let mut file = RWTFile::new();
+        for (i, time) in times.iter().enumerate() {
+            file.add_track_point(i, fields::TIME.name, *time).unwrap();
+        }
+        let path = std::env::temp_dir().join(format!("rwtfmerge-{}-{}.rwtf", process::id(), name));
+        std::fs::write(&path, file.to_bytes().unwrap()).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn read_times(path: &str) -> Vec<i64> {
+        times(&RWTFile::read(&std::fs::read(path).unwrap()).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = parse_args(&strings(&["--rebase-times", "out.rwtf", "a.rwtf", "--metadata-from", "2", "b.rwtf"])).unwrap();
+        assert_eq!(args.output, "out.rwtf");
+        assert_eq!(args.inputs, strings(&["a.rwtf", "b.rwtf"]));
+        assert!(args.rebase_times);
+        assert_eq!(args.metadata_from, 2);
+
+        assert_eq!(parse_args(&strings(&["out.rwtf"])).err(), Some(USAGE.to_string()));
+        assert_eq!(parse_args(&strings(&["--bogus", "out.rwtf", "a.rwtf"])).err(), Some(USAGE.to_string()));
+        assert!(parse_args(&strings(&["--metadata-from", "0", "out.rwtf", "a.rwtf"])).is_err());
+        assert!(parse_args(&strings(&["--metadata-from", "3", "out.rwtf", "a.rwtf", "b.rwtf"])).is_err());
+    }
+
+    #[test]
+    fn test_run() {
+        let a = write_input("a", &[100, 110]);
+        let b = write_input("b", &[5, 15]);
+        let out = std::env::temp_dir().join(format!("rwtfmerge-{}-out.rwtf", process::id())).to_string_lossy().into_owned();
+
+        run(&strings(&[&out, &a, &b])).unwrap();
+        assert_eq!(read_times(&out), vec![100, 110, 5, 15]);
+        run(&strings(&["--rebase-times", &out, &a, &b])).unwrap();
+        assert_eq!(read_times(&out), vec![100, 110, 111, 121]);
+
+        let far = write_input("far", &[i64::MIN]);
+        assert!(run(&strings(&["--rebase-times", &out, &a, &far])).unwrap_err().starts_with("Couldn't rebase"));
+        assert!(run(&strings(&[&out, &a, "missing.rwtf"])).unwrap_err().starts_with("Couldn't read"));
+
+        for path in &[a, b, far, out] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Splits one .rwtf file into several, by row count, duration, or at pauses
+//! in the recording:
+//!
+//!     rwtfsplit <input.rwtf> <output-prefix> (--rows N | --duration SECONDS | --pauses SECONDS)
+//!
+//! Each piece is written to `<output-prefix>-1.rwtf`, `<output-prefix>-2.rwtf`
+//! and so on. See `RWTFile::crop` for what's kept in each piece.
+use std::process;
+use tracklib::{RWTFile, TrackPointReader};
+
+const USAGE: &str = "usage: rwtfsplit <input.rwtf> <output-prefix> (--rows N | --duration SECONDS | --pauses SECONDS)";
+
+enum Split {
+    Rows(usize),
+    Duration(i64),
+    Pauses(i64),
+}
+
+fn parse_args(args: &[String]) -> Result<(String, String, Split), String> {
+    let (input, prefix, flag, value) = match args {
+        [input, prefix, flag, value] => (input, prefix, flag, value),
+        _ => return Err(USAGE.to_string()),
+    };
+    let number = || value.parse::<i64>().ok().filter(|n| *n > 0)
+        .ok_or_else(|| format!("{} needs a number greater than 0, not {:?}", flag, value));
+    let split = match flag.as_str() {
+        "--rows" => Split::Rows(number()? as usize),
+        "--duration" => Split::Duration(number()?),
+        "--pauses" => Split::Pauses(number()?),
+        _ => return Err(USAGE.to_string()),
+    };
+
+    Ok((input.clone(), prefix.clone(), split))
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let (input, prefix, split) = parse_args(args)?;
+    let bytes = std::fs::read(&input).map_err(|e| format!("Couldn't read {}: {}", input, e))?;
+    let file = RWTFile::read(&bytes).map_err(|e| format!("Couldn't parse {}: {}", input, e))?;
+
+    let track = TrackPointReader::new(file.track_points());
+    let pieces = match split {
+        Split::Rows(rows) => (0..track.len()).step_by(rows).map(|start| start..(start + rows).min(track.len())).collect(),
+        Split::Duration(seconds) => track.split_by_duration(seconds),
+        Split::Pauses(min_gap) => track.split_at_pauses(min_gap),
+    };
+
+    for (i, rows) in pieces.into_iter().enumerate() {
+        let path = format!("{}-{}.rwtf", prefix, i + 1);
+        let piece = file.crop(rows.clone()).map_err(|e| format!("Couldn't split rows {:?}: {}", rows, e))?;
+        let out = piece.to_bytes().map_err(|e| format!("Couldn't write {}: {}", path, e))?;
+        std::fs::write(&path, out).map_err(|e| format!("Couldn't write {}: {}", path, e))?;
+        println!("{}: rows {}..{}", path, rows.start, rows.end);
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if let Err(e) = run(&args) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracklib::{fields};
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("rwtfsplit-{}-{}", process::id(), name)).to_string_lossy().into_owned()
+    }
+
+    fn read_times(path: &str) -> Vec<i64> {
+        let file = RWTFile::read(&std::fs::read(path).unwrap()).unwrap();
+        let track = TrackPointReader::new(file.track_points());
+        (0..track.len()).filter_map(|row| track.time(row)).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert!(matches!(parse_args(&strings(&["in.rwtf", "out", "--rows", "5"])), Ok((_, _, Split::Rows(5)))));
+        assert!(matches!(parse_args(&strings(&["in.rwtf", "out", "--duration", "60"])), Ok((_, _, Split::Duration(60)))));
+        assert!(matches!(parse_args(&strings(&["in.rwtf", "out", "--pauses", "30"])), Ok((_, _, Split::Pauses(30)))));
+        assert_eq!(parse_args(&strings(&["in.rwtf", "out", "--rows"])).err(), Some(USAGE.to_string()));
+        assert_eq!(parse_args(&strings(&["in.rwtf", "out", "--bogus", "5"])).err(), Some(USAGE.to_string()));
+        assert!(parse_args(&strings(&["in.rwtf", "out", "--rows", "0"])).is_err());
+        assert!(parse_args(&strings(&["in.rwtf", "out", "--duration", "soon"])).is_err());
+    }
+
+    #[test]
+    fn test_run() {
+        let input = temp_path("in.rwtf");
+        let prefix = temp_path("piece");
+        let mut file = RWTFile::new();
+        for (i, time) in [0, 10, 20, 100, 110].iter().enumerate() {
+            file.add_track_point(i, fields::TIME.name, *time).unwrap();
+        }
+        std::fs::write(&input, file.to_bytes().unwrap()).unwrap();
+        let piece = |i| format!("{}-{}.rwtf", prefix, i);
+
+        run(&strings(&[&input, &prefix, "--rows", "2"])).unwrap();
+        assert_eq!(read_times(&piece(1)), vec![0, 10]);
+        assert_eq!(read_times(&piece(2)), vec![20, 100]);
+        assert_eq!(read_times(&piece(3)), vec![110]);
+        std::fs::remove_file(piece(3)).unwrap();
+
+        run(&strings(&[&input, &prefix, "--pauses", "30"])).unwrap();
+        assert_eq!(read_times(&piece(1)), vec![0, 10, 20]);
+        assert_eq!(read_times(&piece(2)), vec![100, 110]);
+        assert!(!std::path::Path::new(&piece(3)).exists());
+
+        assert!(run(&strings(&["missing.rwtf", &prefix, "--rows", "2"])).unwrap_err().starts_with("Couldn't read"));
+
+        for path in &[input, piece(1), piece(2)] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,118 @@
+// Bit packing for the values of an IDs column. Each row is stored as:
+//   count       - unsigned LEB128
+//   width       - 1 byte, the number of bits in the largest id, left out
+//                 when count is 0
+//   ids         - count * width bits, least significant bit first, padded
+//                 with zeros to a whole number of bytes
+// This beats an LEB128 per id when a row has many ids of similar size, such
+// as a list of radar targets.
+use std::io::{self, Write};
+use crate::utils::{write, unsigned_leb128_len};
+
+// The number of bits in the largest of `ids`
+fn width(ids: &[u64]) -> u32 {
+    ids.iter().map(|id| 64 - id.leading_zeros()).max().unwrap_or(0)
+}
+
+/// The number of bytes `count` ids of `width` bits are packed into, or None
+/// if that doesn't fit in a usize.
+pub(crate) fn packed_len(count: usize, width: u32) -> Option<usize> {
+    count.checked_mul(width as usize).map(|bits| bits.div_ceil(8))
+}
+
+/// The number of bytes `write_row` will write for `ids`.
+pub(crate) fn encoded_len(ids: &[u64]) -> usize {
+    unsigned_leb128_len(ids.len() as u64) + match ids.len() {
+        0 => 0,
+        count => 1 + (count * width(ids) as usize).div_ceil(8),
+    }
+}
+
+pub(crate) fn write_row<W: Write>(out: &mut W, ids: &[u64]) -> io::Result<usize> {
+    let mut written = leb128::write::unsigned(out, ids.len() as u64)?;
+    if ids.is_empty() {
+        return Ok(written);
+    }
+
+    let width = width(ids);
+    written += write(out, &[width as u8])?;
+
+    let mut buf = Vec::with_capacity((ids.len() * width as usize).div_ceil(8));
+    let mut acc: u128 = 0;
+    let mut bits = 0;
+    for id in ids {
+        acc |= u128::from(*id) << bits;
+        bits += width;
+        while bits >= 8 {
+            buf.push(acc as u8);
+            acc >>= 8;
+            bits -= 8;
+        }
+    }
+    if bits > 0 {
+        buf.push(acc as u8);
+    }
+    written += write(out, &buf)?;
+
+    Ok(written)
+}
+
+/// Unpacks `count` ids of `width` bits from `i`, which must hold at least
+/// `packed_len(count, width)` bytes.
+pub(crate) fn unpack(i: &[u8], count: usize, width: u32) -> Vec<u64> {
+    let mask = if width == 64 { u64::MAX } else { (1 << width) - 1 };
+    let mut bytes = i.iter();
+    let mut acc: u128 = 0;
+    let mut bits = 0;
+    (0..count)
+        .map(|_| {
+            while bits < width {
+                acc |= u128::from(*bytes.next().unwrap_or(&0)) << bits;
+                bits += 8;
+            }
+            let id = acc as u64 & mask;
+            acc >>= width;
+            bits -= width;
+            id
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(ids: &[u64]) -> Vec<u8> {
+        let mut buf = vec![];
+        assert_eq!(write_row(&mut buf, ids).unwrap(), buf.len());
+        assert_eq!(encoded_len(ids), buf.len());
+
+        let count_len = unsigned_leb128_len(ids.len() as u64);
+        if !ids.is_empty() {
+            let width = u32::from(buf[count_len]);
+            assert_eq!(packed_len(ids.len(), width), Some(buf.len() - count_len - 1));
+            assert_eq!(unpack(&buf[count_len + 1..], ids.len(), width), ids);
+        }
+        buf
+    }
+
+    #[test]
+    fn test_packing() {
+        assert_eq!(roundtrip(&[]), &[0x00]);
+        assert_eq!(roundtrip(&[0, 0, 0]), &[0x03, 0x00]);
+        // three 3 bit ids: 101 011 111 -> 0b11011101 0b00000001
+        assert_eq!(roundtrip(&[5, 3, 7]), &[0x03, 0x03, 0xDD, 0x01]);
+        assert_eq!(roundtrip(&[u64::MAX, 1, u64::MAX - 1]).len(), 1 + 1 + 24);
+
+        let targets = (0..100).map(|i| 1_000_000 + i * 37).collect::<Vec<u64>>();
+        let buf = roundtrip(&targets);
+        assert_eq!(buf.len(), 1 + 1 + (100 * 20usize).div_ceil(8));
+        assert!(buf.len() < targets.iter().map(|id| unsigned_leb128_len(*id)).sum::<usize>());
+    }
+
+    #[test]
+    fn test_packed_len_overflow() {
+        assert_eq!(packed_len(usize::MAX, 64), None);
+        assert_eq!(packed_len(usize::MAX, 0), Some(0));
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::{BTreeMap};
+use crate::fields::{self, Field};
+use crate::rwtfile::{DataField, RWTFile, Result};
+use crate::section::{Column, Section};
+use crate::simplify::{haversine};
+use crate::trackpoint::{TrackPointReader};
+
+/// What a course point marks. Stored by name in the `fields::COURSE_POINT_TYPE`
+/// column so that planners, apps and devices agree on it.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TurnType {
+    Left,
+    Right,
+    SlightLeft,
+    SlightRight,
+    SharpLeft,
+    SharpRight,
+    Straight,
+    UTurn,
+    Generic,
+    Summit,
+    Valley,
+    Water,
+    Food,
+    Danger,
+    FirstAid,
+}
+
+const TURN_TYPES: &[TurnType] = &[TurnType::Left, TurnType::Right, TurnType::SlightLeft, TurnType::SlightRight,
+                                  TurnType::SharpLeft, TurnType::SharpRight, TurnType::Straight, TurnType::UTurn,
+                                  TurnType::Generic, TurnType::Summit, TurnType::Valley, TurnType::Water,
+                                  TurnType::Food, TurnType::Danger, TurnType::FirstAid];
+
+impl TurnType {
+    /// The name stored in the course points section.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TurnType::Left        => "Left",
+            TurnType::Right       => "Right",
+            TurnType::SlightLeft  => "Slight Left",
+            TurnType::SlightRight => "Slight Right",
+            TurnType::SharpLeft   => "Sharp Left",
+            TurnType::SharpRight  => "Sharp Right",
+            TurnType::Straight    => "Straight",
+            TurnType::UTurn       => "U Turn",
+            TurnType::Generic     => "Generic",
+            TurnType::Summit      => "Summit",
+            TurnType::Valley      => "Valley",
+            TurnType::Water       => "Water",
+            TurnType::Food        => "Food",
+            TurnType::Danger      => "Danger",
+            TurnType::FirstAid    => "First Aid",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        TURN_TYPES.iter().find(|turn_type| turn_type.name() == name).copied()
+    }
+
+    /// Whether following the course means turning here, rather than just
+    /// passing something of note.
+    pub fn is_turn(&self) -> bool {
+        !matches!(self, TurnType::Generic | TurnType::Summit | TurnType::Valley | TurnType::Water |
+                        TurnType::Food | TurnType::Danger | TurnType::FirstAid)
+    }
+}
+
+/// The standard fields of a single course point. See
+/// `RWTFile::add_standard_course_point` to write one.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CoursePoint {
+    pub lat: Option<f64>,
+    pub lng: Option<f64>,
+    /// Meters from the start of the course.
+    pub distance: Option<f64>,
+    pub turn_type: Option<TurnType>,
+    pub note: Option<String>,
+    /// Seconds since the unix epoch.
+    pub eta: Option<i64>,
+}
+
+/// Reads the standard fields of a course points section row by row. Like
+/// `TrackPointReader`, a field stored with the wrong column type reads as
+/// `None`, as does a type name this version doesn't know.
+#[derive(Debug)]
+pub struct CoursePointReader<'a> {
+    len: usize,
+    lat: Option<&'a BTreeMap<usize, f64>>,
+    lng: Option<&'a BTreeMap<usize, f64>>,
+    distance: Option<&'a BTreeMap<usize, f64>>,
+    turn_type: Option<&'a BTreeMap<usize, String>>,
+    note: Option<&'a BTreeMap<usize, String>>,
+    eta: Option<&'a BTreeMap<usize, i64>>,
+}
+
+fn floats<'a>(section: &'a Section, field: &Field) -> Option<&'a BTreeMap<usize, f64>> {
+    match field.column(section) {
+        Some(Column::LongFloat(m)) | Some(Column::ShortFloat(m)) => Some(m),
+        _ => None,
+    }
+}
+
+fn strings<'a>(section: &'a Section, field: &Field) -> Option<&'a BTreeMap<usize, String>> {
+    match field.column(section) {
+        Some(Column::String(m)) => Some(m),
+        _ => None,
+    }
+}
+
+fn numbers<'a>(section: &'a Section, field: &Field) -> Option<&'a BTreeMap<usize, i64>> {
+    match field.column(section) {
+        Some(Column::Numbers(m)) => Some(m),
+        _ => None,
+    }
+}
+
+impl<'a> CoursePointReader<'a> {
+    pub fn new(section: &'a Section) -> Self {
+        CoursePointReader{len: section.len(),
+                          lat: floats(section, &fields::LAT),
+                          lng: floats(section, &fields::LNG),
+                          distance: floats(section, &fields::DISTANCE),
+                          turn_type: strings(section, &fields::COURSE_POINT_TYPE),
+                          note: strings(section, &fields::NOTE),
+                          eta: numbers(section, &fields::ETA)}
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn turn_type(&self, index: usize) -> Option<TurnType> {
+        self.turn_type
+            .and_then(|m| m.get(&index))
+            .and_then(|name| TurnType::from_name(name))
+    }
+
+    pub fn note(&self, index: usize) -> Option<&'a str> {
+        self.note.and_then(|m| m.get(&index)).map(|note| note.as_str())
+    }
+
+    pub fn point(&self, index: usize) -> CoursePoint {
+        CoursePoint{lat: self.lat.and_then(|m| m.get(&index)).copied(),
+                    lng: self.lng.and_then(|m| m.get(&index)).copied(),
+                    distance: self.distance.and_then(|m| m.get(&index)).copied(),
+                    turn_type: self.turn_type(index),
+                    note: self.note(index).map(|note| note.to_string()),
+                    eta: self.eta.and_then(|m| m.get(&index)).copied()}
+    }
+
+    /// Every row in order.
+    pub fn points(&self) -> impl Iterator<Item = CoursePoint> + '_ {
+        (0..self.len).map(move |index| self.point(index))
+    }
+}
+
+// A track row with a position
+#[derive(Debug, Copy, Clone)]
+struct Located {
+    lat: f64,
+    lng: f64,
+    distance: Option<f64>,
+    time: Option<i64>,
+}
+
+// The positioned rows of each segment of `track`. Without a distance column
+// distances are summed along each segment, leaving out the gaps between
+// segments.
+fn located_segments(track: &Section) -> Vec<Vec<Located>> {
+    let reader = TrackPointReader::new(track);
+    let distances = floats(track, &fields::DISTANCE);
+    let mut total = 0.0;
+    reader.segments()
+        .into_iter()
+        .map(|rows| {
+            let mut points: Vec<Located> = vec![];
+            for row in rows {
+                if let (Some(lat), Some(lng)) = (reader.lat(row), reader.lng(row)) {
+                    let distance = match distances {
+                        Some(m) => m.get(&row).copied(),
+                        None => {
+                            if let Some(prev) = points.last() {
+                                total += haversine(prev.lng, prev.lat, lng, lat);
+                            }
+                            Some(total)
+                        }
+                    };
+                    points.push(Located{lat, lng, distance, time: reader.time(row)});
+                }
+            }
+            points
+        })
+        .collect()
+}
+
+// How far along a -> b the point nearest to (lat, lng) is, from 0 to 1, and
+// the square of its distance on a flat map with longitude shrunk by `scale`
+fn project(a: &Located, b: &Located, lat: f64, lng: f64, scale: f64) -> (f64, f64) {
+    let (dx, dy) = ((b.lng - a.lng) * scale, b.lat - a.lat);
+    let (px, py) = ((lng - a.lng) * scale, lat - a.lat);
+    let len = dx * dx + dy * dy;
+    let along = if len > 0.0 { ((px * dx + py * dy) / len).clamp(0.0, 1.0) } else { 0.0 };
+    let (ox, oy) = (px - along * dx, py - along * dy);
+    (along, ox * ox + oy * oy)
+}
+
+/// Fills in the `fields::DISTANCE` and `fields::ETA` of every course point
+/// which has a position, from the nearest point on `track`. Distances and
+/// times are interpolated between the track rows either side of it; a track
+/// without a `fields::DISTANCE` column is measured along its positions.
+/// Where the track passes equally near a course point more than once, the
+/// first pass wins. Values a course point already has are kept. Course
+/// points near rows without times get no ETA, and nothing is written for a
+/// track without positions.
+pub fn locate_course_points(track: &Section, course_points: &mut Section) -> Result<()> {
+    let segments = located_segments(track);
+    let positions = {
+        let reader = CoursePointReader::new(course_points);
+        (0..reader.len())
+            .filter_map(|row| match reader.point(row) {
+                CoursePoint{lat: Some(lat), lng: Some(lng), distance, eta, ..} => {
+                    Some((row, lat, lng, distance.is_none(), eta.is_none()))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    for (row, lat, lng, needs_distance, needs_eta) in positions {
+        let scale = lat.to_radians().cos().abs();
+        let mut nearest: Option<(f64, &Located, &Located, f64)> = None;
+        for points in &segments {
+            // a segment with one point is a leg from it to itself
+            let legs = match points.len() {
+                1 => vec![(&points[0], &points[0])],
+                _ => points.iter().zip(points.iter().skip(1)).collect(),
+            };
+            for (a, b) in legs {
+                let (along, off) = project(a, b, lat, lng, scale);
+                if nearest.is_none_or(|(best, ..)| off < best) {
+                    nearest = Some((off, a, b, along));
+                }
+            }
+        }
+
+        if let Some((_, a, b, along)) = nearest {
+            if let (true, Some(from), Some(to)) = (needs_distance, a.distance, b.distance) {
+                RWTFile::add_point(course_points, row, fields::DISTANCE.name, DataField::ShortFloat(from + (to - from) * along))?;
+            }
+            if let (true, Some(from), Some(to)) = (needs_eta, a.time, b.time) {
+                let eta = from + ((to - from) as f64 * along).round() as i64;
+                RWTFile::add_point(course_points, row, fields::ETA.name, eta)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::section::{SectionType};
+
+    #[test]
+    fn test_turn_type_names() {
+        for turn_type in TURN_TYPES {
+            assert_eq!(TurnType::from_name(turn_type.name()), Some(*turn_type));
+        }
+        assert_eq!(TurnType::from_name("left"), None);
+        assert!(TurnType::SharpLeft.is_turn());
+        assert!(!TurnType::Water.is_turn());
+    }
+
+    #[test]
+    fn test_course_points() {
+        let left = CoursePoint{lat: Some(45.5),
+                               lng: Some(-122.6),
+                               distance: Some(1250.5),
+                               turn_type: Some(TurnType::Left),
+                               note: Some("Turn left onto Main St".into()),
+                               eta: Some(1_600_000_000)};
+        let water = CoursePoint{turn_type: Some(TurnType::Water), ..CoursePoint::default()};
+
+        let mut f = RWTFile::new();
+        assert!(f.add_standard_course_point(0, &left).is_ok());
+        assert!(f.add_standard_course_point(1, &water).is_ok());
+        assert!(f.add_course_point(2, "t", "Portal".to_string()).is_ok());
+
+        let f = RWTFile::read(&f.to_bytes().unwrap()).unwrap();
+        let reader = CoursePointReader::new(f.course_points());
+        assert_eq!(reader.len(), 3);
+        assert_eq!(reader.note(0), Some("Turn left onto Main St"));
+        assert_eq!(reader.points().collect::<Vec<_>>(),
+                   vec![left, water, CoursePoint::default()]);
+    }
+
+    #[test]
+    fn test_locate_course_points() {
+        // east along the equator and back, 0.001 degrees (about 111m) a row,
+        // 10 seconds apart
+        let mut track = Section::new(SectionType::TrackPoints);
+        for i in 0..21 {
+            let lng = if i <= 10 { i } else { 20 - i } as f64 / 1000.0;
+            assert!(track.add_long_float(i, "y", 0.0).is_ok());
+            assert!(track.add_long_float(i, "x", lng).is_ok());
+            assert!(track.add_number(i, "t", 1000 + 10 * i as i64).is_ok());
+        }
+
+        let mut course_points = Section::new(SectionType::CoursePoints);
+        // just off the track, halfway between rows 2 and 3
+        assert!(course_points.add_long_float(0, "y", 0.0001).is_ok());
+        assert!(course_points.add_long_float(0, "x", 0.0025).is_ok());
+        assert!(course_points.add_string(0, "t", "Left".into()).is_ok());
+        // past the turnaround
+        assert!(course_points.add_long_float(1, "y", 0.0).is_ok());
+        assert!(course_points.add_long_float(1, "x", 0.02).is_ok());
+        // no position
+        assert!(course_points.add_string(2, "n", "somewhere".into()).is_ok());
+
+        assert!(locate_course_points(&track, &mut course_points).is_ok());
+        let reader = CoursePointReader::new(&course_points);
+        let points = reader.points().collect::<Vec<_>>();
+        assert_eq!(points[0].eta, Some(1025));
+        assert!((points[0].distance.unwrap() - 278.0).abs() < 1.0);
+        assert_eq!(points[0].turn_type, Some(TurnType::Left));
+        assert_eq!(points[1].eta, Some(1100));
+        assert!((points[1].distance.unwrap() - 1112.0).abs() < 1.0);
+        assert_eq!(points[2], CoursePoint{note: Some("somewhere".into()), ..CoursePoint::default()});
+
+        // the track's own distances win over measuring it, and values the
+        // course points already have are kept
+        for i in 0..21 {
+            assert!(track.add_short_float(i, "d", 100.0 * i as f64).is_ok());
+        }
+        let mut course_points = Section::new(SectionType::CoursePoints);
+        assert!(course_points.add_long_float(0, "y", 0.0).is_ok());
+        assert!(course_points.add_long_float(0, "x", 0.0025).is_ok());
+        assert!(course_points.add_number(0, "E", 5).is_ok());
+        assert!(locate_course_points(&track, &mut course_points).is_ok());
+        let reader = CoursePointReader::new(&course_points);
+        assert_eq!(reader.point(0).distance, Some(250.0));
+        assert_eq!(reader.point(0).eta, Some(5));
+    }
+}
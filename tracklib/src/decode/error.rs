@@ -0,0 +1,167 @@
+use snafu::{Snafu};
+use std::fmt;
+use crate::decode::options::{Location};
+use crate::error::{ErrorKind};
+
+/// Where in the file the parser was when it failed.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Position {
+    pub location: Location,
+    pub column: Option<String>,
+    pub row: Option<usize>,
+}
+
+impl Position {
+    pub(crate) fn new(location: Location) -> Self {
+        Position{location, column: None, row: None}
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Location::Header => write!(f, "header"),
+            Location::Metadata => write!(f, "metadata table"),
+            Location::SectionHeader{section} => write!(f, "section {} header", section),
+            Location::TypesTable{section} => write!(f, "section {} types table", section),
+            Location::Data{section} => write!(f, "section {} data", section),
+            Location::Trailer => write!(f, "trailer"),
+        }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.location)?;
+        if let Some(column) = &self.column {
+            write!(f, ", column {:?}", column)?;
+        }
+        if let Some(row) = self.row {
+            write!(f, ", row {}", row)?;
+        }
+        Ok(())
+    }
+}
+
+/// Offsets are from the start of the input.
+#[derive(Debug, Snafu)]
+#[non_exhaustive]
+pub enum Error {
+    #[snafu(display("Unexpected end of input at byte {} in {}", offset, position))]
+    Incomplete{offset: usize, position: Position},
+    #[snafu(display("Invalid CRC at byte {} in {}: expected {:#x}, found {:#x}", offset, position, expected, found))]
+    InvalidCrc{offset: usize, position: Position, expected: u32, found: u32},
+    #[snafu(display("Length {} at byte {} in {} is over the limit of {}", found, offset, position, limit))]
+    LimitExceeded{offset: usize, position: Position, limit: u64, found: u64},
+    #[snafu(display("Invalid UTF-8 at byte {} in {}", offset, position))]
+    InvalidUtf8{offset: usize, position: Position},
+    #[snafu(display("Malformed data at byte {} in {}", offset, position))]
+    Malformed{offset: usize, position: Position},
+    #[snafu(display("Unsupported file version {} at byte {} in {}, the newest supported version is {}", found, offset, position, supported))]
+    UnsupportedVersion{offset: usize, position: Position, found: u8, supported: u8},
+    #[snafu(display("Second section of the same type at byte {} in {}", offset, position))]
+    DuplicateSection{offset: usize, position: Position},
+    #[snafu(display("Misaligned at byte {} in {}: expected byte {}", offset, position, expected))]
+    Misaligned{offset: usize, position: Position, expected: usize},
+    #[snafu(display("{} unexpected bytes at byte {} in {}", count, offset, position))]
+    TrailingBytes{offset: usize, position: Position, count: usize},
+    #[snafu(display("Continuation section at byte {} in {} has no section before it to continue", offset, position))]
+    MisplacedContinuation{offset: usize, position: Position},
+    #[snafu(display("Continuation section at byte {} in {} doesn't match the section it continues", offset, position))]
+    ContinuationMismatch{offset: usize, position: Position},
+    #[snafu(display("Trailer at byte {} in {} where a section was expected", offset, position))]
+    UnexpectedTrailer{offset: usize, position: Position},
+}
+
+impl Error {
+    pub fn offset(&self) -> usize {
+        match self {
+            Error::Incomplete{offset, ..} |
+            Error::InvalidCrc{offset, ..} |
+            Error::LimitExceeded{offset, ..} |
+            Error::InvalidUtf8{offset, ..} |
+            Error::Malformed{offset, ..} |
+            Error::UnsupportedVersion{offset, ..} |
+            Error::DuplicateSection{offset, ..} |
+            Error::Misaligned{offset, ..} |
+            Error::TrailingBytes{offset, ..} |
+            Error::MisplacedContinuation{offset, ..} |
+            Error::ContinuationMismatch{offset, ..} |
+            Error::UnexpectedTrailer{offset, ..} => *offset,
+        }
+    }
+
+    // The same error with its offsets moved `by` bytes later, for a file
+    // parsed a piece at a time
+    pub(crate) fn moved(mut self, by: usize) -> Self {
+        match &mut self {
+            Error::Incomplete{offset, ..} |
+            Error::InvalidCrc{offset, ..} |
+            Error::LimitExceeded{offset, ..} |
+            Error::InvalidUtf8{offset, ..} |
+            Error::Malformed{offset, ..} |
+            Error::UnsupportedVersion{offset, ..} |
+            Error::DuplicateSection{offset, ..} |
+            Error::TrailingBytes{offset, ..} |
+            Error::MisplacedContinuation{offset, ..} |
+            Error::ContinuationMismatch{offset, ..} |
+            Error::UnexpectedTrailer{offset, ..} => *offset += by,
+            Error::Misaligned{offset, expected, ..} => {
+                *offset += by;
+                *expected += by;
+            }
+        }
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::LimitExceeded{..} => ErrorKind::TooLarge,
+            Error::UnsupportedVersion{..} => ErrorKind::Unsupported,
+            Error::Incomplete{..} |
+            Error::InvalidCrc{..} |
+            Error::InvalidUtf8{..} |
+            Error::Malformed{..} |
+            Error::DuplicateSection{..} |
+            Error::Misaligned{..} |
+            Error::TrailingBytes{..} |
+            Error::MisplacedContinuation{..} |
+            Error::ContinuationMismatch{..} |
+            Error::UnexpectedTrailer{..} => ErrorKind::Corruption,
+        }
+    }
+
+    pub fn position(&self) -> &Position {
+        match self {
+            Error::Incomplete{position, ..} |
+            Error::InvalidCrc{position, ..} |
+            Error::LimitExceeded{position, ..} |
+            Error::InvalidUtf8{position, ..} |
+            Error::Malformed{position, ..} |
+            Error::UnsupportedVersion{position, ..} |
+            Error::DuplicateSection{position, ..} |
+            Error::Misaligned{position, ..} |
+            Error::TrailingBytes{position, ..} |
+            Error::MisplacedContinuation{position, ..} |
+            Error::ContinuationMismatch{position, ..} |
+            Error::UnexpectedTrailer{position, ..} => position,
+        }
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+// Details the nom error codes can't carry, recorded just before failing
+#[derive(Debug)]
+pub(crate) enum Failure {
+    InvalidCrc{expected: u32, found: u32},
+    LimitExceeded{limit: u64, found: u64},
+    InvalidUtf8,
+    UnsupportedVersion{found: u8, supported: u8},
+    DuplicateSection,
+    Misaligned{expected: usize},
+    TrailingBytes{count: usize},
+    MisplacedContinuation,
+    ContinuationMismatch,
+    UnexpectedTrailer,
+}
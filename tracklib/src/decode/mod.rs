@@ -1,19 +1,83 @@
 use std::iter::FromIterator;
 use std::time::{UNIX_EPOCH, Duration};
 use std::collections::{BTreeMap};
+use std::convert::{TryFrom};
 use nom::*;
 use ::crc::crc32::{checksum_ieee};
 use ::crc::crc16::{checksum_usb};
 
 mod varint;
 mod crc;
+mod options;
+mod error;
 
 use varint::{take_signed_leb128, take_unsigned_leb128};
-use crate::flagscolumn::{FlagsColumn};
-use crate::rwtfile::{RWTFMAGIC, RWTFTRAILER, RWTFHeader, RWTFile};
+use crate::flagscolumn::{Flags, FlagsColumn, MAX_FIELDS};
+use crate::rwtfile::{RWTFMAGIC, RWTFTRAILER, RWTF_FILE_VERSION, DataField, RWTFHeader, RWTFile, HEADER_FILE_CHECKSUM};
 use crate::metadata::{RWTFMetadata, TrackType};
-use crate::section::{Column, Section, SectionType};
+use crate::section::{geo_point_scale, Column, RawSection, Section, SectionType, TimestampUnit, MAX_GEO_POINT_DECIMALS, RUN_LENGTH_FLAGS, WIDE_TYPES_TABLE, section_file_version};
 use crate::decode::crc::{CRC};
+pub use crate::decode::options::{CrcPolicy, Location, ParseOptions, ParseWarning, Utf8Policy};
+pub use crate::decode::error::{Error, Position};
+use crate::decode::error::{Failure, Result};
+use crate::xorfloat;
+use crate::bitpack;
+use crate::map;
+use crate::fields::{FieldType};
+use crate::progress::{Progress};
+
+// Everything the parser carries along besides the input
+struct ParseState<'o> {
+    options: &'o ParseOptions,
+    warnings: Vec<ParseWarning>,
+    position: Position,
+    failure: Option<Failure>,
+    progress: Option<&'o mut dyn FnMut(Progress)>,
+}
+
+impl<'o> ParseState<'o> {
+    fn new(options: &'o ParseOptions) -> Self {
+        ParseState{options,
+                   warnings: vec![],
+                   position: Position::new(Location::Header),
+                   failure: None,
+                   progress: None}
+    }
+
+    fn report(&mut self, rows: usize, bytes: usize, total_bytes: usize) {
+        if let Some(progress) = &mut self.progress {
+            progress(Progress{rows, bytes, total_bytes});
+        }
+    }
+
+    fn fail<'a>(&mut self, i: &'a [u8], failure: Failure, code: u32) -> Err<&'a [u8]> {
+        self.failure = Some(failure);
+        Err::Failure(Context::Code(i, ErrorKind::Custom(code)))
+    }
+
+    fn into_error(self, input: &[u8], e: Err<&[u8]>) -> Error {
+        let position = self.position;
+        match e {
+            Err::Incomplete(_) => Error::Incomplete{offset: input.len(), position},
+            Err::Error(Context::Code(rest, _)) | Err::Failure(Context::Code(rest, _)) => {
+                let offset = input.offset(rest);
+                match self.failure {
+                    Some(Failure::InvalidCrc{expected, found}) => Error::InvalidCrc{offset, position, expected, found},
+                    Some(Failure::LimitExceeded{limit, found}) => Error::LimitExceeded{offset, position, limit, found},
+                    Some(Failure::InvalidUtf8) => Error::InvalidUtf8{offset, position},
+                    Some(Failure::UnsupportedVersion{found, supported}) => Error::UnsupportedVersion{offset, position, found, supported},
+                    Some(Failure::DuplicateSection) => Error::DuplicateSection{offset, position},
+                    Some(Failure::Misaligned{expected}) => Error::Misaligned{offset, position, expected},
+                    Some(Failure::TrailingBytes{count}) => Error::TrailingBytes{offset, position, count},
+                    Some(Failure::MisplacedContinuation) => Error::MisplacedContinuation{offset, position},
+                    Some(Failure::ContinuationMismatch) => Error::ContinuationMismatch{offset, position},
+                    Some(Failure::UnexpectedTrailer) => Error::UnexpectedTrailer{offset, position},
+                    None => Error::Malformed{offset, position},
+                }
+            }
+        }
+    }
+}
 
 trait Parsable {
     type Return;
@@ -38,7 +102,8 @@ impl Parsable for RWTFHeader {
         do_parse!(i,
                   tag!(RWTFMAGIC) >>
                   file_version: le_u8 >>
-                  le_u24 >>
+                  flags: le_u8 >>
+                  le_u16 >>
                   creator_version: le_u8 >>
                   le_u24 >>
                   metadata_table_offset: le_u16 >>
@@ -46,7 +111,8 @@ impl Parsable for RWTFHeader {
                   le_u16 >>
                   crc: le_u16 >>
                   ((RWTFHeader{file_version,
-                               creator_version},
+                               creator_version,
+                               file_checksum: flags & HEADER_FILE_CHECKSUM != 0},
                     ParsedHeader{metadata_table_offset,
                                  data_offset,
                                  crc: CRC::new(crc, checksum_usb(&i[0..22]))})))
@@ -60,7 +126,7 @@ impl Parsable for RWTFHeader {
 enum RWTFMetadataEntry {
     TrackType(TrackType),
     CreatedAt(u64),
-    Unknown,
+    Unknown{tag: u8, data: Vec<u8>},
 }
 
 fn parse_metadata_table_entry_data(i: &[u8], tag: u8) -> IResult<&[u8], RWTFMetadataEntry> {
@@ -86,9 +152,8 @@ fn parse_metadata_table_entry_data(i: &[u8], tag: u8) -> IResult<&[u8], RWTFMeta
         }
         _ => {
             let (rest, size) = le_u16(i)?;
-            let (rest, _data) = take!(rest, size)?;
-            // todo: do something with data
-            Ok((rest, RWTFMetadataEntry::Unknown))
+            let (rest, data) = take!(rest, size)?;
+            Ok((rest, RWTFMetadataEntry::Unknown{tag, data: data.to_vec()}))
         }
     }
 }
@@ -114,6 +179,7 @@ impl Parsable for RWTFMetadata {
 
         let mut created_at = None;
         let mut track_type = None;
+        let mut unknown_entries = vec![];
 
         for entry in entries {
             match entry {
@@ -123,11 +189,16 @@ impl Parsable for RWTFMetadata {
                 RWTFMetadataEntry::CreatedAt(time) => {
                     created_at = UNIX_EPOCH.checked_add(Duration::new(time, 0));
                 },
-                RWTFMetadataEntry::Unknown => {},
+                RWTFMetadataEntry::Unknown{tag, data} => {
+                    unknown_entries.push((tag, data));
+                },
             }
         }
 
-        Ok((rest, (RWTFMetadata::new(created_at, track_type),
+        let mut metadata = RWTFMetadata::new(created_at, track_type);
+        metadata.unknown_entries = unknown_entries;
+
+        Ok((rest, (metadata,
                    CRC::new(crc, checksum_usb(&i[..diff])))))
     }
 }
@@ -136,42 +207,70 @@ impl Parsable for RWTFMetadata {
 //       Flags Column       //
 //////////////////////////////
 impl FlagsColumn {
-    fn parse_flags_column<'a, 'b>(i: &'a [u8], types_table: &'b TypesTable, points: u32) -> IResult<&'a [u8], FlagsColumn> {
+    fn parse_flags_column<'a>(i: &'a [u8], types_table: &TypesTable, points: u32, run_length: bool) -> IResult<&'a [u8], FlagsColumn> {
         let width = (types_table.entries.len() + 7) / 8;
 
         let fields = BTreeMap::from_iter(types_table.entries.iter().enumerate().map(|(i, entry)| (entry.name.clone(), i)));
 
-        let mut data = BTreeMap::new();
-        let mut remainder = i;
-        for i in 0..points {
-            let (rest, bitfield_bytes) = take!(remainder, width)?;
-            remainder = rest;
+        if run_length {
+            let (remainder, data) = Self::parse_flags_runs(i, width, points as usize)?;
+            return Ok((remainder, FlagsColumn{fields,
+                                              data,
+                                              rows: points as usize}));
+        }
 
-            let mut bitfield_array = [0; 8];
-            for i in 0..8 {
-                bitfield_array[i] = *bitfield_bytes.get(i).unwrap_or(&0);
-            }
+        // take every row's flags at once so a short input fails right away
+        let (remainder, flags_bytes) = take!(i, width * points as usize)?;
 
-            let bitfield_integer = u64::from_le_bytes(bitfield_array);
+        let mut data = BTreeMap::new();
+        // with no columns there are no flags, and no chunks
+        for (i, bitfield_bytes) in flags_bytes.chunks(width.max(1)).enumerate() {
+            let bitfield = Flags::from_le_bytes(bitfield_bytes);
 
-            if bitfield_integer > 0 {
-                data.insert(i as usize, bitfield_integer);
+            if !bitfield.is_empty() {
+                data.insert(i, bitfield);
             }
         }
 
         Ok((remainder, FlagsColumn{fields: fields,
                                    data: data,
-                                   max: (points - 1) as usize}))
+                                   rows: points as usize}))
+    }
+
+    // Runs of (row count, flags) which must cover exactly `points` rows
+    fn parse_flags_runs(i: &[u8], width: usize, points: usize) -> IResult<&[u8], BTreeMap<usize, Flags>> {
+        let mut data = BTreeMap::new();
+        let mut remainder = i;
+        let mut row = 0;
+        while row < points {
+            let (rest, count) = take_unsigned_leb128(remainder)?;
+            let count = match usize::try_from(count) {
+                Ok(count) if count > 0 && count <= points - row => count,
+                _ => return Err(Err::Error(Context::Code(remainder, ErrorKind::Custom(0)))),
+            };
+            let (rest, bitfield_bytes) = take!(rest, width)?;
+            remainder = rest;
+
+            let bitfield = Flags::from_le_bytes(bitfield_bytes);
+            if !bitfield.is_empty() {
+                data.extend((row..row + count).map(|index| (index, bitfield.clone())));
+            }
+            row += count;
+        }
+
+        Ok((remainder, data))
     }
 }
 
 //////////////////////////////
 //         Section          //
 //////////////////////////////
-fn parse_section_type(i: &[u8]) -> IResult<&[u8], SectionType> {
+// The section type, whether its flags are run-length encoded and whether its
+// types table is wide
+fn parse_section_type(i: &[u8]) -> IResult<&[u8], (SectionType, bool, bool)> {
     let (rest, tag) = le_u8(i)?;
-    match SectionType::from_tag(tag) {
-        Some(st) => Ok((rest, st)),
+    match SectionType::from_tag(tag & !(RUN_LENGTH_FLAGS | WIDE_TYPES_TABLE)) {
+        Some(st) => Ok((rest, (st, tag & RUN_LENGTH_FLAGS != 0, tag & WIDE_TYPES_TABLE != 0))),
         None => Err(Err::Error(Context::Code(i, ErrorKind::Custom(0)))),
     }
 }
@@ -185,6 +284,16 @@ enum ColumnType {
     String,
     Bool,
     IDs,
+    PackedBool,
+    RunLengthNumbers,
+    XorFloat,
+    PackedIDs,
+    Map,
+    GeoPoint,
+    I64Array,
+    F64Array,
+    Timestamp,
+    Unknown(u8),
 }
 
 impl ColumnType {
@@ -197,21 +306,53 @@ impl ColumnType {
             0x04 => Some(ColumnType::String),
             0x05 => Some(ColumnType::Bool),
             0x06 => Some(ColumnType::IDs),
+            0x07 => Some(ColumnType::PackedBool),
+            0x08 => Some(ColumnType::RunLengthNumbers),
+            0x09 => Some(ColumnType::XorFloat),
+            0x0A => Some(ColumnType::PackedIDs),
+            0x0B => Some(ColumnType::Map),
+            0x0C => Some(ColumnType::GeoPoint),
+            0x0D => Some(ColumnType::I64Array),
+            0x0E => Some(ColumnType::F64Array),
+            0x0F => Some(ColumnType::Timestamp),
             _ => None
         }
     }
+
+    // None for types this version doesn't know
+    fn field_type(&self) -> Option<FieldType> {
+        match self {
+            ColumnType::Numbers | ColumnType::RunLengthNumbers => Some(FieldType::Numbers),
+            ColumnType::LongFloat  => Some(FieldType::LongFloat),
+            ColumnType::ShortFloat => Some(FieldType::ShortFloat),
+            ColumnType::Base64     => Some(FieldType::Base64),
+            ColumnType::String     => Some(FieldType::String),
+            ColumnType::Bool       => Some(FieldType::Bool),
+            ColumnType::IDs | ColumnType::PackedIDs => Some(FieldType::IDs),
+            ColumnType::PackedBool => Some(FieldType::PackedBool),
+            ColumnType::XorFloat   => Some(FieldType::XorFloat),
+            ColumnType::Map        => Some(FieldType::Map),
+            ColumnType::GeoPoint   => Some(FieldType::GeoPoint),
+            ColumnType::I64Array   => Some(FieldType::I64Array),
+            ColumnType::F64Array   => Some(FieldType::F64Array),
+            ColumnType::Timestamp  => Some(FieldType::Timestamp),
+            ColumnType::Unknown(_) => None,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct SectionHeader {
     section_type: SectionType,
+    run_length_flags: bool,
+    wide_types_table: bool,
     points: u32,
     size: u64,
     crc: CRC<u16>,
 }
 
 fn parse_section_header(i: &[u8]) -> IResult<&[u8], SectionHeader> {
-    let (rest, section_type) = parse_section_type(i)?;
+    let (rest, (section_type, run_length_flags, wide_types_table)) = parse_section_type(i)?;
     let (rest, points) = le_u24(rest)?;
     let (rest, size) = le_u64(rest)?;
 
@@ -219,6 +360,8 @@ fn parse_section_header(i: &[u8]) -> IResult<&[u8], SectionHeader> {
     let (rest, crc) = le_u16(rest)?;
 
     Ok((rest, SectionHeader{section_type,
+                            run_length_flags,
+                            wide_types_table,
                             points,
                             size,
                             crc: CRC::new(crc, checksum_usb(&i[..diff]))}))
@@ -226,24 +369,31 @@ fn parse_section_header(i: &[u8]) -> IResult<&[u8], SectionHeader> {
 
 #[derive(Debug)]
 struct TypesTableEntry {
+    type_tag: u8,
     column_type: ColumnType,
     name: String,
 }
 
-fn parse_column_type(i: &[u8]) -> IResult<&[u8], ColumnType> {
-    let (rest, tag) = le_u8(i)?;
-    match ColumnType::from_tag(tag) {
-        Some(c) => Ok((rest, c)),
-        None => Err(Err::Error(Context::Code(i, ErrorKind::Custom(0)))),
+// A count or name length in the types table, a leb128 if it's wide
+fn parse_types_table_len(i: &[u8], wide: bool) -> IResult<&[u8], usize> {
+    if !wide {
+        let (rest, len) = le_u8(i)?;
+        return Ok((rest, usize::from(len)));
+    }
+    let (rest, len) = take_unsigned_leb128(i)?;
+    match usize::try_from(len) {
+        Ok(len) => Ok((rest, len)),
+        Err(_) => Err(Err::Error(Context::Code(i, ErrorKind::Custom(0)))),
     }
 }
 
-fn parse_types_table_entry(i: &[u8]) -> IResult<&[u8], TypesTableEntry> {
+fn parse_types_table_entry(i: &[u8], wide: bool) -> IResult<&[u8], TypesTableEntry> {
     do_parse!(i,
-              column_type: parse_column_type >>
-              name_len: le_u8 >>
+              type_tag: le_u8 >>
+              name_len: apply!(parse_types_table_len, wide) >>
               name: take!(name_len) >>
-              (TypesTableEntry{column_type,
+              (TypesTableEntry{type_tag,
+                               column_type: ColumnType::from_tag(type_tag).unwrap_or(ColumnType::Unknown(type_tag)),
                                name: String::from_utf8_lossy(name).into_owned()}))
 }
 
@@ -253,11 +403,12 @@ pub struct TypesTable {
     crc: CRC<u16>,
 }
 
-fn parse_types_table(i: &[u8]) -> IResult<&[u8], TypesTable> {
-    let (rest, entries) = do_parse!(i,
-                                    count: le_u8 >>
-                                    entries: many_m_n!(count as usize, count as usize, parse_types_table_entry) >>
-                                    (entries))?;
+fn parse_types_table(i: &[u8], wide: bool) -> IResult<&[u8], TypesTable> {
+    let (rest, count) = parse_types_table_len(i, wide)?;
+    if count > MAX_FIELDS {
+        return Err(Err::Error(Context::Code(i, ErrorKind::Custom(0))));
+    }
+    let (rest, entries) = many_m_n!(rest, count, count, apply!(parse_types_table_entry, wide))?;
     let diff = i.offset(rest);
     let (rest, crc) = le_u16(rest)?;
 
@@ -269,12 +420,35 @@ fn parse_number_row<'a>(i: &'a [u8]) -> IResult<&'a [u8], i64> {
     take_signed_leb128(i)
 }
 
-fn parse_bytes_row<'a>(i: &'a [u8]) -> IResult<&'a [u8], &'a [u8]> {
-    do_parse!(i,
-              len: take_unsigned_leb128 >>
-              bytes: take!(len) >>
-              (bytes))
+// Tracks the lengths declared by the values of a single column
+struct ColumnLimits {
+    column_size: u64,
+}
 
+impl ColumnLimits {
+    fn new() -> Self {
+        ColumnLimits{column_size: 0}
+    }
+
+    fn check<'a>(&mut self, i: &'a [u8], len: u64, state: &mut ParseState) -> Result<(), Err<&'a [u8]>> {
+        let max_value_len = state.options.max_value_len();
+        let max_column_size = state.options.max_column_size();
+        self.column_size = self.column_size.saturating_add(len);
+
+        if len > max_value_len {
+            Err(state.fail(i, Failure::LimitExceeded{limit: max_value_len, found: len}, LIMIT_EXCEEDED))
+        } else if self.column_size > max_column_size {
+            Err(state.fail(i, Failure::LimitExceeded{limit: max_column_size, found: self.column_size}, LIMIT_EXCEEDED))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn parse_bytes_row<'a>(i: &'a [u8], limits: &mut ColumnLimits, state: &mut ParseState) -> IResult<&'a [u8], &'a [u8]> {
+    let (rest, len) = take_unsigned_leb128(i)?;
+    limits.check(i, len, state)?;
+    take!(rest, len)
 }
 
 fn parse_bool_row<'a>(i: &'a [u8]) -> IResult<&'a [u8], bool> {
@@ -289,29 +463,109 @@ fn parse_bool_row<'a>(i: &'a [u8]) -> IResult<&'a [u8], bool> {
               }))
 }
 
-fn parse_ids_row<'a>(i: &'a [u8]) -> IResult<&'a [u8], Vec<u64>> {
-    do_parse!(i,
-              count: take_unsigned_leb128 >>
-              entries: many_m_n!(count as usize, count as usize, take_unsigned_leb128) >>
-              (entries))
+fn parse_ids_row<'a>(i: &'a [u8], limits: &mut ColumnLimits, state: &mut ParseState) -> IResult<&'a [u8], Vec<u64>> {
+    let (rest, count) = take_unsigned_leb128(i)?;
+    limits.check(i, count, state)?;
+    many_m_n!(rest, count as usize, count as usize, take_unsigned_leb128)
+}
+
+// Each value is stored as its delta from the one before it in the row
+fn parse_i64_array_row<'a>(i: &'a [u8], limits: &mut ColumnLimits, state: &mut ParseState) -> IResult<&'a [u8], Vec<i64>> {
+    let (rest, count) = take_unsigned_leb128(i)?;
+    limits.check(i, count, state)?;
+    let (rest, deltas) = many_m_n!(rest, count as usize, count as usize, take_signed_leb128)?;
+    let values = deltas.into_iter()
+        .scan(0i64, |last, delta| {
+            *last = last.wrapping_add(delta);
+            Some(*last)
+        })
+        .collect();
+    Ok((rest, values))
+}
+
+fn parse_packed_ids_row<'a>(i: &'a [u8], limits: &mut ColumnLimits, state: &mut ParseState) -> IResult<&'a [u8], Vec<u64>> {
+    let (rest, count) = take_unsigned_leb128(i)?;
+    limits.check(i, count, state)?;
+    if count == 0 {
+        return Ok((rest, vec![]));
+    }
+
+    let (rest, width) = verify!(rest, le_u8, |width| width <= 64)?;
+    let count = usize::try_from(count).map_err(|_| Err::Error(Context::Code(i, ErrorKind::Custom(0))))?;
+    match bitpack::packed_len(count, width.into()) {
+        Some(len) => {
+            let (rest, packed) = take!(rest, len)?;
+            Ok((rest, bitpack::unpack(packed, count, width.into())))
+        }
+        None => Err(Err::Error(Context::Code(i, ErrorKind::Custom(0)))),
+    }
+}
+
+// Keys and String values of a map follow the Utf8Policy, except that Raw
+// is lossy since a map has nowhere to keep the bytes
+fn parse_map_str<'a>(i: &'a [u8], limits: &mut ColumnLimits, state: &mut ParseState) -> IResult<&'a [u8], String> {
+    let (rest, bytes) = parse_bytes_row(i, limits, state)?;
+    match state.options.utf8_policy() {
+        Utf8Policy::Strict => match std::str::from_utf8(bytes) {
+            Ok(s) => Ok((rest, s.to_string())),
+            Err(_) => Err(state.fail(bytes, Failure::InvalidUtf8, INVALID_UTF8)),
+        },
+        _ => Ok((rest, String::from_utf8_lossy(bytes).into_owned())),
+    }
+}
+
+fn parse_map_row<'a>(i: &'a [u8], limits: &mut ColumnLimits, state: &mut ParseState) -> IResult<&'a [u8], BTreeMap<String, DataField>> {
+    let (mut remainder, count) = take_unsigned_leb128(i)?;
+    limits.check(i, count, state)?;
+
+    let mut m = BTreeMap::new();
+    for _ in 0..count {
+        let (rest, key) = parse_map_str(remainder, limits, state)?;
+        let (rest, tag) = le_u8(rest)?;
+        let (rest, v) = match tag {
+            map::NUMBER => {
+                let (rest, v) = parse_number_row(rest)?;
+                (rest, DataField::Number(v))
+            }
+            map::LONG_FLOAT => {
+                let (rest, v) = le_f64(rest)?;
+                (rest, DataField::LongFloat(v))
+            }
+            map::STRING => {
+                let (rest, v) = parse_map_str(rest, limits, state)?;
+                (rest, DataField::String(v))
+            }
+            map::BOOL => {
+                let (rest, v) = parse_bool_row(rest)?;
+                (rest, DataField::Bool(v))
+            }
+            _ => return Err(Err::Error(Context::Code(rest, ErrorKind::Custom(0)))),
+        };
+        remainder = rest;
+        m.insert(key, v);
+    }
+
+    Ok((remainder, m))
 }
 
-fn parse_column<'a>(i: &'a [u8], column: &TypesTableEntry, flags: &FlagsColumn) -> IResult<&'a [u8], Column> {
+fn parse_column<'a>(i: &'a [u8], column: &TypesTableEntry, flags: &FlagsColumn, state: &mut ParseState) -> IResult<&'a [u8], Column> {
+    let mut limits = ColumnLimits::new();
     match column.column_type {
         ColumnType::Numbers => {
             let mut m = BTreeMap::new();
             let mut remainder = i;
-            let mut last = 0;
+            let mut last: i64 = 0;
             for index in 0..flags.len() {
+                state.position.row = Some(index);
                 if flags.is_present(index, &column.name) {
                     let (rest, delta) = parse_number_row(remainder)?;
                     remainder = rest;
-                    let v = last + delta;
+                    let v = last.wrapping_add(delta);
                     last = v;
                     m.insert(index, v);
                 } else {
                     // skip forward one byte
-                    remainder = &remainder[1..];
+                    remainder = take!(remainder, 1)?.0;
                 }
             }
 
@@ -320,17 +574,18 @@ fn parse_column<'a>(i: &'a [u8], column: &TypesTableEntry, flags: &FlagsColumn)
         ColumnType::LongFloat => {
             let mut m = BTreeMap::new();
             let mut remainder = i;
-            let mut last = 0;
+            let mut last: i64 = 0;
             for index in 0..flags.len() {
+                state.position.row = Some(index);
                 if flags.is_present(index, &column.name) {
                     let (rest, delta) = parse_number_row(remainder)?;
                     remainder = rest;
-                    let v = last + delta;
+                    let v = last.wrapping_add(delta);
                     last = v;
                     m.insert(index, v as f64 / 10000000.0);
                 } else {
                     // skip forward one byte
-                    remainder = &remainder[1..];
+                    remainder = take!(remainder, 1)?.0;
                 }
             }
 
@@ -339,17 +594,18 @@ fn parse_column<'a>(i: &'a [u8], column: &TypesTableEntry, flags: &FlagsColumn)
         ColumnType::ShortFloat => {
             let mut m = BTreeMap::new();
             let mut remainder = i;
-            let mut last = 0;
+            let mut last: i64 = 0;
             for index in 0..flags.len() {
+                state.position.row = Some(index);
                 if flags.is_present(index, &column.name) {
                     let (rest, delta) = parse_number_row(remainder)?;
                     remainder = rest;
-                    let v = last + delta;
+                    let v = last.wrapping_add(delta);
                     last = v;
                     m.insert(index, v as f64 / 1000.0);
                 } else {
                     // skip forward one byte
-                    remainder = &remainder[1..];
+                    remainder = take!(remainder, 1)?.0;
                 }
             }
 
@@ -359,45 +615,60 @@ fn parse_column<'a>(i: &'a [u8], column: &TypesTableEntry, flags: &FlagsColumn)
             let mut m = BTreeMap::new();
             let mut remainder = i;
             for index in 0..flags.len() {
+                state.position.row = Some(index);
                 if flags.is_present(index, &column.name) {
-                    let (rest, bytes) = parse_bytes_row(remainder)?;
+                    let (rest, bytes) = parse_bytes_row(remainder, &mut limits, state)?;
                     remainder = rest;
                     m.insert(index, bytes.to_vec());
                 } else {
                     // skip forward one byte
-                    remainder = &remainder[1..];
+                    remainder = take!(remainder, 1)?.0;
                 }
             }
 
             Ok((remainder, Column::Base64(m)))
         }
         ColumnType::String => {
+            let utf8_policy = state.options.utf8_policy();
             let mut m = BTreeMap::new();
+            let mut raw = BTreeMap::new();
             let mut remainder = i;
             for index in 0..flags.len() {
+                state.position.row = Some(index);
                 if flags.is_present(index, &column.name) {
-                    let (rest, bytes) = parse_bytes_row(remainder)?;
+                    let (rest, bytes) = parse_bytes_row(remainder, &mut limits, state)?;
                     remainder = rest;
-                    m.insert(index, String::from_utf8_lossy(bytes).into_owned());
+                    match utf8_policy {
+                        Utf8Policy::Strict => match std::str::from_utf8(bytes) {
+                            Ok(s) => { m.insert(index, s.to_string()); }
+                            Err(_) => return Err(state.fail(bytes, Failure::InvalidUtf8, INVALID_UTF8)),
+                        },
+                        Utf8Policy::Lossy => { m.insert(index, String::from_utf8_lossy(bytes).into_owned()); }
+                        Utf8Policy::Raw => { raw.insert(index, bytes.to_vec()); }
+                    }
                 } else {
                     // skip forward one byte
-                    remainder = &remainder[1..];
+                    remainder = take!(remainder, 1)?.0;
                 }
             }
 
-            Ok((remainder, Column::String(m)))
+            match utf8_policy {
+                Utf8Policy::Raw => Ok((remainder, Column::Base64(raw))),
+                _ => Ok((remainder, Column::String(m))),
+            }
         }
         ColumnType::Bool => {
             let mut m = BTreeMap::new();
             let mut remainder = i;
             for index in 0..flags.len() {
+                state.position.row = Some(index);
                 if flags.is_present(index, &column.name) {
                     let (rest, b) = parse_bool_row(remainder)?;
                     remainder = rest;
                     m.insert(index, b);
                 } else {
                     // skip forward one byte
-                    remainder = &remainder[1..];
+                    remainder = take!(remainder, 1)?.0;
                 }
             }
 
@@ -407,52 +678,394 @@ fn parse_column<'a>(i: &'a [u8], column: &TypesTableEntry, flags: &FlagsColumn)
             let mut m = BTreeMap::new();
             let mut remainder = i;
             for index in 0..flags.len() {
+                state.position.row = Some(index);
                 if flags.is_present(index, &column.name) {
-                    let (rest, b) = parse_ids_row(remainder)?;
+                    let (rest, b) = parse_ids_row(remainder, &mut limits, state)?;
                     remainder = rest;
                     m.insert(index, b);
                 } else {
                     // skip forward one byte
-                    remainder = &remainder[1..];
+                    remainder = take!(remainder, 1)?.0;
                 }
             }
 
             Ok((remainder, Column::IDs(m)))
         }
+        ColumnType::PackedIDs => {
+            let mut m = BTreeMap::new();
+            let mut remainder = i;
+            for index in 0..flags.len() {
+                state.position.row = Some(index);
+                if flags.is_present(index, &column.name) {
+                    let (rest, ids) = parse_packed_ids_row(remainder, &mut limits, state)?;
+                    remainder = rest;
+                    m.insert(index, ids);
+                } else {
+                    // skip forward one byte
+                    remainder = take!(remainder, 1)?.0;
+                }
+            }
+
+            Ok((remainder, Column::IDs(m)))
+        }
+        ColumnType::PackedBool => {
+            // one bit per row, whether the value is present or not
+            let (rest, packed) = take!(i, flags.len().div_ceil(8))?;
+
+            let mut m = BTreeMap::new();
+            for index in 0..flags.len() {
+                if flags.is_present(index, &column.name) {
+                    m.insert(index, packed[index / 8] & (1 << (index % 8)) > 0);
+                }
+            }
+
+            Ok((rest, Column::PackedBool(m)))
+        }
+        ColumnType::RunLengthNumbers => {
+            // runs only cover the rows where this column is present
+            let mut present = (0..flags.len()).filter(|index| flags.is_present(*index, &column.name));
+
+            let mut m = BTreeMap::new();
+            let mut remainder = i;
+            let mut last: i64 = 0;
+            let mut index = present.next();
+            while index.is_some() {
+                let (rest, count) = take_unsigned_leb128(remainder)?;
+                let (rest, delta) = parse_number_row(rest)?;
+                remainder = rest;
+                let v = last.wrapping_add(delta);
+                last = v;
+
+                if count == 0 {
+                    return Err(Err::Error(Context::Code(i, ErrorKind::Custom(0))));
+                }
+
+                for _ in 0..count {
+                    match index {
+                        Some(row) => {
+                            state.position.row = Some(row);
+                            m.insert(row, v);
+                            index = present.next();
+                        }
+                        // the run is longer than the number of present rows
+                        None => return Err(Err::Error(Context::Code(i, ErrorKind::Custom(0)))),
+                    }
+                }
+            }
+
+            Ok((remainder, Column::Numbers(m)))
+        }
+        ColumnType::XorFloat => {
+            // only present values are stored
+            let present = (0..flags.len()).filter(|index| flags.is_present(*index, &column.name)).collect::<Vec<_>>();
+
+            match xorfloat::decode(i, present.len()) {
+                Ok((values, consumed)) => {
+                    let m = present.into_iter().zip(values).collect();
+                    Ok((&i[consumed..], Column::XorFloat(m)))
+                }
+                Err(xorfloat::DecodeError::Truncated) => Err(Err::Incomplete(Needed::Unknown)),
+                Err(xorfloat::DecodeError::InvalidWindow) => Err(Err::Error(Context::Code(i, ErrorKind::Custom(0)))),
+            }
+        }
+        ColumnType::Map => {
+            let mut m = BTreeMap::new();
+            let mut remainder = i;
+            for index in 0..flags.len() {
+                state.position.row = Some(index);
+                if flags.is_present(index, &column.name) {
+                    let (rest, map) = parse_map_row(remainder, &mut limits, state)?;
+                    remainder = rest;
+                    m.insert(index, map);
+                } else {
+                    // skip forward one byte
+                    remainder = take!(remainder, 1)?.0;
+                }
+            }
+
+            Ok((remainder, Column::Map(m)))
+        }
+        ColumnType::GeoPoint => {
+            // only present values are stored, after the decimal places
+            let (mut remainder, decimals) = verify!(i, le_u8, |decimals| decimals <= MAX_GEO_POINT_DECIMALS)?;
+            let scale = geo_point_scale(decimals);
+
+            let mut m = BTreeMap::new();
+            let mut last = (0i64, 0i64);
+            for index in (0..flags.len()).filter(|index| flags.is_present(*index, &column.name)) {
+                state.position.row = Some(index);
+                let (rest, lat) = parse_number_row(remainder)?;
+                let (rest, lng) = parse_number_row(rest)?;
+                remainder = rest;
+                last = (last.0.wrapping_add(lat), last.1.wrapping_add(lng));
+                m.insert(index, (last.0 as f64 / scale, last.1 as f64 / scale));
+            }
+
+            Ok((remainder, Column::GeoPoint(m)))
+        }
+        ColumnType::I64Array => {
+            let mut m = BTreeMap::new();
+            let mut remainder = i;
+            for index in 0..flags.len() {
+                state.position.row = Some(index);
+                if flags.is_present(index, &column.name) {
+                    let (rest, v) = parse_i64_array_row(remainder, &mut limits, state)?;
+                    remainder = rest;
+                    m.insert(index, v);
+                } else {
+                    // skip forward one byte
+                    remainder = take!(remainder, 1)?.0;
+                }
+            }
+
+            Ok((remainder, Column::I64Array(m)))
+        }
+        ColumnType::F64Array => {
+            // the decimal places, then rows like an I64Array column's
+            let (mut remainder, decimals) = verify!(i, le_u8, |decimals| decimals <= MAX_GEO_POINT_DECIMALS)?;
+            let scale = geo_point_scale(decimals);
+
+            let mut m = BTreeMap::new();
+            for index in 0..flags.len() {
+                state.position.row = Some(index);
+                if flags.is_present(index, &column.name) {
+                    let (rest, v) = parse_i64_array_row(remainder, &mut limits, state)?;
+                    remainder = rest;
+                    m.insert(index, v.into_iter().map(|v| v as f64 / scale).collect());
+                } else {
+                    // skip forward one byte
+                    remainder = take!(remainder, 1)?.0;
+                }
+            }
+
+            Ok((remainder, Column::F64Array(m)))
+        }
+        ColumnType::Timestamp => {
+            // only present values are stored, after the unit
+            let (mut remainder, unit) = map_opt!(i, le_u8, TimestampUnit::from_decimals)?;
+
+            let mut m = BTreeMap::new();
+            let mut last = 0i64;
+            for index in (0..flags.len()).filter(|index| flags.is_present(*index, &column.name)) {
+                state.position.row = Some(index);
+                let (rest, delta) = parse_number_row(remainder)?;
+                remainder = rest;
+                last = last.wrapping_add(delta);
+                m.insert(index, last.saturating_mul(unit.millis()));
+            }
+
+            Ok((remainder, Column::Timestamp(m)))
+        }
+        // parse_section skips these
+        ColumnType::Unknown(_) => Err(Err::Error(Context::Code(i, ErrorKind::Custom(0)))),
     }
 }
 
-impl Parsable for Section {
-    type Return = Option<Self>;
+// Reads past a column without building its values, see
+// `ParseOptions::set_columns`
+fn skip_column<'a>(i: &'a [u8], column: &TypesTableEntry, flags: &FlagsColumn, state: &mut ParseState) -> IResult<&'a [u8], ()> {
+    let mut limits = ColumnLimits::new();
+    let mut remainder = i;
+    match column.column_type {
+        ColumnType::Numbers |
+        ColumnType::LongFloat |
+        ColumnType::ShortFloat |
+        ColumnType::Base64 |
+        ColumnType::String |
+        ColumnType::Bool => {
+            for index in 0..flags.len() {
+                state.position.row = Some(index);
+                remainder = if !flags.is_present(index, &column.name) {
+                    // skip forward one byte
+                    take!(remainder, 1)?.0
+                } else {
+                    match column.column_type {
+                        ColumnType::Base64 | ColumnType::String => parse_bytes_row(remainder, &mut limits, state)?.0,
+                        ColumnType::Bool => parse_bool_row(remainder)?.0,
+                        _ => parse_number_row(remainder)?.0,
+                    }
+                };
+            }
 
-    fn parse(i: &[u8]) -> IResult<&[u8], Self::Return> {
-        let (rest, section_header) = alt!(i,
-                                          tag!(&RWTFTRAILER) => { |_| None } |
-                                          parse_section_header => {|header| Some(header)})?;
+            Ok((remainder, ()))
+        }
+        ColumnType::PackedBool => Ok((take!(i, flags.len().div_ceil(8))?.0, ())),
+        ColumnType::RunLengthNumbers => {
+            let mut present = (0..flags.len()).filter(|index| flags.is_present(*index, &column.name)).count() as u64;
+            while present > 0 {
+                let (rest, count) = take_unsigned_leb128(remainder)?;
+                remainder = parse_number_row(rest)?.0;
+                if count == 0 || count > present {
+                    return Err(Err::Error(Context::Code(i, ErrorKind::Custom(0))));
+                }
+                present -= count;
+            }
 
-        if let Some(header) = section_header {
-            let (rest, types_table) = parse_types_table(rest)?;
+            Ok((remainder, ()))
+        }
+        ColumnType::GeoPoint => {
+            remainder = verify!(i, le_u8, |decimals| decimals <= MAX_GEO_POINT_DECIMALS)?.0;
+            for index in (0..flags.len()).filter(|index| flags.is_present(*index, &column.name)) {
+                state.position.row = Some(index);
+                remainder = parse_number_row(parse_number_row(remainder)?.0)?.0;
+            }
 
-            let data_column_start = i.offset(rest);
-            let (mut rest, flags) = FlagsColumn::parse_flags_column(&rest, &types_table, header.points)?;
+            Ok((remainder, ()))
+        }
+        // not worth a second decoder
+        _ => parse_column(i, column, flags, state).map(|(rest, _)| (rest, ())),
+    }
+}
 
-            let mut m = BTreeMap::new();
-            for column in types_table.entries.iter() {
-                let (new_rest, data) = parse_column(&rest, &column, &flags)?;
+// Moves columns stored under an alias to the name they're read as, see
+// `ParseOptions::add_alias`
+fn rename_aliases(columns: &mut BTreeMap<String, Column>, flags: &mut FlagsColumn, decimals: &mut BTreeMap<String, u8>, options: &ParseOptions) {
+    for (name, aliases) in options.all_aliases() {
+        if columns.contains_key(name) {
+            continue;
+        }
+        if let Some(alias) = aliases.iter().find(|alias| columns.contains_key(*alias)) {
+            if let Some(column) = columns.remove(alias) {
+                columns.insert(name.clone(), column);
+            }
+            if let Some(v) = decimals.remove(alias) {
+                decimals.insert(name.clone(), v);
+            }
+            flags.rename_field(alias, name);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ParsedSection {
+    header_crc: CRC<u16>,
+    types_table_crc: CRC<u16>,
+    data_crc: CRC<u32>,
+}
+
+fn parse_section<'a>(i: &'a [u8], section_index: usize, state: &mut ParseState) -> IResult<&'a [u8], Option<(Section, ParsedSection)>> {
+    state.position = Position::new(Location::SectionHeader{section: section_index});
+    let (rest, section_header) = alt!(i,
+                                      tag!(&RWTFTRAILER) => { |_| None } |
+                                      parse_section_header => {Some})?;
+
+    if let Some(header) = section_header {
+        let max_section_points = state.options.max_section_points();
+        if header.points > max_section_points {
+            return Err(state.fail(i, Failure::LimitExceeded{limit: max_section_points.into(), found: header.points.into()}, LIMIT_EXCEEDED));
+        }
+
+        state.position = Position::new(Location::TypesTable{section: section_index});
+        let (rest, types_table) = parse_types_table(rest, header.wide_types_table)?;
+
+        state.position = Position::new(Location::Data{section: section_index});
+        let data_column_start = i.offset(rest);
+        let (mut rest, mut flags) = FlagsColumn::parse_flags_column(rest, &types_table, header.points, header.run_length_flags)?;
+
+        let run_length_encoding = header.run_length_flags
+            || types_table.entries.iter().any(|entry| matches!(entry.column_type, ColumnType::RunLengthNumbers));
+        let packed_ids = types_table.entries.iter().any(|entry| matches!(entry.column_type, ColumnType::PackedIDs));
+
+        let mut m = BTreeMap::new();
+        let mut decimals = BTreeMap::new();
+        let mut skipped = vec![];
+        let mut unwanted = vec![];
+        for column in types_table.entries.iter() {
+            let wanted = state.options.columns().is_none_or(|columns| {
+                state.options.read_as(&column.name).any(|name| columns.iter().any(|c| c == name))
+            });
+            if let ColumnType::Unknown(tag) = column.column_type {
+                state.warnings.push(ParseWarning::UnknownColumnType{section: section_index, name: column.name.clone(), tag});
+                skipped.push(column.name.as_str());
+            } else if !skipped.is_empty() {
+                state.warnings.push(ParseWarning::SkippedColumn{section: section_index, name: column.name.clone()});
+                skipped.push(column.name.as_str());
+            } else if !wanted {
+                state.position.column = Some(column.name.clone());
+                rest = skip_column(rest, column, &flags, state)?.0;
+                unwanted.push(column.name.as_str());
+            } else {
+                state.position.column = Some(column.name.clone());
+                let (new_rest, data) = parse_column(rest, column, &flags, state)?;
+                if let ColumnType::GeoPoint | ColumnType::F64Array | ColumnType::Timestamp = column.column_type {
+                    // the column starts with its decimal places
+                    decimals.insert(column.name.clone(), rest[0]);
+                }
                 rest = new_rest;
                 m.insert(column.name.clone(), data);
             }
+        }
+
+        if !skipped.is_empty() {
+            // Jump to the data CRC using the section size, which counts the
+            // header but not the header's CRC (+ 2 - 4)
+            state.position = Position::new(Location::Data{section: section_index});
+            let data_crc_offset = header.size.checked_sub(2).and_then(|offset| usize::try_from(offset).ok());
+            match data_crc_offset.and_then(|offset| offset.checked_sub(i.offset(rest))) {
+                Some(skip) => rest = take!(rest, skip)?.0,
+                None => return Err(Err::Error(Context::Code(rest, ErrorKind::Custom(0)))),
+            }
+            flags.remove_fields(&skipped);
+        }
+        if !unwanted.is_empty() {
+            flags.remove_fields(&unwanted);
+        }
+        rename_aliases(&mut m, &mut flags, &mut decimals, state.options);
+        for (name, field_type) in state.options.all_coercions() {
+            if let Some(column) = m.remove(name) {
+                let stored = column.field_type();
+                let column = column.coerce(*field_type).unwrap_or_else(|column| {
+                    state.warnings.push(ParseWarning::UncoercedColumn{section: section_index, name: name.clone(), stored, wanted: *field_type});
+                    column
+                });
+                m.insert(name.clone(), column);
+            }
+        }
 
-            let data_column_end = i.offset(rest);
-            let (rest, crc) = le_u32(&rest)?;
-            let _actual_crc = CRC::new(crc, checksum_ieee(&i[data_column_start..data_column_end])); // TODO: use this
+        state.position = Position::new(Location::Data{section: section_index});
+        let data_column_end = i.offset(rest);
+        let (rest, crc) = le_u32(rest)?;
+
+        // the size counts the header but not the header's CRC
+        let section_end = usize::try_from(header.size).ok().and_then(|size| size.checked_add(2));
+        check_layout(i, rest, section_end.unwrap_or(usize::MAX), state)?;
+
+        Ok((rest, Some((Section{section_type: header.section_type,
+                                max: flags.max(),
+                                flags,
+                                columns: m,
+                                run_length_encoding,
+                                packed_ids,
+                                float_overflow_policies: BTreeMap::new(),
+                                decimals},
+                        ParsedSection{header_crc: header.crc,
+                                      types_table_crc: types_table.crc,
+                                      data_crc: CRC::new(crc, checksum_ieee(&i[data_column_start..data_column_end]))}))))
+    } else {
+        Ok((rest, None))
+    }
+}
 
-            Ok((rest, Some(Section{section_type: header.section_type,
-                                   max: flags.max(),
-                                   flags: flags,
-                                   columns: m})))
-        } else {
-            Ok((rest, None))
+//////////////////////////////
+//           CRCs           //
+//////////////////////////////
+impl<T: Into<u32>> CRC<T> {
+    // Either fails parsing `i` or records a warning for an invalid CRC, depending on the policy in `state`
+    fn check<'a>(self, i: &'a [u8], location: Location, state: &mut ParseState) -> Result<(), Err<&'a [u8]>> {
+        match self {
+            CRC::Valid(_) => Ok(()),
+            CRC::Invalid{expected, received} => match state.options.crc_policy() {
+                CrcPolicy::Verify => {
+                    state.position = Position::new(location);
+                    Err(state.fail(i, Failure::InvalidCrc{expected: expected.into(), found: received.into()}, INVALID_CRC))
+                }
+                CrcPolicy::Warn => {
+                    state.warnings.push(ParseWarning::InvalidCrc{location,
+                                                           expected: expected.into(),
+                                                           received: received.into()});
+                    Ok(())
+                }
+            }
         }
     }
 }
@@ -460,47 +1073,1578 @@ impl Parsable for Section {
 //////////////////////////////
 //         RWTFile          //
 //////////////////////////////
-impl Parsable for RWTFile {
-    type Return = Self;
+/// nom error code used when a CRC doesn't match under `CrcPolicy::Verify`
+pub const INVALID_CRC: u32 = 1;
+/// nom error code used when a length field goes over one of the limits in `ParseOptions`
+pub const LIMIT_EXCEEDED: u32 = 2;
+/// nom error code used for invalid String column values under `Utf8Policy::Strict`
+pub const INVALID_UTF8: u32 = 3;
+/// nom error code used for gaps, overlaps and trailing bytes under `ParseOptions::strict_layout`,
+/// and for continuation sections with nothing to continue or which don't match it, or a
+/// trailer where a section should be
+pub const INVALID_LAYOUT: u32 = 4;
+/// nom error code used when a file has two track points or two course points sections
+pub const DUPLICATE_SECTION: u32 = 6;
+/// nom error code used for files newer than `RWTF_FILE_VERSION`
+pub const UNSUPPORTED_VERSION: u32 = 7;
+
+// Fails if `i` isn't at `expected` bytes from the start of `input` under a strict layout
+fn check_layout<'a>(input: &'a [u8], i: &'a [u8], expected: usize, state: &mut ParseState) -> Result<(), Err<&'a [u8]>> {
+    if state.options.strict_layout() && input.offset(i) != expected {
+        Err(state.fail(i, Failure::Misaligned{expected}, INVALID_LAYOUT))
+    } else {
+        Ok(())
+    }
+}
 
-    fn parse(i: &[u8]) -> IResult<&[u8], Self::Return> {
-        let (_rest, (header, header_details)) = RWTFHeader::parse(i)?;
-        let (_rest, (metadata, _metadata_crc)) = RWTFMetadata::parse(&i[header_details.metadata_table_offset as usize..])?;
-        // TODO: use metadata_crc
+// Parses the header and metadata table, returning the input from the first section on
+fn parse_file_start<'a>(i: &'a [u8], state: &mut ParseState) -> IResult<&'a [u8], (RWTFHeader, RWTFMetadata)> {
+    state.position = Position::new(Location::Header);
+    let (rest, (header, header_details)) = RWTFHeader::parse(i)?;
+    header_details.crc.check(i, Location::Header, state)?;
+
+    // every older version can be read as it is
+    if header.file_version > RWTF_FILE_VERSION {
+        let (file_version, _) = take!(i, RWTFMAGIC.len())?;
+        return Err(state.fail(file_version, Failure::UnsupportedVersion{found: header.file_version, supported: RWTF_FILE_VERSION}, UNSUPPORTED_VERSION));
+    }
 
-        let mut remainder = &i[header_details.data_offset as usize..];
+    state.position = Position::new(Location::Metadata);
+    let (metadata_table, _) = take!(i, header_details.metadata_table_offset)?;
+    check_layout(i, metadata_table, i.offset(rest), state)?;
+    let (rest, (metadata, metadata_crc)) = RWTFMetadata::parse(metadata_table)?;
+    metadata_crc.check(metadata_table, Location::Metadata, state)?;
 
-        let mut track_points = None;
-        let mut course_points = None;
+    let (remainder, _) = take!(i, header_details.data_offset)?;
+    state.position = Position::new(Location::SectionHeader{section: 0});
+    check_layout(i, remainder, i.offset(rest), state)?;
 
-        loop {
-            let (rest, section) = Section::parse(remainder)?;
+    Ok((remainder, (header, metadata)))
+}
+
+impl ParsedSection {
+    // Checks the CRCs of the section at the start of `i`, and that a section
+    // of its type hasn't been seen already
+    fn check<'a>(self, i: &'a [u8], section_index: usize, section_type: SectionType, seen: &mut Vec<SectionType>, state: &mut ParseState) -> Result<(), Err<&'a [u8]>> {
+        self.header_crc.check(i, Location::SectionHeader{section: section_index}, state)?;
+        self.types_table_crc.check(i, Location::TypesTable{section: section_index}, state)?;
+        self.data_crc.check(i, Location::Data{section: section_index}, state)?;
+
+        // Sections can come in any order, but there's only one of each
+        // type: the section type is what tells them apart. A continuation
+        // section adds rows to the section before it, so it can't come first.
+        state.position = Position::new(Location::SectionHeader{section: section_index});
+        if section_type == SectionType::Continuation {
+            return if seen.is_empty() {
+                Err(state.fail(i, Failure::MisplacedContinuation, INVALID_LAYOUT))
+            } else {
+                Ok(())
+            };
+        }
+        if seen.contains(&section_type) {
+            return Err(state.fail(i, Failure::DuplicateSection, DUPLICATE_SECTION));
+        }
+        seen.push(section_type);
+        Ok(())
+    }
+}
+
+fn check_trailing<'a>(remainder: &'a [u8], state: &mut ParseState) -> Result<(), Err<&'a [u8]>> {
+    if state.options.strict_layout() && !remainder.is_empty() {
+        state.position = Position::new(Location::Trailer);
+        Err(state.fail(remainder, Failure::TrailingBytes{count: remainder.len()}, INVALID_LAYOUT))
+    } else {
+        Ok(())
+    }
+}
+
+// The CRC32 of everything before it which follows the trailer when the
+// header says so, see `RWTFile::set_file_checksum`
+fn parse_file_checksum<'a>(i: &'a [u8], remainder: &'a [u8], header: &RWTFHeader, state: &mut ParseState) -> IResult<&'a [u8], ()> {
+    if !header.file_checksum {
+        return Ok((remainder, ()));
+    }
+    state.position = Position::new(Location::Trailer);
+    let (rest, crc) = le_u32(remainder)?;
+    CRC::new(crc, checksum_ieee(&i[..i.offset(remainder)])).check(remainder, Location::Trailer, state)?;
+    Ok((rest, ()))
+}
+
+fn parse_file<'a>(i: &'a [u8], state: &mut ParseState) -> IResult<&'a [u8], RWTFile> {
+    let (mut remainder, (header, metadata)) = parse_file_start(i, state)?;
+
+    let mut rows = 0;
+    state.report(rows, i.offset(remainder), i.len());
+
+    let mut seen = vec![];
+    let mut track_points = None;
+    let mut course_points = None;
+    let mut points_of_interest = None;
+    let mut laps = None;
+    let mut events = None;
+
+    for section_index in 0.. {
+        let (rest, section) = parse_section(remainder, section_index, state)?;
+
+        if let Some((section, parsed_section)) = section {
+            parsed_section.check(remainder, section_index, section.section_type, &mut seen, state)?;
+            rows += section.len();
+            state.report(rows, i.offset(rest), i.len());
+            match section.section_type {
+                SectionType::TrackPoints => track_points = Some(section),
+                SectionType::CoursePoints => course_points = Some(section),
+                SectionType::PointsOfInterest => points_of_interest = Some(section),
+                SectionType::Laps => laps = Some(section),
+                SectionType::Events => events = Some(section),
+                SectionType::Continuation => {
+                    let continued = match seen.last() {
+                        Some(SectionType::TrackPoints) => &mut track_points,
+                        Some(SectionType::CoursePoints) => &mut course_points,
+                        Some(SectionType::PointsOfInterest) => &mut points_of_interest,
+                        Some(SectionType::Laps) => &mut laps,
+                        Some(SectionType::Events) => &mut events,
+                        _ => unreachable!("continuation sections must follow another section, see ParsedSection::check"),
+                    };
+                    // a column can't change type part way through
+                    if let Some(continued) = continued {
+                        if continued.append(&section).is_err() {
+                            state.position = Position::new(Location::TypesTable{section: section_index});
+                            return Err(state.fail(remainder, Failure::ContinuationMismatch, INVALID_LAYOUT));
+                        }
+                    }
+                }
+            }
+            remainder = rest;
+        } else {
+            // parsing section returned None
             remainder = rest;
+            break;
+        }
+    }
 
-            if let Some(section) = section {
-                match section.section_type {
-                    SectionType::TrackPoints => track_points = Some(section),
-                    SectionType::CoursePoints => course_points = Some(section),
-                    SectionType::Continuation => panic!("SectionType::Continuation unsupported"),
+    let (remainder, _) = parse_file_checksum(i, remainder, &header, state)?;
+    check_trailing(remainder, state)?;
+    state.report(rows, i.len(), i.len());
+
+    Ok((remainder, RWTFile{metadata,
+                           track_points: track_points.unwrap_or(Section::new(SectionType::TrackPoints)),
+                           course_points: course_points.unwrap_or(Section::new(SectionType::CoursePoints)),
+                           points_of_interest: points_of_interest.unwrap_or(Section::new(SectionType::PointsOfInterest)),
+                           laps: laps.unwrap_or(Section::new(SectionType::Laps)),
+                           events: events.unwrap_or(Section::new(SectionType::Events)),
+                           warnings: std::mem::take(&mut state.warnings),
+                           max_section_size: None,
+                           file_checksum: header.file_checksum,
+                           header}))
+}
+
+// Splits a section off the front of `i` using the sizes in it, without
+// decoding any values
+fn parse_raw_section<'a>(i: &'a [u8], section_index: usize, state: &mut ParseState) -> IResult<&'a [u8], Option<(RawSection<'a>, ParsedSection)>> {
+    state.position = Position::new(Location::SectionHeader{section: section_index});
+    let (rest, section_header) = alt!(i,
+                                      tag!(&RWTFTRAILER) => { |_| None } |
+                                      parse_section_header => { Some })?;
+
+    if let Some(header) = section_header {
+        state.position = Position::new(Location::TypesTable{section: section_index});
+        let (rest, types_table) = parse_types_table(rest, header.wide_types_table)?;
+
+        // the size counts the header but not the header's CRC
+        state.position = Position::new(Location::Data{section: section_index});
+        let data_column_start = i.offset(rest);
+        let section_end = usize::try_from(header.size).ok().and_then(|size| size.checked_add(2));
+        let data_column_end = match section_end.and_then(|end| end.checked_sub(4)) {
+            Some(end) if end >= data_column_start => end,
+            _ => return Err(Err::Error(Context::Code(rest, ErrorKind::Custom(0)))),
+        };
+        let (_, data) = take!(rest, data_column_end - data_column_start)?;
+        let (rest, crc) = le_u32(&i[data_column_end..])?;
+
+        let file_version = section_file_version(i[0], types_table.entries.iter().map(|entry| entry.type_tag));
+        Ok((rest, Some((RawSection{section_type: header.section_type,
+                                   points: header.points,
+                                   bytes: &i[..i.offset(rest)],
+                                   file_version},
+                        ParsedSection{header_crc: header.crc,
+                                      types_table_crc: types_table.crc,
+                                      data_crc: CRC::new(crc, checksum_ieee(data))}))))
+    } else {
+        Ok((rest, None))
+    }
+}
+
+fn parse_raw_file<'a>(i: &'a [u8], state: &mut ParseState) -> IResult<&'a [u8], Vec<RawSection<'a>>> {
+    let (mut remainder, (header, _)) = parse_file_start(i, state)?;
+
+    let mut seen = vec![];
+    let mut sections = vec![];
+
+    for section_index in 0.. {
+        let (rest, section) = parse_raw_section(remainder, section_index, state)?;
+        remainder = rest;
+
+        match section {
+            Some((section, parsed_section)) => {
+                parsed_section.check(section.bytes, section_index, section.section_type, &mut seen, state)?;
+                sections.push(section);
+            }
+            None => break,
+        }
+    }
+
+    let (remainder, _) = parse_file_checksum(i, remainder, &header, state)?;
+    check_trailing(remainder, state)?;
+
+    Ok((remainder, sections))
+}
+
+/// Splits a whole RWTF file into its sections, still encoded, checking their
+/// CRCs but not decoding any values. See `RWTFile::write_with_sections`.
+pub fn parse_raw_sections<'a>(i: &'a [u8], options: &ParseOptions) -> Result<Vec<RawSection<'a>>> {
+    let mut state = ParseState::new(options);
+    match parse_raw_file(i, &mut state) {
+        Ok((_rest, sections)) => Ok(sections),
+        Err(e) => Err(state.into_error(i, e)),
+    }
+}
+
+// The rest are for readers which fetch a file a piece at a time, see
+// `RangeReader`. Each parses one piece, with offsets from its start.
+
+// The offset of the first section, from the first 24 bytes of a file
+pub(crate) fn parse_data_offset(i: &[u8], options: &ParseOptions) -> Result<usize> {
+    let mut state = ParseState::new(options);
+    let parsed = RWTFHeader::parse(i).and_then(|(_, (_, header_details))| {
+        header_details.crc.check(i, Location::Header, &mut state)?;
+        Ok(header_details.data_offset)
+    });
+    match parsed {
+        Ok(data_offset) => Ok(usize::from(data_offset)),
+        Err(e) => Err(state.into_error(i, e)),
+    }
+}
+
+// The header and metadata, from everything before the first section
+pub(crate) fn parse_start(i: &[u8], options: &ParseOptions) -> Result<(RWTFHeader, RWTFMetadata)> {
+    let mut state = ParseState::new(options);
+    match parse_file_start(i, &mut state) {
+        Ok((_rest, start)) => Ok(start),
+        Err(e) => Err(state.into_error(i, e)),
+    }
+}
+
+// A section's type, number of points and length in bytes from its 14 byte
+// header, or None for the trailer
+pub(crate) fn parse_section_info(i: &[u8], section_index: usize, options: &ParseOptions) -> Result<Option<(SectionType, usize, usize)>> {
+    let mut state = ParseState::new(options);
+    state.position = Position::new(Location::SectionHeader{section: section_index});
+    let parsed = alt!(i,
+                      tag!(&RWTFTRAILER) => { |_| None } |
+                      parse_section_header => { Some })
+        .and_then(|(rest, header)| match header {
+            Some(header) => {
+                header.crc.check(i, Location::SectionHeader{section: section_index}, &mut state)?;
+                // the size counts the header but not the header's CRC
+                let len = usize::try_from(header.size).ok().and_then(|size| size.checked_add(2)).unwrap_or(usize::MAX);
+                if len >= i.offset(rest) {
+                    Ok(Some((header.section_type, header.points as usize, len)))
+                } else {
+                    // the section would end inside its own header
+                    Err(state.fail(rest, Failure::Misaligned{expected: len}, INVALID_LAYOUT))
                 }
-            } else {
-                // parsing section returned None
-                break;
             }
+            None => Ok(None),
+        });
+    match parsed {
+        Ok(info) => Ok(info),
+        Err(e) => Err(state.into_error(i, e)),
+    }
+}
+
+// The names and types of a section's columns, from the start of the section
+// up to the end of its types table. Columns of types this version doesn't
+// know are left out.
+pub(crate) fn parse_column_types(i: &[u8], section_index: usize, options: &ParseOptions) -> Result<Vec<(String, FieldType)>> {
+    let mut state = ParseState::new(options);
+    state.position = Position::new(Location::SectionHeader{section: section_index});
+    let parsed = parse_section_header(i).and_then(|(rest, header)| {
+        header.crc.check(i, Location::SectionHeader{section: section_index}, &mut state)?;
+        state.position = Position::new(Location::TypesTable{section: section_index});
+        let (_, types_table) = parse_types_table(rest, header.wide_types_table)?;
+        types_table.crc.check(rest, Location::TypesTable{section: section_index}, &mut state)?;
+        Ok(types_table.entries)
+    });
+    match parsed {
+        Ok(entries) => Ok(entries.into_iter()
+                              .filter_map(|entry| entry.column_type.field_type().map(|field_type| (entry.name, field_type)))
+                              .collect()),
+        Err(e) => Err(state.into_error(i, e)),
+    }
+}
+
+// Checks the CRC32 after the trailer ending at `trailer_end`, from the whole
+// file up to the end of it, for a file whose header says it has one
+pub(crate) fn parse_file_checksum_at(i: &[u8], trailer_end: usize, header: &RWTFHeader, options: &ParseOptions) -> Result<()> {
+    let mut options = options.clone();
+    options.set_crc_policy(CrcPolicy::Verify);
+    let mut state = ParseState::new(&options);
+    match parse_file_checksum(i, &i[trailer_end.min(i.len())..], header, &mut state) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(state.into_error(i, e)),
+    }
+}
+
+// A whole section, on its own
+pub(crate) fn parse_single_section(i: &[u8], section_index: usize, options: &ParseOptions) -> Result<Section> {
+    let mut state = ParseState::new(options);
+    let parsed = parse_section(i, section_index, &mut state).and_then(|(_, section)| match section {
+        Some((section, parsed_section)) => {
+            // as if after some other section, so that continuation sections are allowed
+            parsed_section.check(i, section_index, section.section_type, &mut vec![SectionType::Continuation], &mut state)?;
+            Ok(section)
         }
+        None => Err(state.fail(i, Failure::UnexpectedTrailer, INVALID_LAYOUT)),
+    });
+    match parsed {
+        Ok(section) => Ok(section),
+        Err(e) => Err(state.into_error(i, e)),
+    }
+}
 
-        Ok((remainder, RWTFile{header,
-                               metadata,
-                               track_points: track_points.unwrap_or(Section::new(SectionType::TrackPoints)),
-                               course_points: course_points.unwrap_or(Section::new(SectionType::CoursePoints))}))
+/// Parses a whole RWTF file, describing where parsing went wrong on failure.
+pub fn parse_rwtf_with_options(i: &[u8], options: &ParseOptions) -> Result<RWTFile> {
+    let mut state = ParseState::new(options);
+    match parse_file(i, &mut state) {
+        Ok((_rest, rwtf)) => Ok(rwtf),
+        Err(e) => Err(state.into_error(i, e)),
+    }
+}
+
+/// Like `parse_rwtf_with_options`, calling `progress` after the header and
+/// after each section.
+pub fn parse_rwtf_with_progress<F: FnMut(Progress)>(i: &[u8], options: &ParseOptions, mut progress: F) -> Result<RWTFile> {
+    let mut state = ParseState::new(options);
+    state.progress = Some(&mut progress);
+    match parse_file(i, &mut state) {
+        Ok((_rest, rwtf)) => Ok(rwtf),
+        Err(e) => Err(state.into_error(i, e)),
     }
 }
 
 pub fn parse_rwtf(i: &[u8]) -> IResult<&[u8], RWTFile> {
-    RWTFile::parse(i)
+    parse_file(i, &mut ParseState::new(&ParseOptions::new()))
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use proptest::prelude::*;
+    use crate::rwtfile::{DataField};
+    use crate::section::{FloatOverflowPolicy};
+
+    fn write_test_file() -> Vec<u8> {
+        let mut f = RWTFile::new();
+        for i in 0..5 {
+            assert!(f.add_track_point(i, "a", i as i64).is_ok());
+        }
+        assert!(f.add_course_point(0, "b", DataField::String("hi".into())).is_ok());
+
+        let mut buf = vec![];
+        assert!(f.write(&mut buf).is_ok());
+        buf
+    }
+
+    #[test]
+    fn test_crcs_are_valid() {
+        let buf = write_test_file();
+        let (_, rwtf) = parse_rwtf(&buf).unwrap();
+        assert!(rwtf.warnings().is_empty());
+        assert_eq!(rwtf.track_points.len(), 5);
+        assert_eq!(rwtf.course_points.len(), 1);
+    }
+
+    #[test]
+    fn test_invalid_crcs() {
+        let buf = write_test_file();
+        let metadata_len = 14;
+        let track_points_start = 24 + metadata_len;
+        // header + crc, types table + crc, flags, values, data crc
+        let course_points_start = track_points_start + 14 + 6 + 5 + 5 + 4;
+
+        for (offset, location) in &[(12, Location::Header), // creator version
+                                    (28, Location::Metadata), // created_at
+                                    (track_points_start + 5, Location::SectionHeader{section: 0}), // section size
+                                    (track_points_start + 17, Location::TypesTable{section: 0}), // column name
+                                    (track_points_start + 25, Location::Data{section: 0}), // first value
+                                    (course_points_start + 22, Location::Data{section: 1})] { // string contents
+            let mut corrupted = buf.clone();
+            corrupted[*offset] ^= 0x01;
+
+            // the default is to fail
+            assert_matches!(parse_rwtf(&corrupted), Err(Err::Failure(Context::Code(_, ErrorKind::Custom(INVALID_CRC)))));
+            assert_matches!(parse_rwtf_with_options(&corrupted, &ParseOptions::new()),
+                            Err(Error::InvalidCrc{ref position, ..}) if position.location == *location);
+
+            // but the file can still be salvaged
+            let mut options = ParseOptions::new();
+            options.set_crc_policy(CrcPolicy::Warn);
+            let rwtf = parse_rwtf_with_options(&corrupted, &options).unwrap();
+            assert_matches!(rwtf.warnings(), [ParseWarning::InvalidCrc{location: l, ..}] if l == location);
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut f = RWTFile::new();
+        assert!(f.add_track_point(0, "ids", DataField::IDs(vec![1, 2, 3])).is_ok());
+        assert!(f.add_track_point(1, "ids", DataField::IDs(vec![4, 5])).is_ok());
+        assert!(f.add_track_point(2, "s", DataField::String("hello".into())).is_ok());
+
+        let mut buf = vec![];
+        assert!(f.write(&mut buf).is_ok());
+
+        let limit_exceeded = |options: &ParseOptions| {
+            matches!(parse_rwtf_with_options(&buf, options), Err(Error::LimitExceeded{..}))
+        };
+
+        let mut options = ParseOptions::new();
+        assert!(parse_rwtf_with_options(&buf, &options).is_ok());
+
+        options.set_max_section_points(2);
+        assert!(limit_exceeded(&options));
+        options.set_max_section_points(3);
+        assert!(!limit_exceeded(&options));
+
+        options.set_max_value_len(4);
+        assert!(limit_exceeded(&options));
+        options.set_max_value_len(5);
+        assert!(!limit_exceeded(&options));
+
+        // 3 + 2 ids
+        options.set_max_column_size(4);
+        assert!(limit_exceeded(&options));
+        options.set_max_column_size(5);
+        assert!(!limit_exceeded(&options));
+
+        assert_eq!(parse_rwtf_with_options(&buf[..buf.len() - 1], &options).unwrap_err().kind(), crate::ErrorKind::Corruption);
+
+        options.set_max_value_len(2);
+        assert_eq!(parse_rwtf_with_options(&buf, &options).unwrap_err().kind(), crate::ErrorKind::TooLarge);
+        assert_matches!(parse_rwtf_with_options(&buf, &options),
+                        Err(Error::LimitExceeded{position: Position{column: Some(ref column), row: Some(0), ..}, limit: 2, found: 3, ..}) if column == "ids");
+        assert_matches!(parse_rwtf(&buf), Ok(_));
+        assert_matches!(parse_rwtf(&buf[..buf.len() - 1]), Err(_));
+    }
+
+    #[test]
+    fn test_strict_layout() {
+        let buf = write_test_file();
+        let data_offset = usize::from(u16::from_le_bytes([buf[18], buf[19]]));
+        let fix_crc16 = |buf: &mut Vec<u8>, start: usize, end: usize| {
+            let crc = checksum_usb(&buf[start..end]).to_le_bytes();
+            buf[end..end + 2].copy_from_slice(&crc);
+        };
+
+        let lenient = ParseOptions::new();
+        let mut strict = ParseOptions::new();
+        strict.set_strict_layout(true);
+        assert!(parse_rwtf_with_options(&buf, &strict).is_ok());
+
+        // trailing bytes
+        let mut trailing = buf.clone();
+        trailing.extend_from_slice(&[0x00, 0x00]);
+        assert!(parse_rwtf_with_options(&trailing, &lenient).is_ok());
+        assert_matches!(parse_rwtf_with_options(&trailing, &strict),
+                        Err(Error::TrailingBytes{offset, position: Position{location: Location::Trailer, ..}, count: 2}) if offset == buf.len());
+
+        // a gap between the metadata table and the first section
+        let mut gap = buf.clone();
+        gap.insert(data_offset, 0x00);
+        gap[18..20].copy_from_slice(&(data_offset as u16 + 1).to_le_bytes());
+        fix_crc16(&mut gap, 0, 22);
+        assert!(parse_rwtf_with_options(&gap, &lenient).is_ok());
+        assert_matches!(parse_rwtf_with_options(&gap, &strict),
+                        Err(Error::Misaligned{offset, position: Position{location: Location::SectionHeader{section: 0}, ..}, expected})
+                        if offset == data_offset + 1 && expected == data_offset);
+
+        // a section size that doesn't match its contents
+        let mut size = buf.clone();
+        size[data_offset + 4] += 1;
+        fix_crc16(&mut size, data_offset, data_offset + 12);
+        assert!(parse_rwtf_with_options(&size, &lenient).is_ok());
+        assert_matches!(parse_rwtf_with_options(&size, &strict),
+                        Err(Error::Misaligned{position: Position{location: Location::Data{section: 0}, ..}, ..}));
+    }
+
+    #[test]
+    fn test_file_checksum() {
+        let mut f = RWTFile::new();
+        for i in 0..10 {
+            assert!(f.add_track_point(i, "t", 1000 + i as i64).is_ok());
+        }
+        let plain = f.to_bytes().unwrap();
+        assert!(!parse_rwtf_with_options(&plain, &ParseOptions::new()).unwrap().file_checksum());
+        assert_eq!(plain[9], 0x00);
+
+        f.set_file_checksum(true);
+        let buf = f.to_bytes().unwrap();
+        assert_eq!(buf.len(), plain.len() + 4);
+        assert_eq!(buf[9], HEADER_FILE_CHECKSUM);
+        assert_eq!(f.estimated_encoded_size().unwrap(), buf.len());
+        assert_eq!(buf[buf.len() - 4..], checksum_ieee(&buf[..buf.len() - 4]).to_le_bytes());
+
+        let mut strict = ParseOptions::new();
+        strict.set_strict_layout(true);
+        let rwtf = parse_rwtf_with_options(&buf, &strict).unwrap();
+        assert!(rwtf.file_checksum());
+        assert_eq!(parse_raw_sections(&buf, &strict).unwrap().len(), 1);
+        // kept when written again
+        let rewritten = rwtf.to_bytes().unwrap();
+        assert_eq!(rewritten.len(), buf.len());
+
+        // damage the CRCs of each part don't cover, here the header's reserved
+        // space with its CRC fixed up
+        let mut damaged = buf.clone();
+        damaged[20] = 0x01;
+        let crc = checksum_usb(&damaged[..22]).to_le_bytes();
+        damaged[22..24].copy_from_slice(&crc);
+        assert_matches!(parse_rwtf_with_options(&damaged, &ParseOptions::new()),
+                        Err(Error::InvalidCrc{offset, position: Position{location: Location::Trailer, ..}, ..}) if offset == buf.len() - 4);
+        assert_matches!(parse_raw_sections(&damaged, &ParseOptions::new()), Err(Error::InvalidCrc{..}));
+        let mut options = ParseOptions::new();
+        options.set_crc_policy(CrcPolicy::Warn);
+        assert_matches!(parse_rwtf_with_options(&damaged, &options).unwrap().warnings(),
+                        [ParseWarning::InvalidCrc{location: Location::Trailer, ..}]);
+
+        // the header says whether there's a checksum, not the bytes left over
+        let mut junk = buf.clone();
+        junk.extend_from_slice(&[0x01, 0x02, 0x03, 0x04]);
+        let rwtf = parse_rwtf_with_options(&junk, &ParseOptions::new()).unwrap();
+        assert!(rwtf.file_checksum() && rwtf.warnings().is_empty());
+        assert_matches!(parse_rwtf_with_options(&junk, &strict), Err(Error::TrailingBytes{offset, count: 4, ..}) if offset == buf.len());
+
+        let mut junk = plain.clone();
+        junk.extend_from_slice(&[0x01, 0x02, 0x03, 0x04]);
+        let rwtf = parse_rwtf_with_options(&junk, &ParseOptions::new()).unwrap();
+        assert!(!rwtf.file_checksum() && rwtf.warnings().is_empty());
+        assert_matches!(parse_rwtf_with_options(&junk, &strict), Err(Error::TrailingBytes{offset, count: 4, ..}) if offset == plain.len());
+        assert_eq!(parse_raw_sections(&junk, &ParseOptions::new()).unwrap().len(), 1);
+
+        assert_matches!(parse_rwtf_with_options(&buf[..buf.len() - 2], &ParseOptions::new()), Err(Error::Incomplete{..}));
+    }
 
+    #[test]
+    fn test_continuation_sections() {
+        let buf = write_test_file();
+        let data_offset = usize::from(u16::from_le_bytes([buf[18], buf[19]]));
+        let (_, track_size) = le_u64(&buf[data_offset + 4..]).unwrap();
+        let course_start = data_offset + track_size as usize + 2;
+
+        let with_section_type = |start: usize, section_type: u8| {
+            let mut buf = buf.clone();
+            buf[start] = section_type;
+            let crc = checksum_usb(&buf[start..start + 12]).to_le_bytes();
+            buf[start + 12..start + 14].copy_from_slice(&crc);
+            buf
+        };
+
+        // the course points section continues the track points section
+        assert_eq!(buf[course_start], 0x01);
+        let rwtf = parse_rwtf_with_options(&with_section_type(course_start, 0x02), &ParseOptions::new()).unwrap();
+        assert_eq!(rwtf.track_points.len(), 6);
+        assert_eq!(rwtf.course_points.len(), 0);
+        assert_matches!(rwtf.track_points.columns().get("a"), Some(Column::Numbers(m)) if m.len() == 5);
+        assert_matches!(rwtf.track_points.columns().get("b"), Some(Column::String(m)) if m[&5] == "hi");
+
+        // with nothing to continue
+        let continued_first = with_section_type(data_offset, 0x02);
+        assert_matches!(parse_rwtf_with_options(&continued_first, &ParseOptions::new()),
+                        Err(Error::MisplacedContinuation{offset, position: Position{location: Location::SectionHeader{section: 0}, ..}})
+                        if offset == data_offset);
+
+        // a column can't change type
+        let mut f = RWTFile::new();
+        assert!(f.add_track_point(0, "a", 1).is_ok());
+        assert!(f.add_course_point(0, "a", DataField::String("hi".into())).is_ok());
+        let buf = f.to_bytes().unwrap();
+        let retyped = {
+            let mut buf = buf.clone();
+            let (_, track_size) = le_u64(&buf[data_offset + 4..]).unwrap();
+            let course_start = data_offset + track_size as usize + 2;
+            buf[course_start] = 0x02;
+            let crc = checksum_usb(&buf[course_start..course_start + 12]).to_le_bytes();
+            buf[course_start + 12..course_start + 14].copy_from_slice(&crc);
+            buf
+        };
+        assert_matches!(parse_rwtf_with_options(&retyped, &ParseOptions::new()),
+                        Err(Error::ContinuationMismatch{position: Position{location: Location::TypesTable{section: 1}, ..}, ..}));
+    }
+
+    #[test]
+    fn test_max_section_size() {
+        let mut f = RWTFile::new();
+        for i in 0..1000 {
+            assert!(f.add_track_point(i, "t", 1000 + i as i64).is_ok());
+            assert!(f.add_track_point(i, "y", DataField::LongFloat(45.0 + i as f64 / 1000.0)).is_ok());
+            if i % 7 == 0 {
+                assert!(f.add_track_point(i, "n", DataField::String(format!("row {}", i))).is_ok());
+            }
+        }
+        // empty rows at the end are kept
+        assert!(f.add_track_point(1001, "t", 5000).is_ok());
+        for i in 0..3 {
+            assert!(f.add_course_point(i, "n", DataField::String("turn".into())).is_ok());
+        }
+        let unsplit = f.to_bytes().unwrap();
+
+        f.set_max_section_size(Some(1000));
+        let buf = f.to_bytes().unwrap();
+        assert_eq!(f.estimated_encoded_size().unwrap(), buf.len());
+
+        let sections = parse_raw_sections(&buf, &ParseOptions::new()).unwrap();
+        assert!(sections.len() > 3);
+        assert_eq!(sections[0].section_type(), SectionType::TrackPoints);
+        assert!(sections[1..sections.len() - 1].iter().all(|s| s.section_type() == SectionType::Continuation));
+        assert_eq!(sections.last().unwrap().section_type(), SectionType::CoursePoints);
+        assert!(sections.iter().all(|s| s.as_bytes().len() <= 1000));
+        assert_eq!(sections.iter().map(|s| s.len()).sum::<usize>(), 1002 + 3);
+
+        let rwtf = parse_rwtf_with_options(&buf, &ParseOptions::new()).unwrap();
+        assert_eq!(rwtf.fingerprint(), f.fingerprint());
+        // the same sections once joined, though the creation time can differ
+        let data_offset = usize::from(u16::from_le_bytes([unsplit[18], unsplit[19]]));
+        assert_eq!(rwtf.to_bytes().unwrap()[data_offset..], unsplit[data_offset..]);
+
+        // a row bigger than the limit gets a section of its own
+        f.set_max_section_size(Some(1));
+        let rwtf = parse_rwtf_with_options(&f.to_bytes().unwrap(), &ParseOptions::new()).unwrap();
+        assert_eq!(rwtf.fingerprint(), f.fingerprint());
+    }
+
+    #[test]
+    fn test_parse_with_progress() {
+        let buf = write_test_file();
+        let mut reports = vec![];
+        assert!(parse_rwtf_with_progress(&buf, &ParseOptions::new(), |progress| reports.push(progress)).is_ok());
+        let data_offset = usize::from(u16::from_le_bytes([buf[18], buf[19]]));
+        let (_, track_size) = le_u64(&buf[data_offset + 4..]).unwrap();
+        let course_start = data_offset + track_size as usize + 2;
+        assert_eq!(reports.iter().map(|progress| (progress.rows, progress.bytes)).collect::<Vec<_>>(),
+                   vec![(0, data_offset), (5, course_start), (6, buf.len() - RWTFTRAILER.len()), (6, buf.len())]);
+        assert!(reports.iter().all(|progress| progress.total_bytes == buf.len()));
+    }
+
+    #[test]
+    fn test_duplicate_sections() {
+        let buf = write_test_file();
+        let data_offset = usize::from(u16::from_le_bytes([buf[18], buf[19]]));
+        let (_, track_size) = le_u64(&buf[data_offset + 4..]).unwrap();
+        let track_end = data_offset + track_size as usize + 2;
+
+        // the track points section twice, then the course points section
+        let mut duplicated = buf[..track_end].to_vec();
+        duplicated.extend_from_slice(&buf[data_offset..]);
+
+        assert_matches!(parse_rwtf_with_options(&duplicated, &ParseOptions::new()),
+                        Err(Error::DuplicateSection{offset, position: Position{location: Location::SectionHeader{section: 1}, ..}})
+                        if offset == track_end);
+        assert_matches!(parse_rwtf(&duplicated), Err(Err::Failure(Context::Code(_, ErrorKind::Custom(DUPLICATE_SECTION)))));
+
+        // course points first is fine
+        let mut swapped = buf[..data_offset].to_vec();
+        swapped.extend_from_slice(&buf[track_end..buf.len() - RWTFTRAILER.len()]);
+        swapped.extend_from_slice(&buf[data_offset..track_end]);
+        swapped.extend_from_slice(&RWTFTRAILER);
+        let rwtf = parse_rwtf_with_options(&swapped, &ParseOptions::new()).unwrap();
+        assert_eq!(rwtf.track_points.len(), 5);
+        assert_eq!(rwtf.course_points.len(), 1);
+    }
+
+    #[test]
+    fn test_file_versions() {
+        // nothing newer than version 0
+        let mut buf = write_test_file();
+        assert_eq!(buf[8], 0);
+        let rwtf = parse_rwtf_with_options(&buf, &ParseOptions::new()).unwrap();
+        assert_eq!(rwtf.header().file_version(), 0);
+        assert_eq!(rwtf.to_bytes().unwrap()[8], 0);
+        assert_eq!(RWTFile::new().header().file_version(), 0);
+
+        let with = |change: &dyn Fn(&mut RWTFile)| {
+            let mut f = parse_rwtf(&buf).unwrap().1;
+            change(&mut f);
+            let changed = f.to_bytes().unwrap();
+            let mut copied = vec![];
+            f.write_with_sections(&mut copied, &parse_raw_sections(&changed, &ParseOptions::new()).unwrap()).unwrap();
+            assert_eq!(copied, changed);
+            assert_eq!(f.header().file_version(), changed[8]);
+            changed[8]
+        };
+        assert_eq!(with(&|_| ()), 0);
+        assert_eq!(with(&|f| assert!(f.add_lap(0, "a", 1).is_ok())), 1);
+        assert_eq!(with(&|f| assert!(f.add_track_point(0, "b", DataField::Bool(true)).is_ok())), 0);
+        assert_eq!(with(&|f| assert!(f.add_track_point(0, "b", DataField::XorFloat(1.5)).is_ok())), 1);
+        assert_eq!(with(&|f| f.track_points.set_run_length_encoding(true)), 1);
+        assert_eq!(with(&|f| f.set_file_checksum(true)), 1);
+        assert_eq!(with(&|f| assert!(f.add_track_point(0, &"b".repeat(256), 1).is_ok())), 1);
+
+        buf[8] = RWTF_FILE_VERSION + 1;
+        let crc = checksum_usb(&buf[0..22]).to_le_bytes();
+        buf[22..24].copy_from_slice(&crc);
+
+        let e = parse_rwtf_with_options(&buf, &ParseOptions::new()).unwrap_err();
+        assert_matches!(e, Error::UnsupportedVersion{offset: 8, position: Position{location: Location::Header, ..}, found, supported: RWTF_FILE_VERSION}
+                        if found == RWTF_FILE_VERSION + 1);
+        assert_eq!(e.kind(), crate::ErrorKind::Unsupported);
+        assert_matches!(parse_rwtf(&buf), Err(Err::Failure(Context::Code(_, ErrorKind::Custom(UNSUPPORTED_VERSION)))));
+    }
+
+    #[test]
+    fn test_raw_sections() {
+        let a = write_test_file();
+        let mut f = RWTFile::with_track_type(TrackType::Route(3));
+        for i in 0..3 {
+            assert!(f.add_track_point(i, "x", DataField::LongFloat(i as f64)).is_ok());
+        }
+        let b = f.to_bytes().unwrap();
+
+        let raw_a = parse_raw_sections(&a, &ParseOptions::new()).unwrap();
+        let raw_b = parse_raw_sections(&b, &ParseOptions::new()).unwrap();
+        assert_eq!(raw_a.iter().map(|s| (s.section_type(), s.len())).collect::<Vec<_>>(),
+                   vec![(SectionType::TrackPoints, 5), (SectionType::CoursePoints, 1)]);
+        assert_eq!(raw_b.len(), 1);
+
+        // b's metadata and track points with a's course points
+        let mut merged = vec![];
+        assert!(f.write_with_sections(&mut merged, &[raw_b[0], raw_a[1]]).is_ok());
+        let mut strict = ParseOptions::new();
+        strict.set_strict_layout(true);
+        let rwtf = parse_rwtf_with_options(&merged, &strict).unwrap();
+        assert_eq!(rwtf.metadata().track_type(), Some(TrackType::Route(3)));
+        assert_eq!(rwtf.track_points().len(), 3);
+        assert_matches!(rwtf.course_points().columns().get("b"), Some(Column::String(m)) if m[&0] == "hi");
+
+        assert!(f.write_with_sections(&mut vec![], &[raw_a[0], raw_b[0]]).is_err());
+
+        // CRCs are still checked
+        let mut corrupted = a.clone();
+        let data_offset = usize::from(u16::from_le_bytes([a[18], a[19]]));
+        corrupted[data_offset + 25] ^= 0x01;
+        assert_matches!(parse_raw_sections(&corrupted, &ParseOptions::new()),
+                        Err(Error::InvalidCrc{position: Position{location: Location::Data{section: 0}, ..}, ..}));
+        assert_matches!(parse_raw_sections(&a[..a.len() - 6], &ParseOptions::new()), Err(_));
+    }
+
+    #[test]
+    fn test_error_positions() {
+        let buf = write_test_file();
+        let track_points_start = 24 + 14;
+
+        assert_matches!(parse_rwtf_with_options(&buf[..10], &ParseOptions::new()),
+                        Err(Error::Incomplete{offset: 10, position: Position{location: Location::Header, ..}}));
+
+        // cut off in the middle of the values for column "a"
+        let cut = track_points_start + 14 + 6 + 5 + 2;
+        let err = parse_rwtf_with_options(&buf[..cut], &ParseOptions::new()).unwrap_err();
+        assert_eq!(err.offset(), cut);
+        assert_eq!(err.position(), &Position{location: Location::Data{section: 0},
+                                             column: Some("a".to_string()),
+                                             row: Some(2)});
+        assert_eq!(err.to_string(), "Unexpected end of input at byte 65 in section 0 data, column \"a\", row 2");
+
+        // an unknown section type
+        let mut corrupted = buf.clone();
+        corrupted[track_points_start] = 0xEE;
+        assert_matches!(parse_rwtf_with_options(&corrupted, &ParseOptions::new()),
+                        Err(Error::Malformed{offset, position: Position{location: Location::SectionHeader{section: 0}, ..}}) if offset == track_points_start);
+    }
+
+    #[test]
+    fn test_utf8_policy() {
+        let mut buf = write_test_file();
+        let course_points_start = 24 + 14 + 14 + 6 + 5 + 5 + 4;
+        // "hi" -> "\xFFi"
+        buf[course_points_start + 22] = 0xFF;
+
+        let mut options = ParseOptions::new();
+        options.set_crc_policy(CrcPolicy::Warn);
+
+        let rwtf = parse_rwtf_with_options(&buf, &options).unwrap();
+        assert_matches!(rwtf.course_points.columns().get("b"), Some(Column::String(m)) if m[&0] == "\u{FFFD}i");
+
+        options.set_utf8_policy(Utf8Policy::Raw);
+        let rwtf = parse_rwtf_with_options(&buf, &options).unwrap();
+        assert_matches!(rwtf.course_points.columns().get("b"), Some(Column::Base64(m)) if m[&0] == vec![0xFF, b'i']);
+
+        options.set_utf8_policy(Utf8Policy::Strict);
+        assert_matches!(parse_rwtf_with_options(&buf, &options),
+                        Err(Error::InvalidUtf8{offset, position: Position{location: Location::Data{section: 1}, row: Some(0), ..}}) if offset == course_points_start + 22);
+
+        // valid strings are fine
+        let rwtf = parse_rwtf_with_options(&write_test_file(), &options).unwrap();
+        assert_matches!(rwtf.course_points.columns().get("b"), Some(Column::String(m)) if m[&0] == "hi");
+    }
+
+    #[test]
+    fn test_unknown_column_types() {
+        let mut f = RWTFile::new();
+        for i in 0..3 {
+            assert!(f.add_track_point(i, "a", i as i64).is_ok());
+            assert!(f.add_track_point(i, "b", DataField::String("x".into())).is_ok());
+            assert!(f.add_track_point(i, "c", i as i64 * 2).is_ok());
+        }
+        assert!(f.add_course_point(0, "d", DataField::Bool(true)).is_ok());
+
+        let mut buf = vec![];
+        assert!(f.write(&mut buf).is_ok());
+
+        // pretend "b" was written with a column type from the future
+        let types_table = 24 + 14 + 14;
+        assert_eq!(&buf[types_table..types_table + 4], &[0x03, 0x00, 0x01, b'a']);
+        assert_eq!(&buf[types_table + 4..types_table + 7], &[0x04, 0x01, b'b']);
+        buf[types_table + 4] = 0xEE;
+
+        let mut options = ParseOptions::new();
+        options.set_crc_policy(CrcPolicy::Warn);
+        let rwtf = parse_rwtf_with_options(&buf, &options).unwrap();
+        assert_matches!(rwtf.warnings(),
+                        [ParseWarning::UnknownColumnType{section: 0, name: ref b, tag: 0xEE},
+                         ParseWarning::SkippedColumn{section: 0, name: ref c},
+                         ParseWarning::InvalidCrc{location: Location::TypesTable{section: 0}, ..}] if b == "b" && c == "c");
+
+        // "a" is still there, and so is the next section
+        assert_eq!(rwtf.track_points.len(), 3);
+        assert_eq!(rwtf.track_points.columns().keys().collect::<Vec<_>>(), vec!["a"]);
+        assert_matches!(rwtf.track_points.columns().get("a"), Some(Column::Numbers(m)) if m[&2] == 2);
+        assert_matches!(rwtf.course_points.columns().get("d"), Some(Column::Bool(m)) if m[&0]);
+
+        // and what's left can be written out again
+        let mut rewritten = vec![];
+        assert!(rwtf.write(&mut rewritten).is_ok());
+        let rwtf = parse_rwtf_with_options(&rewritten, &ParseOptions::new()).unwrap();
+        assert_eq!(rwtf.track_points.columns().len(), 1);
+        assert_eq!(rwtf.course_points.columns().len(), 1);
+    }
+
+    #[test]
+    fn test_only_columns() {
+        for run_length_encoding in &[false, true] {
+            let mut f = RWTFile::new();
+            f.track_points.set_run_length_encoding(*run_length_encoding);
+            f.track_points.set_packed_ids(*run_length_encoding);
+            for i in 0..20 {
+                let mut add = |name: &str, v: DataField| assert!(f.add_track_point(i, name, v).is_ok());
+                add("a", DataField::Number(i as i64 / 5));
+                if i % 3 != 0 {
+                    add("b", DataField::LongFloat(i as f64 / 3.0));
+                    add("c", DataField::ShortFloat(i as f64 / 7.0));
+                    add("d", DataField::String(format!("{}", i)));
+                    add("e", DataField::Base64("aGk=".into()));
+                    add("f", DataField::Bool(i % 2 == 0));
+                    add("g", DataField::IDs(vec![i as u64, 1]));
+                    add("h", DataField::PackedBool(i % 2 == 0));
+                    add("i", DataField::XorFloat(i as f64 * 1.5));
+                    add("j", DataField::Map(vec![("k".to_string(), DataField::Number(i as i64))].into_iter().collect()));
+                    add("k", DataField::GeoPoint(45.0 + i as f64, -122.0));
+                    add("l", DataField::I64Array(vec![i as i64, -1]));
+                    add("m", DataField::F64Array(vec![i as f64 / 4.0]));
+                    add("o", DataField::Timestamp(i as i64 * 1000));
+                }
+                add("z", DataField::Number(i as i64));
+            }
+            assert!(f.add_course_point(0, "n", DataField::String("start".into())).is_ok());
+            let buf = f.to_bytes().unwrap();
+            let all = parse_rwtf_with_options(&buf, &ParseOptions::new()).unwrap();
+
+            for name in all.track_points.columns().keys() {
+                let mut options = ParseOptions::new();
+                options.set_columns(Some(&[name.as_str(), "n"]));
+                let rwtf = parse_rwtf_with_options(&buf, &options).unwrap();
+                assert_eq!(rwtf.track_points.len(), 20);
+                assert_eq!(rwtf.track_points.columns().keys().collect::<Vec<_>>(), vec![name]);
+                for i in 0..20 {
+                    assert_eq!(rwtf.track_points.columns()[name].get(i), all.track_points.columns()[name].get(i));
+                }
+                assert_eq!(rwtf.course_points.columns().len(), 1);
+            }
+        }
+
+        let mut options = ParseOptions::new();
+        options.set_columns(Some(&[]));
+        let rwtf = parse_rwtf_with_options(&write_test_file(), &options).unwrap();
+        assert_eq!(rwtf.track_points.len(), 5);
+        assert!(rwtf.track_points.columns().is_empty());
+    }
+
+    #[test]
+    fn test_coerce_columns() {
+        let mut f = RWTFile::new();
+        for i in 0..10 {
+            assert!(f.add_track_point(i, "e", i as i64 * 10).is_ok());
+            assert!(f.add_track_point(i, "big", i64::MAX - i as i64).is_ok());
+            if i % 3 != 0 {
+                assert!(f.add_track_point(i, "moving", i % 2 == 0).is_ok());
+                assert!(f.add_track_point(i, "s", DataField::ShortFloat(i as f64 / 4.0)).is_ok());
+            }
+            assert!(f.add_track_point(i, "name", DataField::String("x".into())).is_ok());
+        }
+        let buf = f.to_bytes().unwrap();
+
+        let mut options = ParseOptions::new();
+        options.coerce("e", FieldType::LongFloat);
+        options.coerce("big", FieldType::LongFloat);
+        options.coerce("moving", FieldType::Numbers);
+        options.coerce("s", FieldType::XorFloat);
+        options.coerce("name", FieldType::Numbers);
+        options.coerce("missing", FieldType::Numbers);
+        assert_eq!(options.coercion("e"), Some(FieldType::LongFloat));
+        let rwtf = parse_rwtf_with_options(&buf, &options).unwrap();
+        let columns = rwtf.track_points.columns();
+        assert_eq!(columns["e"].field_type(), FieldType::LongFloat);
+        assert_eq!(columns["e"].get(3), Some(DataField::LongFloat(30.0)));
+        assert_eq!(columns["moving"].get(2), Some(DataField::Number(1)));
+        assert_eq!(columns["moving"].get(3), None);
+        assert_eq!(columns["s"].get(1), Some(DataField::XorFloat(0.25)));
+        assert_eq!(columns["big"].field_type(), FieldType::Numbers);
+        assert_eq!(columns["name"].field_type(), FieldType::String);
+        assert!(!columns.contains_key("missing"));
+        assert_eq!(rwtf.warnings(), &[
+            ParseWarning::UncoercedColumn{section: 0, name: "big".into(), stored: FieldType::Numbers, wanted: FieldType::LongFloat},
+            ParseWarning::UncoercedColumn{section: 0, name: "name".into(), stored: FieldType::String, wanted: FieldType::Numbers},
+        ]);
+    }
+
+    #[test]
+    fn test_column_aliases() {
+        let mut f = RWTFile::new();
+        for i in 0..10 {
+            assert!(f.add_track_point(i, "t", i as i64).is_ok());
+            if i % 2 == 0 {
+                assert!(f.add_track_point(i, "hr", 100 + i as i64).is_ok());
+            }
+            assert!(f.add_track_point(i, "pos", DataField::GeoPoint(45.0, -122.0 + i as f64)).is_ok());
+            assert!(f.add_track_point(i, "position", DataField::GeoPoint(1.0, 2.0)).is_ok());
+        }
+        f.track_points.set_geo_point_decimals("pos", 3);
+        let buf = f.to_bytes().unwrap();
+
+        let mut options = ParseOptions::new();
+        options.add_alias("heart_rate", &["hr"]);
+        options.add_alias("position", &["pos"]);
+        assert_eq!(options.aliases("heart_rate"), &["hr".to_string()]);
+        let rwtf = parse_rwtf_with_options(&buf, &options).unwrap();
+        let columns = rwtf.track_points.columns();
+        assert_eq!(columns.keys().collect::<Vec<_>>(), vec!["heart_rate", "pos", "position", "t"]);
+        for i in 0..10 {
+            assert_eq!(columns["heart_rate"].get(i), f.track_points.columns()["hr"].get(i));
+            assert_eq!(columns["position"].get(i), Some(DataField::GeoPoint(1.0, 2.0)));
+        }
+        assert_eq!(rwtf.track_points.geo_point_decimals("pos"), 3);
+
+        // set_columns takes the new names
+        options.set_columns(Some(&["heart_rate"]));
+        let rwtf = parse_rwtf_with_options(&buf, &options).unwrap();
+        assert_eq!(rwtf.track_points.columns().keys().collect::<Vec<_>>(), vec!["heart_rate"]);
+        assert_eq!(rwtf.track_points.columns()["heart_rate"].get(4), Some(DataField::Number(104)));
+        assert_eq!(rwtf.track_points.columns()["heart_rate"].get(5), None);
+
+        let (_, rwtf) = parse_rwtf(&rwtf.to_bytes().unwrap()).unwrap();
+        assert_eq!(rwtf.track_points.columns()["heart_rate"].get(4), Some(DataField::Number(104)));
+    }
+
+    #[test]
+    fn test_unknown_metadata_entries() {
+        let mut f = RWTFile::with_track_type(TrackType::Route(7));
+        f.metadata.unknown_entries = vec![(0x80, vec![1, 2, 3])];
+        assert!(f.add_track_point(0, "a", 1).is_ok());
+
+        let mut buf = vec![];
+        assert!(f.write(&mut buf).is_ok());
+        let rwtf = parse_rwtf_with_options(&buf, &ParseOptions::new()).unwrap();
+        assert_eq!(rwtf.metadata().track_type(), Some(TrackType::Route(7)));
+        assert_eq!(rwtf.metadata().unknown_entries(), &[(0x80, vec![1, 2, 3])]);
+
+        // rewriting keeps them
+        let mut rewritten = vec![];
+        assert!(rwtf.write(&mut rewritten).is_ok());
+        let rwtf = parse_rwtf_with_options(&rewritten, &ParseOptions::new()).unwrap();
+        assert_eq!(rwtf.metadata().unknown_entries(), &[(0x80, vec![1, 2, 3])]);
+    }
+
+    // A column of one type, with a value or None for each row
+    #[derive(Debug, Clone)]
+    enum ArbitraryColumn {
+        Numbers(Vec<Option<i64>>),
+        LongFloat(Vec<Option<f64>>),
+        ShortFloat(Vec<Option<f64>>),
+        Base64(Vec<Option<Vec<u8>>>),
+        String(Vec<Option<String>>),
+        Bool(Vec<Option<bool>>),
+        IDs(Vec<Option<Vec<u64>>>),
+        PackedBool(Vec<Option<bool>>),
+        XorFloat(Vec<Option<f64>>),
+    }
+
+    impl ArbitraryColumn {
+        // What should be added for each row, and what should be read back
+        fn fields(&self) -> Vec<Option<(DataField, DataField)>> {
+            fn each<T, F: Fn(&T) -> (DataField, DataField)>(rows: &[Option<T>], f: F) -> Vec<Option<(DataField, DataField)>> {
+                rows.iter().map(|row| row.as_ref().map(&f)).collect()
+            }
+
+            match self {
+                ArbitraryColumn::Numbers(rows) => each(rows, |v| (DataField::Number(*v), DataField::Number(*v))),
+                ArbitraryColumn::LongFloat(rows) => each(rows, |v| (DataField::LongFloat(*v), DataField::LongFloat((v * 10000000.0).round() as i64 as f64 / 10000000.0))),
+                ArbitraryColumn::ShortFloat(rows) => each(rows, |v| (DataField::ShortFloat(*v), DataField::ShortFloat((v * 1000.0).round() as i64 as f64 / 1000.0))),
+                ArbitraryColumn::Base64(rows) => each(rows, |v| (DataField::Base64(base64::encode(v)), DataField::Base64(base64::encode(v)))),
+                ArbitraryColumn::String(rows) => each(rows, |v| (DataField::String(v.clone()), DataField::String(v.clone()))),
+                ArbitraryColumn::Bool(rows) => each(rows, |v| (DataField::Bool(*v), DataField::Bool(*v))),
+                ArbitraryColumn::IDs(rows) => each(rows, |v| (DataField::IDs(v.clone()), DataField::IDs(v.clone()))),
+                ArbitraryColumn::PackedBool(rows) => each(rows, |v| (DataField::PackedBool(*v), DataField::PackedBool(*v))),
+                ArbitraryColumn::XorFloat(rows) => each(rows, |v| (DataField::XorFloat(*v), DataField::XorFloat(*v))),
+            }
+        }
+    }
+
+    fn arbitrary_rows<T: std::fmt::Debug, S: Strategy<Value = T>>(rows: usize, values: S) -> impl Strategy<Value = Vec<Option<T>>> {
+        prop::collection::vec(prop::option::weighted(0.8, values), rows)
+    }
+
+    fn arbitrary_column(rows: usize) -> impl Strategy<Value = ArbitraryColumn> {
+        // scaled floats are only stored to 1e-7 or 1e-3, so stick to
+        // magnitudes where f64 can still tell those steps apart
+        let long_float = prop_oneof![Just(0.0), Just(-0.0), -1e8..1e8f64, Just(-1e8)];
+        let short_float = prop_oneof![Just(0.0), Just(-0.0), -1e12..1e12f64, Just(1e12)];
+        let xor_float = prop_oneof![any::<f64>(), Just(f64::NAN), Just(f64::INFINITY), Just(-0.0), Just(f64::MIN_POSITIVE), Just(f64::MAX)];
+        let number = prop_oneof![any::<i64>(), Just(i64::MIN), Just(i64::MAX), Just(0)];
+
+        prop_oneof![
+            arbitrary_rows(rows, number).prop_map(ArbitraryColumn::Numbers),
+            arbitrary_rows(rows, long_float).prop_map(ArbitraryColumn::LongFloat),
+            arbitrary_rows(rows, short_float).prop_map(ArbitraryColumn::ShortFloat),
+            arbitrary_rows(rows, prop::collection::vec(any::<u8>(), 0..64)).prop_map(ArbitraryColumn::Base64),
+            arbitrary_rows(rows, prop_oneof![20 => ".{0,32}", 1 => "[a-z]{1000,5000}"]).prop_map(ArbitraryColumn::String),
+            arbitrary_rows(rows, any::<bool>()).prop_map(ArbitraryColumn::Bool),
+            arbitrary_rows(rows, prop::collection::vec(any::<u64>(), 0..8)).prop_map(ArbitraryColumn::IDs),
+            arbitrary_rows(rows, any::<bool>()).prop_map(ArbitraryColumn::PackedBool),
+            arbitrary_rows(rows, xor_float).prop_map(ArbitraryColumn::XorFloat),
+        ]
+    }
+
+    fn arbitrary_section() -> impl Strategy<Value = (Vec<(String, ArbitraryColumn)>, bool)> {
+        let columns = (1..40usize)
+            .prop_flat_map(|rows| prop::collection::btree_map("[a-zA-Z0-9_]{1,12}", arbitrary_column(rows), 0..12))
+            .prop_map(|columns| columns.into_iter().collect());
+        (columns, any::<bool>())
+    }
+
+    fn build_section(f: &mut RWTFile, course_points: bool, columns: &[(String, ArbitraryColumn)], run_length_encoding: bool) {
+        for (name, column) in columns {
+            for (index, field) in column.fields().into_iter().enumerate() {
+                if let Some((field, _expected)) = field {
+                    if course_points {
+                        f.add_course_point(index, name, field).unwrap();
+                    } else {
+                        f.add_track_point(index, name, field).unwrap();
+                    }
+                }
+            }
+        }
+
+        let section = if course_points { &mut f.course_points } else { &mut f.track_points };
+        section.set_run_length_encoding(run_length_encoding);
+    }
+
+    fn check_section(section: &Section, columns: &[(String, ArbitraryColumn)]) {
+        for (name, column) in columns {
+            let expected = column.fields()
+                .into_iter()
+                .enumerate()
+                .filter_map(|(index, field)| field.map(|(_field, expected)| (index, format!("{:?}", expected))))
+                .collect::<Vec<_>>();
+            let decoded = match section.columns().get(name) {
+                Some(c) => (0..section.len()).filter_map(|index| c.get(index).map(|v| (index, format!("{:?}", v)))).collect(),
+                None => vec![],
+            };
+            assert_eq!(decoded, expected, "column {}", name);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_roundtrip_arbitrary((track_points, track_points_rle) in arbitrary_section(),
+                                    (course_points, course_points_rle) in arbitrary_section()) {
+            let mut f = RWTFile::new();
+            build_section(&mut f, false, &track_points, track_points_rle);
+            build_section(&mut f, true, &course_points, course_points_rle);
+
+            let mut buf = vec![];
+            assert!(f.write(&mut buf).is_ok());
+
+            let rwtf = parse_rwtf_with_options(&buf, &ParseOptions::new()).unwrap();
+            assert!(rwtf.warnings().is_empty());
+            check_section(&rwtf.track_points, &track_points);
+            check_section(&rwtf.course_points, &course_points);
+
+            // writing what was read gives back the same bytes, apart from
+            // the header and metadata, which has the time it was written
+            let mut rewritten = vec![];
+            assert!(rwtf.write(&mut rewritten).is_ok());
+            assert_eq!(&rewritten[24 + 14..], &buf[24 + 14..]);
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_xor_float() {
+        let mut f = RWTFile::new();
+        let temperatures = [21.3, 21.3, 21.35, 21.2, 0.1 + 0.2, -4.0];
+        for (i, temperature) in temperatures.iter().enumerate() {
+            assert!(f.add_track_point(i * 2, "temp", DataField::XorFloat(*temperature)).is_ok());
+            assert!(f.add_track_point(i * 2 + 1, "t", i as i64).is_ok());
+        }
+
+        let mut buf = vec![];
+        assert!(f.write(&mut buf).is_ok());
+
+        let (_, rwtf) = parse_rwtf(&buf).unwrap();
+        match rwtf.track_points.columns().get("temp") {
+            Some(Column::XorFloat(m)) => {
+                assert_eq!(m.keys().cloned().collect::<Vec<_>>(), vec![0, 2, 4, 6, 8, 10]);
+                assert_eq!(m.values().cloned().collect::<Vec<_>>(), temperatures.to_vec());
+            }
+            other => panic!("unexpected temp column: {:?}", other),
+        }
+
+        // a bit window wider than 64 bits is corrupt data, not a short read
+        let encoded = xorfloat::encode(temperatures.iter().cloned());
+        let start = buf.windows(encoded.len()).position(|w| w == &encoded[..]).unwrap();
+        let mut corrupted = buf.clone();
+        corrupted[start + 8] = 0b1111_1111;
+        let mut options = ParseOptions::new();
+        options.set_crc_policy(CrcPolicy::Warn);
+        assert_matches!(parse_rwtf_with_options(&corrupted, &options), Err(Error::Malformed{..}));
+    }
+
+    #[test]
+    fn test_roundtrip_non_finite_floats() {
+        let values = [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, 1.5];
+
+        let mut f = RWTFile::new();
+        f.track_points.set_float_overflow_policy("saturate", FloatOverflowPolicy::Saturate);
+        f.track_points.set_float_overflow_policy("null", FloatOverflowPolicy::Null);
+        for (i, v) in values.iter().enumerate() {
+            assert_eq!(f.add_track_point(i, "error", DataField::LongFloat(*v)).is_err(), !v.is_finite());
+            assert!(f.add_track_point(i, "saturate", DataField::LongFloat(*v)).is_ok());
+            assert!(f.add_track_point(i, "null", DataField::ShortFloat(*v)).is_ok());
+            assert!(f.add_track_point(i, "xor", DataField::XorFloat(*v)).is_ok());
+        }
+
+        let mut buf = vec![];
+        assert!(f.write(&mut buf).is_ok());
+
+        let rwtf = parse_rwtf_with_options(&buf, &ParseOptions::new()).unwrap();
+        let columns = rwtf.track_points.columns();
+        let long_range = Column::long_float_range();
+        assert_matches!(columns.get("error"), Some(Column::LongFloat(m)) if m.len() == 1 && m[&3] == 1.5);
+        assert_matches!(columns.get("saturate"), Some(Column::LongFloat(m))
+                        if m.len() == 3 && m[&1] == *long_range.end() && m[&2] == *long_range.start() && m[&3] == 1.5);
+        assert_matches!(columns.get("null"), Some(Column::ShortFloat(m)) if m.len() == 1 && m[&3] == 1.5);
+        match columns.get("xor") {
+            Some(Column::XorFloat(m)) => {
+                let bits = m.values().map(|v| v.to_bits()).collect::<Vec<_>>();
+                assert_eq!(bits, values.iter().map(|v| v.to_bits()).collect::<Vec<_>>());
+            }
+            other => panic!("unexpected xor column: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_run_length_numbers() {
+        let mut f = RWTFile::new();
+        f.track_points.set_run_length_encoding(true);
+        for i in 0..100 {
+            assert!(f.add_track_point(i, "t", i as i64).is_ok());
+            if i != 50 {
+                assert!(f.add_track_point(i, "S", (i / 40) as i64 - 1).is_ok());
+            }
+        }
+
+        let mut buf = vec![];
+        assert!(f.write(&mut buf).is_ok());
+
+        let (_, rwtf) = parse_rwtf(&buf).unwrap();
+        assert!(rwtf.track_points.run_length_encoding());
+        match rwtf.track_points.columns().get("S") {
+            Some(Column::Numbers(m)) => {
+                let expected = (0..100)
+                    .filter(|i| *i != 50)
+                    .map(|i| (i, (i / 40) as i64 - 1))
+                    .collect::<BTreeMap<usize, i64>>();
+                assert_eq!(m, &expected);
+            }
+            other => panic!("unexpected S column: {:?}", other),
+        }
+        match rwtf.track_points.columns().get("t") {
+            Some(Column::Numbers(m)) => assert_eq!(m.len(), 100),
+            other => panic!("unexpected t column: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_run_length_flags() {
+        let mut f = RWTFile::new();
+        for i in 0..100 {
+            assert!(f.add_track_point(i, "t", i as i64).is_ok());
+            if i != 50 {
+                assert!(f.add_track_point(i, "e", DataField::LongFloat(i as f64)).is_ok());
+            }
+        }
+        let plain = f.to_bytes().unwrap();
+        f.track_points.set_run_length_encoding(true);
+        let buf = f.to_bytes().unwrap();
+        assert_eq!(f.estimated_encoded_size().unwrap(), buf.len());
+        // three runs of one byte counts and one byte flags instead of 100 flags
+        assert_eq!(plain.len() - buf.len(), 100 - 6);
+
+        let data_offset = usize::from(u16::from_le_bytes([buf[18], buf[19]]));
+        assert_eq!(buf[data_offset], 0x80);
+
+        let (_, rwtf) = parse_rwtf(&buf).unwrap();
+        assert!(rwtf.track_points.run_length_encoding());
+        assert_eq!(rwtf.track_points.len(), 100);
+        match rwtf.track_points.columns().get("e") {
+            Some(Column::LongFloat(m)) => assert!(m.len() == 99 && !m.contains_key(&50)),
+            other => panic!("unexpected e column: {:?}", other),
+        }
+        assert_matches!(rwtf.track_points.columns().get("t"), Some(Column::Numbers(m)) if m.len() == 100);
+
+        // runs covering more rows than the section has
+        let flags_start = data_offset + 14 + 1 + 3 + 3 + 2;
+        assert_eq!(&buf[flags_start..flags_start + 2], &[50, 0x03]);
+        let mut corrupted = buf.clone();
+        corrupted[flags_start] = 120;
+        assert_matches!(parse_rwtf_with_options(&corrupted, &ParseOptions::new()), Err(Error::Malformed{..}));
+    }
+
+    #[test]
+    fn test_roundtrip_many_columns() {
+        // e.g. a FIT file with lots of developer fields, flattened
+        let mut f = RWTFile::new();
+        for i in 0..10 {
+            for c in 0..200 {
+                if (i + c) % 3 != 0 {
+                    assert!(f.add_track_point(i, &format!("dev_{}", c), (i * c) as i64).is_ok());
+                }
+            }
+        }
+        assert_matches!(f.add_track_point(0, "t", 0), Ok(()));
+
+        for run_length in [false, true] {
+            f.track_points.set_run_length_encoding(run_length);
+            let buf = f.to_bytes().unwrap();
+            assert_eq!(f.estimated_encoded_size().unwrap(), buf.len());
+
+            let (_, rwtf) = parse_rwtf(&buf).unwrap();
+            assert_eq!(rwtf.track_points.columns().len(), 201);
+            for i in 0..10 {
+                for c in 0..200 {
+                    let expected = if (i + c) % 3 != 0 { Some(DataField::Number((i * c) as i64)) } else { None };
+                    assert_eq!(rwtf.track_points.columns()[&format!("dev_{}", c)].get(i), expected);
+                }
+            }
+            assert_eq!(rwtf.track_points.columns()["t"].get(0), Some(DataField::Number(0)));
+            assert_eq!(rwtf.track_points.columns()["t"].get(1), None);
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_wide_types_table() {
+        let long_name = "x".repeat(300);
+        let mut f = RWTFile::new();
+        assert!(f.add_track_point(0, "t", 0).is_ok());
+        let buf = f.to_bytes().unwrap();
+        let data_offset = usize::from(u16::from_le_bytes([buf[18], buf[19]]));
+        assert_eq!(buf[data_offset] & WIDE_TYPES_TABLE, 0);
+
+        // a long name alone makes the types table wide
+        assert!(f.add_track_point(1, &long_name, 1).is_ok());
+        let buf = f.to_bytes().unwrap();
+        assert_eq!(f.estimated_encoded_size().unwrap(), buf.len());
+        assert_eq!(buf[data_offset] & WIDE_TYPES_TABLE, WIDE_TYPES_TABLE);
+
+        for i in 0..5 {
+            for c in 0..300 {
+                if (i + c) % 7 != 0 {
+                    assert!(f.add_track_point(i, &format!("dev_{}", c), (i * c) as i64).is_ok());
+                }
+            }
+        }
+
+        for run_length in [false, true] {
+            f.track_points.set_run_length_encoding(run_length);
+            let buf = f.to_bytes().unwrap();
+            assert_eq!(f.estimated_encoded_size().unwrap(), buf.len());
+            assert_eq!(buf[data_offset] & WIDE_TYPES_TABLE, WIDE_TYPES_TABLE);
+
+            let (_, rwtf) = parse_rwtf(&buf).unwrap();
+            assert_eq!(rwtf.track_points.columns().len(), 302);
+            for i in 0..5 {
+                for c in 0..300 {
+                    let expected = if (i + c) % 7 != 0 { Some(DataField::Number((i * c) as i64)) } else { None };
+                    assert_eq!(rwtf.track_points.columns()[&format!("dev_{}", c)].get(i), expected);
+                }
+            }
+            assert_eq!(rwtf.track_points.columns()[&long_name].get(1), Some(DataField::Number(1)));
+            assert_eq!(rwtf.track_points.columns()[&long_name].get(0), None);
+
+            let columns = parse_column_types(&buf[data_offset..], 0, &ParseOptions::new()).unwrap();
+            assert_eq!(columns.len(), 302);
+            assert!(columns.contains(&(long_name.clone(), FieldType::Numbers)));
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_packed_ids() {
+        let mut f = RWTFile::new();
+        for i in 0..20 {
+            assert!(f.add_track_point(i, "t", i as i64).is_ok());
+            if i % 4 != 0 {
+                let targets = (0..i as u64).map(|j| 40_000 + j * 3).collect::<Vec<_>>();
+                assert!(f.add_track_point(i, "targets", DataField::IDs(targets)).is_ok());
+            }
+            // small ids, which LEB128 already stores in a byte each
+            assert!(f.add_track_point(i, "small", DataField::IDs(vec![1, 2])).is_ok());
+        }
+        let plain = f.to_bytes().unwrap();
+        f.track_points.set_packed_ids(true);
+        let buf = f.to_bytes().unwrap();
+        assert_eq!(f.estimated_encoded_size().unwrap(), buf.len());
+        assert!(buf.len() < plain.len());
+
+        for (bytes, packed) in &[(buf, true), (plain, false)] {
+            let (_, rwtf) = parse_rwtf(bytes).unwrap();
+            assert_eq!(rwtf.track_points.packed_ids(), *packed);
+            for name in &["targets", "small"] {
+                assert_eq!(rwtf.track_points.columns().get(*name).map(|column| column.field_type()), Some(FieldType::IDs));
+                for i in 0..20 {
+                    assert_eq!(rwtf.track_points.columns()[*name].get(i), f.track_points.columns()[*name].get(i));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_packed_bool() {
+        let mut f = RWTFile::new();
+        for i in 0..10 {
+            assert!(f.add_track_point(i, "a", i as i64).is_ok());
+            if i % 3 != 0 {
+                assert!(f.add_track_point(i, "paused", DataField::PackedBool(i % 2 == 0)).is_ok());
+            }
+        }
+
+        let mut buf = vec![];
+        assert!(f.write(&mut buf).is_ok());
+
+        let (_, rwtf) = parse_rwtf(&buf).unwrap();
+        match rwtf.track_points.columns().get("paused") {
+            Some(Column::PackedBool(m)) => {
+                let expected = (0..10)
+                    .filter(|i| i % 3 != 0)
+                    .map(|i| (i, i % 2 == 0))
+                    .collect::<BTreeMap<usize, bool>>();
+                assert_eq!(m, &expected);
+            }
+            other => panic!("unexpected paused column: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_geo_point() {
+        let mut f = RWTFile::new();
+        let mut separate = RWTFile::new();
+        for i in 0..50 {
+            let (lat, lng) = (45.5 + i as f64 * 0.0000123, -122.6 - i as f64 * 0.0000234);
+            if i % 7 != 3 {
+                assert!(f.add_track_point(i, "pos", DataField::GeoPoint(lat, lng)).is_ok());
+                assert!(separate.add_track_point(i, "lat", DataField::LongFloat(lat)).is_ok());
+                assert!(separate.add_track_point(i, "lng", DataField::LongFloat(lng)).is_ok());
+            }
+            assert!(f.add_track_point(i, "t", i as i64).is_ok());
+            assert!(separate.add_track_point(i, "t", i as i64).is_ok());
+        }
+        f.track_points.set_geo_point_decimals("coarse", 5);
+        assert!(f.add_track_point(0, "coarse", DataField::GeoPoint(-33.8688197, 151.2092955)).is_ok());
+
+        let buf = f.to_bytes().unwrap();
+        assert_eq!(f.estimated_encoded_size().unwrap(), buf.len());
+        assert!(buf.len() < separate.to_bytes().unwrap().len());
+
+        let (_, rwtf) = parse_rwtf(&buf).unwrap();
+        assert_eq!(rwtf.track_points.columns().get("pos").map(|column| column.field_type()), Some(FieldType::GeoPoint));
+        for i in 0..50 {
+            assert_eq!(rwtf.track_points.columns()["pos"].get(i), f.track_points.columns()["pos"].get(i));
+        }
+        assert_eq!(rwtf.track_points.columns()["pos"].get(3), None);
+        assert_eq!(rwtf.track_points.columns()["coarse"].get(0), Some(DataField::GeoPoint(-33.86882, 151.2093)));
+        assert_eq!(rwtf.track_points.geo_point_decimals("coarse"), 5);
+        assert_eq!(rwtf.track_points.geo_point_decimals("pos"), 7);
+        assert_eq!(&rwtf.to_bytes().unwrap()[24..], &buf[24..]);
+
+        // the points already added are rounded when the precision changes
+        f.track_points.set_geo_point_decimals("pos", 4);
+        let (_, rwtf) = parse_rwtf(&f.to_bytes().unwrap()).unwrap();
+        for i in 0..50 {
+            assert_eq!(rwtf.track_points.columns()["pos"].get(i), f.track_points.columns()["pos"].get(i));
+        }
+        assert_eq!(rwtf.track_points.columns()["pos"].get(1), Some(DataField::GeoPoint(45.5, -122.6)));
+    }
+
+    #[test]
+    fn test_roundtrip_extreme_geo_points() {
+        // decoded points aren't range checked, so a crafted file can hold
+        // points whose deltas overflow
+        let mut f = RWTFile::new();
+        f.track_points.set_geo_point_decimals("pos", 0);
+        let (max, min) = (i64::MAX as f64, i64::MIN as f64);
+        assert!(f.track_points.insert_geo_point(0, "pos", (max, min)).is_ok());
+        assert!(f.track_points.insert_geo_point(1, "pos", (min, max)).is_ok());
+        assert!(f.track_points.insert_geo_point(2, "pos", (0.0, max)).is_ok());
+        let buf = f.to_bytes().unwrap();
+        assert_eq!(f.estimated_encoded_size().unwrap(), buf.len());
+
+        let (_, rwtf) = parse_rwtf(&buf).unwrap();
+        for i in 0..3 {
+            assert_eq!(rwtf.track_points.columns()["pos"].get(i), f.track_points.columns()["pos"].get(i));
+        }
+        assert_eq!(rwtf.to_bytes().unwrap(), buf);
+    }
+
+    #[test]
+    fn test_roundtrip_i64_array_f64_array() {
+        let mut f = RWTFile::new();
+        for i in 0..20 {
+            assert!(f.add_track_point(i, "t", i as i64).is_ok());
+            if i % 5 != 2 {
+                let values = (0..i as i64).map(|n| if n % 2 == 0 { n * 1000 } else { -n }).collect::<Vec<_>>();
+                assert!(f.add_track_point(i, "a", values).is_ok());
+            }
+            if i % 4 != 1 {
+                let values = (0..i % 6).map(|n| n as f64 * -0.123456).collect::<Vec<_>>();
+                assert!(f.add_track_point(i, "b", values).is_ok());
+            }
+        }
+        f.track_points.set_f64_array_decimals("c", 6);
+        assert!(f.add_track_point(0, "c", vec![1.23456789]).is_ok());
+        assert!(f.add_track_point(1, "d", vec![i64::MIN, i64::MAX, 0]).is_ok());
+
+        let buf = f.to_bytes().unwrap();
+        assert_eq!(f.estimated_encoded_size().unwrap(), buf.len());
+
+        let (_, rwtf) = parse_rwtf(&buf).unwrap();
+        assert_eq!(rwtf.track_points.columns().get("a").map(|column| column.field_type()), Some(FieldType::I64Array));
+        assert_eq!(rwtf.track_points.columns().get("b").map(|column| column.field_type()), Some(FieldType::F64Array));
+        for name in &["a", "b", "c", "d"] {
+            for i in 0..20 {
+                assert_eq!(rwtf.track_points.columns()[*name].get(i), f.track_points.columns()[*name].get(i));
+            }
+        }
+        assert_eq!(rwtf.track_points.columns()["a"].get(0), Some(DataField::I64Array(vec![])));
+        assert_eq!(rwtf.track_points.columns()["a"].get(2), None);
+        assert_eq!(rwtf.track_points.columns()["b"].get(3), Some(DataField::F64Array(vec![0.0, -0.123, -0.247])));
+        assert_eq!(rwtf.track_points.columns()["c"].get(0), Some(DataField::F64Array(vec![1.234568])));
+        assert_eq!(rwtf.track_points.f64_array_decimals("c"), 6);
+        assert_eq!(&rwtf.to_bytes().unwrap()[24..], &buf[24..]);
+
+        // the values already added are rounded when the precision changes
+        f.track_points.set_f64_array_decimals("b", 1);
+        let (_, rwtf) = parse_rwtf(&f.to_bytes().unwrap()).unwrap();
+        for i in 0..20 {
+            assert_eq!(rwtf.track_points.columns()["b"].get(i), f.track_points.columns()["b"].get(i));
+        }
+        assert_eq!(rwtf.track_points.columns()["b"].get(3), Some(DataField::F64Array(vec![0.0, -0.1, -0.2])));
+    }
+
+    #[test]
+    fn test_roundtrip_timestamp() {
+        let mut f = RWTFile::new();
+        for i in 0..20 {
+            assert!(f.add_track_point(i, "t", i as i64).is_ok());
+            if i % 6 != 4 {
+                assert!(f.add_track_point(i, "at", DataField::Timestamp(1614834367000 + i as i64 * 1250)).is_ok());
+            }
+        }
+        f.track_points.set_timestamp_unit("secs", TimestampUnit::Seconds);
+        assert!(f.add_track_point(0, "secs", DataField::Timestamp(1614834367600)).is_ok());
+        assert!(f.add_track_point(1, "secs", DataField::Timestamp(-1400)).is_ok());
+        // added in millis, then rounded when the unit changes
+        assert!(f.add_track_point(0, "later", DataField::Timestamp(1400)).is_ok());
+        assert!(f.add_track_point(1, "later", DataField::Timestamp(-1600)).is_ok());
+        f.track_points.set_timestamp_unit("later", TimestampUnit::Seconds);
+
+        let buf = f.to_bytes().unwrap();
+        assert_eq!(f.estimated_encoded_size().unwrap(), buf.len());
+
+        let (_, rwtf) = parse_rwtf(&buf).unwrap();
+        assert_eq!(rwtf.track_points.columns().get("at").map(|column| column.field_type()), Some(FieldType::Timestamp));
+        for i in 0..20 {
+            assert_eq!(rwtf.track_points.columns()["at"].get(i), f.track_points.columns()["at"].get(i));
+        }
+        assert_eq!(rwtf.track_points.columns()["at"].get(4), None);
+        assert_eq!(rwtf.track_points.columns()["at"].get(1), Some(DataField::Timestamp(1614834368250)));
+        assert_eq!(rwtf.track_points.columns()["secs"].get(0), Some(DataField::Timestamp(1614834368000)));
+        assert_eq!(rwtf.track_points.columns()["secs"].get(1), Some(DataField::Timestamp(-1000)));
+        for i in 0..2 {
+            assert_eq!(rwtf.track_points.columns()["later"].get(i), f.track_points.columns()["later"].get(i));
+        }
+        assert_eq!(rwtf.track_points.columns()["later"].get(1), Some(DataField::Timestamp(-2000)));
+        assert_eq!(rwtf.track_points.timestamp_unit("secs"), TimestampUnit::Seconds);
+        assert_eq!(rwtf.track_points.timestamp_unit("at"), TimestampUnit::Millis);
+        assert_eq!(&rwtf.to_bytes().unwrap()[24..], &buf[24..]);
+    }
+
+    #[test]
+    fn test_roundtrip_map() {
+        let mut f = RWTFile::new();
+        for i in 0..10 {
+            assert!(f.add_track_point(i, "t", i as i64).is_ok());
+            if i % 3 != 0 {
+                let mut m = BTreeMap::new();
+                m.insert("lap".to_string(), DataField::Number(i as i64 / 4));
+                m.insert("power_balance".to_string(), DataField::LongFloat(0.1 * i as f64));
+                if i % 2 == 0 {
+                    m.insert("device".to_string(), DataField::String("wahoo ✓".to_string()));
+                    m.insert("standing".to_string(), DataField::Bool(true));
+                }
+                assert!(f.add_track_point(i, "attrs", DataField::Map(m)).is_ok());
+            }
+        }
+        assert!(f.add_track_point(10, "attrs", DataField::Map(BTreeMap::new())).is_ok());
+
+        let buf = f.to_bytes().unwrap();
+        assert_eq!(f.estimated_encoded_size().unwrap(), buf.len());
+        let (_, rwtf) = parse_rwtf(&buf).unwrap();
+        assert_eq!(rwtf.track_points.columns().get("attrs").map(|column| column.field_type()), Some(FieldType::Map));
+        for i in 0..11 {
+            assert_eq!(rwtf.track_points.columns()["attrs"].get(i), f.track_points.columns()["attrs"].get(i));
+        }
+        assert_eq!(rwtf.track_points.columns()["attrs"].get(3), None);
+        assert_eq!(rwtf.track_points.columns()["attrs"].get(10), Some(DataField::Map(BTreeMap::new())));
+
+        // an unknown value tag
+        let mut m = BTreeMap::new();
+        m.insert("a".to_string(), DataField::Bool(true));
+        let mut f = RWTFile::new();
+        assert!(f.add_track_point(0, "attrs", DataField::Map(m)).is_ok());
+        let mut buf = f.to_bytes().unwrap();
+        let at = buf.windows(2).rposition(|w| w == [map::BOOL, 0x01]).unwrap();
+        buf[at] = 0x7F;
+        let mut options = ParseOptions::new();
+        options.set_crc_policy(CrcPolicy::Warn);
+        assert!(parse_rwtf_with_options(&buf, &options).is_err());
+        buf[at] = map::BOOL;
+        assert!(parse_rwtf_with_options(&buf, &options).is_ok());
+    }
 }
@@ -0,0 +1,202 @@
+use std::collections::{BTreeMap};
+use crate::fields::{FieldType};
+
+/// What to do when a stored CRC doesn't match the data it covers.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CrcPolicy {
+    /// Fail parsing.
+    Verify,
+    /// Keep parsing and record a `ParseWarning` on the resulting `RWTFile`.
+    /// Useful for salvaging what's left of a damaged file.
+    Warn,
+}
+
+/// How to decode String column values that aren't valid UTF-8.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Utf8Policy {
+    /// Fail parsing.
+    Strict,
+    /// Replace invalid sequences with U+FFFD.
+    Lossy,
+    /// Keep the bytes as they are. String columns are returned as
+    /// `Column::Base64` columns instead.
+    Raw,
+}
+
+/// A part of an RWTF file. Sections are numbered in the order they appear.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Location {
+    Header,
+    Metadata,
+    SectionHeader{section: usize},
+    TypesTable{section: usize},
+    Data{section: usize},
+    Trailer,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ParseWarning {
+    InvalidCrc{location: Location, expected: u32, received: u32},
+    /// A column with a type this version doesn't know about (probably from a
+    /// newer writer) was left out of a section.
+    UnknownColumnType{section: usize, name: String, tag: u8},
+    /// Columns aren't prefixed with their size, so a column after one with
+    /// an unknown type can't be found and was left out of a section too.
+    SkippedColumn{section: usize, name: String},
+    /// A column couldn't be converted to the type asked for with
+    /// `ParseOptions::coerce`, so it was kept as the type it was stored as.
+    UncoercedColumn{section: usize, name: String, stored: FieldType, wanted: FieldType},
+}
+
+/// Length fields in a file can't be trusted, so the decoder refuses to
+/// allocate more than these limits allow and fails with `LIMIT_EXCEEDED`
+/// instead.
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    crc_policy: CrcPolicy,
+    utf8_policy: Utf8Policy,
+    max_section_points: u32,
+    max_value_len: u64,
+    max_column_size: u64,
+    strict_layout: bool,
+    columns: Option<Vec<String>>,
+    aliases: BTreeMap<String, Vec<String>>,
+    coercions: BTreeMap<String, FieldType>,
+}
+
+impl ParseOptions {
+    pub fn new() -> Self {
+        ParseOptions{crc_policy: CrcPolicy::Verify,
+                     utf8_policy: Utf8Policy::Lossy,
+                     max_section_points: 0xFF_FF_FF,
+                     max_value_len: 16 * 1024 * 1024,
+                     max_column_size: 256 * 1024 * 1024,
+                     strict_layout: false,
+                     columns: None,
+                     aliases: BTreeMap::new(),
+                     coercions: BTreeMap::new()}
+    }
+
+    pub fn crc_policy(&self) -> CrcPolicy {
+        self.crc_policy
+    }
+
+    pub fn set_crc_policy(&mut self, crc_policy: CrcPolicy) {
+        self.crc_policy = crc_policy;
+    }
+
+    pub fn utf8_policy(&self) -> Utf8Policy {
+        self.utf8_policy
+    }
+
+    pub fn set_utf8_policy(&mut self, utf8_policy: Utf8Policy) {
+        self.utf8_policy = utf8_policy;
+    }
+
+    /// The most points a single section may declare.
+    pub fn max_section_points(&self) -> u32 {
+        self.max_section_points
+    }
+
+    pub fn set_max_section_points(&mut self, max_section_points: u32) {
+        self.max_section_points = max_section_points;
+    }
+
+    /// The longest single value: bytes for String and Base64 values, ids for
+    /// IDs values.
+    pub fn max_value_len(&self) -> u64 {
+        self.max_value_len
+    }
+
+    pub fn set_max_value_len(&mut self, max_value_len: u64) {
+        self.max_value_len = max_value_len;
+    }
+
+    /// The sum of all value lengths in a single String, Base64 or IDs column.
+    pub fn max_column_size(&self) -> u64 {
+        self.max_column_size
+    }
+
+    pub fn set_max_column_size(&mut self, max_column_size: u64) {
+        self.max_column_size = max_column_size;
+    }
+
+    /// Require each part of the file to start exactly where the one before it
+    /// ends: the metadata table right after the header, the first section
+    /// right after the metadata table, each section where the size of the
+    /// one before it says, and nothing after the trailer. Gaps, overlaps and
+    /// trailing bytes fail with `INVALID_LAYOUT`. Off by default.
+    pub fn strict_layout(&self) -> bool {
+        self.strict_layout
+    }
+
+    pub fn set_strict_layout(&mut self, strict_layout: bool) {
+        self.strict_layout = strict_layout;
+    }
+
+    /// Only decode these columns, leaving every other column out of each
+    /// section, e.g. for jobs which only need elevation or power. Columns
+    /// aren't prefixed with their size, so the ones in between are still
+    /// read past, but their values aren't built. None, the default, decodes
+    /// every column.
+    pub fn columns(&self) -> Option<&[String]> {
+        self.columns.as_deref()
+    }
+
+    pub fn set_columns(&mut self, columns: Option<&[&str]>) {
+        self.columns = columns.map(|columns| columns.iter().map(|name| name.to_string()).collect());
+    }
+
+    /// Read columns stored under any of `aliases` as the column `name`, for
+    /// files written before a field was renamed, e.g.
+    /// `add_alias("heart_rate", &["hr"])`. When a section has more than one
+    /// of them, `name` itself is preferred, then the aliases in order, and
+    /// the others are left under their stored names. `set_columns` takes
+    /// the new names.
+    pub fn add_alias(&mut self, name: &str, aliases: &[&str]) {
+        self.aliases.entry(name.to_string()).or_default().extend(aliases.iter().map(|alias| alias.to_string()));
+    }
+
+    /// The stored names read as the column `name`.
+    pub fn aliases(&self, name: &str) -> &[String] {
+        self.aliases.get(name).map(|aliases| aliases.as_slice()).unwrap_or(&[])
+    }
+
+    // The stored name and every name it's an alias of
+    pub(crate) fn read_as<'a>(&'a self, stored: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        std::iter::once(stored).chain(self.aliases.iter()
+                                      .filter(move |(_, aliases)| aliases.iter().any(|alias| alias == stored))
+                                      .map(|(name, _)| name.as_str()))
+    }
+
+    pub(crate) fn all_aliases(&self) -> impl Iterator<Item = (&String, &Vec<String>)> {
+        self.aliases.iter()
+    }
+
+    /// Convert the named column to `field_type` when it was stored as a
+    /// type that widens to it, so files written with an older type still
+    /// produce the values a reader expects: Numbers and the float types to
+    /// a float type that holds more, and the bool types to each other or
+    /// to Numbers as 0 and 1. Numbers only become a LongFloat or ShortFloat
+    /// column if every value is in its range. A column that can't be
+    /// converted is kept as it was stored, with a
+    /// `ParseWarning::UncoercedColumn`. The name is the one the column is
+    /// read as, see `add_alias`.
+    pub fn coerce(&mut self, name: &str, field_type: FieldType) {
+        self.coercions.insert(name.to_string(), field_type);
+    }
+
+    pub fn coercion(&self, name: &str) -> Option<FieldType> {
+        self.coercions.get(name).cloned()
+    }
+
+    pub(crate) fn all_coercions(&self) -> impl Iterator<Item = (&String, &FieldType)> {
+        self.coercions.iter()
+    }
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,186 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap};
+use crate::rwtfile::{RWTFile, Result};
+use crate::section::{Section};
+use crate::trackpoint::{TrackPointReader};
+
+// A point's effective area, ordered so the smallest comes out of a
+// BinaryHeap<Reverse<_>> first, with ties going to the earlier point
+#[derive(Debug, PartialEq)]
+struct Area {
+    area: f64,
+    point: usize,
+}
+
+impl Eq for Area {}
+
+impl PartialOrd for Area {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Area {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.area.total_cmp(&other.area).then(self.point.cmp(&other.point))
+    }
+}
+
+// Twice the area of the triangle a, b, c on a flat map, with longitude
+// shrunk by `scale` so that both axes are the same length on the ground
+fn triangle_area(a: (f64, f64), b: (f64, f64), c: (f64, f64), scale: f64) -> f64 {
+    ((b.1 - a.1) * scale * (c.0 - a.0) - (c.1 - a.1) * scale * (b.0 - a.0)).abs()
+}
+
+// The indexes into `positions` to keep, in order, by Visvalingam-Whyatt:
+// repeatedly drop the point whose triangle with its neighbours is smallest,
+// which keeps the points that do the most for the shape
+fn simplify(positions: &[(f64, f64)], max_points: usize) -> Vec<usize> {
+    if positions.len() <= max_points {
+        return (0..positions.len()).collect();
+    }
+    if max_points < 2 {
+        return (0..max_points).collect();
+    }
+
+    let mean_lat = positions.iter().map(|(lat, _)| lat).sum::<f64>() / positions.len() as f64;
+    let scale = mean_lat.to_radians().cos().abs();
+    let last = positions.len() - 1;
+    let mut prev = (0..positions.len()).map(|i| i.wrapping_sub(1)).collect::<Vec<_>>();
+    let mut next = (1..=positions.len()).collect::<Vec<_>>();
+    let area = |prev: &[usize], next: &[usize], i: usize| {
+        triangle_area(positions[prev[i]], positions[i], positions[next[i]], scale)
+    };
+    // the ends are never dropped
+    let mut areas = (0..positions.len())
+        .map(|i| if i == 0 || i == last { f64::INFINITY } else { area(&prev, &next, i) })
+        .collect::<Vec<_>>();
+
+    let mut heap = (1..last).map(|i| Reverse(Area{area: areas[i], point: i})).collect::<BinaryHeap<_>>();
+
+    let mut remaining = positions.len();
+    while remaining > max_points {
+        let Reverse(Area{area: smallest, point}) = match heap.pop() {
+            Some(entry) => entry,
+            None => break,
+        };
+        // left over from before a neighbour was dropped
+        if smallest != areas[point] {
+            continue;
+        }
+
+        let (before, after) = (prev[point], next[point]);
+        next[before] = after;
+        prev[after] = before;
+        areas[point] = f64::NAN;
+        remaining -= 1;
+
+        // a neighbour's area never drops below the area of a point already
+        // dropped, so that points are dropped in a sensible order
+        for neighbour in [before, after].iter().copied().filter(|i| *i != 0 && *i != last) {
+            areas[neighbour] = area(&prev, &next, neighbour).max(smallest);
+            heap.push(Reverse(Area{area: areas[neighbour], point: neighbour}));
+        }
+    }
+
+    let mut kept = vec![];
+    let mut i = 0;
+    while i <= last {
+        kept.push(i);
+        i = next[i];
+    }
+    kept
+}
+
+/// Reduces a track points section to at most `max_points` rows while keeping
+/// its shape, for thumbnails and overviews. The first and last points are
+/// always kept. Rows without both a latitude and a longitude are dropped.
+/// The rows that are left are renumbered from 0 and keep every column, or
+/// only those named in `columns`.
+pub fn downsample(section: &Section, max_points: usize, columns: Option<&[&str]>) -> Result<Section> {
+    let reader = TrackPointReader::new(section);
+    let (rows, positions): (Vec<usize>, Vec<(f64, f64)>) = (0..reader.len())
+        .filter_map(|row| match (reader.lat(row), reader.lng(row)) {
+            (Some(lat), Some(lng)) if lat.is_finite() && lng.is_finite() => Some((row, (lat, lng))),
+            _ => None,
+        })
+        .unzip();
+
+    let kept = simplify(&positions, max_points).into_iter().map(|i| rows[i]).collect::<Vec<_>>();
+    select_rows(section, &kept, columns)
+}
+
+// A copy of `rows` of `section`, renumbered from 0, with every column or
+// only those named in `columns`
+pub(crate) fn select_rows(section: &Section, rows: &[usize], columns: Option<&[&str]>) -> Result<Section> {
+    let mut out = Section::new(section.section_type());
+    out.run_length_encoding = section.run_length_encoding;
+    out.packed_ids = section.packed_ids;
+    out.float_overflow_policies = section.float_overflow_policies.clone();
+    out.decimals = section.decimals.clone();
+    let kept_columns = section.columns()
+        .iter()
+        .filter(|(name, _)| columns.is_none_or(|columns| columns.contains(&name.as_str())))
+        .collect::<Vec<_>>();
+    for (out_index, row) in rows.iter().enumerate() {
+        for (name, column) in &kept_columns {
+            if let Some(v) = column.get(*row) {
+                RWTFile::add_point(&mut out, out_index, name, v)?;
+            }
+        }
+    }
+    out.extend_to(rows.len());
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use crate::rwtfile::{DataField};
+    use crate::section::{Column, SectionType};
+
+    #[test]
+    fn test_simplify() {
+        // an L with extra points along each leg
+        let positions = [(0.0, 0.0), (0.0, 1.0), (0.0, 2.0), (0.01, 3.0), (0.0, 4.0),
+                         (1.0, 4.0), (2.0, 4.0), (3.0, 4.0)];
+        assert_eq!(simplify(&positions, 100), (0..8).collect::<Vec<_>>());
+        assert_eq!(simplify(&positions, 3), vec![0, 4, 7]);
+        assert_eq!(simplify(&positions, 4), vec![0, 3, 4, 7]);
+        assert_eq!(simplify(&positions, 2), vec![0, 7]);
+        assert_eq!(simplify(&positions, 1), vec![0]);
+        assert!(simplify(&positions, 0).is_empty());
+        assert!(simplify(&[], 5).is_empty());
+    }
+
+    #[test]
+    fn test_downsample() {
+        let mut s = Section::new(SectionType::TrackPoints);
+        for i in 0..1000 {
+            let angle = i as f64 / 1000.0 * std::f64::consts::PI;
+            assert!(s.add_long_float(i, "y", 45.0 + angle.sin()).is_ok());
+            assert!(s.add_long_float(i, "x", -122.0 + angle.cos()).is_ok());
+            assert!(s.add_number(i, "t", i as i64).is_ok());
+            assert!(s.add_number(i, "h", 120).is_ok());
+        }
+        // no position
+        assert!(s.add_number(1000, "t", 1000).is_ok());
+
+        let out = downsample(&s, 50, None).unwrap();
+        assert_eq!(out.len(), 50);
+        assert_matches!(out.columns().get("t"), Some(Column::Numbers(m))
+                        if m[&0] == 0 && m[&49] == 999 && m.values().zip(m.values().skip(1)).all(|(a, b)| a < b));
+        assert!(out.columns().contains_key("h"));
+
+        let out = downsample(&s, 10, Some(&["y", "x", "t"])).unwrap();
+        assert_eq!(out.len(), 10);
+        assert_eq!(out.columns().keys().collect::<Vec<_>>(), vec!["t", "x", "y"]);
+
+        // fewer points than asked for
+        let out = downsample(&s, 2000, None).unwrap();
+        assert_eq!(out.len(), 1000);
+        assert_eq!(out.columns().get("t").and_then(|column| column.get(999)), Some(DataField::Number(999)));
+    }
+}
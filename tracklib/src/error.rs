@@ -0,0 +1,18 @@
+/// A broad category for an error, so callers can decide what to do with it
+/// without matching on every variant of every error type.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The file is damaged: a CRC doesn't match, it's cut short, or its
+    /// structure doesn't add up. Reading it again won't help.
+    Corruption,
+    /// The file uses a part of the format this version can't read.
+    Unsupported,
+    /// A value doesn't fit the column it was added to.
+    Schema,
+    /// Something is larger than the file format, or the limits in
+    /// `ParseOptions`, allow.
+    TooLarge,
+    /// Writing failed, or the system clock couldn't be read.
+    Io,
+}
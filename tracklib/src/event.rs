@@ -0,0 +1,154 @@
+use std::ops::{Range};
+use crate::fields;
+use crate::section::{Column, Section};
+
+/// A change in whether the recording was running, stored by name in the
+/// `fields::EVENT_TYPE` column.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum EventType {
+    /// The rider paused the recording.
+    Pause,
+    /// The device paused the recording because the rider stopped moving.
+    AutoPause,
+    /// The recording started again after either kind of pause.
+    Resume,
+}
+
+const EVENT_TYPES: &[EventType] = &[EventType::Pause, EventType::AutoPause, EventType::Resume];
+
+impl EventType {
+    /// The name stored in the events section.
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventType::Pause     => "pause",
+            EventType::AutoPause => "auto_pause",
+            EventType::Resume    => "resume",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        EVENT_TYPES.iter().find(|event_type| event_type.name() == name).copied()
+    }
+}
+
+/// The standard fields of a single event. See `RWTFile::add_standard_event`
+/// to write one.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Event {
+    /// Seconds since the unix epoch.
+    pub time: i64,
+    pub event_type: EventType,
+}
+
+/// Reads the events section. Rows without a time or a known type are
+/// skipped.
+#[derive(Debug)]
+pub struct EventReader {
+    events: Vec<Event>,
+}
+
+impl EventReader {
+    pub fn new(section: &Section) -> Self {
+        let times = match fields::TIME.column(section) {
+            Some(Column::Numbers(m)) => Some(m),
+            _ => None,
+        };
+        let types = match fields::EVENT_TYPE.column(section) {
+            Some(Column::String(m)) => Some(m),
+            _ => None,
+        };
+
+        let mut events = Vec::new();
+        if let (Some(times), Some(types)) = (times, types) {
+            for (index, time) in times {
+                if let Some(event_type) = types.get(index).and_then(|name| EventType::from_name(name)) {
+                    events.push(Event{time: *time, event_type});
+                }
+            }
+        }
+        // stable, so events at the same time keep their order
+        events.sort_by_key(|event| event.time);
+
+        EventReader{events}
+    }
+
+    /// Every event, ordered by time.
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// The times the recording was paused, each from a pause to the resume
+    /// after it. A pause which is never resumed lasts forever.
+    pub fn paused(&self) -> Vec<Range<i64>> {
+        let mut paused = Vec::new();
+        let mut paused_at = None;
+        for event in &self.events {
+            match event.event_type {
+                EventType::Pause | EventType::AutoPause => {
+                    paused_at.get_or_insert(event.time);
+                }
+                EventType::Resume => {
+                    if let Some(start) = paused_at.take() {
+                        paused.push(start..event.time);
+                    }
+                }
+            }
+        }
+        if let Some(start) = paused_at {
+            paused.push(start..i64::MAX);
+        }
+
+        paused
+    }
+
+    /// Seconds from `time.start` to `time.end` during which the recording
+    /// wasn't paused.
+    pub fn moving_time(&self, time: Range<i64>) -> i64 {
+        let paused = self.paused()
+            .iter()
+            .map(|pause| (pause.end.min(time.end) - pause.start.max(time.start)).max(0))
+            .sum::<i64>();
+
+        (time.end - time.start).max(0) - paused
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rwtfile::{RWTFile};
+
+    #[test]
+    fn test_event_type_names() {
+        for event_type in EVENT_TYPES {
+            assert_eq!(EventType::from_name(event_type.name()), Some(*event_type));
+        }
+        assert_eq!(EventType::from_name("stop"), None);
+    }
+
+    #[test]
+    fn test_events() {
+        let mut f = RWTFile::new();
+        let events = [Event{time: 1100, event_type: EventType::AutoPause},
+                      Event{time: 1130, event_type: EventType::Resume},
+                      Event{time: 1000, event_type: EventType::Pause},
+                      Event{time: 1010, event_type: EventType::Resume},
+                      Event{time: 1200, event_type: EventType::Pause}];
+        for (index, event) in events.iter().enumerate() {
+            assert!(f.add_standard_event(index, event).is_ok());
+        }
+        // a type this version doesn't know
+        assert!(f.add_event(5, "t", 1050).is_ok());
+        assert!(f.add_event(5, "v", "lap".to_string()).is_ok());
+
+        let f = RWTFile::read(&f.to_bytes().unwrap()).unwrap();
+        let reader = EventReader::new(f.events());
+        assert_eq!(reader.events().len(), 5);
+        assert_eq!(reader.events()[0], events[2]);
+        assert_eq!(reader.paused(), vec![1000..1010, 1100..1130, 1200..i64::MAX]);
+
+        assert_eq!(reader.moving_time(900..1300), 400 - 10 - 30 - 100);
+        assert_eq!(reader.moving_time(1005..1120), 115 - 5 - 20);
+        assert_eq!(reader.moving_time(1300..1400), 0);
+    }
+}
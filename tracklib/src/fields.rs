@@ -0,0 +1,196 @@
+//! Names and column types for the fields RWGPS tracks use, so every writer
+//! stores the same thing under the same name.
+use crate::section::{Column, Section};
+
+/// The kind of column a field is stored in, without its values.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FieldType {
+    Numbers,
+    LongFloat,
+    ShortFloat,
+    Base64,
+    String,
+    Bool,
+    IDs,
+    PackedBool,
+    XorFloat,
+    /// String keys to Number, LongFloat, String or Bool values, for sparse
+    /// attributes that would otherwise each need a mostly empty column.
+    Map,
+    /// A latitude and longitude stored together, with the deltas of both
+    /// from the point before. See `Section::set_geo_point_decimals`.
+    GeoPoint,
+    /// Any number of signed integers per row, e.g. R-R intervals.
+    I64Array,
+    /// Any number of floats per row, e.g. samples from several sensor
+    /// channels, kept to the column's decimal places. See
+    /// `Section::set_f64_array_decimals`.
+    F64Array,
+    /// Milliseconds since the unix epoch, stored to the second or to the
+    /// millisecond. See `Section::set_timestamp_unit`.
+    Timestamp,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Field {
+    pub name: &'static str,
+    pub field_type: FieldType,
+}
+
+impl Field {
+    const fn new(name: &'static str, field_type: FieldType) -> Self {
+        Field{name, field_type}
+    }
+
+    /// The column for this field in `section`, if it has one of the right
+    /// type.
+    pub fn column<'a>(&self, section: &'a Section) -> Option<&'a Column> {
+        section.columns()
+            .get(self.name)
+            .filter(|column| column.field_type() == self.field_type)
+    }
+}
+
+/// Longitude, in degrees.
+pub const LNG: Field = Field::new("x", FieldType::LongFloat);
+/// Latitude, in degrees.
+pub const LAT: Field = Field::new("y", FieldType::LongFloat);
+/// Elevation, in meters.
+pub const ELEVATION: Field = Field::new("e", FieldType::LongFloat);
+/// Seconds since the unix epoch.
+pub const TIME: Field = Field::new("t", FieldType::Numbers);
+/// Distance from the start, in meters.
+pub const DISTANCE: Field = Field::new("d", FieldType::ShortFloat);
+/// Speed, in km/h.
+pub const SPEED: Field = Field::new("s", FieldType::ShortFloat);
+/// Heart rate, in beats per minute.
+pub const HEART_RATE: Field = Field::new("h", FieldType::Numbers);
+/// Cadence, in revolutions per minute.
+pub const CADENCE: Field = Field::new("c", FieldType::Numbers);
+/// Power, in watts.
+pub const POWER: Field = Field::new("p", FieldType::Numbers);
+/// Temperature, in degrees Celsius.
+pub const TEMPERATURE: Field = Field::new("T", FieldType::ShortFloat);
+/// A `SurfaceTypeId`, see `SurfaceMapping`.
+pub const SURFACE: Field = Field::new("S", FieldType::Numbers);
+/// A `RoadClassId`, see `SurfaceMapping`.
+pub const ROAD_CLASS: Field = Field::new("R", FieldType::Numbers);
+/// The id of the segment a point belongs to, such as the GPX `<trkseg>` or
+/// the stretch between two FIT timer stops. A new segment starts wherever
+/// the id changes; a point without one stays in the segment before it.
+pub const SEGMENT: Field = Field::new("g", FieldType::Numbers);
+
+/// Every standard track points field.
+pub const TRACK_POINT_FIELDS: &[Field] = &[LNG, LAT, ELEVATION, TIME, DISTANCE, SPEED, HEART_RATE,
+                                           CADENCE, POWER, TEMPERATURE, SURFACE, ROAD_CLASS, SEGMENT];
+
+/// The standard track points field called `name`, if there is one.
+pub fn track_point_field(name: &str) -> Option<Field> {
+    TRACK_POINT_FIELDS.iter().find(|field| field.name == name).copied()
+}
+
+/// A course point's `TurnType`, by name.
+pub const COURSE_POINT_TYPE: Field = Field::new("t", FieldType::String);
+/// Free text shown with a course point, such as the street to turn onto.
+pub const NOTE: Field = Field::new("n", FieldType::String);
+/// When a course point is expected to be reached, in seconds since the unix
+/// epoch. Course points can't use `TIME`, since `COURSE_POINT_TYPE` has its
+/// name.
+pub const ETA: Field = Field::new("E", FieldType::Numbers);
+
+/// Every standard course points field.
+pub const COURSE_POINT_FIELDS: &[Field] = &[LNG, LAT, DISTANCE, COURSE_POINT_TYPE, NOTE, ETA];
+
+/// The standard course points field called `name`, if there is one.
+pub fn course_point_field(name: &str) -> Option<Field> {
+    COURSE_POINT_FIELDS.iter().find(|field| field.name == name).copied()
+}
+
+/// A point of interest's name.
+pub const POI_NAME: Field = Field::new("N", FieldType::String);
+/// What kind of place a point of interest is, such as "bike_shop".
+pub const POI_TYPE: Field = Field::new("k", FieldType::String);
+/// The name of the icon to show for a point of interest.
+pub const ICON: Field = Field::new("i", FieldType::String);
+/// Free text describing a point of interest.
+pub const DESCRIPTION: Field = Field::new("D", FieldType::String);
+
+/// Every standard points of interest field.
+pub const POINT_OF_INTEREST_FIELDS: &[Field] = &[LNG, LAT, POI_NAME, POI_TYPE, ICON, DESCRIPTION];
+
+/// The standard points of interest field called `name`, if there is one.
+pub fn point_of_interest_field(name: &str) -> Option<Field> {
+    POINT_OF_INTEREST_FIELDS.iter().find(|field| field.name == name).copied()
+}
+
+/// The first track points row of a lap.
+pub const START_ROW: Field = Field::new("a", FieldType::Numbers);
+/// The track points row after the last one of a lap.
+pub const END_ROW: Field = Field::new("b", FieldType::Numbers);
+/// When a lap ended, in seconds since the unix epoch. It started at `TIME`.
+pub const END_TIME: Field = Field::new("u", FieldType::Numbers);
+/// Seconds spent moving.
+pub const MOVING_TIME: Field = Field::new("m", FieldType::Numbers);
+
+/// Every standard laps field. `DISTANCE`, `HEART_RATE` and `POWER` are the
+/// lap's total distance and its averages.
+pub const LAP_FIELDS: &[Field] = &[START_ROW, END_ROW, TIME, END_TIME, DISTANCE, MOVING_TIME, HEART_RATE, POWER];
+
+/// The standard laps field called `name`, if there is one.
+pub fn lap_field(name: &str) -> Option<Field> {
+    LAP_FIELDS.iter().find(|field| field.name == name).copied()
+}
+
+/// An event's `EventType`, by name.
+pub const EVENT_TYPE: Field = Field::new("v", FieldType::String);
+
+/// Every standard events field. `TIME` is when the event happened.
+pub const EVENT_FIELDS: &[Field] = &[TIME, EVENT_TYPE];
+
+/// The standard events field called `name`, if there is one.
+pub fn event_field(name: &str) -> Option<Field> {
+    EVENT_FIELDS.iter().find(|field| field.name == name).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::section::{SectionType};
+
+    #[test]
+    fn test_names_are_unique() {
+        for field in TRACK_POINT_FIELDS {
+            assert_eq!(track_point_field(field.name), Some(*field));
+        }
+        assert_eq!(track_point_field("lat"), None);
+
+        for field in COURSE_POINT_FIELDS {
+            assert_eq!(course_point_field(field.name), Some(*field));
+        }
+        assert_eq!(course_point_field("t"), Some(COURSE_POINT_TYPE));
+
+        for field in POINT_OF_INTEREST_FIELDS {
+            assert_eq!(point_of_interest_field(field.name), Some(*field));
+        }
+
+        for field in LAP_FIELDS {
+            assert_eq!(lap_field(field.name), Some(*field));
+        }
+
+        for field in EVENT_FIELDS {
+            assert_eq!(event_field(field.name), Some(*field));
+        }
+    }
+
+    #[test]
+    fn test_column() {
+        let mut s = Section::new(SectionType::TrackPoints);
+        assert!(s.add_long_float(0, LAT.name, 45.5).is_ok());
+        assert!(s.add_long_float(0, TIME.name, 1.0).is_ok());
+
+        assert!(matches!(LAT.column(&s), Some(Column::LongFloat(_))));
+        assert!(LNG.column(&s).is_none());
+        // there, but with the wrong type
+        assert!(TIME.column(&s).is_none());
+    }
+}
@@ -0,0 +1,424 @@
+//! Reads the record messages of a FIT activity file into a section with the
+//! standard fields, see `fields`. Only the messages and fields this needs
+//! are decoded; everything else is skipped using its definition.
+use std::collections::{HashMap};
+use std::convert::{TryFrom};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use snafu::{Snafu, ResultExt};
+use crate::error::{ErrorKind};
+use crate::fields::{self};
+use crate::rwtfile::{DataField, RWTFile, Error as RWTFileError};
+use crate::section::{Section, SectionType};
+use crate::transform::{strip_columns};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Not a FIT file"))]
+    NotFit{},
+    #[snafu(display("Cut short at byte {}", offset))]
+    Truncated{offset: usize},
+    #[snafu(display("FIT CRC mismatch: expected {:#06x}, found {:#06x}", expected, found))]
+    InvalidCrc{expected: u16, found: u16},
+    #[snafu(display("Local message type {} at byte {} has no definition", local, offset))]
+    UndefinedMessage{local: u8, offset: usize},
+    #[snafu(display("Couldn't add track point {}: {}", point, source))]
+    AddPoint{point: usize, source: RWTFileError},
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::NotFit{..} |
+            Error::Truncated{..} |
+            Error::InvalidCrc{..} |
+            Error::UndefinedMessage{..} => ErrorKind::Corruption,
+            Error::AddPoint{source, ..} => source.kind(),
+        }
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// What `read_fit` finds in a FIT file.
+#[derive(Debug, Clone)]
+pub struct FitActivity {
+    /// When the file was created, from its file_id message.
+    pub created_at: Option<SystemTime>,
+    pub track_points: Section,
+}
+
+// FIT times are seconds since 1989-12-31T00:00:00Z
+const FIT_EPOCH: i64 = 631065600;
+
+const FILE_ID: u16 = 0;
+const RECORD: u16 = 20;
+const EVENT: u16 = 21;
+
+// The CRC-16 the FIT SDK describes, a nibble at a time
+fn fit_crc(bytes: &[u8]) -> u16 {
+    const TABLE: [u16; 16] = [0x0000, 0xCC01, 0xD801, 0x1400, 0xF001, 0x3C00, 0x2800, 0xE401,
+                              0xA001, 0x6C00, 0x7800, 0xB401, 0x5000, 0x9C01, 0x8801, 0x4400];
+    bytes.iter().fold(0, |crc, byte| {
+        let crc = ((crc >> 4) & 0x0FFF) ^ TABLE[(crc & 0xF) as usize] ^ TABLE[(byte & 0xF) as usize];
+        ((crc >> 4) & 0x0FFF) ^ TABLE[(crc & 0xF) as usize] ^ TABLE[((byte >> 4) & 0xF) as usize]
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+struct FieldDefinition {
+    number: u8,
+    size: usize,
+    base_type: u8,
+}
+
+#[derive(Debug, Clone)]
+struct Definition {
+    big_endian: bool,
+    global: u16,
+    fields: Vec<FieldDefinition>,
+    // the bytes of the developer fields, which are skipped
+    developer_size: usize,
+}
+
+// An integer field's value, or None for the base type's invalid value or a
+// field which isn't a single integer
+fn integer(bytes: &[u8], field: &FieldDefinition, big_endian: bool) -> Option<i64> {
+    let (size, signed, zero_invalid) = match field.base_type & 0x1F {
+        0x00 | 0x02 | 0x0D => (1, false, false),
+        0x0A => (1, false, true),
+        0x01 => (1, true, false),
+        0x03 => (2, true, false),
+        0x04 => (2, false, false),
+        0x0B => (2, false, true),
+        0x05 => (4, true, false),
+        0x06 => (4, false, false),
+        0x0C => (4, false, true),
+        _ => return None,
+    };
+    if field.size != size {
+        return None;
+    }
+
+    let raw = if big_endian {
+        bytes.iter().fold(0u64, |v, b| (v << 8) | u64::from(*b))
+    } else {
+        bytes.iter().rev().fold(0u64, |v, b| (v << 8) | u64::from(*b))
+    };
+    let bits = size as u32 * 8;
+    let invalid = match (signed, zero_invalid) {
+        (true, _) => (1u64 << (bits - 1)) - 1,
+        (false, true) => 0,
+        (false, false) => u64::MAX >> (64 - bits),
+    };
+    if raw == invalid {
+        None
+    } else if signed {
+        // sign extend
+        Some(((raw << (64 - bits)) as i64) >> (64 - bits))
+    } else {
+        Some(raw as i64)
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let start = self.at;
+        match self.bytes.get(start..start + len) {
+            Some(taken) => {
+                self.at += len;
+                Ok(taken)
+            }
+            None => Truncated{offset: start}.fail(),
+        }
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+}
+
+fn read_definition(reader: &mut Reader, developer_fields: bool) -> Result<Definition> {
+    let header = reader.take(5)?;
+    let big_endian = header[1] == 1;
+    let global = if big_endian {
+        u16::from_be_bytes([header[2], header[3]])
+    } else {
+        u16::from_le_bytes([header[2], header[3]])
+    };
+    let fields = (0..header[4])
+        .map(|_| {
+            let field = reader.take(3)?;
+            Ok(FieldDefinition{number: field[0], size: field[1].into(), base_type: field[2]})
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut developer_size = 0;
+    if developer_fields {
+        for _ in 0..reader.u8()? {
+            developer_size += usize::from(reader.take(3)?[1]);
+        }
+    }
+
+    Ok(Definition{big_endian, global, fields, developer_size})
+}
+
+// The values of the fields of a data message, by field number
+fn read_values(reader: &mut Reader, definition: &Definition) -> Result<HashMap<u8, i64>> {
+    let mut values = HashMap::new();
+    for field in &definition.fields {
+        let bytes = reader.take(field.size)?;
+        if let Some(v) = integer(bytes, field, definition.big_endian) {
+            values.insert(field.number, v);
+        }
+    }
+    reader.take(definition.developer_size)?;
+
+    Ok(values)
+}
+
+// Adds the fields of a record message as row `point`
+fn add_record(section: &mut Section, point: usize, values: &HashMap<u8, i64>, segment: i64) -> Result<()> {
+    let semicircles = |v: i64| v as f64 * (180.0 / 2_147_483_648.0);
+    let altitude = |v: i64| v as f64 / 5.0 - 500.0;
+    // FIT speeds are in mm/s, the standard field is in km/h
+    let speed = |v: i64| v as f64 * 0.0036;
+
+    let mut add = |name: &str, v: DataField| RWTFile::add_point(section, point, name, v).context(AddPoint{point});
+    if let Some(v) = values.get(&253) {
+        add(fields::TIME.name, DataField::Number(v + FIT_EPOCH))?;
+    }
+    if let (Some(lat), Some(lng)) = (values.get(&0), values.get(&1)) {
+        add(fields::LAT.name, DataField::LongFloat(semicircles(*lat)))?;
+        add(fields::LNG.name, DataField::LongFloat(semicircles(*lng)))?;
+    }
+    if let Some(v) = values.get(&78).or_else(|| values.get(&2)) {
+        add(fields::ELEVATION.name, DataField::LongFloat(altitude(*v)))?;
+    }
+    if let Some(v) = values.get(&3) {
+        add(fields::HEART_RATE.name, DataField::Number(*v))?;
+    }
+    if let Some(v) = values.get(&4) {
+        add(fields::CADENCE.name, DataField::Number(*v))?;
+    }
+    if let Some(v) = values.get(&5) {
+        add(fields::DISTANCE.name, DataField::ShortFloat(*v as f64 / 100.0))?;
+    }
+    if let Some(v) = values.get(&73).or_else(|| values.get(&6)) {
+        add(fields::SPEED.name, DataField::ShortFloat(speed(*v)))?;
+    }
+    if let Some(v) = values.get(&7) {
+        add(fields::POWER.name, DataField::Number(*v))?;
+    }
+    if let Some(v) = values.get(&13) {
+        add(fields::TEMPERATURE.name, DataField::ShortFloat(*v as f64))?;
+    }
+    add(fields::SEGMENT.name, DataField::Number(segment))?;
+
+    Ok(())
+}
+
+/// The record messages of `bytes`, in order, as track points, and when the
+/// file was created. Each record's time, position, altitude, heart rate,
+/// cadence, distance, speed, power and temperature are kept. A timer stop
+/// starts a new `fields::SEGMENT`, which is left out when there's only one
+/// segment. Chained FIT files are read one after another.
+pub fn read_fit(bytes: &[u8]) -> Result<FitActivity> {
+    let mut reader = Reader{bytes, at: 0};
+    let mut section = Section::new(SectionType::TrackPoints);
+    let mut created_at = None;
+    let mut point = 0;
+    let mut segment = 0;
+    let mut stopped = false;
+
+    while reader.at < bytes.len() {
+        let start = reader.at;
+        let header_size = usize::from(reader.u8()?);
+        let header = reader.take(header_size.saturating_sub(1))?;
+        if (header_size != 12 && header_size != 14) || &header[7..11] != b".FIT" {
+            return NotFit{}.fail();
+        }
+        let data_size = u32::from_le_bytes([header[3], header[4], header[5], header[6]]) as usize;
+        let end = start + header_size + data_size;
+        if let Some(crc) = bytes.get(end..end + 2) {
+            let expected = u16::from_le_bytes([crc[0], crc[1]]);
+            let found = fit_crc(&bytes[start..end]);
+            if expected != found {
+                return InvalidCrc{expected, found}.fail();
+            }
+        } else {
+            return Truncated{offset: bytes.len()}.fail();
+        }
+
+        let mut definitions: HashMap<u8, Definition> = HashMap::new();
+        let mut last_timestamp = 0i64;
+        while reader.at < end {
+            let offset = reader.at;
+            let record_header = reader.u8()?;
+            let (local, compressed_offset) = if record_header & 0x80 != 0 {
+                ((record_header >> 5) & 0x03, Some(i64::from(record_header & 0x1F)))
+            } else {
+                (record_header & 0x0F, None)
+            };
+
+            if compressed_offset.is_none() && record_header & 0x40 != 0 {
+                definitions.insert(local, read_definition(&mut reader, record_header & 0x20 != 0)?);
+                continue;
+            }
+
+            let definition = definitions.get(&local).ok_or(Error::UndefinedMessage{local, offset})?;
+            let mut values = read_values(&mut reader, definition)?;
+            if let Some(time_offset) = compressed_offset {
+                // the low 5 bits of the time since the last full timestamp
+                let mut timestamp = (last_timestamp & !0x1F) | time_offset;
+                if timestamp < last_timestamp {
+                    timestamp += 0x20;
+                }
+                values.insert(253, timestamp);
+            }
+            if let Some(timestamp) = values.get(&253) {
+                last_timestamp = *timestamp;
+            }
+
+            match definition.global {
+                FILE_ID => {
+                    if let Some(v) = values.get(&4).filter(|_| created_at.is_none()) {
+                        // a sint32 time_created can be before the unix epoch,
+                    // which SystemTime needn't represent, so it's dropped
+                    created_at = u64::try_from(v + FIT_EPOCH).ok()
+                        .and_then(|secs| UNIX_EPOCH.checked_add(Duration::from_secs(secs)));
+                    }
+                }
+                RECORD => {
+                    if stopped {
+                        segment += 1;
+                        stopped = false;
+                    }
+                    add_record(&mut section, point, &values, segment)?;
+                    point += 1;
+                }
+                // timer events, with an event_type of stop or stop_all
+                EVENT if values.get(&0) == Some(&0) && matches!(values.get(&1), Some(1) | Some(4)) => {
+                    stopped = point > 0;
+                }
+                _ => {}
+            }
+        }
+        reader.at = end + 2;
+    }
+    section.extend_to(point);
+
+    if segment == 0 {
+        section = strip_columns(&section, &[fields::SEGMENT.name]);
+    }
+
+    Ok(FitActivity{created_at, track_points: section})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    // A FIT file holding `records`, each a definition or data message
+    // without its CRC or header
+    fn fit_file(records: &[&[u8]]) -> Vec<u8> {
+        let data = records.concat();
+        let mut bytes = vec![14, 0x10, 0x08, 0x08];
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(b".FIT");
+        let header_crc = fit_crc(&bytes);
+        bytes.extend_from_slice(&header_crc.to_le_bytes());
+        bytes.extend_from_slice(&data);
+        let crc = fit_crc(&bytes);
+        bytes.extend_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_fit_crc() {
+        assert_eq!(fit_crc(b"123456789"), 0xBB3D);
+    }
+
+    #[test]
+    fn test_read_fit() {
+        let time = 1_000_000_000u32;
+        let lat = ((45.5 / 180.0) * 2_147_483_648.0) as i32;
+        let mut record_data = vec![0x01];
+        record_data.extend_from_slice(&time.to_le_bytes());
+        record_data.extend_from_slice(&lat.to_le_bytes());
+        record_data.extend_from_slice(&(-1i32).to_le_bytes());
+        record_data.extend_from_slice(&2600u16.to_le_bytes());
+        record_data.extend_from_slice(&[140, 0xFF, 0xEC]);
+        record_data.extend_from_slice(&250u16.to_le_bytes());
+        // a second record with a compressed timestamp 3s later, no power,
+        // and big endian
+        let mut compressed = vec![0x80 | (2 << 5) | (((time + 3) & 0x1F) as u8)];
+        compressed.extend_from_slice(&lat.to_be_bytes());
+        compressed.extend_from_slice(&(-1i32).to_be_bytes());
+
+        let mut file_id_data = vec![0x00];
+        file_id_data.extend_from_slice(&time.to_le_bytes());
+
+        let bytes = fit_file(&[
+            // file_id, with time_created
+            &[0x40, 0, 0, 0, 0, 1, 4, 4, 0x86],
+            &file_id_data,
+            // record: timestamp, lat, long, altitude, heart rate, cadence,
+            // temperature, power, and a developer field
+            &[0x61, 0, 0, 20, 0, 8, 253, 4, 0x86, 0, 4, 0x85, 1, 4, 0x85, 2, 2, 0x84, 3, 1, 0x02, 4, 1, 0x02, 13, 1, 0x01, 7, 2, 0x84,
+              1, 0, 1, 0],
+            &[&record_data[..], &[0x2A]].concat(),
+            // a timer stop_all event
+            &[0x43, 0, 0, 21, 0, 2, 0, 1, 0x00, 1, 1, 0x00],
+            &[0x03, 0, 4],
+            &[0x42, 0, 1, 0, 20, 2, 0, 4, 0x85, 1, 4, 0x85],
+            &compressed,
+        ]);
+
+        let fit = read_fit(&bytes).unwrap();
+        assert_eq!(fit.created_at, Some(UNIX_EPOCH + Duration::from_secs(time as u64 + FIT_EPOCH as u64)));
+        let s = &fit.track_points;
+        assert_eq!(s.len(), 2);
+        let get = |name: &str, i| s.columns().get(name).and_then(|column| column.get(i));
+        assert_eq!(get(fields::TIME.name, 0), Some(DataField::Number(time as i64 + FIT_EPOCH)));
+        assert_eq!(get(fields::TIME.name, 1), Some(DataField::Number(time as i64 + 3 + FIT_EPOCH)));
+        assert_matches!(get(fields::LAT.name, 1), Some(DataField::LongFloat(v)) if (v - 45.5).abs() < 1e-6);
+        assert_matches!(get(fields::LNG.name, 0), Some(DataField::LongFloat(v)) if v < 0.0 && v > -1e-6);
+        assert_eq!(get(fields::ELEVATION.name, 0), Some(DataField::LongFloat(20.0)));
+        assert_eq!(get(fields::HEART_RATE.name, 0), Some(DataField::Number(140)));
+        assert_eq!(get(fields::CADENCE.name, 0), None);
+        assert_eq!(get(fields::TEMPERATURE.name, 0), Some(DataField::ShortFloat(-20.0)));
+        assert_eq!(get(fields::POWER.name, 0), Some(DataField::Number(250)));
+        assert_eq!(get(fields::POWER.name, 1), None);
+        assert_eq!(get(fields::SEGMENT.name, 0), Some(DataField::Number(0)));
+        assert_eq!(get(fields::SEGMENT.name, 1), Some(DataField::Number(1)));
+    }
+
+    #[test]
+    fn test_read_fit_negative_time_created() {
+        // file_id with time_created declared as a sint32
+        let mut file_id_data = vec![0x00];
+        file_id_data.extend_from_slice(&i32::MIN.wrapping_add(1).to_le_bytes());
+        let bytes = fit_file(&[&[0x40, 0, 0, 0, 0, 1, 4, 4, 0x85], &file_id_data]);
+        assert_eq!(read_fit(&bytes).unwrap().created_at, None);
+    }
+
+    #[test]
+    fn test_read_fit_errors() {
+        let bytes = fit_file(&[&[0x00, 1, 2]]);
+        assert_matches!(read_fit(&bytes), Err(Error::UndefinedMessage{local: 0, offset: 14}));
+
+        let mut bytes = fit_file(&[&[0x40, 0, 0, 0, 0, 0]]);
+        assert!(read_fit(&bytes).is_ok());
+        bytes[15] ^= 0x01;
+        assert_matches!(read_fit(&bytes), Err(Error::InvalidCrc{..}));
+        assert_matches!(read_fit(&bytes[..bytes.len() - 1]), Err(Error::Truncated{..}));
+        bytes[8] = b'X';
+        assert_matches!(read_fit(&bytes), Err(Error::NotFit{}));
+        assert_eq!(read_fit(b"").unwrap().track_points.len(), 0);
+    }
+}
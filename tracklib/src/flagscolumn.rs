@@ -1,8 +1,10 @@
 use std::io::{Write};
 use std::cmp;
+use std::ops::{Range};
 use snafu::{Snafu, ResultExt};
 use std::collections::btree_map::{self, BTreeMap};
-use crate::utils::{write};
+use crate::utils::{write, unsigned_leb128_len};
+use crate::error::{ErrorKind};
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -10,21 +12,117 @@ pub enum Error {
     WriteFlagsColumn{source: std::io::Error},
 }
 
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::WriteFlagsColumn{..} => ErrorKind::Io,
+        }
+    }
+}
+
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+// Removes the entries for `rows` from a map keyed by row index, and moves the
+// entries after them up to fill the gap
+pub(crate) fn delete_rows<T>(m: &mut BTreeMap<usize, T>, rows: &Range<usize>) {
+    let tail = m.split_off(&rows.start);
+    m.extend(tail.into_iter()
+             .filter(|(index, _)| *index >= rows.end)
+             .map(|(index, v)| (index - rows.len(), v)));
+}
+
+
+/// The most columns a section can have. Sections with more than 255 write
+/// their types table with leb128 counts, see `WIDE_TYPES_TABLE`.
+pub(crate) const MAX_FIELDS: usize = 4096;
+
+/// Which columns have a value in a row, one bit per column in types table
+/// order. Written as the fewest whole bytes that hold a bit for every
+/// column, least significant first. The first 256 columns are held inline
+/// so that most rows don't allocate.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub(crate) struct Flags {
+    inline: [u64; 4],
+    // columns from 256 on, with no trailing zero words so that equal flags
+    // compare equal
+    rest: Vec<u64>,
+}
+
+impl Flags {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.inline == [0; 4] && self.rest.is_empty()
+    }
+
+    fn word(&self, word: usize) -> u64 {
+        match word.checked_sub(4) {
+            None => self.inline[word],
+            Some(word) => self.rest.get(word).copied().unwrap_or(0),
+        }
+    }
+
+    fn word_mut(&mut self, word: usize) -> &mut u64 {
+        match word.checked_sub(4) {
+            None => &mut self.inline[word],
+            Some(word) => {
+                if word >= self.rest.len() {
+                    self.rest.resize(word + 1, 0);
+                }
+                &mut self.rest[word]
+            }
+        }
+    }
+
+    fn trim(&mut self) {
+        while self.rest.last() == Some(&0) {
+            self.rest.pop();
+        }
+    }
+
+    pub(crate) fn is_set(&self, shift: usize) -> bool {
+        self.word(shift / 64) & (1 << (shift % 64)) > 0
+    }
+
+    pub(crate) fn set(&mut self, shift: usize) {
+        *self.word_mut(shift / 64) |= 1 << (shift % 64);
+    }
+
+    pub(crate) fn unset(&mut self, shift: usize) {
+        if self.is_set(shift) {
+            *self.word_mut(shift / 64) &= !(1 << (shift % 64));
+            self.trim();
+        }
+    }
+
+    pub(crate) fn from_le_bytes(bytes: &[u8]) -> Self {
+        let mut flags = Flags::default();
+        for (i, byte) in bytes.iter().enumerate().filter(|(_, byte)| **byte != 0) {
+            *flags.word_mut(i / 8) |= u64::from(*byte) << (8 * (i % 8));
+        }
+        flags
+    }
 
-#[derive(Debug)]
+    // Fills `bytes` with the first `bytes.len() * 8` flags
+    pub(crate) fn to_le_bytes(&self, bytes: &mut [u8]) {
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = (self.word(i / 8) >> (8 * (i % 8))) as u8;
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub(crate) struct FlagsColumn {
     pub(crate) fields: BTreeMap<String, usize>,
-    pub(crate) data: BTreeMap<usize, u64>,
-    pub(crate) max: usize,
+    pub(crate) data: BTreeMap<usize, Flags>,
+    // the number of rows, which the flags can't tell when the last ones, or
+    // all of them, are empty
+    pub(crate) rows: usize,
 }
 
 impl FlagsColumn {
     pub(crate) fn new() -> Self {
         FlagsColumn{fields: BTreeMap::new(),
                     data: BTreeMap::new(),
-                    max: 0}
+                    rows: 0}
     }
 
     pub(crate) fn fields(&self) -> Vec<&String> {
@@ -39,19 +137,12 @@ impl FlagsColumn {
     }
 
     pub(crate) fn len(&self) -> usize {
-        if self.max > 0 {
-            self.max + 1
-        } else {
-            if self.data.len() > 0 {
-                1
-            } else {
-                0
-            }
-        }
+        self.rows
     }
 
+    // The index of the last row, or 0 with no rows
     pub(crate) fn max(&self) -> usize {
-        self.max
+        self.rows.saturating_sub(1)
     }
 
     pub(crate) fn set(&mut self, index: usize, name: &str) {
@@ -68,20 +159,20 @@ impl FlagsColumn {
 
         match self.data.entry(index) {
             btree_map::Entry::Vacant(entry) => {
-                entry.insert(1 << shift);
+                entry.insert(Flags::default()).set(shift);
             }
             btree_map::Entry::Occupied(entry) => {
-                *entry.into_mut() |= 1 << shift;
+                entry.into_mut().set(shift);
             }
         }
 
-        self.max = cmp::max(self.max, index);
+        self.rows = cmp::max(self.rows, index + 1);
     }
 
     pub fn is_present(&self, index: usize, name: &str) -> bool {
         if let Some(f) = self.data.get(&index) {
             if let Some(shift) = self.fields.get(name) {
-                (*f & (1 << *shift)) > 0
+                f.is_set(*shift)
             } else {
                 // no fields present with this name
                 false
@@ -94,18 +185,103 @@ impl FlagsColumn {
 
     }
 
+    pub(crate) fn unset(&mut self, index: usize, name: &str) {
+        if let (Some(shift), Some(f)) = (self.fields.get(name), self.data.get_mut(&index)) {
+            f.unset(*shift);
+            if f.is_empty() {
+                self.data.remove(&index);
+            }
+        }
+    }
+
+    // Drops the rows in `rows`, which must be within `len`, moving the ones
+    // after them up
+    pub(crate) fn delete_rows(&mut self, rows: &Range<usize>) {
+        delete_rows(&mut self.data, rows);
+        self.rows -= rows.len();
+    }
+
+    // Moves a field's flags to a new name, which mustn't be in use
+    pub(crate) fn rename_field(&mut self, from: &str, to: &str) {
+        if let Some(shift) = self.fields.remove(from) {
+            self.fields.insert(to.into(), shift);
+        }
+    }
+
+    // Drops the named fields, renumbering the ones that are left
+    pub(crate) fn remove_fields(&mut self, names: &[&str]) {
+        let mut remaining = self.fields
+            .iter()
+            .filter(|(name, _shift)| !names.contains(&name.as_str()))
+            .map(|(name, shift)| (name.clone(), *shift))
+            .collect::<Vec<(String, usize)>>();
+        remaining.sort_by_key(|(_name, shift)| *shift);
+
+        let mut fields = BTreeMap::new();
+        let mut data = BTreeMap::new();
+        for (new_shift, (name, old_shift)) in remaining.into_iter().enumerate() {
+            for (index, f) in self.data.iter() {
+                if f.is_set(old_shift) {
+                    data.entry(*index).or_insert_with(Flags::default).set(new_shift);
+                }
+            }
+            fields.insert(name, new_shift);
+        }
+
+        self.fields = fields;
+        self.data = data;
+    }
+
     fn bytes_required(&self) -> usize {
         (self.fields.len() + 7) / 8
     }
 
+    // The number of bytes write will write.
+    pub(crate) fn encoded_size(&self) -> usize {
+        self.bytes_required() * self.rows
+    }
+
+    // Runs of rows with the same flags, as (count, flags), covering every row
+    fn runs(&self) -> Vec<(u64, Flags)> {
+        let mut runs: Vec<(u64, Flags)> = Vec::new();
+        for i in 0..self.rows {
+            let f = self.data.get(&i).cloned().unwrap_or_default();
+            match runs.last_mut() {
+                Some((count, last)) if *last == f => *count += 1,
+                _ => runs.push((1, f)),
+            }
+        }
+        runs
+    }
+
+    // The number of bytes write_run_length will write.
+    pub(crate) fn run_length_encoded_size(&self) -> usize {
+        let width = self.bytes_required();
+        self.runs().iter().map(|(count, _)| unsigned_leb128_len(*count) + width).sum()
+    }
+
+    // Writes each run as its row count followed by the flags of those rows
+    pub(crate) fn write_run_length<W: Write>(&self, out: &mut W) -> Result<usize> {
+        let mut written = 0;
+
+        let mut bytes = vec![0; self.bytes_required()];
+        for (count, f) in self.runs() {
+            written += leb128::write::unsigned(out, count).context(WriteFlagsColumn{})?;
+            f.to_le_bytes(&mut bytes);
+            written += write(out, &bytes).context(WriteFlagsColumn{})?;
+        }
+
+        Ok(written)
+    }
+
     pub(crate) fn write<W: Write>(&self, out: &mut W) -> Result<usize> {
         let mut written = 0;
 
-        let width = self.bytes_required();
-        for i in 0..=self.max {
-            let f = *self.data.get(&i).unwrap_or(&0);
-            let bytes = &f.to_le_bytes()[..width];
-            written += write(out, bytes).context(WriteFlagsColumn{})?;
+        let mut bytes = vec![0; self.bytes_required()];
+        let empty = Flags::default();
+        for i in 0..self.rows {
+            self.data.get(&i).unwrap_or(&empty).to_le_bytes(&mut bytes);
+            written += write(out, &bytes).context(WriteFlagsColumn{})?;
         }
 
         Ok(written)
@@ -132,6 +308,62 @@ mod tests {
         assert_eq!(c.fields(), vec!["a"]);
     }
 
+    #[test]
+    fn test_delete_rows() {
+        let mut c = FlagsColumn::new();
+        for i in 0..6 {
+            c.set(i, "a");
+        }
+        c.set(4, "b");
+        c.unset(0, "a");
+        c.unset(0, "b");
+
+        c.delete_rows(&(1..3));
+        assert_eq!(c.len(), 4);
+        assert!(!c.is_present(0, "a"));
+        assert!(c.is_present(1, "a"));
+        assert!(c.is_present(2, "b"));
+        assert!(!c.is_present(3, "b"));
+        assert_eq!(c.data.len(), 3);
+        // down to one empty row
+        c.delete_rows(&(1..4));
+        assert_eq!(c.len(), 1);
+        assert!(c.data.is_empty());
+        let mut buf = vec![];
+        assert_eq!(c.write(&mut buf).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_unset_only_flag() {
+        let mut c = FlagsColumn::new();
+        c.set(0, "a");
+        c.unset(0, "a");
+        assert_eq!(c.len(), 1);
+        assert_eq!(c.max(), 0);
+        assert_eq!(c.encoded_size(), 1);
+    }
+
+    #[test]
+    fn test_remove_fields() {
+        let mut c = FlagsColumn::new();
+        c.set(0, "a");
+        c.set(0, "b");
+        c.set(1, "b");
+        c.set(1, "c");
+        c.set(2, "a");
+
+        c.remove_fields(&["a"]);
+        assert_eq!(c.fields(), vec!["b", "c"]);
+        assert!(c.is_present(0, "b"));
+        assert!(c.is_present(1, "c"));
+        assert!(!c.is_present(2, "a"));
+        assert_eq!(c.len(), 3);
+
+        let mut buf = vec![];
+        assert!(c.write(&mut buf).is_ok());
+        assert_eq!(buf, &[0x01, 0x03, 0x00]);
+    }
+
     #[test]
     fn test_multiple_flags() {
         let mut c = FlagsColumn::new();
@@ -206,6 +438,56 @@ mod tests {
         assert_eq!(c.fields(), vec!["a","b","c","d","e","f","g","h","i","j"]);
     }
 
+    #[test]
+    fn test_wide_flags() {
+        let mut c = FlagsColumn::new();
+        for f in 0..255 {
+            c.set(0, &f.to_string());
+        }
+        c.set(1, "0");
+        c.set(1, "64");
+        c.set(1, "254");
+        c.unset(0, "200");
+
+        let mut buf = vec![];
+        assert_eq!(c.write(&mut buf).unwrap(), 64);
+        let mut row = [0xff; 32];
+        row[25] = 0xfe; // 200
+        row[31] = 0x7f;
+        assert_eq!(buf[..32], row);
+        let mut row = [0x00; 32];
+        row[0] = 0x01;
+        row[8] = 0x01;
+        row[31] = 0x40;
+        assert_eq!(buf[32..], row);
+        assert_eq!(Flags::from_le_bytes(&buf[32..]), c.data[&1]);
+        assert!(c.is_present(1, "254") && !c.is_present(0, "200"));
+    }
+
+    #[test]
+    fn test_flags_past_256() {
+        let mut c = FlagsColumn::new();
+        for f in 0..300 {
+            c.set(0, &f.to_string());
+        }
+        c.set(1, "299");
+        c.unset(0, "299");
+
+        let mut buf = vec![];
+        assert_eq!(c.write(&mut buf).unwrap(), 76);
+        let mut row = [0xff; 38];
+        row[37] = 0x07;
+        assert_eq!(buf[..38], row);
+        let mut row = [0x00; 38];
+        row[37] = 0x08;
+        assert_eq!(buf[38..], row);
+        assert_eq!(Flags::from_le_bytes(&buf[38..]), c.data[&1]);
+
+        c.unset(1, "299");
+        assert!(!c.data.contains_key(&1));
+        assert_eq!(Flags::from_le_bytes(&[0; 38]), Flags::default());
+    }
+
     #[test]
     fn test_insert_order_matters() {
         // Insert order #1
@@ -240,4 +522,23 @@ mod tests {
         assert_eq!(written.unwrap(), expected.len());
         assert_eq!(c.fields(), vec!["c", "b", "a"]);
     }
+
+    #[test]
+    fn test_run_length() {
+        let mut c = FlagsColumn::new();
+        for i in 0..200 {
+            c.set(i, "a");
+            c.set(i, "b");
+        }
+        c.unset(150, "b");
+
+        let mut buf = vec![];
+        let written = c.write_run_length(&mut buf);
+        assert!(written.is_ok());
+        let expected = &[0x96, 0x01, 0x03, 0x01, 0x01, 0x31, 0x03];
+        assert_eq!(buf, expected);
+        assert_eq!(written.unwrap(), expected.len());
+        assert_eq!(c.run_length_encoded_size(), expected.len());
+        assert_eq!(c.encoded_size(), 200);
+    }
 }
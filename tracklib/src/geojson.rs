@@ -0,0 +1,231 @@
+use serde::ser::{Serialize, Serializer, SerializeMap, SerializeSeq};
+use crate::fields::{self};
+use crate::rwtfile::{DataField};
+use crate::section::{Section};
+
+/// How `GeoJson` lays out the columns other than latitude and longitude.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeoJsonProperties {
+    /// A single LineString Feature whose properties hold an array per
+    /// column, lined up with the coordinates, with null where a point has no
+    /// value.
+    Arrays,
+    /// A FeatureCollection of the LineString, without properties, followed
+    /// by a Point Feature per point holding its values.
+    Points,
+}
+
+/// Serializes a section as GeoJSON, e.g. with serde_json, for web maps. The
+/// LineString is made of the points with both `fields::LAT` and
+/// `fields::LNG`; points without a position are left out.
+pub struct GeoJson<'a> {
+    section: &'a Section,
+    properties: GeoJsonProperties,
+    columns: Option<Vec<String>>,
+}
+
+impl<'a> GeoJson<'a> {
+    pub fn new(section: &'a Section, properties: GeoJsonProperties) -> Self {
+        GeoJson{section, properties, columns: None}
+    }
+
+    /// Only includes these columns in the properties, rather than every
+    /// column.
+    pub fn with_columns(mut self, columns: &[&str]) -> Self {
+        self.columns = Some(columns.iter().map(|name| name.to_string()).collect());
+        self
+    }
+
+    fn position(&self, index: usize) -> Option<(f64, f64)> {
+        let get = |name| self.section.columns().get(name).and_then(|column| column.get(index)).and_then(|v| v.as_f64());
+        Some((get(fields::LNG.name)?, get(fields::LAT.name)?))
+    }
+
+    fn positions(&self) -> Vec<(usize, (f64, f64))> {
+        (0..self.section.len()).filter_map(|i| self.position(i).map(|p| (i, p))).collect()
+    }
+
+    fn property_names(&self) -> Vec<&str> {
+        self.section.columns().keys()
+            .map(|name| name.as_str())
+            .filter(|name| *name != fields::LAT.name && *name != fields::LNG.name)
+            .filter(|name| self.columns.as_ref().is_none_or(|columns| columns.iter().any(|c| c == name)))
+            .collect()
+    }
+
+    fn value(&self, name: &str, index: usize) -> Option<DataField> {
+        self.section.columns().get(name).and_then(|column| column.get(index))
+    }
+}
+
+struct Coordinates<'a>(&'a [(usize, (f64, f64))]);
+
+impl Serialize for Coordinates<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.0.len()))?;
+        for (_, (lng, lat)) in self.0 {
+            seq.serialize_element(&[lng, lat])?;
+        }
+        seq.end()
+    }
+}
+
+struct Geometry<'a, T> {
+    geometry_type: &'static str,
+    coordinates: &'a T,
+}
+
+impl<T: Serialize> Serialize for Geometry<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("type", self.geometry_type)?;
+        map.serialize_entry("coordinates", self.coordinates)?;
+        map.end()
+    }
+}
+
+struct Feature<G, P> {
+    geometry: G,
+    properties: P,
+}
+
+impl<G: Serialize, P: Serialize> Serialize for Feature<G, P> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(3))?;
+        map.serialize_entry("type", "Feature")?;
+        map.serialize_entry("geometry", &self.geometry)?;
+        map.serialize_entry("properties", &self.properties)?;
+        map.end()
+    }
+}
+
+// Each column's values for the positioned points, null where missing
+struct PropertyArrays<'a> {
+    geojson: &'a GeoJson<'a>,
+    positions: &'a [(usize, (f64, f64))],
+}
+
+impl Serialize for PropertyArrays<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let names = self.geojson.property_names();
+        let mut map = serializer.serialize_map(Some(names.len()))?;
+        for name in names {
+            let values = self.positions.iter().map(|(i, _)| self.geojson.value(name, *i)).collect::<Vec<_>>();
+            map.serialize_entry(name, &values)?;
+        }
+        map.end()
+    }
+}
+
+// One point's values, leaving out the columns it has no value for
+struct PointProperties<'a> {
+    geojson: &'a GeoJson<'a>,
+    index: usize,
+}
+
+impl Serialize for PointProperties<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        for name in self.geojson.property_names() {
+            if let Some(v) = self.geojson.value(name, self.index) {
+                map.serialize_entry(name, &v)?;
+            }
+        }
+        map.end()
+    }
+}
+
+struct EmptyProperties;
+
+impl Serialize for EmptyProperties {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_map(Some(0))?.end()
+    }
+}
+
+struct PointFeatures<'a> {
+    geojson: &'a GeoJson<'a>,
+    positions: &'a [(usize, (f64, f64))],
+}
+
+impl Serialize for PointFeatures<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.positions.len() + 1))?;
+        seq.serialize_element(&Feature{geometry: Geometry{geometry_type: "LineString", coordinates: &Coordinates(self.positions)},
+                                       properties: EmptyProperties})?;
+        for (index, (lng, lat)) in self.positions {
+            seq.serialize_element(&Feature{geometry: Geometry{geometry_type: "Point", coordinates: &[lng, lat]},
+                                           properties: PointProperties{geojson: self.geojson, index: *index}})?;
+        }
+        seq.end()
+    }
+}
+
+impl Serialize for GeoJson<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let positions = self.positions();
+        let line = Geometry{geometry_type: "LineString", coordinates: &Coordinates(&positions)};
+        match self.properties {
+            GeoJsonProperties::Arrays => {
+                Feature{geometry: line, properties: PropertyArrays{geojson: self, positions: &positions}}.serialize(serializer)
+            }
+            GeoJsonProperties::Points => {
+                let mut map = serializer.serialize_map(Some(2))?;
+                map.serialize_entry("type", "FeatureCollection")?;
+                map.serialize_entry("features", &PointFeatures{geojson: self, positions: &positions})?;
+                map.end()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::section::{SectionType};
+    use serde_json::json;
+
+    fn section() -> Section {
+        let mut s = Section::new(SectionType::TrackPoints);
+        for i in 0..3 {
+            if i != 1 {
+                assert!(s.add(i, fields::LAT.name, DataField::LongFloat(45.0 + i as f64)).is_ok());
+                assert!(s.add(i, fields::LNG.name, DataField::LongFloat(-122.0)).is_ok());
+            }
+            assert!(s.add(i, fields::TIME.name, 1000 + i as i64).is_ok());
+        }
+        assert!(s.add(2, fields::HEART_RATE.name, 140).is_ok());
+        s
+    }
+
+    #[test]
+    fn test_geojson_arrays() {
+        let s = section();
+        assert_eq!(serde_json::to_value(GeoJson::new(&s, GeoJsonProperties::Arrays)).unwrap(), json!({
+            "type": "Feature",
+            "geometry": {"type": "LineString", "coordinates": [[-122.0, 45.0], [-122.0, 47.0]]},
+            "properties": {"h": [null, 140], "t": [1000, 1002]},
+        }));
+        assert_eq!(serde_json::to_value(GeoJson::new(&s, GeoJsonProperties::Arrays).with_columns(&["t"])).unwrap()["properties"],
+                   json!({"t": [1000, 1002]}));
+    }
+
+    #[test]
+    fn test_geojson_points() {
+        let s = section();
+        assert_eq!(serde_json::to_value(GeoJson::new(&s, GeoJsonProperties::Points)).unwrap(), json!({
+            "type": "FeatureCollection",
+            "features": [
+                {"type": "Feature",
+                 "geometry": {"type": "LineString", "coordinates": [[-122.0, 45.0], [-122.0, 47.0]]},
+                 "properties": {}},
+                {"type": "Feature",
+                 "geometry": {"type": "Point", "coordinates": [-122.0, 45.0]},
+                 "properties": {"t": 1000}},
+                {"type": "Feature",
+                 "geometry": {"type": "Point", "coordinates": [-122.0, 47.0]},
+                 "properties": {"h": 140, "t": 1002}},
+            ],
+        }));
+    }
+}
@@ -0,0 +1,369 @@
+//! Reads the track points of a GPX 1.1 file into a section with the
+//! standard fields, see `fields`. This only needs the small part of XML that
+//! GPX files use, so it's read with a scanner of its own rather than a
+//! dependency.
+use snafu::{Snafu, ResultExt};
+use crate::error::{ErrorKind};
+use crate::fields::{self, Field};
+use crate::rwtfile::{DataField, RWTFile, Error as RWTFileError};
+use crate::section::{Section, SectionType};
+use crate::transform::{strip_columns};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Malformed XML at byte {}", offset))]
+    Syntax{offset: usize},
+    #[snafu(display("Track point {} has no {}", point, name))]
+    MissingCoordinate{point: usize, name: String},
+    #[snafu(display("Track point {} has an invalid {}: {:?}", point, name, value))]
+    InvalidValue{point: usize, name: String, value: String},
+    #[snafu(display("Couldn't add track point {}: {}", point, source))]
+    AddPoint{point: usize, source: RWTFileError},
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Syntax{..} |
+            Error::MissingCoordinate{..} |
+            Error::InvalidValue{..} => ErrorKind::Corruption,
+            Error::AddPoint{source, ..} => source.kind(),
+        }
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+// The parts of a document the scanner hands out
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    // a local name, without its namespace prefix, and the raw attributes
+    Start{name: &'a str, attributes: &'a str, empty: bool},
+    End{name: &'a str},
+    Text(String),
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+// Replaces the predefined entities and character references
+fn unescape(s: &str, offset: usize) -> Result<String> {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(at) = rest.find('&') {
+        out.push_str(&rest[..at]);
+        let end = rest[at..].find(';').ok_or(Error::Syntax{offset})?;
+        let entity = &rest[at + 1..at + end];
+        let c = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16).ok().and_then(char::from_u32),
+            _ if entity.starts_with('#') => entity[1..].parse().ok().and_then(char::from_u32),
+            _ => None,
+        };
+        out.push(c.ok_or(Error::Syntax{offset})?);
+        rest = &rest[at + end + 1..];
+    }
+    out.push_str(rest);
+
+    Ok(out)
+}
+
+struct Scanner<'a> {
+    xml: &'a str,
+    at: usize,
+}
+
+impl<'a> Scanner<'a> {
+    // Skips past `end`, failing if it never comes
+    fn skip_past(&mut self, end: &str) -> Result<&'a str> {
+        let start = self.at;
+        match self.xml[start..].find(end) {
+            Some(len) => {
+                self.at = start + len + end.len();
+                Ok(&self.xml[start..start + len])
+            }
+            None => Syntax{offset: start}.fail(),
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token<'a>>> {
+        loop {
+            let rest = &self.xml[self.at..];
+            if rest.is_empty() {
+                return Ok(None);
+            }
+
+            let offset = self.at;
+            if !rest.starts_with('<') {
+                let text = self.skip_past("<").map(|text| text.len()).unwrap_or(rest.len());
+                self.at = offset + text;
+                return Ok(Some(Token::Text(unescape(&rest[..text], offset)?)));
+            } else if rest.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if rest.starts_with("<![CDATA[") {
+                self.at += "<![CDATA[".len();
+                return Ok(Some(Token::Text(self.skip_past("]]>")?.to_string())));
+            } else if rest.starts_with("<!") {
+                self.skip_past(">")?;
+            } else if rest.starts_with("</") {
+                self.at += 2;
+                let name = self.skip_past(">")?.trim();
+                return Ok(Some(Token::End{name: local_name(name)}));
+            } else {
+                self.at += 1;
+                let tag = self.skip_past(">")?;
+                let (tag, empty) = match tag.strip_suffix('/') {
+                    Some(tag) => (tag, true),
+                    None => (tag, false),
+                };
+                let name_len = tag.find(|c: char| c.is_whitespace()).unwrap_or(tag.len());
+                if name_len == 0 {
+                    return Syntax{offset}.fail();
+                }
+                return Ok(Some(Token::Start{name: local_name(&tag[..name_len]),
+                                            attributes: &tag[name_len..],
+                                            empty}));
+            }
+        }
+    }
+}
+
+// The value of the attribute called `name`, in either kind of quotes
+fn attribute(attributes: &str, name: &str, offset: usize) -> Result<Option<String>> {
+    let mut rest = attributes;
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].trim();
+        let value = rest[eq + 1..].trim_start();
+        let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'').ok_or(Error::Syntax{offset})?;
+        let end = value[1..].find(quote).ok_or(Error::Syntax{offset})?;
+        if local_name(key) == name {
+            return Ok(Some(unescape(&value[1..end + 1], offset)?));
+        }
+        rest = &value[end + 2..];
+    }
+
+    Ok(None)
+}
+
+// Days from 1970-01-01 to a date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Seconds since the unix epoch for an xsd:dateTime such as
+/// "2021-03-04T05:06:07.5Z", with any fraction of a second dropped. A time
+/// without a zone is taken to be UTC.
+fn parse_time(s: &str) -> Option<i64> {
+    let number = |range: std::ops::Range<usize>| s.get(range).filter(|v| v.bytes().all(|b| b.is_ascii_digit()))?.parse::<i64>().ok();
+    if !s.is_ascii() || s.len() < 19 || &s[4..5] != "-" || &s[7..8] != "-" || &s[10..11] != "T" || &s[13..14] != ":" || &s[16..17] != ":" {
+        return None;
+    }
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let mut rest = &s[19..];
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction.bytes().take_while(|b| b.is_ascii_digit()).count();
+        if digits == 0 {
+            return None;
+        }
+        rest = &fraction[digits..];
+    }
+    let offset = match rest {
+        "" | "Z" => 0,
+        _ if rest.len() == 6 && &rest[3..4] == ":" => {
+            let hours = rest[1..3].parse::<i64>().ok()?;
+            let minutes = rest[4..6].parse::<i64>().ok()?;
+            match &rest[..1] {
+                "+" => hours * 3600 + minutes * 60,
+                "-" => -(hours * 3600 + minutes * 60),
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+
+    Some(days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset)
+}
+
+type Parse = fn(&str) -> Option<DataField>;
+
+// The field an element inside a track point is stored in, and how to turn
+// its text into a value. Extensions are matched by local name, which covers
+// the Garmin TrackPointExtension and the common power extensions.
+fn point_field(name: &str) -> Option<(Field, Parse)> {
+    fn float(s: &str) -> Option<f64> {
+        s.parse::<f64>().ok().filter(|v| v.is_finite())
+    }
+    fn number(s: &str) -> Option<DataField> {
+        float(s).map(|v| DataField::Number(v.round() as i64))
+    }
+
+    match name {
+        "ele" => Some((fields::ELEVATION, |s| float(s).map(DataField::LongFloat))),
+        "time" => Some((fields::TIME, |s| parse_time(s).map(DataField::Number))),
+        "hr" | "heartrate" => Some((fields::HEART_RATE, number)),
+        "cad" | "cadence" => Some((fields::CADENCE, number)),
+        "power" | "watts" => Some((fields::POWER, number)),
+        "atemp" | "temp" | "temperature" => Some((fields::TEMPERATURE, |s| float(s).map(DataField::ShortFloat))),
+        // meters per second
+        "speed" => Some((fields::SPEED, |s| float(s).map(|v| DataField::ShortFloat(v * 3.6)))),
+        _ => None,
+    }
+}
+
+/// The `<trkpt>`s of `gpx`, in order, as a track points section. Each
+/// point's `lat` and `lon`, `<ele>` and `<time>` are kept, along with heart
+/// rate, cadence, power, temperature and speed from its `<extensions>`.
+/// Points in different `<trkseg>`s get different `fields::SEGMENT` ids,
+/// which are left out when there's only one segment. Anything else in the
+/// file is skipped.
+pub fn read_gpx(gpx: &str) -> Result<Section> {
+    let mut scanner = Scanner{xml: gpx, at: 0};
+    let mut section = Section::new(SectionType::TrackPoints);
+    let mut point = 0;
+    let mut segment = None;
+    let mut in_point = false;
+    let mut text = String::new();
+
+    while let Some(token) = scanner.next_token()? {
+        match token {
+            Token::Start{name: "trkseg", ..} => segment = Some(segment.map_or(0, |segment| segment + 1)),
+            Token::Start{name: "trkpt", attributes, empty} => {
+                let offset = scanner.at;
+                for (field, name) in &[(fields::LAT, "lat"), (fields::LNG, "lon")] {
+                    let value = attribute(attributes, name, offset)?.ok_or_else(|| Error::MissingCoordinate{point, name: name.to_string()})?;
+                    let v = value.trim().parse::<f64>().map_err(|_| Error::InvalidValue{point, name: name.to_string(), value: value.clone()})?;
+                    RWTFile::add_point(&mut section, point, field.name, DataField::LongFloat(v)).context(AddPoint{point})?;
+                }
+                if let Some(segment) = segment {
+                    RWTFile::add_point(&mut section, point, fields::SEGMENT.name, segment).context(AddPoint{point})?;
+                }
+                if empty {
+                    point += 1;
+                } else {
+                    in_point = true;
+                }
+            }
+            Token::Start{..} => text.clear(),
+            Token::Text(t) => text = t,
+            Token::End{name: "trkpt"} if in_point => {
+                in_point = false;
+                point += 1;
+            }
+            Token::End{name} if in_point => {
+                if let Some((field, parse)) = point_field(name) {
+                    let value = text.trim();
+                    let v = parse(value).ok_or_else(|| Error::InvalidValue{point, name: name.to_string(), value: value.to_string()})?;
+                    // the first of a repeated element wins
+                    if section.columns().get(field.name).and_then(|column| column.get(point)).is_none() {
+                        RWTFile::add_point(&mut section, point, field.name, v).context(AddPoint{point})?;
+                    }
+                }
+                text.clear();
+            }
+            Token::End{..} => text.clear(),
+        }
+    }
+    section.extend_to(point);
+
+    if segment.unwrap_or(0) == 0 {
+        section = strip_columns(&section, &[fields::SEGMENT.name]);
+    }
+
+    Ok(section)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    const GPX: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx version="1.1" creator="test &amp; co" xmlns="http://www.topografix.com/GPX/1/1"
+     xmlns:gpxtpx="http://www.garmin.com/xmlschemas/TrackPointExtension/v1">
+  <metadata><time>2020-01-01T00:00:00Z</time></metadata>
+  <!-- a <trkpt> in a comment -->
+  <trk>
+    <name><![CDATA[Morning <ride>]]></name>
+    <trkseg>
+      <trkpt lat="45.5" lon='-122.6'>
+        <ele>10.5</ele>
+        <time>2021-03-04T05:06:07Z</time>
+        <extensions>
+          <gpxtpx:TrackPointExtension>
+            <gpxtpx:hr>120</gpxtpx:hr>
+            <gpxtpx:cad>85</gpxtpx:cad>
+            <gpxtpx:atemp>21.5</gpxtpx:atemp>
+          </gpxtpx:TrackPointExtension>
+          <power>250</power>
+        </extensions>
+      </trkpt>
+      <trkpt lat="45.5001" lon="-122.6001"/>
+    </trkseg>
+    <trkseg>
+      <trkpt lat="45.5002" lon="-122.6002"><time>2021-03-04T07:06:09.5+02:00</time></trkpt>
+    </trkseg>
+  </trk>
+</gpx>"#;
+
+    #[test]
+    fn test_read_gpx() {
+        let s = read_gpx(GPX).unwrap();
+        assert_eq!(s.len(), 3);
+        let get = |field: Field, i| s.columns().get(field.name).and_then(|column| column.get(i));
+        assert_eq!(get(fields::LAT, 0), Some(DataField::LongFloat(45.5)));
+        assert_eq!(get(fields::LNG, 2), Some(DataField::LongFloat(-122.6002)));
+        assert_eq!(get(fields::ELEVATION, 0), Some(DataField::LongFloat(10.5)));
+        assert_eq!(get(fields::TIME, 0), Some(DataField::Number(1614834367)));
+        assert_eq!(get(fields::TIME, 1), None);
+        assert_eq!(get(fields::TIME, 2), Some(DataField::Number(1614834369)));
+        assert_eq!(get(fields::HEART_RATE, 0), Some(DataField::Number(120)));
+        assert_eq!(get(fields::CADENCE, 0), Some(DataField::Number(85)));
+        assert_eq!(get(fields::POWER, 0), Some(DataField::Number(250)));
+        assert_eq!(get(fields::TEMPERATURE, 0), Some(DataField::ShortFloat(21.5)));
+        assert_eq!((0..3).map(|i| get(fields::SEGMENT, i)).collect::<Vec<_>>(),
+                   vec![Some(DataField::Number(0)), Some(DataField::Number(0)), Some(DataField::Number(1))]);
+
+        // one segment
+        let s = read_gpx(r#"<gpx><trk><trkseg><trkpt lat="1" lon="2"/></trkseg></trk></gpx>"#).unwrap();
+        assert_eq!(s.len(), 1);
+        assert!(s.columns().get(fields::SEGMENT.name).is_none());
+    }
+
+    #[test]
+    fn test_read_gpx_errors() {
+        assert_matches!(read_gpx(r#"<gpx><trkpt lat="1"/></gpx>"#), Err(Error::MissingCoordinate{point: 0, ..}));
+        assert_matches!(read_gpx(r#"<gpx><trkpt lat="1" lon="x"/></gpx>"#), Err(Error::InvalidValue{point: 0, ..}));
+        assert_matches!(read_gpx(r#"<gpx><trkpt lat="1" lon="2"><time>yesterday</time></trkpt></gpx>"#),
+                        Err(Error::InvalidValue{point: 0, ref name, ..}) if name == "time");
+        assert_matches!(read_gpx(r#"<gpx><trkpt lat="1" lon="2""#), Err(Error::Syntax{..}));
+        assert_eq!(read_gpx("<gpx>&bogus;</gpx>").unwrap_err().kind(), ErrorKind::Corruption);
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_time("2000-03-01T00:00:00"), Some(951868800));
+        assert_eq!(parse_time("1969-12-31T23:59:59.999Z"), Some(-1));
+        assert_eq!(parse_time("2021-03-04T05:06:07-01:30"), Some(1614834367 + 5400));
+        assert_eq!(parse_time("2021-13-04T05:06:07Z"), None);
+        assert_eq!(parse_time("2021-03-04 05:06:07Z"), None);
+        assert_eq!(parse_time("2021-03-04T05:06:07.Z"), None);
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::{BTreeMap};
+use std::convert::{TryFrom};
+use std::ops::{Range};
+use crate::fields::{self, Field};
+use crate::section::{Column, Section};
+use crate::trackpoint::{TrackPointReader};
+
+/// The standard fields of a single lap, such as a FIT lap message. See
+/// `RWTFile::add_standard_lap` to write one.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Lap {
+    /// The first track points row of this lap.
+    pub start_row: Option<usize>,
+    /// The track points row after the last one of this lap.
+    pub end_row: Option<usize>,
+    /// Seconds since the unix epoch.
+    pub start_time: Option<i64>,
+    /// Seconds since the unix epoch.
+    pub end_time: Option<i64>,
+    /// Meters covered during this lap.
+    pub distance: Option<f64>,
+    /// Seconds spent moving during this lap.
+    pub moving_time: Option<i64>,
+    /// Beats per minute.
+    pub avg_heart_rate: Option<i64>,
+    /// Watts.
+    pub avg_power: Option<i64>,
+}
+
+/// Reads the standard fields of a laps section row by row. A field stored
+/// with the wrong column type reads as `None`.
+#[derive(Debug)]
+pub struct LapReader<'a> {
+    len: usize,
+    start_row: Option<&'a BTreeMap<usize, i64>>,
+    end_row: Option<&'a BTreeMap<usize, i64>>,
+    start_time: Option<&'a BTreeMap<usize, i64>>,
+    end_time: Option<&'a BTreeMap<usize, i64>>,
+    distance: Option<&'a BTreeMap<usize, f64>>,
+    moving_time: Option<&'a BTreeMap<usize, i64>>,
+    avg_heart_rate: Option<&'a BTreeMap<usize, i64>>,
+    avg_power: Option<&'a BTreeMap<usize, i64>>,
+}
+
+fn numbers<'a>(section: &'a Section, field: &Field) -> Option<&'a BTreeMap<usize, i64>> {
+    match field.column(section) {
+        Some(Column::Numbers(m)) => Some(m),
+        _ => None,
+    }
+}
+
+fn number(m: Option<&BTreeMap<usize, i64>>, index: usize) -> Option<i64> {
+    m.and_then(|m| m.get(&index)).copied()
+}
+
+fn row(m: Option<&BTreeMap<usize, i64>>, index: usize) -> Option<usize> {
+    number(m, index).and_then(|v| usize::try_from(v).ok())
+}
+
+impl<'a> LapReader<'a> {
+    pub fn new(section: &'a Section) -> Self {
+        LapReader{len: section.len(),
+                  start_row: numbers(section, &fields::START_ROW),
+                  end_row: numbers(section, &fields::END_ROW),
+                  start_time: numbers(section, &fields::TIME),
+                  end_time: numbers(section, &fields::END_TIME),
+                  distance: match fields::DISTANCE.column(section) {
+                      Some(Column::ShortFloat(m)) => Some(m),
+                      _ => None,
+                  },
+                  moving_time: numbers(section, &fields::MOVING_TIME),
+                  avg_heart_rate: numbers(section, &fields::HEART_RATE),
+                  avg_power: numbers(section, &fields::POWER)}
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn lap(&self, index: usize) -> Lap {
+        Lap{start_row: row(self.start_row, index),
+            end_row: row(self.end_row, index),
+            start_time: number(self.start_time, index),
+            end_time: number(self.end_time, index),
+            distance: self.distance.and_then(|m| m.get(&index)).copied(),
+            moving_time: number(self.moving_time, index),
+            avg_heart_rate: number(self.avg_heart_rate, index),
+            avg_power: number(self.avg_power, index)}
+    }
+
+    /// Every row in order.
+    pub fn laps(&self) -> impl Iterator<Item = Lap> + '_ {
+        (0..self.len).map(move |index| self.lap(index))
+    }
+
+    /// The track points rows of lap `index`. Uses the lap's rows if it has
+    /// them, and otherwise its times, for which the track's times must be in
+    /// order. None if the lap has neither.
+    pub fn track_rows(&self, index: usize, track: &TrackPointReader) -> Option<Range<usize>> {
+        let lap = self.lap(index);
+        if let (Some(start), Some(end)) = (lap.start_row, lap.end_row) {
+            let start = start.min(track.len());
+            return Some(start..end.min(track.len()).max(start));
+        }
+
+        let (start_time, end_time) = (lap.start_time?, lap.end_time?);
+        // the first row at or after `time`, skipping rows without one
+        let find = |time: i64| {
+            (0..track.len())
+                .find(|row| track.time(*row).is_some_and(|t| t >= time))
+                .unwrap_or_else(|| track.len())
+        };
+        let start = find(start_time);
+        Some(start..find(end_time).max(start))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rwtfile::{RWTFile};
+
+    #[test]
+    fn test_laps() {
+        let first = Lap{start_row: Some(0),
+                        end_row: Some(3),
+                        start_time: Some(1000),
+                        end_time: Some(1030),
+                        distance: Some(250.5),
+                        moving_time: Some(28),
+                        avg_heart_rate: Some(140),
+                        avg_power: Some(210)};
+        let second = Lap{start_time: Some(1030), end_time: Some(1100), ..Lap::default()};
+        let third = Lap{distance: Some(10.0), ..Lap::default()};
+
+        let mut f = RWTFile::new();
+        for i in 0..6 {
+            assert!(f.add_track_point(i, "t", 1000 + i as i64 * 10).is_ok());
+        }
+        assert!(f.add_standard_lap(0, &first).is_ok());
+        assert!(f.add_standard_lap(1, &second).is_ok());
+        assert!(f.add_standard_lap(2, &third).is_ok());
+
+        let f = RWTFile::read(&f.to_bytes().unwrap()).unwrap();
+        let reader = LapReader::new(f.laps());
+        assert_eq!(reader.laps().collect::<Vec<_>>(), vec![first, second, third]);
+
+        let track = TrackPointReader::new(f.track_points());
+        assert_eq!(reader.track_rows(0, &track), Some(0..3));
+        // by time, running off the end of the track
+        assert_eq!(reader.track_rows(1, &track), Some(3..6));
+        assert_eq!(reader.track_rows(2, &track), None);
+    }
+}
@@ -7,10 +7,53 @@ mod flagscolumn;
 mod surface;
 mod polyline;
 mod simplify;
+mod xorfloat;
+mod bitpack;
+mod map;
+mod trackpoint;
+mod spatial;
+mod coursepoint;
+mod poi;
+mod lap;
+mod event;
+mod transform;
+mod downsample;
+mod rangereader;
+mod source;
+mod stream;
+mod writer;
+mod geojson;
+#[cfg(feature = "gpx")]
+mod gpx;
+#[cfg(feature = "fit")]
+mod fit;
+mod progress;
+mod error;
+pub mod fields;
 
-pub use rwtfile::{RWTFMAGIC, RWTFile, DataField};
+pub use error::{ErrorKind};
+pub use rwtfile::{RWTFMAGIC, RWTF_FILE_VERSION, RWTFile, DataField};
 pub use metadata::{RWTFMetadata, TrackType};
-pub use section::{Column, SectionType, Section};
-pub use decode::{parse_rwtf};
+pub use section::{Column, ColumnsDiff, FloatOverflowPolicy, RawSection, SectionType, Section, SectionSizes, IndexedColumns, Row, TimestampUnit, MAX_GEO_POINT_DECIMALS};
+pub use decode::{parse_rwtf, parse_rwtf_with_options, parse_rwtf_with_progress, parse_raw_sections, CrcPolicy, Location, ParseOptions, ParseWarning, Position, Utf8Policy, Error as ParseError, INVALID_CRC, LIMIT_EXCEEDED, INVALID_UTF8, INVALID_LAYOUT, DUPLICATE_SECTION, UNSUPPORTED_VERSION};
+pub use trackpoint::{TimeIndex, TrackPoint, TrackPointReader};
+pub use spatial::{SpatialIndex};
+pub use coursepoint::{locate_course_points, CoursePoint, CoursePointReader, TurnType};
+pub use poi::{PointOfInterest, PointOfInterestReader};
+pub use lap::{Lap, LapReader};
+pub use event::{Event, EventReader, EventType};
+pub use progress::{Progress};
+pub use downsample::{downsample};
+pub use simplify::{simplify_section};
+pub use rangereader::{RangeReader, SectionInfo, Error as RangeReaderError};
+pub use source::{TrackReader, TrackSource};
+pub use stream::{StreamReader};
+pub use writer::{TrackWriter};
+pub use geojson::{GeoJson, GeoJsonProperties};
+#[cfg(feature = "gpx")]
+pub use gpx::{read_gpx, Error as GpxError};
+#[cfg(feature = "fit")]
+pub use fit::{read_fit, FitActivity, Error as FitError};
+pub use transform::{merge, reduce_precision, strip_columns, Transform, Values};
 pub use polyline::{FieldEncodeOptions, PointField};
 pub use surface::{RoadClassMapping, SurfaceMapping};
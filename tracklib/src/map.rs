@@ -0,0 +1,98 @@
+// Encoding for the values of a Map column. Each row is stored as:
+//   count       - unsigned LEB128, the number of entries
+//   entries     - in key order, each:
+//     key       - unsigned LEB128 length, then the UTF-8 bytes
+//     tag       - 1 byte, the type tag of the column the value would
+//                 otherwise go in
+//     value     - Numbers:   signed LEB128
+//                 LongFloat: the f64's 8 bytes, little endian, so any value
+//                            is kept exactly
+//                 String:    unsigned LEB128 length, then the UTF-8 bytes
+//                 Bool:      1 byte, 0 or 1
+// A row without a value is written as a map with no entries.
+use std::collections::{BTreeMap};
+use std::io::{self, Write};
+use crate::rwtfile::{DataField};
+use crate::utils::{write, signed_leb128_len, unsigned_leb128_len};
+
+pub(crate) const NUMBER: u8 = 0x00;
+pub(crate) const LONG_FLOAT: u8 = 0x01;
+pub(crate) const STRING: u8 = 0x04;
+pub(crate) const BOOL: u8 = 0x05;
+
+/// Whether `v` can be a value in a Map column: a Number, LongFloat, String
+/// or Bool.
+pub(crate) fn is_scalar(v: &DataField) -> bool {
+    matches!(v, DataField::Number(_) | DataField::LongFloat(_) | DataField::String(_) | DataField::Bool(_))
+}
+
+fn entries(map: &BTreeMap<String, DataField>) -> impl Iterator<Item = (&String, &DataField)> {
+    map.iter().filter(|(_, v)| is_scalar(v))
+}
+
+/// The number of bytes `write_row` will write for `map`.
+pub(crate) fn encoded_len(map: &BTreeMap<String, DataField>) -> usize {
+    let count = entries(map).count();
+    unsigned_leb128_len(count as u64) + entries(map)
+        .map(|(key, v)| {
+            let value_len = match v {
+                DataField::Number(v) => signed_leb128_len(*v),
+                DataField::LongFloat(_) => 8,
+                DataField::String(v) => unsigned_leb128_len(v.len() as u64) + v.len(),
+                _ => 1,
+            };
+            unsigned_leb128_len(key.len() as u64) + key.len() + 1 + value_len
+        })
+        .sum::<usize>()
+}
+
+/// Writes the scalar entries of `map`, see `is_scalar`. Sections only accept
+/// maps of scalars, so there aren't any others.
+pub(crate) fn write_row<W: Write>(out: &mut W, map: &BTreeMap<String, DataField>) -> io::Result<usize> {
+    let mut written = leb128::write::unsigned(out, entries(map).count() as u64)?;
+    for (key, v) in entries(map) {
+        written += leb128::write::unsigned(out, key.len() as u64)?;
+        written += write(out, key.as_bytes())?;
+        written += match v {
+            DataField::Number(v) => write(out, &[NUMBER])? + leb128::write::signed(out, *v)?,
+            DataField::LongFloat(v) => write(out, &[LONG_FLOAT])? + write(out, &v.to_le_bytes())?,
+            DataField::String(v) => {
+                write(out, &[STRING])? + leb128::write::unsigned(out, v.len() as u64)? + write(out, v.as_bytes())?
+            }
+            DataField::Bool(v) => write(out, &[BOOL, *v as u8])?,
+            _ => 0,
+        };
+    }
+
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_row() {
+        let mut buf = vec![];
+        assert_eq!(write_row(&mut buf, &BTreeMap::new()).unwrap(), 1);
+        assert_eq!(buf, &[0x00]);
+
+        let mut map = BTreeMap::new();
+        map.insert("n".to_string(), DataField::Number(-2));
+        map.insert("b".to_string(), DataField::Bool(true));
+        map.insert("s".to_string(), DataField::String("hi".to_string()));
+        map.insert("f".to_string(), DataField::LongFloat(0.5));
+        map.insert("ids".to_string(), DataField::IDs(vec![1]));
+
+        let mut buf = vec![];
+        assert_eq!(write_row(&mut buf, &map).unwrap(), buf.len());
+        assert_eq!(encoded_len(&map), buf.len());
+        let mut expected = vec![0x04,
+                                0x01, b'b', BOOL, 0x01,
+                                0x01, b'f', LONG_FLOAT];
+        expected.extend_from_slice(&0.5f64.to_le_bytes());
+        expected.extend_from_slice(&[0x01, b'n', NUMBER, 0x7E,
+                                     0x01, b's', STRING, 0x02, b'h', b'i']);
+        assert_eq!(buf, expected);
+    }
+}
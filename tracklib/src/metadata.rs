@@ -1,8 +1,10 @@
 use std::io::{Write};
+use std::convert::{TryFrom};
 use snafu::{Snafu, ResultExt};
 use std::time::{UNIX_EPOCH, SystemTime, SystemTimeError};
 use serde::ser::{Error as SerError, Serialize, Serializer, SerializeMap};
 use crate::utils::{write};
+use crate::error::{ErrorKind};
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -10,6 +12,21 @@ pub enum Error {
     WriteMetadataTable{source: std::io::Error},
     #[snafu(display("Couldn't compute the system time: {}", source))]
     GetTime{source: SystemTimeError},
+    #[snafu(display("Too many metadata entries: {}", source))]
+    TooManyEntries{source: std::num::TryFromIntError},
+    #[snafu(display("Metadata entry is too large: {}", source))]
+    EntryTooLarge{source: std::num::TryFromIntError},
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::WriteMetadataTable{..} |
+            Error::GetTime{..} => ErrorKind::Io,
+            Error::TooManyEntries{..} |
+            Error::EntryTooLarge{..} => ErrorKind::TooLarge,
+        }
+    }
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -30,7 +47,7 @@ impl TrackType {
         }
     }
 
-    fn type_tag(&self) -> u8 {
+    pub(crate) fn type_tag(&self) -> u8 {
         match self {
             TrackType::Trip(_)    => 0x00,
             TrackType::Route(_)   => 0x01,
@@ -70,16 +87,18 @@ impl Serialize for TrackType {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct RWTFMetadata {
     created_at: Option<SystemTime>,
     track_type: Option<TrackType>,
+    pub(crate) unknown_entries: Vec<(u8, Vec<u8>)>,
 }
 
 impl RWTFMetadata {
     pub(crate) fn new(created_at: Option<SystemTime>, track_type: Option<TrackType>) -> Self {
         RWTFMetadata{created_at: created_at,
-                     track_type: track_type}
+                     track_type,
+                     unknown_entries: vec![]}
     }
 
     pub fn created_at(&self) -> Option<SystemTime> {
@@ -90,6 +109,13 @@ impl RWTFMetadata {
         self.track_type
     }
 
+    /// Entries this version doesn't understand, as (tag, data) pairs. They're
+    /// written back out unchanged so a newer writer's metadata isn't lost
+    /// when the file is rewritten.
+    pub fn unknown_entries(&self) -> &[(u8, Vec<u8>)] {
+        &self.unknown_entries
+    }
+
     fn write_created_at<W: Write>(&self, out: &mut W) -> Result<usize> {
         let mut written = 0;
         let now_buf = SystemTime::now().duration_since(UNIX_EPOCH).context(GetTime)?.as_secs().to_le_bytes();
@@ -122,22 +148,41 @@ impl RWTFMetadata {
         Ok(written)
     }
 
+    fn write_unknown_entry<W: Write>(&self, out: &mut W, tag: u8, data: &[u8]) -> Result<usize> {
+        let mut written = 0;
+
+        written += write(out, &[tag]).context(WriteMetadataTable{})?;
+
+        // write size-prefixed entry data
+        let entry_size_buf: [u8; 2] = u16::try_from(data.len()).context(EntryTooLarge{})?.to_le_bytes();
+        written += write(out, &entry_size_buf).context(WriteMetadataTable{})?;
+        written += write(out, data).context(WriteMetadataTable{})?;
+
+        Ok(written)
+    }
+
     pub(crate) fn write<W: Write>(&self, out: &mut W) -> Result<usize> {
         let mut buf = Vec::new();
 
         if let Some(track_type) = self.track_type {
             // there are two entries - the track type and created_at
-            write(&mut buf, &[0x02]).context(WriteMetadataTable{})?;
+            let count = u8::try_from(2 + self.unknown_entries.len()).context(TooManyEntries{})?;
+            write(&mut buf, &[count]).context(WriteMetadataTable{})?;
 
             self.write_created_at(&mut buf)?;
             self.write_track_type(&mut buf, &track_type)?;
         } else {
             // self.track_type isn't set so there is just one entry: created_at
-            write(&mut buf, &[0x01]).context(WriteMetadataTable{})?;
+            let count = u8::try_from(1 + self.unknown_entries.len()).context(TooManyEntries{})?;
+            write(&mut buf, &[count]).context(WriteMetadataTable{})?;
 
             self.write_created_at(&mut buf)?;
         }
 
+        for (tag, data) in self.unknown_entries.iter() {
+            self.write_unknown_entry(&mut buf, *tag, data)?;
+        }
+
         // Write 2 bytes - CRC
         let crc = crc::crc16::checksum_usb(&buf).to_le_bytes();
         write(&mut buf, &crc).context(WriteMetadataTable{})?;
@@ -271,6 +316,29 @@ mod tests {
         test_buf(&buf, expected_head, expected_tail);
     }
 
+    #[test]
+    fn test_write_metadata_table_with_unknown_entries() {
+        let mut m = RWTFMetadata::new(None, None);
+        m.unknown_entries = vec![(0x42, vec![0xAA, 0xBB]), (0x43, vec![])];
+
+        let mut buf = vec![];
+        let written = m.write(&mut buf);
+        assert!(written.is_ok());
+        let expected_head = &[0x03, // 3 table entries
+                              0x01, // entry #1 is of type created_at
+                              0x08, // entry data is 8 bytes
+                              0x00];
+        let expected_tail = &[0x42, // entry #2 is of unknown type 0x42
+                              0x02, // entry data is 2 bytes
+                              0x00,
+                              0xAA,
+                              0xBB,
+                              0x43, // entry #3 is of unknown type 0x43
+                              0x00, // entry data is 0 bytes
+                              0x00];
+        test_buf(&buf, expected_head, expected_tail);
+    }
+
     #[test]
     fn test_roundtrip_metadata() {
         let created_at = Some(SystemTime::now());
@@ -0,0 +1,110 @@
+use std::collections::{BTreeMap};
+use crate::fields::{self, Field};
+use crate::section::{Column, Section};
+
+/// The standard fields of a single point of interest. See
+/// `RWTFile::add_standard_point_of_interest` to write one.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PointOfInterest {
+    pub lat: Option<f64>,
+    pub lng: Option<f64>,
+    pub name: Option<String>,
+    pub poi_type: Option<String>,
+    pub icon: Option<String>,
+    pub description: Option<String>,
+}
+
+/// Reads the standard fields of a points of interest section row by row. A
+/// field stored with the wrong column type reads as `None`.
+#[derive(Debug)]
+pub struct PointOfInterestReader<'a> {
+    len: usize,
+    lat: Option<&'a BTreeMap<usize, f64>>,
+    lng: Option<&'a BTreeMap<usize, f64>>,
+    name: Option<&'a BTreeMap<usize, String>>,
+    poi_type: Option<&'a BTreeMap<usize, String>>,
+    icon: Option<&'a BTreeMap<usize, String>>,
+    description: Option<&'a BTreeMap<usize, String>>,
+}
+
+fn floats<'a>(section: &'a Section, field: &Field) -> Option<&'a BTreeMap<usize, f64>> {
+    match field.column(section) {
+        Some(Column::LongFloat(m)) => Some(m),
+        _ => None,
+    }
+}
+
+fn strings<'a>(section: &'a Section, field: &Field) -> Option<&'a BTreeMap<usize, String>> {
+    match field.column(section) {
+        Some(Column::String(m)) => Some(m),
+        _ => None,
+    }
+}
+
+fn string(m: Option<&BTreeMap<usize, String>>, index: usize) -> Option<String> {
+    m.and_then(|m| m.get(&index)).cloned()
+}
+
+impl<'a> PointOfInterestReader<'a> {
+    pub fn new(section: &'a Section) -> Self {
+        PointOfInterestReader{len: section.len(),
+                              lat: floats(section, &fields::LAT),
+                              lng: floats(section, &fields::LNG),
+                              name: strings(section, &fields::POI_NAME),
+                              poi_type: strings(section, &fields::POI_TYPE),
+                              icon: strings(section, &fields::ICON),
+                              description: strings(section, &fields::DESCRIPTION)}
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn point(&self, index: usize) -> PointOfInterest {
+        PointOfInterest{lat: self.lat.and_then(|m| m.get(&index)).copied(),
+                        lng: self.lng.and_then(|m| m.get(&index)).copied(),
+                        name: string(self.name, index),
+                        poi_type: string(self.poi_type, index),
+                        icon: string(self.icon, index),
+                        description: string(self.description, index)}
+    }
+
+    /// Every row in order.
+    pub fn points(&self) -> impl Iterator<Item = PointOfInterest> + '_ {
+        (0..self.len).map(move |index| self.point(index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rwtfile::{RWTFile};
+    use crate::section::{SectionType};
+
+    #[test]
+    fn test_points_of_interest() {
+        let shop = PointOfInterest{lat: Some(45.52),
+                                   lng: Some(-122.68),
+                                   name: Some("Bike Shop".into()),
+                                   poi_type: Some("bike_shop".into()),
+                                   icon: Some("wrench".into()),
+                                   description: Some("Open until 6".into())};
+        let camp = PointOfInterest{name: Some("Camp".into()), ..PointOfInterest::default()};
+
+        let mut f = RWTFile::new();
+        assert!(f.add_track_point(0, "t", 1).is_ok());
+        assert!(f.add_standard_point_of_interest(0, &shop).is_ok());
+        assert!(f.add_standard_point_of_interest(1, &camp).is_ok());
+
+        let f = RWTFile::read(&f.to_bytes().unwrap()).unwrap();
+        assert_eq!(f.points_of_interest().section_type(), SectionType::PointsOfInterest);
+        assert_eq!(f.track_points().len(), 1);
+        assert_eq!(f.course_points().len(), 0);
+        assert_eq!(PointOfInterestReader::new(f.points_of_interest()).points().collect::<Vec<_>>(),
+                   vec![shop, camp]);
+    }
+}
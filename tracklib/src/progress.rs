@@ -0,0 +1,25 @@
+/// How far a long read or write has got. Passed to the callbacks of
+/// `RWTFile::write_with_progress`, `Section::write_with_progress` and
+/// `parse_rwtf_with_progress`.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Progress {
+    /// Rows finished so far, counting every section. Sections are encoded
+    /// and decoded column by column, so a section's rows count once the
+    /// whole section is done.
+    pub rows: usize,
+    /// Bytes written or read so far.
+    pub bytes: usize,
+    /// Bytes in the whole file or section. `bytes` reaches this at the end.
+    pub total_bytes: usize,
+}
+
+impl Progress {
+    /// How far along as a number from 0 to 1, for progress bars.
+    pub fn fraction(&self) -> f64 {
+        if self.total_bytes == 0 {
+            1.0
+        } else {
+            (self.bytes as f64 / self.total_bytes as f64).min(1.0)
+        }
+    }
+}
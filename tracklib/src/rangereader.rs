@@ -0,0 +1,388 @@
+use std::future::{Future};
+use std::ops::{Range};
+use snafu::{Snafu, ResultExt};
+use crate::decode::{parse_column_types, parse_data_offset, parse_file_checksum_at, parse_section_info, parse_single_section, parse_start, Error as ParseError, ParseOptions};
+use crate::error::{ErrorKind};
+use crate::fields::{FieldType};
+use crate::metadata::{RWTFMetadata};
+use crate::rwtfile::{RWTFHeader, RWTFTRAILER};
+use crate::section::{Section, SectionSizes, SectionType, Error as SectionError};
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
+pub enum Error {
+    #[snafu(display("Couldn't fetch {} bytes at byte {}: {}", len, offset, source))]
+    Fetch{offset: usize, len: usize, source: std::io::Error},
+    #[snafu(display("Couldn't parse: {}", source))]
+    Parse{source: ParseError},
+    #[snafu(display("Couldn't join section {} to the rows before it: {}", section, source))]
+    Join{section: usize, source: SectionError},
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Fetch{..} => ErrorKind::Io,
+            Error::Parse{source} => source.kind(),
+            Error::Join{source, ..} => source.kind(),
+        }
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+pub(crate) const HEADER_SIZE: usize = 24;
+// the section header and its CRC
+pub(crate) const SECTION_HEADER_SIZE: usize = 14;
+// enough for the types tables of most sections in one fetch
+const TYPES_TABLE_GUESS: usize = 1024;
+
+/// Where a section is in a file, from its header. See `RangeReader::sections`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct SectionInfo {
+    /// The position of this section in the file, counting from 0.
+    pub index: usize,
+    pub section_type: SectionType,
+    pub points: usize,
+    /// Bytes from the start of the file.
+    pub offset: usize,
+    pub len: usize,
+}
+
+async fn fetch_range<F, Fut>(fetch: &mut F, offset: usize, len: usize) -> Result<Vec<u8>>
+where F: FnMut(usize, usize) -> Fut,
+      Fut: Future<Output = std::io::Result<Vec<u8>>> {
+    Ok(fetch(offset, len).await.context(Fetch{offset, len})?)
+}
+
+/// Reads parts of a file without having all of it, e.g. with HTTP range
+/// requests to a file in S3. `fetch(offset, len)` returns `len` bytes from
+/// `offset`, or fewer at the end of the file.
+///
+/// Opening reads the header, the metadata and the header of each section,
+/// one fetch per section. After that, a section's columns can be read from
+/// its types table alone, and its values by fetching just that section.
+pub struct RangeReader<F> {
+    fetch: F,
+    options: ParseOptions,
+    header: RWTFHeader,
+    metadata: RWTFMetadata,
+    sections: Vec<SectionInfo>,
+    trailer_end: usize,
+}
+
+impl<F, Fut> RangeReader<F>
+where F: FnMut(usize, usize) -> Fut,
+      Fut: Future<Output = std::io::Result<Vec<u8>>> {
+    pub async fn open(mut fetch: F, options: ParseOptions) -> Result<Self> {
+        let mut start = fetch_range(&mut fetch, 0, HEADER_SIZE).await?;
+        let data_offset = parse_data_offset(&start, &options).context(Parse)?;
+        if data_offset > start.len() {
+            start.extend(fetch_range(&mut fetch, start.len(), data_offset - start.len()).await?);
+        }
+        let (header, metadata) = parse_start(&start, &options).context(Parse)?;
+
+        let mut sections = vec![];
+        let mut offset = data_offset;
+        loop {
+            let bytes = fetch_range(&mut fetch, offset, SECTION_HEADER_SIZE).await?;
+            let index = sections.len();
+            match parse_section_info(&bytes, index, &options).map_err(|e| e.moved(offset)).context(Parse)? {
+                Some((section_type, points, len)) => {
+                    sections.push(SectionInfo{index, section_type, points, offset, len});
+                    offset += len;
+                }
+                None => break,
+            }
+        }
+        let trailer_end = offset + RWTFTRAILER.len();
+
+        Ok(RangeReader{fetch, options, header, metadata, sections, trailer_end})
+    }
+
+    pub fn header(&self) -> &RWTFHeader {
+        &self.header
+    }
+
+    pub fn metadata(&self) -> &RWTFMetadata {
+        &self.metadata
+    }
+
+    /// Every section in the file, in order. Unlike `RWTFile::read`, each
+    /// continuation section is listed separately.
+    pub fn sections(&self) -> &[SectionInfo] {
+        &self.sections
+    }
+
+    async fn fetch(&mut self, offset: usize, len: usize) -> Result<Vec<u8>> {
+        fetch_range(&mut self.fetch, offset, len).await
+    }
+
+    /// The names and types of a section's columns, in types table order,
+    /// fetching only the types table. Columns of types this version doesn't
+    /// know are left out.
+    pub async fn columns(&mut self, section: SectionInfo) -> Result<Vec<(String, FieldType)>> {
+        // the types table has no size of its own, so fetch more until it
+        // parses or the whole section has been fetched
+        let mut len = section.len.min(SECTION_HEADER_SIZE + TYPES_TABLE_GUESS);
+        loop {
+            let bytes = self.fetch(section.offset, len).await?;
+            match parse_column_types(&bytes, section.index, &self.options) {
+                Err(ParseError::Incomplete{..}) if section.len > bytes.len() => {
+                    len = section.len.min(len * 4);
+                }
+                parsed => return Ok(parsed.map_err(|e| e.moved(section.offset)).context(Parse)?),
+            }
+        }
+    }
+
+    /// Checks the CRC32 of the whole file which follows the trailer of files
+    /// written with `RWTFile::set_file_checksum`, failing with `INVALID_CRC`
+    /// if it doesn't match. This fetches the whole file. Returns false,
+    /// without fetching anything, for a file whose header says it has none.
+    pub async fn verify_file_checksum(&mut self) -> Result<bool> {
+        if !self.header.file_checksum {
+            return Ok(false);
+        }
+        let bytes = self.fetch(0, self.trailer_end + 4).await?;
+        parse_file_checksum_at(&bytes, self.trailer_end, &self.header, &self.options).context(Parse)?;
+        Ok(true)
+    }
+
+    /// Fetches and decodes a whole section.
+    pub async fn section(&mut self, section: SectionInfo) -> Result<Section> {
+        let bytes = self.fetch(section.offset, section.len).await?;
+        Ok(parse_single_section(&bytes, section.index, &self.options)
+           .map_err(|e| e.moved(section.offset))
+           .context(Parse)?)
+    }
+
+    /// Fetches and decodes a whole section to break down its size, see
+    /// `Section::encoded_sizes`.
+    pub async fn section_sizes(&mut self, section: SectionInfo) -> Result<SectionSizes> {
+        Ok(self.section(section).await?.encoded_sizes())
+    }
+
+    /// Like `section`, decoding only the named columns. The whole section is
+    /// still fetched, since its columns can't be found without reading past
+    /// the ones before them, see `ParseOptions::set_columns`.
+    pub async fn section_columns(&mut self, section: SectionInfo, names: &[&str]) -> Result<Section> {
+        let bytes = self.fetch(section.offset, section.len).await?;
+        let mut options = self.options.clone();
+        options.set_columns(Some(names));
+        Ok(parse_single_section(&bytes, section.index, &options)
+           .map_err(|e| e.moved(section.offset))
+           .context(Parse)?)
+    }
+
+    /// `rows` of the `section_type` section, counting across its
+    /// continuation sections, fetching and decoding only the sections which
+    /// hold them. A huge track written with `RWTFile::set_max_section_size`
+    /// can be read from any row this way without decoding the rows before
+    /// it. Rows past the end are left out.
+    pub async fn rows(&mut self, section_type: SectionType, rows: Range<usize>) -> Result<Section> {
+        let mut pieces = vec![];
+        let mut continued = false;
+        let mut start = 0;
+        for section in &self.sections {
+            continued = section.section_type == section_type ||
+                (continued && section.section_type == SectionType::Continuation);
+            if continued {
+                pieces.push((start, *section));
+                start += section.points;
+            }
+        }
+
+        let mut out: Option<Section> = None;
+        for (start, section) in pieces {
+            let end = start + section.points;
+            if end <= rows.start || start >= rows.end {
+                continue;
+            }
+            let piece = self.section(section).await?
+                .copy_rows(section_type, rows.start.max(start) - start..rows.end.min(end) - start)
+                .context(Join{section: section.index})?;
+            match out.as_mut() {
+                Some(out) => out.append(&piece).context(Join{section: section.index})?,
+                None => out = Some(piece),
+            }
+        }
+
+        Ok(out.unwrap_or_else(|| Section::new(section_type)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell};
+    use std::pin::{Pin};
+    use std::task::{Context, Poll, Waker};
+    use assert_matches::assert_matches;
+    use crate::rwtfile::{DataField, RWTFile};
+    use crate::section::{Column};
+    use ::crc::crc16::{checksum_usb};
+
+    fn block_on<T>(future: impl Future<Output = T>) -> T {
+        let mut future = Box::pin(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(v) = Pin::as_mut(&mut future).poll(&mut cx) {
+                return v;
+            }
+        }
+    }
+
+    #[test]
+    fn test_range_reader() {
+        let mut f = RWTFile::new();
+        for i in 0..20000 {
+            assert!(f.add_track_point(i, "t", 1000 + i as i64).is_ok());
+            assert!(f.add_track_point(i, "y", DataField::LongFloat(45.0 + i as f64 / 1000.0)).is_ok());
+        }
+        assert!(f.add_course_point(0, "n", DataField::String("start".into())).is_ok());
+        assert!(f.add_course_point(0, "d", DataField::ShortFloat(1.5)).is_ok());
+        let buf = f.to_bytes().unwrap();
+
+        let fetched = Cell::new(0);
+        let fetch = |offset: usize, len: usize| {
+            let bytes = buf[offset.min(buf.len())..(offset + len).min(buf.len())].to_vec();
+            fetched.set(fetched.get() + bytes.len());
+            std::future::ready(Ok(bytes))
+        };
+
+        let mut reader = block_on(RangeReader::open(fetch, ParseOptions::new())).unwrap();
+        assert_eq!(reader.sections().iter().map(|s| (s.section_type, s.points)).collect::<Vec<_>>(),
+                   vec![(SectionType::TrackPoints, 20000), (SectionType::CoursePoints, 1)]);
+        let (track, course) = (reader.sections()[0], reader.sections()[1]);
+        assert_eq!(track.offset + track.len, course.offset);
+
+        assert_eq!(block_on(reader.columns(track)).unwrap(),
+                   vec![("t".to_string(), FieldType::Numbers), ("y".to_string(), FieldType::LongFloat)]);
+        let course_points = block_on(reader.section_columns(course, &["n"])).unwrap();
+        assert_eq!(course_points.columns().keys().collect::<Vec<_>>(), vec!["n"]);
+        assert_matches!(course_points.columns().get("n"), Some(Column::String(m)) if m[&0] == "start");
+        // the track points values were never fetched
+        assert!(fetched.get() < buf.len() / 50);
+
+        let track_points = block_on(reader.section(track)).unwrap();
+        assert_eq!(track_points.len(), 20000);
+    }
+
+    #[test]
+    fn test_range_reader_wide_types_table() {
+        // a types table several times bigger than the first fetch
+        let mut f = RWTFile::new();
+        for i in 0..2000 {
+            for c in 0..300 {
+                assert!(f.add_track_point(i, &format!("developer_field_{}", c), i as i64).is_ok());
+            }
+        }
+        let buf = f.to_bytes().unwrap();
+
+        let fetched = Cell::new(0);
+        let fetch = |offset: usize, len: usize| {
+            let bytes = buf[offset.min(buf.len())..(offset + len).min(buf.len())].to_vec();
+            fetched.set(fetched.get() + bytes.len());
+            std::future::ready(Ok(bytes))
+        };
+
+        let mut reader = block_on(RangeReader::open(fetch, ParseOptions::new())).unwrap();
+        let columns = block_on(reader.columns(reader.sections()[0])).unwrap();
+        assert_eq!(columns.len(), 300);
+        assert_eq!(columns[299], ("developer_field_299".to_string(), FieldType::Numbers));
+        assert!(fetched.get() < buf.len() / 10);
+    }
+
+    #[test]
+    fn test_range_reader_rows() {
+        let mut f = RWTFile::new();
+        for i in 0..20000 {
+            assert!(f.add_track_point(i, "t", 1000 + i as i64).is_ok());
+        }
+        assert!(f.add_course_point(0, "n", DataField::String("start".into())).is_ok());
+        f.set_max_section_size(Some(4096));
+        let buf = f.to_bytes().unwrap();
+
+        let fetched = Cell::new(0);
+        let fetch = |offset: usize, len: usize| {
+            let bytes = buf[offset.min(buf.len())..(offset + len).min(buf.len())].to_vec();
+            fetched.set(fetched.get() + bytes.len());
+            std::future::ready(Ok(bytes))
+        };
+        let mut reader = block_on(RangeReader::open(fetch, ParseOptions::new())).unwrap();
+        assert!(reader.sections().len() > 10);
+        let opened = fetched.get();
+
+        // across the end of one section and the start of the next
+        let second = reader.sections()[1];
+        let start = reader.sections()[0].points - 5;
+        let rows = block_on(reader.rows(SectionType::TrackPoints, start..start + 10)).unwrap();
+        assert_eq!(rows.section_type(), SectionType::TrackPoints);
+        assert_eq!(rows.len(), 10);
+        assert_eq!(rows.columns()["t"].get(0), Some(DataField::Number(1000 + start as i64)));
+        assert_eq!(rows.columns()["t"].get(9), Some(DataField::Number(1009 + start as i64)));
+        assert_eq!(fetched.get() - opened, reader.sections()[0].len + second.len);
+
+        let rows = block_on(reader.rows(SectionType::TrackPoints, 19995..30000)).unwrap();
+        assert_eq!(rows.len(), 5);
+        assert_eq!(rows.columns()["t"].get(4), Some(DataField::Number(20999)));
+        assert_eq!(block_on(reader.rows(SectionType::TrackPoints, 30000..30010)).unwrap().len(), 0);
+        let rows = block_on(reader.rows(SectionType::CoursePoints, 0..10)).unwrap();
+        assert_eq!(rows.columns()["n"].get(0), Some(DataField::String("start".into())));
+    }
+
+    #[test]
+    fn test_range_reader_errors() {
+        let mut f = RWTFile::new();
+        assert!(f.add_track_point(0, "t", 1000).is_ok());
+        let mut buf = f.to_bytes().unwrap();
+
+        let failing = |_offset: usize, _len: usize| {
+            std::future::ready(Err(std::io::Error::other("offline")))
+        };
+        let e = block_on(RangeReader::open(failing, ParseOptions::new())).err().unwrap();
+        assert_matches!(e, Error::Fetch{offset: 0, len: 24, ..});
+        assert_eq!(e.kind(), ErrorKind::Io);
+
+        // cut off in the section header
+        let data_offset = usize::from(u16::from_le_bytes([buf[18], buf[19]]));
+        let short = buf[..data_offset + 6].to_vec();
+        let fetch = |offset: usize, len: usize| {
+            std::future::ready(Ok(short[offset.min(short.len())..(offset + len).min(short.len())].to_vec()))
+        };
+        assert_matches!(block_on(RangeReader::open(fetch, ParseOptions::new())).err(),
+                        Some(Error::Parse{source: ParseError::Incomplete{offset, ..}}) if offset == data_offset + 6);
+
+        // a bad section header CRC, with the offset from the start of the file
+        buf[data_offset + 1] ^= 0xff;
+        let fetch = |offset: usize, len: usize| {
+            std::future::ready(Ok(buf[offset.min(buf.len())..(offset + len).min(buf.len())].to_vec()))
+        };
+        assert_matches!(block_on(RangeReader::open(fetch, ParseOptions::new())).err(),
+                        Some(Error::Parse{source: ParseError::InvalidCrc{offset, ..}}) if offset == data_offset);
+
+        // a section size ending inside the section's header
+        let mut buf = f.to_bytes().unwrap();
+        buf[data_offset + 4..data_offset + 12].copy_from_slice(&3u64.to_le_bytes());
+        let crc = checksum_usb(&buf[data_offset..data_offset + 12]).to_le_bytes();
+        buf[data_offset + 12..data_offset + 14].copy_from_slice(&crc);
+        let fetch = |offset: usize, len: usize| {
+            std::future::ready(Ok(buf[offset.min(buf.len())..(offset + len).min(buf.len())].to_vec()))
+        };
+        assert_matches!(block_on(RangeReader::open(fetch, ParseOptions::new())).err(),
+                        Some(Error::Parse{source: ParseError::Misaligned{offset, expected, ..}})
+                        if offset == data_offset + 14 && expected == data_offset + 5);
+
+        // asked for a section where the trailer is
+        let buf = f.to_bytes().unwrap();
+        let fetch = |offset: usize, len: usize| {
+            std::future::ready(Ok(buf[offset.min(buf.len())..(offset + len).min(buf.len())].to_vec()))
+        };
+        let mut reader = block_on(RangeReader::open(fetch, ParseOptions::new())).unwrap();
+        let section = reader.sections()[0];
+        let trailer = SectionInfo{index: 1, offset: section.offset + section.len, len: RWTFTRAILER.len(), ..section};
+        assert_matches!(block_on(reader.section(trailer)).err(),
+                        Some(Error::Parse{source: ParseError::UnexpectedTrailer{offset, ..}}) if offset == section.offset + section.len);
+    }
+}
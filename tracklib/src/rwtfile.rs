@@ -1,11 +1,28 @@
 use snafu::{Snafu, ResultExt};
 use std::io::{Write};
 use std::convert::{TryFrom};
-use crate::section::{Section, SectionType, Error as SectionError};
+use std::collections::{BTreeMap};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::section::{RawSection, Section, SectionType, Error as SectionError};
 use crate::metadata::{RWTFMetadata, TrackType, Error as MetadataError};
-use crate::utils::{write};
+use ::crc::crc64::{self, Hasher64};
+use crate::utils::{format_time, write, Crc32Writer};
+use crate::fields::{self, FieldType};
+use crate::coursepoint::{CoursePoint};
+use crate::poi::{PointOfInterest};
+use crate::lap::{Lap, LapReader};
+use crate::event::{Event};
+use crate::trackpoint::{TrackPointReader};
+use crate::transform::{merge, Transform};
+use std::ops::{Range};
+use std::cell::{Cell};
+use crate::progress::{Progress};
+use crate::error::{ErrorKind};
+use crate::decode::{parse_rwtf_with_options, ParseOptions, ParseWarning, Error as ParseError};
 
 #[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
+#[non_exhaustive]
 pub enum Error {
     #[snafu(display("Couldn't write header: {}", source))]
     WriteHeader{source: std::io::Error},
@@ -25,11 +42,42 @@ pub enum Error {
     WriteTrailer{source: std::io::Error},
     #[snafu(display("Couldn't decode base64: {}", source))]
     DecodeBase64{source: base64::DecodeError},
+    #[snafu(display("More than one {:?} section", section_type))]
+    DuplicateSection{section_type: SectionType},
+    #[snafu(display("Couldn't crop section: {}", source))]
+    CropSection{source: SectionError},
+    #[snafu(display("Continuation sections are only written, not set"))]
+    SetContinuation{},
+    #[snafu(display("Couldn't read the file being appended to: {}", source))]
+    ReadExisting{source: ParseError},
+    #[snafu(display("Shifting the times by {} seconds overflows", seconds))]
+    ShiftTimes{seconds: i64},
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::WriteHeader{..} |
+            Error::WriteBytes{..} |
+            Error::WriteTrailer{..} => ErrorKind::Io,
+            Error::NumberTruncation{..} |
+            Error::ShiftTimes{..} => ErrorKind::TooLarge,
+            Error::DecodeBase64{..} |
+            Error::DuplicateSection{..} |
+            Error::SetContinuation{..} => ErrorKind::Schema,
+            Error::WriteMetadataTable{source} => source.kind(),
+            Error::AddTrackPoint{source} |
+            Error::AddCoursePoint{source} |
+            Error::WriteSection{source} |
+            Error::CropSection{source} => source.kind(),
+            Error::ReadExisting{source} => source.kind(),
+        }
+    }
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum DataField {
     Number(i64),
     LongFloat(f64),
@@ -38,6 +86,15 @@ pub enum DataField {
     String(String),
     Bool(bool),
     IDs(Vec<u64>),
+    PackedBool(bool),
+    XorFloat(f64),
+    Map(BTreeMap<String, DataField>),
+    /// A latitude and longitude, in that order.
+    GeoPoint(f64, f64),
+    I64Array(Vec<i64>),
+    F64Array(Vec<f64>),
+    /// Milliseconds since the unix epoch.
+    Timestamp(i64),
 }
 
 impl From<i64> for DataField {
@@ -64,6 +121,238 @@ impl From<Vec<u64>> for DataField {
     }
 }
 
+impl From<Vec<i64>> for DataField {
+    fn from(v: Vec<i64>) -> Self {
+        DataField::I64Array(v)
+    }
+}
+
+impl From<Vec<f64>> for DataField {
+    fn from(v: Vec<f64>) -> Self {
+        DataField::F64Array(v)
+    }
+}
+
+impl From<SystemTime> for DataField {
+    fn from(v: SystemTime) -> Self {
+        let millis = match v.duration_since(UNIX_EPOCH) {
+            Ok(d) => i64::try_from(d.as_millis()).unwrap_or(i64::MAX),
+            Err(e) => i64::try_from(e.duration().as_millis()).map(|v| -v).unwrap_or(i64::MIN),
+        };
+        DataField::Timestamp(millis)
+    }
+}
+
+impl From<BTreeMap<String, DataField>> for DataField {
+    fn from(v: BTreeMap<String, DataField>) -> Self {
+        DataField::Map(v)
+    }
+}
+
+impl DataField {
+    /// The type of column this value goes in.
+    pub fn field_type(&self) -> FieldType {
+        match self {
+            DataField::Number(_)     => FieldType::Numbers,
+            DataField::LongFloat(_)  => FieldType::LongFloat,
+            DataField::ShortFloat(_) => FieldType::ShortFloat,
+            DataField::Base64(_)     => FieldType::Base64,
+            DataField::String(_)     => FieldType::String,
+            DataField::Bool(_)       => FieldType::Bool,
+            DataField::IDs(_)        => FieldType::IDs,
+            DataField::PackedBool(_) => FieldType::PackedBool,
+            DataField::XorFloat(_)   => FieldType::XorFloat,
+            DataField::Map(_)        => FieldType::Map,
+            DataField::GeoPoint(..)  => FieldType::GeoPoint,
+            DataField::I64Array(_)   => FieldType::I64Array,
+            DataField::F64Array(_)   => FieldType::F64Array,
+            DataField::Timestamp(_)  => FieldType::Timestamp,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            DataField::Number(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// The value of any of the float types.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            DataField::LongFloat(v) | DataField::ShortFloat(v) | DataField::XorFloat(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// The value of either of the bool types.
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            DataField::Bool(v) | DataField::PackedBool(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// The value of a String. Base64 values aren't text, so they're `None`.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            DataField::String(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_ids(&self) -> Option<&[u64]> {
+        match self {
+            DataField::IDs(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_map(&self) -> Option<&BTreeMap<String, DataField>> {
+        match self {
+            DataField::Map(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// The latitude and longitude of a GeoPoint.
+    pub fn as_geo_point(&self) -> Option<(f64, f64)> {
+        match self {
+            DataField::GeoPoint(lat, lng) => Some((*lat, *lng)),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64_array(&self) -> Option<&[i64]> {
+        match self {
+            DataField::I64Array(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64_array(&self) -> Option<&[f64]> {
+        match self {
+            DataField::F64Array(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// The milliseconds since the unix epoch of a Timestamp.
+    pub fn as_timestamp(&self) -> Option<i64> {
+        match self {
+            DataField::Timestamp(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// The time of a Timestamp.
+    pub fn as_system_time(&self) -> Option<SystemTime> {
+        let millis = self.as_timestamp()?;
+        let d = Duration::from_millis(millis.unsigned_abs());
+        if millis < 0 {
+            UNIX_EPOCH.checked_sub(d)
+        } else {
+            UNIX_EPOCH.checked_add(d)
+        }
+    }
+}
+
+// Each of these hands the value back when it's of another type
+impl TryFrom<DataField> for i64 {
+    type Error = DataField;
+
+    fn try_from(v: DataField) -> Result<Self, DataField> {
+        v.as_i64().ok_or(v)
+    }
+}
+
+impl TryFrom<DataField> for f64 {
+    type Error = DataField;
+
+    fn try_from(v: DataField) -> Result<Self, DataField> {
+        v.as_f64().ok_or(v)
+    }
+}
+
+impl TryFrom<DataField> for bool {
+    type Error = DataField;
+
+    fn try_from(v: DataField) -> Result<Self, DataField> {
+        v.as_bool().ok_or(v)
+    }
+}
+
+impl TryFrom<DataField> for String {
+    type Error = DataField;
+
+    fn try_from(v: DataField) -> Result<Self, DataField> {
+        match v {
+            DataField::String(v) => Ok(v),
+            v => Err(v),
+        }
+    }
+}
+
+impl TryFrom<DataField> for Vec<u64> {
+    type Error = DataField;
+
+    fn try_from(v: DataField) -> Result<Self, DataField> {
+        match v {
+            DataField::IDs(v) => Ok(v),
+            v => Err(v),
+        }
+    }
+}
+
+impl TryFrom<DataField> for Vec<i64> {
+    type Error = DataField;
+
+    fn try_from(v: DataField) -> Result<Self, DataField> {
+        match v {
+            DataField::I64Array(v) => Ok(v),
+            v => Err(v),
+        }
+    }
+}
+
+impl TryFrom<DataField> for Vec<f64> {
+    type Error = DataField;
+
+    fn try_from(v: DataField) -> Result<Self, DataField> {
+        match v {
+            DataField::F64Array(v) => Ok(v),
+            v => Err(v),
+        }
+    }
+}
+
+impl TryFrom<DataField> for SystemTime {
+    type Error = DataField;
+
+    fn try_from(v: DataField) -> Result<Self, DataField> {
+        v.as_system_time().ok_or(v)
+    }
+}
+
+impl TryFrom<DataField> for (f64, f64) {
+    type Error = DataField;
+
+    fn try_from(v: DataField) -> Result<Self, DataField> {
+        v.as_geo_point().ok_or(v)
+    }
+}
+
+impl TryFrom<DataField> for BTreeMap<String, DataField> {
+    type Error = DataField;
+
+    fn try_from(v: DataField) -> Result<Self, DataField> {
+        match v {
+            DataField::Map(v) => Ok(v),
+            v => Err(v),
+        }
+    }
+}
+
 use serde::ser::{Serialize, Serializer, SerializeSeq, SerializeMap};
 
 impl Serialize for DataField {
@@ -72,9 +361,11 @@ impl Serialize for DataField {
             DataField::Number(v) => serializer.serialize_i64(*v),
             DataField::LongFloat(v) => serializer.serialize_f64(*v),
             DataField::ShortFloat(v) => serializer.serialize_f64(*v),
+            DataField::XorFloat(v) => serializer.serialize_f64(*v),
             DataField::Base64(v) => serializer.serialize_str(v),
             DataField::String(v) => serializer.serialize_str(v),
             DataField::Bool(v) => serializer.serialize_bool(*v),
+            DataField::PackedBool(v) => serializer.serialize_bool(*v),
             DataField::IDs(v) => {
                 let mut seq = serializer.serialize_seq(Some(v.len()))?;
                 for e in v {
@@ -82,6 +373,34 @@ impl Serialize for DataField {
                 }
                 seq.end()
             }
+            DataField::Map(v) => {
+                let mut map = serializer.serialize_map(Some(v.len()))?;
+                for (k, e) in v {
+                    map.serialize_entry(k, e)?;
+                }
+                map.end()
+            }
+            DataField::GeoPoint(lat, lng) => {
+                let mut seq = serializer.serialize_seq(Some(2))?;
+                seq.serialize_element(lat)?;
+                seq.serialize_element(lng)?;
+                seq.end()
+            }
+            DataField::I64Array(v) => {
+                let mut seq = serializer.serialize_seq(Some(v.len()))?;
+                for e in v {
+                    seq.serialize_element(e)?;
+                }
+                seq.end()
+            }
+            DataField::F64Array(v) => {
+                let mut seq = serializer.serialize_seq(Some(v.len()))?;
+                for e in v {
+                    seq.serialize_element(e)?;
+                }
+                seq.end()
+            }
+            DataField::Timestamp(v) => serializer.serialize_str(&format_time(*v)),
         }
     }
 }
@@ -101,16 +420,38 @@ pub(crate) const RWTFTRAILER: [u8; 5] = [0xff,  // SectionType
                                          0x57,  // W
                                          0x52]; // R
 
-#[derive(Debug)]
+/// The newest file version this version of tracklib can read. Files from
+/// older versions are read too. Each file is written with the oldest version
+/// that holds everything in it, so readers which only know version 0 can
+/// still read files which don't need anything newer.
+///
+/// - 0: track points, course points and continuation sections, with
+///   Numbers, LongFloat, ShortFloat, Base64, String, Bool and IDs columns.
+/// - 1: points of interest, laps and events sections, every other column
+///   type, run-length encoding, packed IDs, types tables for more than 255
+///   columns or names longer than 255 bytes, and file checksums.
+///
+/// `TrackWriter` always writes the newest version, since the header comes
+/// before the sections.
+pub const RWTF_FILE_VERSION: u8 = 1;
+
+// Set in the first byte of the header's file version reserve when a file
+// checksum follows the trailer, see `RWTFile::set_file_checksum`
+pub(crate) const HEADER_FILE_CHECKSUM: u8 = 0x01;
+
+#[derive(Debug, Clone, Copy)]
 pub struct RWTFHeader {
     pub(crate) file_version: u8,
     pub(crate) creator_version: u8,
+    // whether the file read had a file checksum after its trailer
+    pub(crate) file_checksum: bool,
 }
 
 impl RWTFHeader {
     fn new() -> Self {
-        RWTFHeader{file_version: 0,
-                   creator_version: 0}
+        RWTFHeader{file_version: RWTF_FILE_VERSION,
+                   creator_version: 0,
+                   file_checksum: false}
     }
 
     pub fn file_version(&self) -> u8 {
@@ -121,17 +462,19 @@ impl RWTFHeader {
         self.creator_version
     }
 
-    fn write<W: Write>(&self, out: &mut W, metadata_table_offset: u16, data_offset: u16) -> Result<usize> {
+    fn write<W: Write>(&self, out: &mut W, file_version: u8, metadata_table_offset: u16, data_offset: u16, file_checksum: bool) -> Result<usize> {
         let mut buf = Vec::with_capacity(24);
 
         // Write 8 bytes - Magic Number
         write(&mut buf, &RWTFMAGIC).context(WriteHeader{})?;
 
         // Write 1 byte - File Version
-        write(&mut buf, &self.file_version.to_le_bytes()).context(WriteHeader{})?;
+        write(&mut buf, &file_version.to_le_bytes()).context(WriteHeader{})?;
 
-        // Write 3 bytes - File Version Reserve
-        write(&mut buf, &[0x00, 0x00, 0x00]).context(WriteHeader{})?;
+        // Write 3 bytes - File Version Reserve, the first holding flags for
+        // what follows the trailer
+        let flags = if file_checksum { HEADER_FILE_CHECKSUM } else { 0x00 };
+        write(&mut buf, &[flags, 0x00, 0x00]).context(WriteHeader{})?;
 
         // Write 1 byte - Creator Version
         write(&mut buf, &self.creator_version.to_le_bytes()).context(WriteHeader{})?;
@@ -165,6 +508,12 @@ pub struct RWTFile {
     pub(crate) metadata: RWTFMetadata,
     pub track_points: Section,
     pub course_points: Section,
+    pub points_of_interest: Section,
+    pub laps: Section,
+    pub events: Section,
+    pub(crate) warnings: Vec<ParseWarning>,
+    pub(crate) max_section_size: Option<usize>,
+    pub(crate) file_checksum: bool,
 }
 
 impl RWTFile {
@@ -172,21 +521,79 @@ impl RWTFile {
         Self{header: RWTFHeader::new(),
              metadata: RWTFMetadata::new(None, None),
              track_points: Section::new(SectionType::TrackPoints),
-             course_points: Section::new(SectionType::CoursePoints)}
+             course_points: Section::new(SectionType::CoursePoints),
+             points_of_interest: Section::new(SectionType::PointsOfInterest),
+             laps: Section::new(SectionType::Laps),
+             events: Section::new(SectionType::Events),
+             warnings: vec![],
+             max_section_size: None,
+             file_checksum: false}
     }
 
     pub fn with_track_type(track_type: TrackType) -> Self {
         Self{header: RWTFHeader::new(),
              metadata: RWTFMetadata::new(None, Some(track_type)),
              track_points: Section::new(SectionType::TrackPoints),
-             course_points: Section::new(SectionType::CoursePoints)}
+             course_points: Section::new(SectionType::CoursePoints),
+             points_of_interest: Section::new(SectionType::PointsOfInterest),
+             laps: Section::new(SectionType::Laps),
+             events: Section::new(SectionType::Events),
+             warnings: vec![],
+             max_section_size: None,
+             file_checksum: false}
     }
 
-    pub fn header(&self) -> &RWTFHeader {
-        &self.header
+    /// Parses a whole file with the default `ParseOptions`.
+    pub fn read(bytes: &[u8]) -> std::result::Result<Self, ParseError> {
+        parse_rwtf_with_options(bytes, &ParseOptions::new())
     }
 
-    fn add_point<V: Into<DataField>>(section: &mut Section, index: usize, k: &str, v: V) -> Result<()>{
+    /// The header this file would be written with now. Its file version is
+    /// the one its sections and file checksum need, see
+    /// `RWTF_FILE_VERSION`, which needn't be the version it was read with.
+    pub fn header(&self) -> RWTFHeader {
+        RWTFHeader{file_version: self.file_version(),
+                   file_checksum: self.file_checksum,
+                   ..self.header}
+    }
+
+    pub fn track_points(&self) -> &Section {
+        &self.track_points
+    }
+
+    pub fn course_points(&self) -> &Section {
+        &self.course_points
+    }
+
+    pub fn points_of_interest(&self) -> &Section {
+        &self.points_of_interest
+    }
+
+    pub fn laps(&self) -> &Section {
+        &self.laps
+    }
+
+    pub fn events(&self) -> &Section {
+        &self.events
+    }
+
+    /// Replaces the section of the same type as `section`, returning the
+    /// one it replaces. The section is moved into the file rather than
+    /// copied, so sections can be built on separate threads, see
+    /// `Section::new`, and put together here.
+    pub fn set_section(&mut self, section: Section) -> Result<Section> {
+        let slot = match section.section_type() {
+            SectionType::TrackPoints => &mut self.track_points,
+            SectionType::CoursePoints => &mut self.course_points,
+            SectionType::PointsOfInterest => &mut self.points_of_interest,
+            SectionType::Laps => &mut self.laps,
+            SectionType::Events => &mut self.events,
+            SectionType::Continuation => return SetContinuation{}.fail(),
+        };
+        Ok(std::mem::replace(slot, section))
+    }
+
+    pub(crate) fn add_point<V: Into<DataField>>(section: &mut Section, index: usize, k: &str, v: V) -> Result<()>{
         match v.into() {
             DataField::Number(v) => section.add_number(index, k, v).eager_context(AddTrackPoint),
             DataField::LongFloat(v) => section.add_long_float(index, k, v).eager_context(AddTrackPoint),
@@ -195,6 +602,13 @@ impl RWTFile {
             DataField::String(v) => section.add_string(index, k, v).eager_context(AddTrackPoint),
             DataField::Bool(v) => section.add_bool(index, k, v).eager_context(AddTrackPoint),
             DataField::IDs(v) => section.add_ids(index, k, v).eager_context(AddTrackPoint),
+            DataField::PackedBool(v) => section.add_packed_bool(index, k, v).eager_context(AddTrackPoint),
+            DataField::XorFloat(v) => section.add_xor_float(index, k, v).eager_context(AddTrackPoint),
+            DataField::Map(v) => section.add_map(index, k, v).eager_context(AddTrackPoint),
+            DataField::GeoPoint(lat, lng) => section.add_geo_point(index, k, lat, lng).eager_context(AddTrackPoint),
+            DataField::I64Array(v) => section.add_i64_array(index, k, v).eager_context(AddTrackPoint),
+            DataField::F64Array(v) => section.add_f64_array(index, k, v).eager_context(AddTrackPoint),
+            DataField::Timestamp(v) => section.add_timestamp(index, k, v).eager_context(AddTrackPoint),
         }
     }
 
@@ -206,38 +620,478 @@ impl RWTFile {
         Self::add_point(&mut self.course_points, index, k, v)
     }
 
+    pub fn add_point_of_interest<V: Into<DataField>>(&mut self, index: usize, k: &str, v: V) -> Result<()>{
+        Self::add_point(&mut self.points_of_interest, index, k, v)
+    }
+
+    pub fn add_lap<V: Into<DataField>>(&mut self, index: usize, k: &str, v: V) -> Result<()>{
+        Self::add_point(&mut self.laps, index, k, v)
+    }
+
+    pub fn add_event<V: Into<DataField>>(&mut self, index: usize, k: &str, v: V) -> Result<()>{
+        Self::add_point(&mut self.events, index, k, v)
+    }
+
+    /// Adds the fields of `point` which are set to the course point at
+    /// `index`, under their standard names. See `fields::COURSE_POINT_FIELDS`.
+    pub fn add_standard_course_point(&mut self, index: usize, point: &CoursePoint) -> Result<()> {
+        if let Some(lat) = point.lat {
+            self.add_course_point(index, fields::LAT.name, DataField::LongFloat(lat))?;
+        }
+        if let Some(lng) = point.lng {
+            self.add_course_point(index, fields::LNG.name, DataField::LongFloat(lng))?;
+        }
+        if let Some(distance) = point.distance {
+            self.add_course_point(index, fields::DISTANCE.name, DataField::ShortFloat(distance))?;
+        }
+        if let Some(turn_type) = point.turn_type {
+            self.add_course_point(index, fields::COURSE_POINT_TYPE.name, turn_type.name().to_string())?;
+        }
+        if let Some(note) = &point.note {
+            self.add_course_point(index, fields::NOTE.name, note.clone())?;
+        }
+        if let Some(eta) = point.eta {
+            self.add_course_point(index, fields::ETA.name, eta)?;
+        }
+
+        Ok(())
+    }
+
+    /// Adds the fields of `point` which are set to the point of interest at
+    /// `index`, under their standard names. See
+    /// `fields::POINT_OF_INTEREST_FIELDS`.
+    pub fn add_standard_point_of_interest(&mut self, index: usize, point: &PointOfInterest) -> Result<()> {
+        if let Some(lat) = point.lat {
+            self.add_point_of_interest(index, fields::LAT.name, DataField::LongFloat(lat))?;
+        }
+        if let Some(lng) = point.lng {
+            self.add_point_of_interest(index, fields::LNG.name, DataField::LongFloat(lng))?;
+        }
+        let strings = [(fields::POI_NAME, &point.name),
+                       (fields::POI_TYPE, &point.poi_type),
+                       (fields::ICON, &point.icon),
+                       (fields::DESCRIPTION, &point.description)];
+        for (field, value) in strings.iter() {
+            if let Some(value) = value {
+                self.add_point_of_interest(index, field.name, value.clone())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds the fields of `lap` which are set to the lap at `index`, under
+    /// their standard names. See `fields::LAP_FIELDS`.
+    pub fn add_standard_lap(&mut self, index: usize, lap: &Lap) -> Result<()> {
+        let rows = [(fields::START_ROW, lap.start_row), (fields::END_ROW, lap.end_row)];
+        for (field, row) in rows.iter() {
+            if let Some(row) = row {
+                self.add_lap(index, field.name, i64::try_from(*row).context(NumberTruncation{})?)?;
+            }
+        }
+        let numbers = [(fields::TIME, lap.start_time),
+                       (fields::END_TIME, lap.end_time),
+                       (fields::MOVING_TIME, lap.moving_time),
+                       (fields::HEART_RATE, lap.avg_heart_rate),
+                       (fields::POWER, lap.avg_power)];
+        for (field, value) in numbers.iter() {
+            if let Some(value) = value {
+                self.add_lap(index, field.name, *value)?;
+            }
+        }
+        if let Some(distance) = lap.distance {
+            self.add_lap(index, fields::DISTANCE.name, DataField::ShortFloat(distance))?;
+        }
+
+        Ok(())
+    }
+
+    /// Adds `event` as the event at `index`, under the standard names. See
+    /// `fields::EVENT_FIELDS`.
+    pub fn add_standard_event(&mut self, index: usize, event: &Event) -> Result<()> {
+        self.add_event(index, fields::TIME.name, event.time)?;
+        self.add_event(index, fields::EVENT_TYPE.name, event.event_type.name().to_string())
+    }
+
+    fn set_point<V: Into<DataField>>(section: &mut Section, index: usize, k: &str, v: V) -> Result<Option<DataField>> {
+        let old = section.remove(index, k);
+        match Self::add_point(section, index, k, v) {
+            Ok(()) => Ok(old),
+            Err(e) => {
+                if let Some(old) = old {
+                    // this value was just in the column, so it fits
+                    let _ = Self::add_point(section, index, k, old);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Like `add_track_point`, but replaces any value already at `index`,
+    /// returning it. If `v` can't be added the old value is kept.
+    pub fn set_track_point<V: Into<DataField>>(&mut self, index: usize, k: &str, v: V) -> Result<Option<DataField>> {
+        Self::set_point(&mut self.track_points, index, k, v)
+    }
+
+    /// Like `add_course_point`, but replaces any value already at `index`,
+    /// returning it. If `v` can't be added the old value is kept.
+    pub fn set_course_point<V: Into<DataField>>(&mut self, index: usize, k: &str, v: V) -> Result<Option<DataField>> {
+        Self::set_point(&mut self.course_points, index, k, v)
+    }
+
+    /// Like `add_point_of_interest`, but replaces any value already at
+    /// `index`, returning it. If `v` can't be added the old value is kept.
+    pub fn set_point_of_interest<V: Into<DataField>>(&mut self, index: usize, k: &str, v: V) -> Result<Option<DataField>> {
+        Self::set_point(&mut self.points_of_interest, index, k, v)
+    }
+
+    /// Like `add_lap`, but replaces any value already at `index`, returning
+    /// it. If `v` can't be added the old value is kept.
+    pub fn set_lap<V: Into<DataField>>(&mut self, index: usize, k: &str, v: V) -> Result<Option<DataField>> {
+        Self::set_point(&mut self.laps, index, k, v)
+    }
+
+    /// Like `add_event`, but replaces any value already at `index`,
+    /// returning it. If `v` can't be added the old value is kept.
+    pub fn set_event<V: Into<DataField>>(&mut self, index: usize, k: &str, v: V) -> Result<Option<DataField>> {
+        Self::set_point(&mut self.events, index, k, v)
+    }
+
     pub fn metadata(&self) -> &RWTFMetadata {
         &self.metadata
     }
 
+    /// Problems which were tolerated while parsing this file, see `ParseOptions`.
+    pub fn warnings(&self) -> &[ParseWarning] {
+        &self.warnings
+    }
+
+    /// Writes the track points, course points, points of interest, laps and
+    /// events sections, skipping any without points. Readers accept them in any
+    /// order but refuse a file with two sections of the same type.
     pub fn write<W: Write>(&self, out: &mut W) -> Result<usize> {
-        // Prepare all the data
-        let mut metadata_table_buf = vec![];
-        self.metadata.write(&mut metadata_table_buf).context(WriteMetadataTable)?;
+        self.write_reporting(out, 0, &mut |_| {})
+    }
 
-        let mut track_points_buf = vec![];
-        if self.track_points.len() > 0 {
-            self.track_points.write(&mut track_points_buf).context(WriteSection)?;
+    /// Like `write`, calling `progress` after the header and as each column
+    /// of each section is encoded. Working out `Progress::total_bytes` takes
+    /// an extra pass over any XorFloat columns, see `estimated_encoded_size`.
+    pub fn write_with_progress<W: Write, F: FnMut(Progress)>(&self, out: &mut W, mut progress: F) -> Result<usize> {
+        self.write_reporting(out, self.estimated_encoded_size()?, &mut progress)
+    }
+
+    fn write_reporting<W: Write>(&self, out: &mut W, total_bytes: usize, report: &mut dyn FnMut(Progress)) -> Result<usize> {
+        let mut out = Crc32Writer::new(out);
+        let out = &mut out;
+        let mut written = self.write_start(out, self.file_version())?;
+        let mut rows = 0;
+        report(Progress{rows, bytes: written, total_bytes});
+        for section in self.sections().iter().copied().filter(|section| section.len() > 0) {
+            let parts = self.split_section(section)?;
+            for part in parts.as_deref().unwrap_or_else(|| std::slice::from_ref(section)) {
+                written += part.write_reporting(out, total_bytes, &mut |p| report(Progress{rows: rows + p.rows,
+                                                                                          bytes: written + p.bytes,
+                                                                                          total_bytes}))
+                    .context(WriteSection)?;
+                rows += part.len();
+            }
         }
+        written += write(out, &RWTFTRAILER).context(WriteTrailer)?;
+        written += self.write_file_checksum(out)?;
+        report(Progress{rows, bytes: written, total_bytes});
 
-        let mut course_points_buf = vec![];
-        if self.course_points.len() > 0 {
-            self.course_points.write(&mut course_points_buf).context(WriteSection)?;
+        Ok(written)
+    }
+
+    // The sections `write` writes, in order
+    fn sections(&self) -> [&Section; 5] {
+        [&self.track_points, &self.course_points, &self.points_of_interest, &self.laps, &self.events]
+    }
+
+    // The parts `section` will be written in, if it's over the max section size
+    fn split_section(&self, section: &Section) -> Result<Option<Vec<Section>>> {
+        match self.max_section_size {
+            Some(max_size) => Ok(section.split(max_size).context(WriteSection)?),
+            None => Ok(None),
+        }
+    }
+
+    /// Sections which would encode to more than `max_size` bytes are written
+    /// as a section of up to `max_size` bytes followed by continuation
+    /// sections, each also up to `max_size` bytes, holding the rest of its
+    /// rows. Readers join them back together. A single row larger than
+    /// `max_size` gets a section to itself. The default is None, meaning no
+    /// limit, which files for readers older than continuation sections need.
+    pub fn set_max_section_size(&mut self, max_size: Option<usize>) {
+        self.max_section_size = max_size;
+    }
+
+    pub fn max_section_size(&self) -> Option<usize> {
+        self.max_section_size
+    }
+
+    /// Follow the trailer with a CRC32 of everything before it, so damage
+    /// anywhere in the file is caught, including in the header and metadata
+    /// and between sections, which the CRCs of each part don't cover. A flag
+    /// in the header says it's there, and readers check it when it is, see
+    /// `TrackReader::verify_file_checksum` for reading a file a piece at a
+    /// time. Files read with one have this set.
+    /// The default is false, since readers older than file checksums refuse
+    /// the extra bytes with `ParseOptions::set_strict_layout`.
+    pub fn set_file_checksum(&mut self, file_checksum: bool) {
+        self.file_checksum = file_checksum;
+    }
+
+    pub fn file_checksum(&self) -> bool {
+        self.file_checksum
+    }
+
+    pub(crate) fn write_file_checksum<W: Write>(&self, out: &mut Crc32Writer<W>) -> Result<usize> {
+        if self.file_checksum {
+            let crc = out.crc().to_le_bytes();
+            Ok(write(out, &crc).context(WriteTrailer)?)
+        } else {
+            Ok(0)
+        }
+    }
+
+    /// Writes this file's header and metadata followed by `sections`, copied
+    /// without decoding them, in place of this file's own sections. See
+    /// `parse_raw_sections`.
+    pub fn write_with_sections<W: Write>(&self, out: &mut W, sections: &[RawSection]) -> Result<usize> {
+        for (i, section) in sections.iter().enumerate() {
+            if section.section_type != SectionType::Continuation &&
+                sections[..i].iter().any(|other| other.section_type == section.section_type) {
+                return DuplicateSection{section_type: section.section_type}.fail();
+            }
         }
 
+        let file_version = sections.iter().map(|section| section.file_version).max().unwrap_or(0);
+        let file_version = if self.file_checksum { file_version.max(1) } else { file_version };
+
+        let mut out = Crc32Writer::new(out);
+        let out = &mut out;
+        let mut written = self.write_start(out, file_version)?;
+        for section in sections {
+            written += write(out, section.bytes).context(WriteBytes)?;
+        }
+        written += write(out, &RWTFTRAILER).context(WriteTrailer)?;
+        written += self.write_file_checksum(out)?;
+
+        Ok(written)
+    }
+
+    // The file version needed to hold this file's sections and file
+    // checksum, see `RWTF_FILE_VERSION`
+    fn file_version(&self) -> u8 {
+        let sections = self.sections().iter().map(|section| section.file_version()).max().unwrap_or(0);
+        if self.file_checksum { sections.max(1) } else { sections }
+    }
+
+    // Writes the header, saying it's `file_version`, and metadata table
+    pub(crate) fn write_start<W: Write>(&self, out: &mut W, file_version: u8) -> Result<usize> {
+        // Prepare all the data
+        let mut metadata_table_buf = vec![];
+        self.metadata.write(&mut metadata_table_buf).context(WriteMetadataTable)?;
+
         let header_size: u16 = 24;
         let metadata_table_offset: u16 = header_size;
         let data_offset: u16 = metadata_table_offset + u16::try_from(metadata_table_buf.len()).context(NumberTruncation{})?;
 
-        // Write all the data
-        let mut written = self.header.write(out, metadata_table_offset, data_offset)?;
+        // Write all the data - sections go straight to `out` since only the
+        // metadata table size is needed up front
+        let mut written = self.header.write(out, file_version, metadata_table_offset, data_offset, self.file_checksum)?;
         written += write(out, &metadata_table_buf).context(WriteBytes)?;
-        written += write(out, &track_points_buf).context(WriteBytes)?;
-        written += write(out, &course_points_buf).context(WriteBytes)?;
-        written += write(out, &RWTFTRAILER).context(WriteTrailer)?;
 
         Ok(written)
     }
+
+    /// A hash of this file's contents rather than its bytes: the track type
+    /// and the values in each section. Copies of a file which were written
+    /// again, with different encodings or at a different time, have the
+    /// same fingerprint.
+    pub fn fingerprint(&self) -> u64 {
+        let mut digest = crc64::Digest::new(crc64::ECMA);
+        match self.metadata.track_type() {
+            Some(track_type) => {
+                digest.write(&[0x01, track_type.type_tag()]);
+                digest.write(&track_type.id().to_le_bytes());
+            }
+            None => digest.write(&[0x00]),
+        }
+        self.track_points.hash_content(&mut digest);
+        self.course_points.hash_content(&mut digest);
+        // skipped when empty so files without them keep the fingerprint they
+        // had before these sections existed
+        if self.points_of_interest.len() > 0 {
+            self.points_of_interest.hash_content(&mut digest);
+        }
+        if self.laps.len() > 0 {
+            self.laps.hash_content(&mut digest);
+        }
+        if self.events.len() > 0 {
+            self.events.hash_content(&mut digest);
+        }
+        digest.sum64()
+    }
+
+    /// The number of bytes `write` will write for this file, worked out
+    /// without encoding its sections. See `Section::estimated_encoded_size`.
+    pub fn estimated_encoded_size(&self) -> Result<usize> {
+        let mut size = self.write_start(&mut std::io::sink(), self.file_version())?;
+        for section in self.sections().iter().filter(|section| section.len() > 0) {
+            match self.split_section(section)? {
+                Some(parts) => size += parts.iter().map(|part| part.estimated_encoded_size()).sum::<usize>(),
+                None => size += section.estimated_encoded_size(),
+            }
+        }
+
+        let checksum_size = if self.file_checksum { 4 } else { 0 };
+        Ok(size + RWTFTRAILER.len() + checksum_size)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+        self.write(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// A copy of this file with only the track points in `rows`, renumbered
+    /// from 0, e.g. one piece of a ride split up with
+    /// `TrackPointReader::split_at_pauses`. The metadata, course points and
+    /// points of interest are kept as they are. Laps are kept if all their
+    /// rows are in `rows`, with their rows renumbered to match, and events
+    /// if they're within the times of the track points kept.
+    pub fn crop(&self, rows: Range<usize>) -> Result<RWTFile> {
+        let len = self.track_points.len();
+        let rows = rows.start.min(len)..rows.end.min(len).max(rows.start.min(len));
+        let track = TrackPointReader::new(&self.track_points);
+
+        let mut out = RWTFile::new();
+        out.metadata = self.metadata.clone();
+        out.max_section_size = self.max_section_size;
+        out.file_checksum = self.file_checksum;
+        out.track_points = self.track_points
+            .copy_rows(SectionType::TrackPoints, rows.clone())
+            .context(CropSection)?;
+        out.course_points = self.course_points.clone();
+        out.points_of_interest = self.points_of_interest.clone();
+
+        let laps = LapReader::new(&self.laps);
+        let kept_laps = (0..laps.len())
+            .filter(|index| laps.track_rows(*index, &track).is_some_and(|lap| rows.start <= lap.start && lap.end <= rows.end))
+            .collect::<Vec<_>>();
+        out.laps = Transform::new()
+            .filter(|index, _| kept_laps.contains(&index))
+            .map(|_, values| {
+                for field in &[fields::START_ROW, fields::END_ROW] {
+                    if let Some(DataField::Number(row)) = values.get_mut(field.name) {
+                        *row -= rows.start as i64;
+                    }
+                }
+            })
+            .apply(&self.laps)?;
+
+        let times = rows.clone().filter_map(|row| track.time(row));
+        let time_range = times.clone().min().zip(times.max());
+        out.events = Transform::new()
+            .filter(|_, values| match (values.get(fields::TIME.name), time_range) {
+                (Some(DataField::Number(time)), Some((first, last))) => first <= *time && *time <= last,
+                _ => false,
+            })
+            .apply(&self.events)?;
+
+        Ok(out)
+    }
+
+    /// `files` one after another as a single file, the other way around
+    /// from `crop`, e.g. to stitch together the recordings a device made
+    /// either side of a crash. The track points, laps and events sections
+    /// are combined with `transform::merge`, with lap rows renumbered to
+    /// point at the same track points as before. The metadata is taken from
+    /// the first file, and the course points and points of interest from the
+    /// first file with any, since every piece of a ride usually has the
+    /// same ones.
+    pub fn join(files: &[&RWTFile]) -> Result<RWTFile> {
+        let mut out = RWTFile::new();
+        if let Some(first) = files.first() {
+            out.metadata = first.metadata.clone();
+            out.max_section_size = first.max_section_size;
+            out.file_checksum = first.file_checksum;
+        }
+
+        let sections = |f: fn(&RWTFile) -> &Section| files.iter().map(|file| f(file)).collect::<Vec<_>>();
+        out.track_points = merge(SectionType::TrackPoints, &sections(RWTFile::track_points))?;
+        out.events = merge(SectionType::Events, &sections(RWTFile::events))?;
+
+        let mut start = 0;
+        let mut laps = Vec::with_capacity(files.len());
+        for file in files {
+            laps.push(Transform::new()
+                .map(|_, values| {
+                    for field in &[fields::START_ROW, fields::END_ROW] {
+                        if let Some(DataField::Number(row)) = values.get_mut(field.name) {
+                            *row += start as i64;
+                        }
+                    }
+                })
+                .apply(&file.laps)?);
+            start += file.track_points.len();
+        }
+        out.laps = merge(SectionType::Laps, &laps.iter().collect::<Vec<_>>())?;
+
+        let first_with = |f: fn(&RWTFile) -> &Section| sections(f).into_iter().find(|section| section.len() > 0).cloned();
+        if let Some(course_points) = first_with(RWTFile::course_points) {
+            out.course_points = course_points;
+        }
+        if let Some(points_of_interest) = first_with(RWTFile::points_of_interest) {
+            out.points_of_interest = points_of_interest;
+        }
+
+        Ok(out)
+    }
+
+    /// A copy of this file with every time moved by `seconds`: the track
+    /// point, lap and event times and the course point ETAs. For fixing up a
+    /// recording made with the device's clock set wrong, or before `join`ing
+    /// it onto another. Fails if any time would overflow.
+    pub fn shift_times(&self, seconds: i64) -> Result<RWTFile> {
+        let overflowed = Cell::new(false);
+        let shift = |section: &Section, names: &[&str]| {
+            Transform::new()
+                .map(|_, values| {
+                    for name in names {
+                        if let Some(DataField::Number(time)) = values.get_mut(*name) {
+                            match time.checked_add(seconds) {
+                                Some(shifted) => *time = shifted,
+                                None => overflowed.set(true),
+                            }
+                        }
+                    }
+                })
+                .apply(section)
+        };
+
+        let mut out = RWTFile::new();
+        out.metadata = self.metadata.clone();
+        out.max_section_size = self.max_section_size;
+        out.file_checksum = self.file_checksum;
+        out.track_points = shift(&self.track_points, &[fields::TIME.name])?;
+        out.course_points = shift(&self.course_points, &[fields::ETA.name])?;
+        out.points_of_interest = self.points_of_interest.clone();
+        out.laps = shift(&self.laps, &[fields::TIME.name, fields::END_TIME.name])?;
+        out.events = shift(&self.events, &[fields::TIME.name])?;
+        if overflowed.get() {
+            return ShiftTimes{seconds}.fail();
+        }
+
+        Ok(out)
+    }
+
+    /// Replaces the metadata, e.g. with another file's when combining files.
+    pub fn set_metadata(&mut self, metadata: RWTFMetadata) {
+        self.metadata = metadata;
+    }
 }
 
 impl Serialize for RWTFile {
@@ -252,6 +1106,18 @@ impl Serialize for RWTFile {
             map.serialize_entry("course_points", &self.course_points)?;
         }
 
+        if self.points_of_interest.len() > 0 {
+            map.serialize_entry("points_of_interest", &self.points_of_interest)?;
+        }
+
+        if self.laps.len() > 0 {
+            map.serialize_entry("laps", &self.laps)?;
+        }
+
+        if self.events.len() > 0 {
+            map.serialize_entry("events", &self.events)?;
+        }
+
         map.end()
     }
 }
@@ -259,6 +1125,9 @@ impl Serialize for RWTFile {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use assert_matches::assert_matches;
+    use crate::section::{Column};
+    use crate::event::{EventReader, EventType};
 
     #[test]
     fn testfoo() {
@@ -282,11 +1151,290 @@ mod tests {
         assert!(f.add_track_point(1, "foo", DataField::Base64("invalid base64".into())).is_err());
     }
 
+    #[test]
+    fn test_read() {
+        let mut f = RWTFile::with_track_type(TrackType::Route(7));
+        assert!(f.add_track_point(0, "x", DataField::LongFloat(1.5)).is_ok());
+        assert!(f.add_track_point(1, "x", DataField::LongFloat(2.5)).is_ok());
+        assert!(f.add_course_point(0, "name", DataField::String("hi".into())).is_ok());
+
+        let bytes = f.to_bytes().unwrap();
+        let rwtf = RWTFile::read(&bytes).unwrap();
+        assert_eq!(rwtf.metadata().track_type(), Some(TrackType::Route(7)));
+        assert_eq!(rwtf.track_points().len(), 2);
+        assert_eq!(rwtf.course_points().len(), 1);
+
+        assert!(RWTFile::read(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_edit() {
+        let mut f = RWTFile::new();
+        for i in 0..6 {
+            assert!(f.add_track_point(i, "t", i as i64).is_ok());
+            assert!(f.add_track_point(i, "h", 100 + i as i64).is_ok());
+        }
+
+        // an hr spike, and a point that should never have been there
+        assert_matches!(f.set_track_point(2, "h", 250), Ok(Some(DataField::Number(102))));
+        assert_matches!(f.track_points.remove(2, "h"), Some(DataField::Number(250)));
+        assert_matches!(f.track_points.remove(2, "h"), None);
+        f.track_points.delete_rows(4..5);
+
+        // the old value stays when the new one doesn't fit
+        assert!(f.set_track_point(0, "t", DataField::String("noon".into())).is_err());
+        assert_matches!(f.set_track_point(9, "h", 1), Ok(None));
+        f.track_points.delete_rows(5..100);
+
+        let rwtf = RWTFile::read(&f.to_bytes().unwrap()).unwrap();
+        let columns = rwtf.track_points().columns();
+        assert_eq!(rwtf.track_points().len(), 5);
+        assert_matches!(columns.get("t"), Some(Column::Numbers(m)) if m.values().cloned().collect::<Vec<_>>() == vec![0, 1, 2, 3, 5]);
+        assert_matches!(columns.get("h"), Some(Column::Numbers(m)) if m.iter().map(|(i, v)| (*i, *v)).collect::<Vec<_>>() == vec![(0, 100), (1, 101), (3, 103), (4, 105)]);
+        // rows left with no values are still rows
+        let mut f = RWTFile::new();
+        assert!(f.add_track_point(0, "t", 1).is_ok());
+        assert_matches!(f.track_points.remove(0, "t"), Some(DataField::Number(1)));
+        assert_eq!(f.track_points().len(), 1);
+        assert_eq!(RWTFile::read(&f.to_bytes().unwrap()).unwrap().track_points().len(), 1);
+
+        let mut f = RWTFile::new();
+        assert!(f.add_track_point(0, "t", 1).is_ok());
+        f.track_points.extend_to(3);
+        f.track_points.delete_rows(0..2);
+        assert_eq!(f.track_points().len(), 1);
+        assert_eq!(RWTFile::read(&f.to_bytes().unwrap()).unwrap().track_points().len(), 1);
+        f.track_points.delete_rows(0..1);
+        assert_eq!(f.track_points().len(), 0);
+    }
+
+    #[test]
+    fn test_estimated_encoded_size() {
+        let empty = RWTFile::new();
+        assert_eq!(empty.estimated_encoded_size().unwrap(), empty.to_bytes().unwrap().len());
+
+        for run_length_encoding in &[false, true] {
+            let mut f = RWTFile::with_track_type(TrackType::Route(7));
+            f.track_points.set_run_length_encoding(*run_length_encoding);
+            for i in 0..200 {
+                assert!(f.add_track_point(i, "n", (i / 50) as i64 * 1000).is_ok());
+                assert!(f.add_track_point(i, "x", DataField::LongFloat(i as f64 / 7.0)).is_ok());
+                assert!(f.add_track_point(i, "d", DataField::ShortFloat(i as f64 * 12.5)).is_ok());
+                assert!(f.add_track_point(i, "v", DataField::XorFloat((i as f64).sqrt())).is_ok());
+                assert!(f.add_track_point(i, "p", DataField::PackedBool(i % 3 == 0)).is_ok());
+                if i % 4 == 0 {
+                    assert!(f.add_track_point(i, "b", DataField::Bool(true)).is_ok());
+                    assert!(f.add_track_point(i, "i", DataField::IDs(vec![i as u64, 300])).is_ok());
+                    assert!(f.add_track_point(i, "B", DataField::Base64(base64::encode(&[i as u8; 5]))).is_ok());
+                }
+            }
+            assert!(f.add_course_point(3, "name", DataField::String("turn left".into())).is_ok());
+            assert!(f.add_point_of_interest(0, "N", DataField::String("Bike Shop".into())).is_ok());
+            assert!(f.add_lap(0, "b", 100).is_ok());
+            assert!(f.add_event(0, "t", 1000).is_ok());
+
+            assert_eq!(f.estimated_encoded_size().unwrap(), f.to_bytes().unwrap().len());
+        }
+    }
+
+    #[test]
+    fn test_write_with_progress() {
+        let mut f = RWTFile::new();
+        for i in 0..100 {
+            assert!(f.add_track_point(i, "t", 1000 + i as i64).is_ok());
+            assert!(f.add_track_point(i, "x", DataField::XorFloat(i as f64 / 7.0)).is_ok());
+        }
+        assert!(f.add_course_point(0, "n", DataField::String("start".into())).is_ok());
+        f.set_max_section_size(Some(400));
+
+        let mut reports = vec![];
+        let mut buf = vec![];
+        let written = f.write_with_progress(&mut buf, |progress| reports.push(progress)).unwrap();
+        assert_eq!(buf, f.to_bytes().unwrap());
+        assert!(reports.len() > 6);
+        assert!(reports.windows(2).all(|w| w[0].rows <= w[1].rows && w[0].bytes <= w[1].bytes));
+        assert_eq!(f.estimated_encoded_size().unwrap(), written);
+        assert!(reports.iter().all(|progress| progress.total_bytes == written));
+        assert_eq!(reports.last(), Some(&Progress{rows: 101, bytes: written, total_bytes: written}));
+        assert_eq!(reports.last().unwrap().fraction(), 1.0);
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let build = |packed: bool, run_length_encoding: bool| {
+            let mut f = RWTFile::with_track_type(TrackType::Trip(1));
+            f.track_points.set_run_length_encoding(run_length_encoding);
+            for i in 0..20 {
+                assert!(f.add_track_point(i, "x", DataField::LongFloat(i as f64 / 3.0)).is_ok());
+                assert!(f.add_track_point(i, "S", (i / 10) as i64).is_ok());
+                let paused = if packed { DataField::PackedBool(i > 15) } else { DataField::Bool(i > 15) };
+                assert!(f.add_track_point(i, "paused", paused).is_ok());
+            }
+            assert!(f.add_course_point(0, "n", DataField::String("start".into())).is_ok());
+            f
+        };
+
+        let f = build(false, false);
+        let fingerprint = f.fingerprint();
+        assert_eq!(RWTFile::read(&f.to_bytes().unwrap()).unwrap().fingerprint(), fingerprint);
+        assert_eq!(build(true, true).fingerprint(), fingerprint);
+
+        let mut changed = build(false, false);
+        assert!(changed.set_track_point(3, "S", 2).is_ok());
+        assert_ne!(changed.fingerprint(), fingerprint);
+
+        let mut renamed = RWTFile::with_track_type(TrackType::Trip(1));
+        assert!(renamed.add_track_point(0, "a", 1).is_ok());
+        let mut other = RWTFile::with_track_type(TrackType::Trip(1));
+        assert!(other.add_track_point(0, "b", 1).is_ok());
+        assert_ne!(renamed.fingerprint(), other.fingerprint());
+        assert_ne!(RWTFile::new().fingerprint(), RWTFile::with_track_type(TrackType::Trip(0)).fingerprint());
+
+        let mut with_poi = build(false, false);
+        assert!(with_poi.add_point_of_interest(0, "N", "Summit".to_string()).is_ok());
+        assert_ne!(with_poi.fingerprint(), fingerprint);
+    }
+
+    #[test]
+    fn test_crop() {
+        let mut f = RWTFile::with_track_type(TrackType::Trip(7));
+        for i in 0..10 {
+            assert!(f.add_track_point(i, fields::TIME.name, 1000 + i as i64 * 10).is_ok());
+        }
+        assert!(f.add_course_point(0, "n", "Start".to_string()).is_ok());
+        assert!(f.add_standard_lap(0, &Lap{start_row: Some(0), end_row: Some(4), ..Lap::default()}).is_ok());
+        assert!(f.add_standard_lap(1, &Lap{start_row: Some(5), end_row: Some(7), ..Lap::default()}).is_ok());
+        assert!(f.add_standard_lap(2, &Lap{start_row: Some(4), end_row: Some(9), ..Lap::default()}).is_ok());
+        assert!(f.add_standard_event(0, &Event{time: 1020, event_type: EventType::Pause}).is_ok());
+        assert!(f.add_standard_event(1, &Event{time: 1060, event_type: EventType::Resume}).is_ok());
+
+        let cropped = f.crop(5..8).unwrap();
+        assert_eq!(cropped.metadata().track_type(), Some(TrackType::Trip(7)));
+        let track = TrackPointReader::new(cropped.track_points());
+        assert_eq!(track.len(), 3);
+        assert_eq!(track.time(0), Some(1050));
+        assert_eq!(cropped.course_points().len(), 1);
+        assert_eq!(LapReader::new(cropped.laps()).laps().collect::<Vec<_>>(),
+                   vec![Lap{start_row: Some(0), end_row: Some(2), ..Lap::default()}]);
+        assert_eq!(EventReader::new(cropped.events()).events(),
+                   &[Event{time: 1060, event_type: EventType::Resume}]);
+
+        let empty = f.crop(20..30).unwrap();
+        assert_eq!(empty.track_points().len(), 0);
+        assert_eq!(empty.laps().len(), 0);
+        assert_eq!(empty.events().len(), 0);
+        assert!(RWTFile::read(&cropped.to_bytes().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_join() {
+        let mut f = RWTFile::with_track_type(TrackType::Trip(7));
+        for i in 0..10 {
+            assert!(f.add_track_point(i, fields::TIME.name, 1000 + i as i64 * 10).is_ok());
+        }
+        assert!(f.add_course_point(0, "n", "Start".to_string()).is_ok());
+        assert!(f.add_standard_lap(0, &Lap{start_row: Some(6), end_row: Some(9), ..Lap::default()}).is_ok());
+        assert!(f.add_standard_event(0, &Event{time: 1070, event_type: EventType::Pause}).is_ok());
+
+        let first = f.crop(0..5).unwrap();
+        let second = f.crop(5..10).unwrap();
+        let joined = RWTFile::join(&[&first, &second]).unwrap();
+        assert_eq!(joined.metadata().track_type(), Some(TrackType::Trip(7)));
+        let times = |file: &RWTFile| (0..file.track_points().len()).map(|i| TrackPointReader::new(file.track_points()).time(i)).collect::<Vec<_>>();
+        assert_eq!(times(&joined), times(&f));
+        assert_eq!(joined.course_points().len(), 1);
+        assert_eq!(LapReader::new(joined.laps()).lap(0), Lap{start_row: Some(6), end_row: Some(9), ..Lap::default()});
+        assert_eq!(EventReader::new(joined.events()).events(), EventReader::new(f.events()).events());
+
+        let shifted = second.shift_times(-100).unwrap();
+        assert_eq!(TrackPointReader::new(shifted.track_points()).time(0), Some(950));
+        assert_eq!(EventReader::new(shifted.events()).events()[0].time, 970);
+        assert_eq!(shifted.course_points().len(), 1);
+        assert_matches!(second.shift_times(i64::MAX), Err(Error::ShiftTimes{seconds: i64::MAX}));
+        assert_matches!(second.shift_times(-2000).and_then(|f| f.shift_times(i64::MIN)), Err(Error::ShiftTimes{..}));
+
+        let mut other = RWTFile::with_track_type(TrackType::Route(3));
+        assert!(other.add_track_point(0, fields::TIME.name, 5).is_ok());
+        let mut joined = RWTFile::join(&[&other, &first]).unwrap();
+        assert_eq!(joined.metadata().track_type(), Some(TrackType::Route(3)));
+        assert_eq!(joined.course_points().len(), 1);
+        joined.set_metadata(first.metadata().clone());
+        assert_eq!(joined.metadata().track_type(), Some(TrackType::Trip(7)));
+
+        assert_eq!(RWTFile::join(&[]).unwrap().track_points().len(), 0);
+    }
+
+    #[test]
+    fn test_set_section() {
+        fn assert_send<T: Send + Sync>() {}
+        assert_send::<Section>();
+        assert_send::<RWTFile>();
+
+        let (track_points, laps) = std::thread::scope(|scope| {
+            let track_points = scope.spawn(|| {
+                let mut s = Section::new(SectionType::TrackPoints);
+                for i in 0..100 {
+                    assert!(s.add(i, fields::TIME.name, i as i64).is_ok());
+                }
+                s
+            });
+            let laps = scope.spawn(|| {
+                let mut s = Section::new(SectionType::Laps);
+                assert!(s.add(0, fields::START_ROW.name, 0).is_ok());
+                assert!(s.add(0, fields::END_ROW.name, 99).is_ok());
+                s
+            });
+            (track_points.join().unwrap(), laps.join().unwrap())
+        });
+
+        let mut f = RWTFile::new();
+        assert!(f.add_track_point(0, "x", 1).is_ok());
+        assert_eq!(f.set_section(track_points).unwrap().len(), 1);
+        assert_eq!(f.set_section(laps).unwrap().len(), 0);
+        assert_matches!(f.set_section(Section::new(SectionType::Continuation)), Err(Error::SetContinuation{}));
+
+        let rwtf = RWTFile::read(&f.to_bytes().unwrap()).unwrap();
+        assert_eq!(rwtf.track_points().len(), 100);
+        assert!(!rwtf.track_points().columns().contains_key("x"));
+        assert_eq!(LapReader::new(rwtf.laps()).lap(0).end_row, Some(99));
+
+        let mut s = Section::new(SectionType::Events);
+        assert!(s.add(0, "t", 1).is_ok());
+        assert_matches!(s.add(0, "t", 2), Err(Error::AddTrackPoint{..}));
+    }
+
+    #[test]
+    fn test_data_field_conversions() {
+        assert_eq!(DataField::Number(5).as_i64(), Some(5));
+        assert_eq!(DataField::Number(5).as_f64(), None);
+        assert_eq!(DataField::ShortFloat(1.5).as_f64(), Some(1.5));
+        assert_eq!(DataField::PackedBool(true).as_bool(), Some(true));
+        assert_eq!(DataField::String("hi".into()).as_str(), Some("hi"));
+        assert_eq!(DataField::Base64("aGk=".into()).as_str(), None);
+        assert_eq!(DataField::IDs(vec![1, 2]).as_ids(), Some(&[1, 2][..]));
+        assert_eq!(DataField::XorFloat(0.0).field_type(), FieldType::XorFloat);
+
+        assert_eq!(i64::try_from(DataField::Number(-1)), Ok(-1));
+        assert_eq!(f64::try_from(DataField::LongFloat(2.5)), Ok(2.5));
+        assert_eq!(bool::try_from(DataField::Bool(false)), Ok(false));
+        assert_eq!(String::try_from(DataField::String("a".into())), Ok("a".to_string()));
+        assert_eq!(Vec::<u64>::try_from(DataField::IDs(vec![3])), Ok(vec![3]));
+        assert_eq!(i64::try_from(DataField::String("7".into())), Err(DataField::String("7".into())));
+
+        let t = UNIX_EPOCH + Duration::from_millis(1614834367500);
+        assert_eq!(DataField::from(t), DataField::Timestamp(1614834367500));
+        assert_eq!(DataField::from(UNIX_EPOCH - Duration::from_secs(1)), DataField::Timestamp(-1000));
+        assert_eq!(SystemTime::try_from(DataField::Timestamp(1614834367500)), Ok(t));
+        assert_eq!(DataField::Timestamp(-1000).as_system_time(), Some(UNIX_EPOCH - Duration::from_secs(1)));
+        assert_eq!(DataField::Timestamp(5).as_i64(), None);
+        assert_eq!(serde_json::to_string(&DataField::Timestamp(1614834367500)).unwrap(), "\"2021-03-04T05:06:07.500Z\"");
+    }
+
     #[test]
     fn test_write_header() {
         let f = RWTFHeader::new();
         let mut buf = vec![];
-        let written = f.write(&mut buf, 0x0A, 0x1A);
+        let written = f.write(&mut buf, 0, 0x0A, 0x1A, false);
         assert!(written.is_ok());
         let expected = &[0x89, // magic number
                          0x52,
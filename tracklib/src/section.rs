@@ -3,13 +3,23 @@ use snafu::{Snafu, ResultExt};
 use std::collections::btree_map::{self, BTreeMap};
 use std::convert::{TryFrom};
 use std::cmp;
+use std::ops::{Range, RangeInclusive};
+use ::crc::crc64::{self, Hasher64};
 use serde::ser::{Serialize, Serializer, SerializeSeq, SerializeMap};
-use crate::rwtfile::{DataField};
-use crate::flagscolumn::{self, FlagsColumn};
-use crate::utils::{write};
+use crate::rwtfile::{DataField, RWTFile};
+use crate::flagscolumn::{self, delete_rows, FlagsColumn, MAX_FIELDS};
+use crate::error::{ErrorKind};
+use crate::fields::{FieldType};
+use crate::utils::{write, signed_leb128_len, unsigned_leb128_len, Crc32Writer};
 use crate::polyline::FieldEncodeOptions;
 use crate::simplify::simplify_and_encode;
 use crate::surface::SurfaceMapping;
+use crate::xorfloat;
+use crate::bitpack;
+use crate::map;
+use crate::progress::{Progress};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -31,12 +41,43 @@ pub enum Error {
     WriteDataColumnNumberOfPoints{},
     #[snafu(display("Number truncation error: {}", source))]
     NumberTruncation{source: std::num::TryFromIntError},
+    #[snafu(display("Column {} value {} at index {} is out of range", name, value, index))]
+    FloatOutOfRange{name: String, index: usize, value: f64},
+    #[snafu(display("Column {} would be more than the {} columns a section can have", name, max))]
+    TooManyColumns{name: String, max: usize},
+    #[snafu(display("Column {} key {} isn't a Number, LongFloat, String or Bool", name, key))]
+    NonScalarMapValue{name: String, key: String},
+    #[snafu(display("Column {} has flags but no values", name))]
+    MissingColumn{name: String},
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::ColumnTypeChange{..} |
+            Error::IndexAlreadyUsed{..} |
+            Error::FloatOutOfRange{..} |
+            Error::NonScalarMapValue{..} |
+            Error::MissingColumn{..} => ErrorKind::Schema,
+            Error::WriteDataColumnNumberOfPoints{..} |
+            Error::NumberTruncation{..} |
+            Error::TooManyColumns{..} => ErrorKind::TooLarge,
+            Error::WriteTypesTable{..} |
+            Error::WriteDataColumn{..} |
+            Error::WriteHeader{..} |
+            Error::WriteBytes{..} => ErrorKind::Io,
+            Error::WriteFlagsColumn{source} => source.kind(),
+        }
+    }
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+// The (count, value) runs of each run-length encoded Numbers column, by name
+type RunLengthColumns<'a> = BTreeMap<&'a str, Vec<(u64, i64)>>;
 
-#[derive(Debug)]
+
+#[derive(Debug, Clone)]
 pub enum Column {
     Numbers(BTreeMap<usize, i64>),
     LongFloat(BTreeMap<usize, f64>),
@@ -45,9 +86,153 @@ pub enum Column {
     String(BTreeMap<usize, String>),
     Bool(BTreeMap<usize, bool>),
     IDs(BTreeMap<usize, Vec<u64>>),
+    PackedBool(BTreeMap<usize, bool>),
+    XorFloat(BTreeMap<usize, f64>),
+    Map(BTreeMap<usize, BTreeMap<String, DataField>>),
+    GeoPoint(BTreeMap<usize, (f64, f64)>),
+    I64Array(BTreeMap<usize, Vec<i64>>),
+    F64Array(BTreeMap<usize, Vec<f64>>),
+    /// Milliseconds since the unix epoch.
+    Timestamp(BTreeMap<usize, i64>),
+}
+
+// LongFloat and ShortFloat values are stored as deltas between i64s scaled by
+// these factors. Keeping the scaled values within +/- 2^61 means no delta can
+// overflow.
+const LONG_FLOAT_SCALE: f64 = 10000000.0;
+const SHORT_FLOAT_SCALE: f64 = 1000.0;
+const MAX_SCALED_FLOAT: f64 = 2305843009213693952.0;
+
+/// The most decimal places a GeoPoint column can keep, about 0.1mm. This is
+/// the most for an F64Array column too.
+pub const MAX_GEO_POINT_DECIMALS: u8 = 9;
+const DEFAULT_GEO_POINT_DECIMALS: u8 = 7;
+const DEFAULT_F64_ARRAY_DECIMALS: u8 = 3;
+
+/// How precisely a Timestamp column stores its times.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TimestampUnit {
+    Seconds,
+    Millis,
+}
+
+impl TimestampUnit {
+    // Stored as the decimal places of a second it keeps
+    pub(crate) fn from_decimals(decimals: u8) -> Option<Self> {
+        match decimals {
+            0 => Some(TimestampUnit::Seconds),
+            3 => Some(TimestampUnit::Millis),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn decimals(self) -> u8 {
+        match self {
+            TimestampUnit::Seconds => 0,
+            TimestampUnit::Millis => 3,
+        }
+    }
+
+    // The number of milliseconds in one of the unit
+    pub(crate) fn millis(self) -> i64 {
+        match self {
+            TimestampUnit::Seconds => 1000,
+            TimestampUnit::Millis => 1,
+        }
+    }
+
+    // A time in milliseconds rounded to the nearest whole unit
+    pub(crate) fn round(self, v: i64) -> i64 {
+        let millis = self.millis();
+        v.saturating_add(millis / 2).div_euclid(millis).saturating_mul(millis)
+    }
+}
+
+/// What to do with a LongFloat, ShortFloat or F64Array value outside of the
+/// range its column can store. NaN and the infinities are always outside of it, so a
+/// decoded LongFloat or ShortFloat value is always finite. XorFloat columns
+/// store any f64 exactly, NaN and infinities included.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FloatOverflowPolicy {
+    /// Refuse to add the value.
+    Error,
+    /// Clamp the value to the nearest end of the range, so the infinities
+    /// become the range's ends. NaN has no nearest end and is dropped.
+    Saturate,
+    /// Drop the value, leaving the row without one.
+    Null,
 }
 
 impl Column {
+    /// The values a LongFloat column can store.
+    pub fn long_float_range() -> RangeInclusive<f64> {
+        (-MAX_SCALED_FLOAT / LONG_FLOAT_SCALE)..=(MAX_SCALED_FLOAT / LONG_FLOAT_SCALE)
+    }
+
+    /// The values a ShortFloat column can store.
+    pub fn short_float_range() -> RangeInclusive<f64> {
+        (-MAX_SCALED_FLOAT / SHORT_FLOAT_SCALE)..=(MAX_SCALED_FLOAT / SHORT_FLOAT_SCALE)
+    }
+
+    /// The smallest float column type that stores every one of `values`
+    /// exactly: ShortFloat if none has more than 3 decimals, LongFloat if
+    /// none has more than 7, and XorFloat otherwise.
+    pub fn infer_float_type(values: &[f64]) -> FieldType {
+        let fits = |scale: f64, range: RangeInclusive<f64>| {
+            values.iter().all(|v| range.contains(v) && (v * scale).round() / scale == *v)
+        };
+
+        if fits(SHORT_FLOAT_SCALE, Self::short_float_range()) {
+            FieldType::ShortFloat
+        } else if fits(LONG_FLOAT_SCALE, Self::long_float_range()) {
+            FieldType::LongFloat
+        } else {
+            FieldType::XorFloat
+        }
+    }
+
+    // The same values as a column of a type that holds at least as much,
+    // or the column back if it can't be converted
+    pub(crate) fn coerce(self, field_type: FieldType) -> std::result::Result<Column, Column> {
+        fn numbers_in(m: &BTreeMap<usize, i64>, range: RangeInclusive<f64>) -> Option<BTreeMap<usize, f64>> {
+            m.iter().map(|(index, v)| Some((*index, *v as f64)).filter(|(_, v)| range.contains(v))).collect()
+        }
+
+        match (self, field_type) {
+            (column, field_type) if column.field_type() == field_type => Ok(column),
+            (Column::Numbers(m), FieldType::LongFloat) => numbers_in(&m, Self::long_float_range()).map(Column::LongFloat).ok_or(Column::Numbers(m)),
+            (Column::Numbers(m), FieldType::ShortFloat) => numbers_in(&m, Self::short_float_range()).map(Column::ShortFloat).ok_or(Column::Numbers(m)),
+            (Column::Numbers(m), FieldType::XorFloat) => Ok(Column::XorFloat(m.into_iter().map(|(index, v)| (index, v as f64)).collect())),
+            (Column::ShortFloat(m), FieldType::LongFloat) => Ok(Column::LongFloat(m)),
+            (Column::ShortFloat(m), FieldType::XorFloat) | (Column::LongFloat(m), FieldType::XorFloat) => Ok(Column::XorFloat(m)),
+            (Column::Bool(m), FieldType::PackedBool) => Ok(Column::PackedBool(m)),
+            (Column::PackedBool(m), FieldType::Bool) => Ok(Column::Bool(m)),
+            (Column::Bool(m), FieldType::Numbers) | (Column::PackedBool(m), FieldType::Numbers) => {
+                Ok(Column::Numbers(m.into_iter().map(|(index, v)| (index, i64::from(v))).collect()))
+            }
+            (column, _) => Err(column),
+        }
+    }
+
+    pub fn field_type(&self) -> FieldType {
+        match self {
+            Column::Numbers(_)    => FieldType::Numbers,
+            Column::LongFloat(_)  => FieldType::LongFloat,
+            Column::ShortFloat(_) => FieldType::ShortFloat,
+            Column::Base64(_)     => FieldType::Base64,
+            Column::String(_)     => FieldType::String,
+            Column::Bool(_)       => FieldType::Bool,
+            Column::IDs(_)        => FieldType::IDs,
+            Column::PackedBool(_) => FieldType::PackedBool,
+            Column::XorFloat(_)   => FieldType::XorFloat,
+            Column::Map(_)        => FieldType::Map,
+            Column::GeoPoint(_)   => FieldType::GeoPoint,
+            Column::I64Array(_)   => FieldType::I64Array,
+            Column::F64Array(_)   => FieldType::F64Array,
+            Column::Timestamp(_)  => FieldType::Timestamp,
+        }
+    }
+
     fn type_tag(&self) -> u8 {
         match self {
             Column::Numbers(_)    => 0x00,
@@ -57,15 +242,205 @@ impl Column {
             Column::String(_)     => 0x04,
             Column::Bool(_)       => 0x05,
             Column::IDs(_)        => 0x06,
+            Column::PackedBool(_) => 0x07,
+            Column::XorFloat(_)   => 0x09,
+            Column::Map(_)        => 0x0B,
+            Column::GeoPoint(_)   => 0x0C,
+            Column::I64Array(_)   => 0x0D,
+            Column::F64Array(_)   => 0x0E,
+            Column::Timestamp(_)  => 0x0F,
+        }
+    }
+
+    /// The value at row `index` of this column, if there is one.
+    pub fn get(&self, index: usize) -> Option<DataField> {
+        match self {
+            Column::Numbers(m) => m.get(&index).map(|v| DataField::Number(*v)),
+            Column::LongFloat(m) => m.get(&index).map(|v| DataField::LongFloat(*v)),
+            Column::ShortFloat(m) => m.get(&index).map(|v| DataField::ShortFloat(*v)),
+            Column::Base64(m) => m.get(&index).map(|v| DataField::Base64(base64::encode(v))),
+            Column::String(m) => m.get(&index).map(|v| DataField::String(v.to_string())),
+            Column::Bool(m) => m.get(&index).map(|v| DataField::Bool(*v)),
+            Column::IDs(m) => m.get(&index).map(|v| DataField::IDs(v.to_vec())),
+            Column::PackedBool(m) => m.get(&index).map(|v| DataField::PackedBool(*v)),
+            Column::XorFloat(m) => m.get(&index).map(|v| DataField::XorFloat(*v)),
+            Column::Map(m) => m.get(&index).map(|v| DataField::Map(v.clone())),
+            Column::GeoPoint(m) => m.get(&index).map(|(lat, lng)| DataField::GeoPoint(*lat, *lng)),
+            Column::I64Array(m) => m.get(&index).map(|v| DataField::I64Array(v.clone())),
+            Column::F64Array(m) => m.get(&index).map(|v| DataField::F64Array(v.clone())),
+            Column::Timestamp(m) => m.get(&index).map(|v| DataField::Timestamp(*v)),
+        }
+    }
+
+    fn remove(&mut self, index: usize) -> Option<DataField> {
+        let v = self.get(index);
+        match self {
+            Column::Numbers(m) => { m.remove(&index); }
+            Column::LongFloat(m) => { m.remove(&index); }
+            Column::ShortFloat(m) => { m.remove(&index); }
+            Column::Base64(m) => { m.remove(&index); }
+            Column::String(m) => { m.remove(&index); }
+            Column::Bool(m) => { m.remove(&index); }
+            Column::IDs(m) => { m.remove(&index); }
+            Column::PackedBool(m) => { m.remove(&index); }
+            Column::XorFloat(m) => { m.remove(&index); }
+            Column::Map(m) => { m.remove(&index); }
+            Column::GeoPoint(m) => { m.remove(&index); }
+            Column::I64Array(m) => { m.remove(&index); }
+            Column::F64Array(m) => { m.remove(&index); }
+            Column::Timestamp(m) => { m.remove(&index); }
+        }
+        v
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Column::Numbers(m) => m.is_empty(),
+            Column::LongFloat(m) => m.is_empty(),
+            Column::ShortFloat(m) => m.is_empty(),
+            Column::Base64(m) => m.is_empty(),
+            Column::String(m) => m.is_empty(),
+            Column::Bool(m) => m.is_empty(),
+            Column::IDs(m) => m.is_empty(),
+            Column::PackedBool(m) => m.is_empty(),
+            Column::XorFloat(m) => m.is_empty(),
+            Column::Map(m) => m.is_empty(),
+            Column::GeoPoint(m) => m.is_empty(),
+            Column::I64Array(m) => m.is_empty(),
+            Column::F64Array(m) => m.is_empty(),
+            Column::Timestamp(m) => m.is_empty(),
+        }
+    }
+
+    // Feeds the values into `digest` the same way whichever encoding they
+    // were or will be stored with
+    fn hash_content(&self, digest: &mut crc64::Digest) {
+        fn each<T, F: Fn(&mut crc64::Digest, &T)>(digest: &mut crc64::Digest, m: &BTreeMap<usize, T>, f: F) {
+            digest.write(&(m.len() as u64).to_le_bytes());
+            for (index, v) in m {
+                digest.write(&(*index as u64).to_le_bytes());
+                f(digest, v);
+            }
+        }
+        fn bytes(digest: &mut crc64::Digest, v: &[u8]) {
+            digest.write(&(v.len() as u64).to_le_bytes());
+            digest.write(v);
+        }
+
+        match self {
+            Column::Numbers(m) => each(digest, m, |d, v| d.write(&v.to_le_bytes())),
+            Column::LongFloat(m) => each(digest, m, |d, v| d.write(&((v * LONG_FLOAT_SCALE).round() as i64).to_le_bytes())),
+            Column::ShortFloat(m) => each(digest, m, |d, v| d.write(&((v * SHORT_FLOAT_SCALE).round() as i64).to_le_bytes())),
+            Column::Base64(m) => each(digest, m, |d, v| bytes(d, v)),
+            Column::String(m) => each(digest, m, |d, v| bytes(d, v.as_bytes())),
+            Column::Bool(m) | Column::PackedBool(m) => each(digest, m, |d, v| d.write(&[*v as u8])),
+            Column::IDs(m) => each(digest, m, |d, v| {
+                d.write(&(v.len() as u64).to_le_bytes());
+                for id in v {
+                    d.write(&id.to_le_bytes());
+                }
+            }),
+            Column::XorFloat(m) => each(digest, m, |d, v| d.write(&v.to_bits().to_le_bytes())),
+            Column::Map(m) => each(digest, m, |d, v| {
+                let mut buf = vec![];
+                let _ = map::write_row(&mut buf, v);
+                bytes(d, &buf)
+            }),
+            // already rounded to the column's scale when they were added
+            Column::GeoPoint(m) => each(digest, m, |d, (lat, lng)| {
+                d.write(&lat.to_bits().to_le_bytes());
+                d.write(&lng.to_bits().to_le_bytes());
+            }),
+            Column::I64Array(m) => each(digest, m, |d, v| {
+                d.write(&(v.len() as u64).to_le_bytes());
+                for e in v {
+                    d.write(&e.to_le_bytes());
+                }
+            }),
+            // already rounded to the column's scale too
+            Column::F64Array(m) => each(digest, m, |d, v| {
+                d.write(&(v.len() as u64).to_le_bytes());
+                for e in v {
+                    d.write(&e.to_bits().to_le_bytes());
+                }
+            }),
+            Column::Timestamp(m) => each(digest, m, |d, v| d.write(&v.to_le_bytes())),
+        }
+    }
+
+    fn delete_rows(&mut self, rows: &Range<usize>) {
+        match self {
+            Column::Numbers(m) => delete_rows(m, rows),
+            Column::LongFloat(m) => delete_rows(m, rows),
+            Column::ShortFloat(m) => delete_rows(m, rows),
+            Column::Base64(m) => delete_rows(m, rows),
+            Column::String(m) => delete_rows(m, rows),
+            Column::Bool(m) => delete_rows(m, rows),
+            Column::IDs(m) => delete_rows(m, rows),
+            Column::PackedBool(m) => delete_rows(m, rows),
+            Column::XorFloat(m) => delete_rows(m, rows),
+            Column::Map(m) => delete_rows(m, rows),
+            Column::GeoPoint(m) => delete_rows(m, rows),
+            Column::I64Array(m) => delete_rows(m, rows),
+            Column::F64Array(m) => delete_rows(m, rows),
+            Column::Timestamp(m) => delete_rows(m, rows),
         }
     }
 }
 
+pub(crate) fn geo_point_scale(decimals: u8) -> f64 {
+    10f64.powi(decimals.into())
+}
+
+// The number of bytes a row of an IDs column takes without bit packing
+fn ids_len(ids: &[u64]) -> usize {
+    unsigned_leb128_len(ids.len() as u64) + ids.iter().map(|id| unsigned_leb128_len(*id)).sum::<usize>()
+}
+
+// The deltas of a row of an I64Array or scaled F64Array column, each from
+// the value before it in the row, or of a Timestamp column's values
+fn array_deltas(values: impl Iterator<Item = i64>) -> impl Iterator<Item = i64> {
+    values.scan(0i64, |last, v| {
+        let delta = v.wrapping_sub(*last);
+        *last = v;
+        Some(delta)
+    })
+}
+
+fn array_len(len: usize, values: impl Iterator<Item = i64>) -> usize {
+    unsigned_leb128_len(len as u64) + array_deltas(values).map(signed_leb128_len).sum::<usize>()
+}
+
+fn scaled(v: &[f64], scale: f64) -> impl Iterator<Item = i64> + '_ {
+    v.iter().map(move |e| (e * scale).round() as i64)
+}
+
+// Set in a section header's type tag when the flags are run-length encoded
+pub(crate) const RUN_LENGTH_FLAGS: u8 = 0x80;
+// Set in a section header's type tag when the types table's count and name
+// lengths are leb128 rather than single bytes, for sections with more than
+// 255 columns or a name longer than 255 bytes
+pub(crate) const WIDE_TYPES_TABLE: u8 = 0x40;
+
+// The file version a section needs, from the type tag in its header, flag
+// bits and all, and the type tags in its types table. Everything after
+// continuation sections and IDs columns needs version 1.
+pub(crate) fn section_file_version(type_tag: u8, column_tags: impl IntoIterator<Item = u8>) -> u8 {
+    if type_tag > 0x02 || column_tags.into_iter().any(|tag| tag > 0x06) {
+        1
+    } else {
+        0
+    }
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum SectionType {
     TrackPoints,
     CoursePoints,
     Continuation,
+    PointsOfInterest,
+    Laps,
+    Events,
 }
 
 impl SectionType {
@@ -74,6 +449,9 @@ impl SectionType {
             0x00 => Some(SectionType::TrackPoints),
             0x01 => Some(SectionType::CoursePoints),
             0x02 => Some(SectionType::Continuation),
+            0x03 => Some(SectionType::PointsOfInterest),
+            0x04 => Some(SectionType::Laps),
+            0x05 => Some(SectionType::Events),
             // 0xff is reserved for the RWTF Trailer
             _ => None
         }
@@ -81,19 +459,44 @@ impl SectionType {
 
     fn type_tag(&self) -> u8 {
         match self {
-            SectionType::TrackPoints  => 0x00,
-            SectionType::CoursePoints => 0x01,
-            SectionType::Continuation => 0x02,
+            SectionType::TrackPoints      => 0x00,
+            SectionType::CoursePoints     => 0x01,
+            SectionType::Continuation     => 0x02,
+            SectionType::PointsOfInterest => 0x03,
+            SectionType::Laps             => 0x04,
+            SectionType::Events           => 0x05,
         }
     }
 }
 
-#[derive(Debug)]
+/// Where the bytes of an encoded section go, see `Section::encoded_sizes`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectionSizes {
+    /// The name, type and size of the values of each column, by name.
+    pub columns: Vec<(String, FieldType, usize)>,
+    /// The flags recording which columns each row has a value in.
+    pub presence: usize,
+    /// The header, the types table and the CRCs.
+    pub overhead: usize,
+}
+
+impl SectionSizes {
+    pub fn total(&self) -> usize {
+        self.overhead + self.presence + self.columns.iter().map(|(_, _, size)| size).sum::<usize>()
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Section {
     pub(crate) section_type: SectionType,
     pub(crate) max: usize,
     pub(crate) flags: FlagsColumn,
     pub(crate) columns: BTreeMap<String, Column>,
+    pub(crate) run_length_encoding: bool,
+    pub(crate) packed_ids: bool,
+    pub(crate) float_overflow_policies: BTreeMap<String, FloatOverflowPolicy>,
+    // the decimal places of GeoPoint, F64Array and Timestamp columns
+    pub(crate) decimals: BTreeMap<String, u8>,
 }
 
 macro_rules! add_x {
@@ -128,6 +531,9 @@ macro_rules! add_x {
                     }
                 },
                 None => {
+                    if self.columns.len() >= MAX_FIELDS {
+                        return TooManyColumns{name: k, max: MAX_FIELDS}.fail();
+                    }
                     let mut m = BTreeMap::new();
                     m.insert(index, v);
                     self.columns.insert(k.into(), $variant(m));
@@ -141,53 +547,548 @@ macro_rules! add_x {
 }
 
 impl Section {
-    pub(crate) fn new(section_type: SectionType) -> Self {
+    /// An empty section, e.g. to build on a thread of its own and then add
+    /// to a file with `RWTFile::set_section`.
+    pub fn new(section_type: SectionType) -> Self {
         Section{section_type: section_type,
                 max: 0,
                 flags: FlagsColumn::new(),
-                columns: BTreeMap::new()}
+                columns: BTreeMap::new(),
+                run_length_encoding: false,
+                packed_ids: false,
+                float_overflow_policies: BTreeMap::new(),
+                decimals: BTreeMap::new()}
+    }
+
+    /// Like `RWTFile::add_track_point`, for a section which isn't part of a
+    /// file yet.
+    pub fn add<V: Into<DataField>>(&mut self, index: usize, k: &str, v: V) -> crate::rwtfile::Result<()> {
+        RWTFile::add_point(self, index, k, v)
     }
 
     add_x!(add_number, Column::Numbers, i64);
-    add_x!(add_long_float, Column::LongFloat, f64);
-    add_x!(add_short_float, Column::ShortFloat, f64);
+    add_x!(insert_long_float, Column::LongFloat, f64);
+    add_x!(insert_short_float, Column::ShortFloat, f64);
     add_x!(add_base64, Column::Base64, Vec<u8>);
     add_x!(add_string, Column::String, String);
     add_x!(add_bool, Column::Bool, bool);
     add_x!(add_ids, Column::IDs, Vec<u64>);
+    add_x!(add_packed_bool, Column::PackedBool, bool);
+    add_x!(add_xor_float, Column::XorFloat, f64);
+    add_x!(insert_map, Column::Map, BTreeMap<String, DataField>);
+
+    pub(crate) fn add_map(&mut self, index: usize, k: &str, v: BTreeMap<String, DataField>) -> Result<()> {
+        if let Some((key, _)) = v.iter().find(|(_, v)| !map::is_scalar(v)) {
+            return NonScalarMapValue{name: k, key: key.as_str()}.fail();
+        }
+        self.insert_map(index, k, v)
+    }
+
+    add_x!(insert_geo_point, Column::GeoPoint, (f64, f64));
+
+    add_x!(add_i64_array, Column::I64Array, Vec<i64>);
+    add_x!(insert_f64_array, Column::F64Array, Vec<f64>);
+    add_x!(insert_timestamp, Column::Timestamp, i64);
+
+    // Rounded to the column's unit so it's the value a reader will get back
+    pub(crate) fn add_timestamp(&mut self, index: usize, k: &str, v: i64) -> Result<()> {
+        let v = self.timestamp_unit(k).round(v);
+        self.insert_timestamp(index, k, v)
+    }
+
+    // Each value goes through the column's float overflow policy, and is
+    // rounded to its scale. A value the policy drops leaves the whole row
+    // without one, since the rest would no longer line up.
+    pub(crate) fn add_f64_array(&mut self, index: usize, k: &str, v: Vec<f64>) -> Result<()> {
+        let scale = geo_point_scale(self.f64_array_decimals(k));
+        let range = (-MAX_SCALED_FLOAT / scale)..=(MAX_SCALED_FLOAT / scale);
+        let mut values = Vec::with_capacity(v.len());
+        for e in v {
+            match self.check_float_range(index, k, e, range.clone())? {
+                Some(e) => values.push((e * scale).round() / scale),
+                None => return Ok(()),
+            }
+        }
+        self.insert_f64_array(index, k, values)
+    }
+
+    // Both coordinates go through the column's float overflow policy, and
+    // are rounded to its scale so they're the values a reader will get back
+    pub(crate) fn add_geo_point(&mut self, index: usize, k: &str, lat: f64, lng: f64) -> Result<()> {
+        let lat = self.check_float_range(index, k, lat, -90.0..=90.0)?;
+        let lng = self.check_float_range(index, k, lng, -180.0..=180.0)?;
+        match (lat, lng) {
+            (Some(lat), Some(lng)) => {
+                let scale = geo_point_scale(self.geo_point_decimals(k));
+                self.insert_geo_point(index, k, ((lat * scale).round() / scale, (lng * scale).round() / scale))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn add_long_float(&mut self, index: usize, k: &str, v: f64) -> Result<()> {
+        match self.check_float_range(index, k, v, Column::long_float_range())? {
+            Some(v) => self.insert_long_float(index, k, v),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn add_short_float(&mut self, index: usize, k: &str, v: f64) -> Result<()> {
+        match self.check_float_range(index, k, v, Column::short_float_range())? {
+            Some(v) => self.insert_short_float(index, k, v),
+            None => Ok(()),
+        }
+    }
+
+    // Applies the column's overflow policy, returning the value to add, if any
+    fn check_float_range(&self, index: usize, k: &str, v: f64, range: RangeInclusive<f64>) -> Result<Option<f64>> {
+        if range.contains(&v) {
+            return Ok(Some(v));
+        }
+
+        match self.float_overflow_policy(k) {
+            FloatOverflowPolicy::Error => FloatOutOfRange{name: k, index, value: v}.fail(),
+            FloatOverflowPolicy::Saturate if v.is_nan() => Ok(None),
+            FloatOverflowPolicy::Saturate => Ok(Some(v.max(*range.start()).min(*range.end()))),
+            FloatOverflowPolicy::Null => Ok(None),
+        }
+    }
+
+    /// Set what happens when a LongFloat or ShortFloat value added to the
+    /// named column is out of range. This only affects values added after
+    /// it's set. The default is `FloatOverflowPolicy::Error`.
+    pub fn set_float_overflow_policy(&mut self, k: &str, policy: FloatOverflowPolicy) {
+        self.float_overflow_policies.insert(k.into(), policy);
+    }
+
+    pub fn float_overflow_policy(&self, k: &str) -> FloatOverflowPolicy {
+        self.float_overflow_policies.get(k).cloned().unwrap_or(FloatOverflowPolicy::Error)
+    }
+
+    /// Set how many decimal places the named GeoPoint column keeps, up to
+    /// `MAX_GEO_POINT_DECIMALS`. The default is 7, about 1cm. Unlike the
+    /// overflow policy, this also rounds the values already added, since
+    /// every value is written with the same scale.
+    pub fn set_geo_point_decimals(&mut self, k: &str, decimals: u8) {
+        let decimals = decimals.min(MAX_GEO_POINT_DECIMALS);
+        self.decimals.insert(k.into(), decimals);
+        if let Some(Column::GeoPoint(m)) = self.columns.get_mut(k) {
+            let scale = geo_point_scale(decimals);
+            for (lat, lng) in m.values_mut() {
+                *lat = (*lat * scale).round() / scale;
+                *lng = (*lng * scale).round() / scale;
+            }
+        }
+    }
+
+    pub fn geo_point_decimals(&self, k: &str) -> u8 {
+        self.decimals.get(k).cloned().unwrap_or(DEFAULT_GEO_POINT_DECIMALS)
+    }
+
+    /// Set how many decimal places the values of the named F64Array column
+    /// keep, up to `MAX_GEO_POINT_DECIMALS`. The default is 3. Like
+    /// `set_geo_point_decimals`, the values already added are rounded too.
+    pub fn set_f64_array_decimals(&mut self, k: &str, decimals: u8) {
+        let decimals = decimals.min(MAX_GEO_POINT_DECIMALS);
+        self.decimals.insert(k.into(), decimals);
+        if let Some(Column::F64Array(m)) = self.columns.get_mut(k) {
+            let scale = geo_point_scale(decimals);
+            for v in m.values_mut().flat_map(|values| values.iter_mut()) {
+                *v = (*v * scale).round() / scale;
+            }
+        }
+    }
+
+    pub fn f64_array_decimals(&self, k: &str) -> u8 {
+        self.decimals.get(k).cloned().unwrap_or(DEFAULT_F64_ARRAY_DECIMALS)
+    }
+
+    /// Set whether the named Timestamp column keeps seconds or
+    /// milliseconds. The default is `TimestampUnit::Millis`. The column's
+    /// values, including those already added, are rounded to the unit, so
+    /// they're the values a reader will get back.
+    pub fn set_timestamp_unit(&mut self, k: &str, unit: TimestampUnit) {
+        self.decimals.insert(k.into(), unit.decimals());
+        if let Some(Column::Timestamp(m)) = self.columns.get_mut(k) {
+            for v in m.values_mut() {
+                *v = unit.round(*v);
+            }
+        }
+    }
+
+    pub fn timestamp_unit(&self, k: &str) -> TimestampUnit {
+        self.decimals.get(k).and_then(|decimals| TimestampUnit::from_decimals(*decimals)).unwrap_or(TimestampUnit::Millis)
+    }
+
+    pub fn section_type(&self) -> SectionType {
+        self.section_type
+    }
 
     pub fn len(&self) -> usize {
         self.flags.len()
     }
 
+    /// Removes the value at row `index` of the named column, returning it.
+    pub fn remove(&mut self, index: usize, k: &str) -> Option<DataField> {
+        let v = self.columns.get_mut(k)?.remove(index)?;
+        self.flags.unset(index, k);
+        Some(v)
+    }
+
+    /// Deletes the rows in `rows` from every column, moving the rows after
+    /// them up. Columns are kept even if no values are left in them.
+    pub fn delete_rows(&mut self, rows: Range<usize>) {
+        let len = self.len();
+        let rows = cmp::min(rows.start, len)..cmp::min(rows.end, len);
+        if rows.is_empty() {
+            return;
+        }
+
+        for column in self.columns.values_mut() {
+            column.delete_rows(&rows);
+        }
+        self.flags.delete_rows(&rows);
+        self.max = self.flags.max();
+    }
+
+    // Adds the values of `column` in `rows` under `name`, starting at row `to`
+    fn add_column_rows(&mut self, name: &str, column: &Column, rows: &Range<usize>, to: usize) -> Result<()> {
+        macro_rules! copy {
+            ($m: expr, $add: ident, $convert: expr) => {
+                for (index, v) in $m.range(rows.clone()) {
+                    self.$add(index - rows.start + to, name, $convert(v))?;
+                }
+            }
+        }
+
+        match column {
+            Column::Numbers(m)    => copy!(m, add_number, |v: &i64| *v),
+            Column::LongFloat(m)  => copy!(m, insert_long_float, |v: &f64| *v),
+            Column::ShortFloat(m) => copy!(m, insert_short_float, |v: &f64| *v),
+            Column::Base64(m)     => copy!(m, add_base64, |v: &Vec<u8>| v.clone()),
+            Column::String(m)     => copy!(m, add_string, |v: &String| v.clone()),
+            Column::Bool(m)       => copy!(m, add_bool, |v: &bool| *v),
+            Column::IDs(m)        => copy!(m, add_ids, |v: &Vec<u64>| v.clone()),
+            Column::PackedBool(m) => copy!(m, add_packed_bool, |v: &bool| *v),
+            Column::XorFloat(m)   => copy!(m, add_xor_float, |v: &f64| *v),
+            Column::Map(m)        => copy!(m, insert_map, |v: &BTreeMap<String, DataField>| v.clone()),
+            Column::GeoPoint(m)   => copy!(m, insert_geo_point, |v: &(f64, f64)| *v),
+            Column::I64Array(m)   => copy!(m, add_i64_array, |v: &Vec<i64>| v.clone()),
+            Column::F64Array(m)   => copy!(m, insert_f64_array, |v: &Vec<f64>| v.clone()),
+            Column::Timestamp(m)  => copy!(m, insert_timestamp, |v: &i64| *v),
+        }
+
+        Ok(())
+    }
+
+    // Makes sure there are at least `len` rows, even if the last ones are
+    // empty
+    pub(crate) fn extend_to(&mut self, len: usize) {
+        if len > self.len() {
+            self.max = len - 1;
+            self.flags.rows = len;
+        }
+    }
+
+    // A copy of `rows` as a section of its own, with the same settings
+    pub(crate) fn copy_rows(&self, section_type: SectionType, rows: Range<usize>) -> Result<Section> {
+        let mut section = Section::new(section_type);
+        section.run_length_encoding = self.run_length_encoding;
+        section.packed_ids = self.packed_ids;
+        section.float_overflow_policies = self.float_overflow_policies.clone();
+        section.decimals = self.decimals.clone();
+        for name in self.flags.fields() {
+            if let Some(column) = self.columns.get(name) {
+                section.add_column_rows(name, column, &rows, 0)?;
+            }
+        }
+        section.extend_to(rows.len());
+
+        Ok(section)
+    }
+
+    /// Splits this section into a section of the same type followed by
+    /// continuation sections, each encoding to at most `max_size` bytes, or
+    /// a single row if one row is larger than that. None if it fits already.
+    pub(crate) fn split(&self, max_size: usize) -> Result<Option<Vec<Section>>> {
+        if self.len() < 2 || self.estimated_encoded_size() <= max_size {
+            return Ok(None);
+        }
+
+        let mut sections = vec![];
+        let mut start = 0;
+        while start < self.len() {
+            let section_type = if start == 0 { self.section_type } else { SectionType::Continuation };
+
+            // the largest number of rows from `start` which fit, found by
+            // bisection since encoded size grows with the number of rows
+            let (mut fits, mut too_many) = (1, self.len() - start + 1);
+            while too_many - fits > 1 {
+                let rows = fits + (too_many - fits) / 2;
+                if self.copy_rows(section_type, start..start + rows)?.estimated_encoded_size() <= max_size {
+                    fits = rows;
+                } else {
+                    too_many = rows;
+                }
+            }
+            // a section of one empty row would read back as no rows at all
+            if fits == 1 && start + 1 < self.len() && self.copy_rows(section_type, start..start + 1)?.len() == 0 {
+                fits = 2;
+            }
+
+            sections.push(self.copy_rows(section_type, start..start + fits)?);
+            start += fits;
+        }
+
+        Ok(Some(sections))
+    }
+
+    /// Adds the rows of `other` after the rows of this section, as a reader
+    /// does with a continuation section.
+    pub(crate) fn append(&mut self, other: &Section) -> Result<()> {
+        let len = self.len();
+        for name in other.flags.fields() {
+            if let Some(column) = other.columns.get(name) {
+                self.add_column_rows(name, column, &(0..other.len()), len)?;
+            }
+        }
+        self.extend_to(len + other.len());
+
+        Ok(())
+    }
+
     pub(crate) fn type_tag(&self) -> u8 {
         self.section_type.type_tag()
     }
 
+    // Columns are fed in by name, leaving out any without values
+    pub(crate) fn hash_content(&self, digest: &mut crc64::Digest) {
+        digest.write(&[self.type_tag()]);
+        digest.write(&(self.len() as u64).to_le_bytes());
+        for (name, column) in self.columns.iter().filter(|(_, column)| !column.is_empty()) {
+            digest.write(&(name.len() as u64).to_le_bytes());
+            digest.write(name.as_bytes());
+            let type_tag = match column {
+                // the same values as a Bool column
+                Column::PackedBool(_) => 0x05,
+                _ => column.type_tag(),
+            };
+            digest.write(&[type_tag]);
+            column.hash_content(digest);
+        }
+    }
+
     pub fn columns(&self) -> &BTreeMap<String, Column> {
         &self.columns
     }
 
+    /// The position of the named column in this section's types table. This
+    /// index never changes once a column has been added, so it can be looked
+    /// up once and then used with `IndexedColumns` in place of the name.
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.flags.fields.get(name).cloned()
+    }
+
+    /// How the columns of `other` differ from the columns of this section,
+    /// by name and type. Values aren't compared.
+    pub fn diff_columns(&self, other: &Section) -> ColumnsDiff {
+        let mut diff = ColumnsDiff::default();
+        for (name, column) in self.columns.iter() {
+            match other.columns.get(name) {
+                Some(other_column) if other_column.field_type() != column.field_type() => {
+                    diff.retyped.push((name.clone(), column.field_type(), other_column.field_type()));
+                }
+                Some(_) => {}
+                None => diff.removed.push(name.clone()),
+            }
+        }
+        diff.added = other.columns.keys()
+            .filter(|name| !self.columns.contains_key(*name))
+            .cloned()
+            .collect();
+        diff
+    }
+
+    /// Whether every column in `other` is in this section too, with the
+    /// same type.
+    pub fn has_columns_of(&self, other: &Section) -> bool {
+        other.columns.iter().all(|(name, column)| {
+            self.columns.get(name).map(|c| c.field_type()) == Some(column.field_type())
+        })
+    }
+
+    pub fn indexed_columns(&self) -> IndexedColumns<'_> {
+        IndexedColumns{columns: self.flags.fields()
+                       .into_iter()
+                       .filter_map(|name| self.columns.get(name).map(|column| (name, column)))
+                       .collect(),
+                       points: self.len()}
+    }
+
+    /// Like `indexed_columns`, with just the columns at the given
+    /// `column_index` positions, in the order given. Column `i` of the result
+    /// is the column at `indices[i]`. None if any of them isn't a column.
+    pub fn indexed_columns_for(&self, indices: &[usize]) -> Option<IndexedColumns<'_>> {
+        let fields = self.flags.fields();
+        indices.iter()
+            .map(|index| fields.get(*index).and_then(|name| self.columns.get(*name).map(|column| (*name, column))))
+            .collect::<Option<Vec<_>>>()
+            .map(|columns| IndexedColumns{columns, points: self.len()})
+    }
+
+    /// Allow Numbers columns to be written run-length encoded. Each column
+    /// is only written this way when it comes out smaller than the usual
+    /// delta encoding, so this is a good fit for values which rarely change
+    /// (surface type ids, gear index, zone, ...). The flags recording which
+    /// columns each row has are run-length encoded the same way, which
+    /// shrinks sections where most rows have every column. Files with it on
+    /// are written as file version 1, see `RWTF_FILE_VERSION`.
+    pub fn set_run_length_encoding(&mut self, enabled: bool) {
+        self.run_length_encoding = enabled;
+    }
+
+    pub fn run_length_encoding(&self) -> bool {
+        self.run_length_encoding
+    }
+
+    /// Allow IDs columns to be written bit-packed, with each row's ids
+    /// stored in as many bits as its largest one needs. Like run-length
+    /// encoding, each column is only written this way when it comes out
+    /// smaller, which suits rows with many ids (radar targets, ...). Files
+    /// with it on and an IDs column are written as file version 1.
+    pub fn set_packed_ids(&mut self, enabled: bool) {
+        self.packed_ids = enabled;
+    }
+
+    pub fn packed_ids(&self) -> bool {
+        self.packed_ids
+    }
+
+    // Whether this IDs column should be written bit-packed
+    fn use_packed_ids(&self, m: &BTreeMap<usize, Vec<u64>>) -> bool {
+        self.packed_ids
+            && m.values().map(|v| bitpack::encoded_len(v)).sum::<usize>() < m.values().map(|v| ids_len(v)).sum::<usize>()
+    }
+
+    // Returns the runs of identical values in this column if it should be
+    // written run-length encoded rather than delta encoded.
+    fn run_length_runs(&self, m: &BTreeMap<usize, i64>) -> Option<Vec<(u64, i64)>> {
+        if !self.run_length_encoding {
+            return None;
+        }
+
+        let mut runs: Vec<(u64, i64)> = Vec::new();
+        for v in m.values() {
+            match runs.last_mut() {
+                Some((count, last)) if *last == *v => *count += 1,
+                _ => runs.push((1, *v)),
+            }
+        }
+
+        let mut last = 0;
+        let rle_size = runs.iter()
+            .map(|(count, value)| {
+                let delta = value.wrapping_sub(last);
+                last = *value;
+                unsigned_leb128_len(*count) + signed_leb128_len(delta)
+            })
+            .sum::<usize>();
+
+        let mut last = 0;
+        let delta_size = (0..=self.max)
+            .map(|index| match m.get(&index) {
+                Some(v) => {
+                    let delta = v.wrapping_sub(last);
+                    last = *v;
+                    signed_leb128_len(delta)
+                }
+                None => 1,
+            })
+            .sum::<usize>();
+
+        if rle_size < delta_size {
+            Some(runs)
+        } else {
+            None
+        }
+    }
+
+    // The runs of each Numbers column to write run-length encoded, worked
+    // out once for each write and passed to everything that depends on them
+    fn run_length_columns(&self) -> RunLengthColumns<'_> {
+        self.columns.iter()
+            .filter_map(|(name, column)| match column {
+                Column::Numbers(m) => self.run_length_runs(m).map(|runs| (name.as_str(), runs)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Whether the flags should be written run-length encoded, which is
+    // marked in the section header
+    fn run_length_flags(&self) -> bool {
+        self.run_length_encoding
+            && self.len() > 0
+            && self.flags.run_length_encoded_size() < self.flags.encoded_size()
+    }
+
+    fn column_type_tag(&self, name: &str, column: &Column, runs: &RunLengthColumns) -> u8 {
+        match column {
+            Column::Numbers(_) if runs.contains_key(name) => 0x08,
+            Column::IDs(m) if self.use_packed_ids(m) => 0x0A,
+            _ => column.type_tag(),
+        }
+    }
+
     pub fn simplify_and_encode(&self, mapping: &SurfaceMapping, tolerance: f64, fields: &[FieldEncodeOptions]) -> String {
         simplify_and_encode(self, mapping, tolerance, fields)
     }
 
-    fn write_types_table<W: Write>(&self, out: &mut W) -> Result<usize> {
+    // Whether the types table needs leb128 counts, see `WIDE_TYPES_TABLE`
+    fn wide_types_table(&self) -> bool {
+        self.columns.len() > 255 || self.columns.keys().any(|name| name.len() > 255)
+    }
+
+    // The file version needed to hold this section, see `RWTF_FILE_VERSION`.
+    // Run-length encoding and packed IDs are chosen per section, and each
+    // part of a section split by `RWTFile::set_max_section_size` can choose
+    // differently, so having them on is enough.
+    pub(crate) fn file_version(&self) -> u8 {
+        let packed_ids = self.packed_ids && self.columns.values().any(|column| matches!(column, Column::IDs(_)));
+        if self.len() == 0 {
+            0
+        } else if self.run_length_encoding || packed_ids || self.wide_types_table() {
+            1
+        } else {
+            section_file_version(self.type_tag(), self.columns.values().map(Column::type_tag))
+        }
+    }
+
+    fn write_types_table<W: Write>(&self, out: &mut W, runs: &RunLengthColumns) -> Result<usize> {
         let mut buf = Vec::new();
+        let wide = self.wide_types_table();
 
-        // Write 1 byte - the number of entries in the types table
-        write(&mut buf, &u8::try_from(self.columns.len()).context(NumberTruncation{})?.to_le_bytes()).context(WriteTypesTable{})?;
+        // Write 1 byte, or a leb128 if wide - the number of entries in the types table
+        if wide {
+            leb128::write::unsigned(&mut buf, self.columns.len() as u64).context(WriteTypesTable{})?;
+        } else {
+            write(&mut buf, &u8::try_from(self.columns.len()).context(NumberTruncation{})?.to_le_bytes()).context(WriteTypesTable{})?;
+        }
 
         for name in self.flags.fields() {
             if let Some(column) = self.columns.get(name) {
                 // Write 1 byte - the Type Tag for this type
-                write(&mut buf, &column.type_tag().to_le_bytes()).context(WriteTypesTable{})?;
-                // Write 1 byte - the length of the name of this type
-                write(&mut buf, &u8::try_from(name.len()).context(NumberTruncation{})?.to_le_bytes()).context(WriteTypesTable{})?;
+                write(&mut buf, &self.column_type_tag(name, column, runs).to_le_bytes()).context(WriteTypesTable{})?;
+                // Write 1 byte, or a leb128 if wide - the length of the name of this type
+                if wide {
+                    leb128::write::unsigned(&mut buf, name.len() as u64).context(WriteTypesTable{})?;
+                } else {
+                    write(&mut buf, &u8::try_from(name.len()).context(NumberTruncation{})?.to_le_bytes()).context(WriteTypesTable{})?;
+                }
                 // Write name.len() bytes - the name of this type
                 write(&mut buf, name.as_bytes()).context(WriteTypesTable{})?;
             } else {
-                panic!("TODO")
+                return MissingColumn{name}.fail();
             }
         }
 
@@ -201,123 +1102,258 @@ impl Section {
         Ok(written)
     }
 
-    fn write_data<W: Write>(&self, out: &mut W) -> Result<usize> {
-        let mut buf = Vec::new();
-
-        // Write the "Flags" column
-        self.flags.write(&mut buf).context(WriteFlagsColumn)?;
+    fn write_column<W: Write>(&self, out: &mut W, name: &str, column: &Column, runs: &RunLengthColumns) -> Result<usize> {
+        let mut written = 0;
 
-        // Write all other columns
-        for name in self.flags.fields() {
-            if let Some(column) = self.columns.get(name) {
-                match column {
-                    Column::Numbers(m) => {
-                        let mut last = 0;
-                        for index in 0..=self.max {
-                            let delta = match m.get(&index) {
-                                Some(v) => {
-                                    let value = *v;
-                                    let delta = value - last;
-                                    last = value;
-                                    delta
-                                }
-                                None => 0
-                            };
-
-                            // Write the signed delta from the previous value
-                            leb128::write::signed(&mut buf, delta).with_context(|| WriteDataColumn{name: name.clone()})?;
-                        }
+        match column {
+            // Deltas wrap around so that every i64 can be stored, even when
+            // it was read from a file rather than added within range
+            Column::Numbers(m) => {
+                if let Some(runs) = runs.get(name) {
+                    let mut last: i64 = 0;
+                    for (count, value) in runs {
+                        let delta = value.wrapping_sub(last);
+                        last = *value;
+
+                        // Write the number of present rows in this run
+                        written += leb128::write::unsigned(out, *count).with_context(|| WriteDataColumn{name})?;
+                        // Write the signed delta from the previous run's value
+                        written += leb128::write::signed(out, delta).with_context(|| WriteDataColumn{name})?;
                     }
-                    Column::LongFloat(m) => {
-                        let mut last = 0;
-                        for index in 0..=self.max {
-                            let delta = match m.get(&index) {
-                                Some(v) => {
-                                    let value = (*v * 10000000.0) as i64;
-                                    let delta = value - last;
-                                    last = value;
-                                    delta
-                                }
-                                None => 0
-                            };
-
-                            // Write the signed delta from the previous value
-                            leb128::write::signed(&mut buf, delta).with_context(|| WriteDataColumn{name: name.clone()})?;
-                        }
+                } else {
+                    let mut last: i64 = 0;
+                    for index in 0..=self.max {
+                        let delta = match m.get(&index) {
+                            Some(v) => {
+                                let value = *v;
+                                let delta = value.wrapping_sub(last);
+                                last = value;
+                                delta
+                            }
+                            None => 0
+                        };
+
+                        // Write the signed delta from the previous value
+                        written += leb128::write::signed(out, delta).with_context(|| WriteDataColumn{name})?;
                     }
-                    Column::ShortFloat(m) => {
-                        let mut last = 0;
-                        for index in 0..=self.max {
-                            let delta = match m.get(&index) {
-                                Some(v) => {
-                                    let value = (*v * 1000.0) as i64;
-                                    let delta = value - last;
-                                    last = value;
-                                    delta
-                                }
-                                None => 0
-                            };
-
-                            // Write the signed delta from the previous value
-                            leb128::write::signed(&mut buf, delta).with_context(|| WriteDataColumn{name: name.clone()})?;
+                }
+            }
+            Column::LongFloat(m) => {
+                let mut last: i64 = 0;
+                for index in 0..=self.max {
+                    let delta = match m.get(&index) {
+                        Some(v) => {
+                            let value = (*v * LONG_FLOAT_SCALE).round() as i64;
+                            let delta = value.wrapping_sub(last);
+                            last = value;
+                            delta
                         }
-                    }
-                    Column::Base64(m) => {
-                        for index in 0..=self.max {
-                            let empty = Vec::with_capacity(0);
-                            let v = m.get(&index).unwrap_or(&empty);
-
-                            // Write the length of the bytes
-                            leb128::write::unsigned(&mut buf, u64::try_from(v.len()).context(NumberTruncation{})?).with_context(|| WriteDataColumn{name: name.clone()})?;
-                            // Write the bytes themselves
-                            write(&mut buf, &v).with_context(|| WriteDataColumn{name: name.clone()})?;
+                        None => 0
+                    };
+
+                    // Write the signed delta from the previous value
+                    written += leb128::write::signed(out, delta).with_context(|| WriteDataColumn{name})?;
+                }
+            }
+            Column::ShortFloat(m) => {
+                let mut last: i64 = 0;
+                for index in 0..=self.max {
+                    let delta = match m.get(&index) {
+                        Some(v) => {
+                            let value = (*v * SHORT_FLOAT_SCALE).round() as i64;
+                            let delta = value.wrapping_sub(last);
+                            last = value;
+                            delta
                         }
+                        None => 0
+                    };
+
+                    // Write the signed delta from the previous value
+                    written += leb128::write::signed(out, delta).with_context(|| WriteDataColumn{name})?;
+                }
+            }
+            Column::Base64(m) => {
+                for index in 0..=self.max {
+                    let empty = Vec::with_capacity(0);
+                    let v = m.get(&index).unwrap_or(&empty);
+
+                    // Write the length of the bytes
+                    written += leb128::write::unsigned(out, u64::try_from(v.len()).context(NumberTruncation{})?).with_context(|| WriteDataColumn{name})?;
+                    // Write the bytes themselves
+                    written += write(out, v).with_context(|| WriteDataColumn{name})?;
+                }
+            }
+            Column::String(m) => {
+                let empty = "".to_string();
+                for index in 0..=self.max {
+                    let v = m.get(&index).unwrap_or(&empty);
+
+                    // Write the length of the string
+                    written += leb128::write::unsigned(out, u64::try_from(v.len()).context(NumberTruncation{})?).with_context(|| WriteDataColumn{name})?;
+                    // Write the string itself
+                    written += write(out, v.as_bytes()).with_context(|| WriteDataColumn{name})?;
+                }
+            }
+            Column::Bool(m) => {
+                for index in 0..=self.max {
+                    let b = m.get(&index).unwrap_or(&false);
+                    let v = *b as u8;
+
+                    // write a 0 for false and a 1 for true
+                    written += write(out, &v.to_le_bytes()).with_context(|| WriteDataColumn{name})?;
+                }
+            }
+            Column::IDs(m) if self.use_packed_ids(m) => {
+                let empty = Vec::with_capacity(0);
+                for index in 0..=self.max {
+                    let v = m.get(&index).unwrap_or(&empty);
+                    written += bitpack::write_row(out, v).with_context(|| WriteDataColumn{name})?;
+                }
+            }
+            Column::IDs(m) => {
+                let empty = Vec::with_capacity(0);
+                for index in 0..=self.max {
+                    let v = m.get(&index).unwrap_or(&empty);
+
+                    // Write the length of the vec
+                    written += leb128::write::unsigned(out, u64::try_from(v.len()).context(NumberTruncation{})?).with_context(|| WriteDataColumn{name})?;
+                    // Write the ids themselves
+                    for id in v {
+                        written += leb128::write::unsigned(out, *id).with_context(|| WriteDataColumn{name})?;
                     }
-                    Column::String(m) => {
-                        let empty = "".to_string();
-                        for index in 0..=self.max {
-                            let v = m.get(&index).unwrap_or(&empty);
-
-                            // Write the length of the string
-                            leb128::write::unsigned(&mut buf, u64::try_from(v.len()).context(NumberTruncation{})?).with_context(|| WriteDataColumn{name: name.clone()})?;
-                            // Write the string itself
-                            write(&mut buf, v.as_bytes()).with_context(|| WriteDataColumn{name: name.clone()})?;
-                        }
+                }
+            }
+            Column::PackedBool(m) => {
+                let mut packed = vec![0u8; (self.max + 8) / 8];
+                for (index, b) in m.iter() {
+                    if *b {
+                        // bit i of byte i / 8 holds the value for row i, absent rows are 0
+                        packed[index / 8] |= 1 << (index % 8);
                     }
-                    Column::Bool(m) => {
-                        for index in 0..=self.max {
-                            let b = m.get(&index).unwrap_or(&false);
-                            let v = *b as u8;
+                }
 
-                            // write a 0 for false and a 1 for true
-                            write(&mut buf, &v.to_le_bytes()).with_context(|| WriteDataColumn{name: name.clone()})?;
-                        }
+                written += write(out, &packed).with_context(|| WriteDataColumn{name})?;
+            }
+            Column::XorFloat(m) => {
+                // Only present values are written, each XORed with the one before it
+                let packed = xorfloat::encode(m.values().cloned());
+                written += write(out, &packed).with_context(|| WriteDataColumn{name})?;
+            }
+            Column::Map(m) => {
+                let empty = BTreeMap::new();
+                for index in 0..=self.max {
+                    let v = m.get(&index).unwrap_or(&empty);
+                    written += map::write_row(out, v).with_context(|| WriteDataColumn{name})?;
+                }
+            }
+            Column::GeoPoint(m) => {
+                // The column's decimal places, then only present values are
+                // written, each as the signed deltas of its scaled latitude
+                // and longitude from the one before it. Deltas wrap like
+                // Numbers deltas, since decoded points aren't range checked.
+                let decimals = self.geo_point_decimals(name);
+                let scale = geo_point_scale(decimals);
+                written += write(out, &[decimals]).with_context(|| WriteDataColumn{name})?;
+                let mut last = (0i64, 0i64);
+                for (lat, lng) in m.values() {
+                    let v = ((lat * scale).round() as i64, (lng * scale).round() as i64);
+                    written += leb128::write::signed(out, v.0.wrapping_sub(last.0)).with_context(|| WriteDataColumn{name})?;
+                    written += leb128::write::signed(out, v.1.wrapping_sub(last.1)).with_context(|| WriteDataColumn{name})?;
+                    last = v;
+                }
+            }
+            Column::I64Array(m) => {
+                let empty = Vec::with_capacity(0);
+                for index in 0..=self.max {
+                    let v = m.get(&index).unwrap_or(&empty);
+
+                    // Write the length of the vec, then the deltas of its values
+                    written += leb128::write::unsigned(out, u64::try_from(v.len()).context(NumberTruncation{})?).with_context(|| WriteDataColumn{name})?;
+                    for delta in array_deltas(v.iter().copied()) {
+                        written += leb128::write::signed(out, delta).with_context(|| WriteDataColumn{name})?;
                     }
-                    Column::IDs(m) => {
-                        let empty = Vec::with_capacity(0);
-                        for index in 0..=self.max {
-                            let v = m.get(&index).unwrap_or(&empty);
-
-                            // Write the length of the vec
-                            leb128::write::unsigned(&mut buf, u64::try_from(v.len()).context(NumberTruncation{})?).with_context(|| WriteDataColumn{name: name.clone()})?;
-                            // Write the ids themselves
-                            for id in v {
-                                leb128::write::unsigned(&mut buf, *id).with_context(|| WriteDataColumn{name: name.clone()})?;
-                            }
-                        }
+                }
+            }
+            Column::F64Array(m) => {
+                // The column's decimal places, then each row like an
+                // I64Array row of its scaled values
+                let decimals = self.f64_array_decimals(name);
+                let scale = geo_point_scale(decimals);
+                written += write(out, &[decimals]).with_context(|| WriteDataColumn{name})?;
+                let empty = Vec::with_capacity(0);
+                for index in 0..=self.max {
+                    let v = m.get(&index).unwrap_or(&empty);
+                    written += leb128::write::unsigned(out, u64::try_from(v.len()).context(NumberTruncation{})?).with_context(|| WriteDataColumn{name})?;
+                    for delta in array_deltas(scaled(v, scale)) {
+                        written += leb128::write::signed(out, delta).with_context(|| WriteDataColumn{name})?;
                     }
                 }
+            }
+            Column::Timestamp(m) => {
+                // The column's unit, then only present values are written,
+                // each as the signed delta from the one before it
+                let unit = self.timestamp_unit(name);
+                written += write(out, &[unit.decimals()]).with_context(|| WriteDataColumn{name})?;
+                for delta in array_deltas(m.values().map(|v| v.div_euclid(unit.millis()))) {
+                    written += leb128::write::signed(out, delta).with_context(|| WriteDataColumn{name})?;
+                }
+            }
+        }
+
+        Ok(written)
+    }
+
+    #[cfg(test)]
+    fn write_data<W: Write>(&self, out: &mut W) -> Result<usize> {
+        self.write_data_reporting(out, &self.run_length_columns(), &mut |_| {})
+    }
+
+    // Calls `report` with the bytes written so far after each column
+    fn write_data_reporting<W: Write>(&self, out: &mut W, runs: &RunLengthColumns, report: &mut dyn FnMut(usize)) -> Result<usize> {
+        let mut out = Crc32Writer::new(out);
+
+        // Write the "Flags" column
+        let mut written = if self.run_length_flags() {
+            self.flags.write_run_length(&mut out).context(WriteFlagsColumn)?
+        } else {
+            self.flags.write(&mut out).context(WriteFlagsColumn)?
+        };
+        report(written);
+
+        // Write all other columns
+        #[cfg(not(feature = "rayon"))]
+        for name in self.flags.fields() {
+            if let Some(column) = self.columns.get(name) {
+                written += self.write_column(&mut out, name, column, runs)?;
+                report(written);
             } else {
-                panic!("TODO")
+                return MissingColumn{name}.fail();
             }
         }
 
-        // Write 4 bytes - Data CRC
-        let crc = crc::crc32::checksum_ieee(&buf).to_le_bytes();
-        write(&mut buf, &crc).with_context(|| WriteDataColumn{name: "crc"})?;
+        // Columns don't depend on each other, so encode them all in parallel
+        // and then copy them out in types table order. Every column's bytes
+        // are held until they're all done.
+        #[cfg(feature = "rayon")]
+        for column_buf in self.flags.fields()
+            .into_par_iter()
+            .map(|name| {
+                let mut column_buf = Vec::new();
+                if let Some(column) = self.columns.get(name) {
+                    self.write_column(&mut column_buf, name, column, runs)?;
+                } else {
+                    return MissingColumn{name}.fail();
+                }
+                Ok(column_buf)
+            })
+            .collect::<Result<Vec<_>>>()? {
+            written += write(&mut out, &column_buf).context(WriteBytes{})?;
+            report(written);
+        }
 
-        // Write buf -> out
-        let written = write(out, &buf).with_context(|| WriteDataColumn{name: "full"})?;
+        // Write 4 bytes - Data CRC
+        let crc = out.crc().to_le_bytes();
+        written += write(&mut out, &crc).with_context(|| WriteDataColumn{name: "crc"})?;
 
         Ok(written)
     }
@@ -325,8 +1361,17 @@ impl Section {
     fn write_header<W: Write>(&self, out: &mut W, section_size: u64) -> Result<usize> {
         let mut buf = Vec::new();
 
-        // Write 1 byte - this section type
-        write(&mut buf, &self.type_tag().to_le_bytes()).context(WriteHeader{})?;
+        // Write 1 byte - this section type, with the high bit set if the
+        // flags are run-length encoded and the next one if the types table
+        // is wide
+        let mut type_tag = self.type_tag();
+        if self.run_length_flags() {
+            type_tag |= RUN_LENGTH_FLAGS;
+        }
+        if self.len() > 0 && self.wide_types_table() {
+            type_tag |= WIDE_TYPES_TABLE;
+        }
+        write(&mut buf, &type_tag.to_le_bytes()).context(WriteHeader{})?;
 
         // Write 3 bytes - number of points in this section
         let len = self.len();
@@ -349,25 +1394,255 @@ impl Section {
         Ok(written)
     }
 
+    // The number of bytes write_column will write for `column`.
+    fn column_size(&self, name: &str, column: &Column, runs: &RunLengthColumns) -> usize {
+        fn deltas<T, F: Fn(&T) -> i64>(m: &BTreeMap<usize, T>, max: usize, f: F) -> usize {
+            let mut last: i64 = 0;
+            (0..=max)
+                .map(|index| match m.get(&index) {
+                    Some(v) => {
+                        let value = f(v);
+                        let delta = value.wrapping_sub(last);
+                        last = value;
+                        signed_leb128_len(delta)
+                    }
+                    None => 1,
+                })
+                .sum()
+        }
+
+        fn lengths<T, F: Fn(&T) -> usize>(m: &BTreeMap<usize, T>, max: usize, f: F) -> usize {
+            (0..=max)
+                .map(|index| match m.get(&index) {
+                    Some(v) => f(v),
+                    None => 1,
+                })
+                .sum()
+        }
+
+        match column {
+            Column::Numbers(m) => match runs.get(name) {
+                Some(runs) => {
+                    let mut last: i64 = 0;
+                    runs.iter()
+                        .map(|(count, value)| {
+                            let delta = value.wrapping_sub(last);
+                            last = *value;
+                            unsigned_leb128_len(*count) + signed_leb128_len(delta)
+                        })
+                        .sum()
+                }
+                None => deltas(m, self.max, |v| *v),
+            },
+            Column::LongFloat(m)  => deltas(m, self.max, |v| (*v * LONG_FLOAT_SCALE).round() as i64),
+            Column::ShortFloat(m) => deltas(m, self.max, |v| (*v * SHORT_FLOAT_SCALE).round() as i64),
+            Column::Base64(m)     => lengths(m, self.max, |v| unsigned_leb128_len(v.len() as u64) + v.len()),
+            Column::String(m)     => lengths(m, self.max, |v| unsigned_leb128_len(v.len() as u64) + v.len()),
+            Column::Bool(_)       => self.max + 1,
+            Column::IDs(m) if self.use_packed_ids(m) => lengths(m, self.max, |v| bitpack::encoded_len(v)),
+            Column::IDs(m)        => lengths(m, self.max, |v| ids_len(v)),
+            Column::PackedBool(_) => (self.max + 8) / 8,
+            Column::XorFloat(m)   => xorfloat::encode(m.values().cloned()).len(),
+            Column::Map(m)        => lengths(m, self.max, map::encoded_len),
+            Column::GeoPoint(m)   => {
+                let scale = geo_point_scale(self.geo_point_decimals(name));
+                let mut last = (0i64, 0i64);
+                1 + m.values()
+                    .map(|(lat, lng)| {
+                        let v = ((lat * scale).round() as i64, (lng * scale).round() as i64);
+                        let len = signed_leb128_len(v.0.wrapping_sub(last.0)) + signed_leb128_len(v.1.wrapping_sub(last.1));
+                        last = v;
+                        len
+                    })
+                    .sum::<usize>()
+            }
+            Column::I64Array(m)   => lengths(m, self.max, |v| array_len(v.len(), v.iter().copied())),
+            Column::F64Array(m)   => {
+                let scale = geo_point_scale(self.f64_array_decimals(name));
+                1 + lengths(m, self.max, |v| array_len(v.len(), scaled(v, scale)))
+            }
+            Column::Timestamp(m)  => {
+                let millis = self.timestamp_unit(name).millis();
+                1 + array_deltas(m.values().map(|v| v.div_euclid(millis))).map(signed_leb128_len).sum::<usize>()
+            }
+        }
+    }
+
+    /// The number of bytes `write` will write for this section, worked out
+    /// without encoding it. Use it to decide where or whether to store a
+    /// track before paying for the encoding.
+    pub fn estimated_encoded_size(&self) -> usize {
+        self.encoded_sizes().total()
+    }
+
+    /// `estimated_encoded_size` broken down by column, to see which fields
+    /// take up the most room.
+    pub fn encoded_sizes(&self) -> SectionSizes {
+        self.encoded_sizes_with(&self.run_length_columns())
+    }
+
+    fn encoded_sizes_with(&self, runs: &RunLengthColumns) -> SectionSizes {
+        // the header and its CRC
+        let mut sizes = SectionSizes{columns: vec![], presence: 0, overhead: 14};
+
+        if self.len() > 0 {
+            // the types table, with its count and CRC, and the data CRC
+            let wide = self.wide_types_table();
+            let len = |n: usize| if wide { unsigned_leb128_len(n as u64) } else { 1 };
+            sizes.overhead += len(self.columns.len())
+                + self.columns.keys().map(|name| 1 + len(name.len()) + name.len()).sum::<usize>()
+                + 2 + 4;
+            sizes.presence = if self.run_length_flags() {
+                self.flags.run_length_encoded_size()
+            } else {
+                self.flags.encoded_size()
+            };
+            sizes.columns = self.columns.iter()
+                .map(|(name, column)| (name.clone(), column.field_type(), self.column_size(name, column, runs)))
+                .collect();
+        }
+
+        sizes
+    }
+
     pub fn write<W: Write>(&self, out: &mut W) -> Result<usize> {
-        let mut written = 0;
+        self.write_reporting(out, 0, &mut |_| {})
+    }
+
+    /// Like `write`, calling `progress` as each column is encoded. Working
+    /// out `Progress::total_bytes` takes an extra pass over any XorFloat
+    /// columns, see `estimated_encoded_size`.
+    pub fn write_with_progress<W: Write, F: FnMut(Progress)>(&self, out: &mut W, mut progress: F) -> Result<usize> {
+        let runs = self.run_length_columns();
+        self.write_with_runs(out, &runs, self.encoded_sizes_with(&runs).total(), &mut progress)
+    }
 
+    // Calls `report` after each column, and once more when everything has
+    // been written
+    pub(crate) fn write_reporting<W: Write>(&self, out: &mut W, total_bytes: usize, report: &mut dyn FnMut(Progress)) -> Result<usize> {
+        self.write_with_runs(out, &self.run_length_columns(), total_bytes, report)
+    }
+
+    fn write_with_runs<W: Write>(&self, out: &mut W, runs: &RunLengthColumns, total_bytes: usize, report: &mut dyn FnMut(Progress)) -> Result<usize> {
         let mut buf = Vec::new();
 
         if self.len() > 0 {
-            written += self.write_types_table(&mut buf)?;
-            written += self.write_data(&mut buf)?;
+            let types_table = self.write_types_table(&mut buf, runs)?;
+            self.write_data_reporting(&mut buf, runs, &mut |bytes| report(Progress{rows: 0,
+                                                                                   bytes: types_table + bytes,
+                                                                                   total_bytes}))?;
         }
 
         let header_size: u64 = 12;
         let data_size = u64::try_from(buf.len()).context(NumberTruncation{})?;
-        written += self.write_header(out, header_size + data_size)?;
+        let mut written = self.write_header(out, header_size + data_size)?;
         written += write(out, &buf).context(WriteBytes{})?;
+        report(Progress{rows: self.len(), bytes: written, total_bytes});
 
         Ok(written)
     }
 }
 
+/// See `Section::diff_columns`. Names are in order.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct ColumnsDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// Name, old type, new type.
+    pub retyped: Vec<(String, FieldType, FieldType)>,
+}
+
+impl ColumnsDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.retyped.is_empty()
+    }
+}
+
+/// A section as it's encoded in a file, from its header to its data CRC.
+/// Writing it copies these bytes as they are, so columns of types this
+/// version doesn't know about are kept too.
+#[derive(Debug, Copy, Clone)]
+pub struct RawSection<'a> {
+    pub(crate) section_type: SectionType,
+    pub(crate) points: u32,
+    pub(crate) bytes: &'a [u8],
+    // see `Section::file_version`
+    pub(crate) file_version: u8,
+}
+
+impl<'a> RawSection<'a> {
+    pub fn section_type(&self) -> SectionType {
+        self.section_type
+    }
+
+    pub fn len(&self) -> usize {
+        self.points as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points == 0
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+}
+
+/// A section's columns, addressed by their `Section::column_index` rather
+/// than by name, or by their position in the list given to
+/// `Section::indexed_columns_for`.
+#[derive(Debug)]
+pub struct IndexedColumns<'a> {
+    columns: Vec<(&'a String, &'a Column)>,
+    points: usize,
+}
+
+impl<'a> IndexedColumns<'a> {
+    pub fn len(&self) -> usize {
+        self.columns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.columns.is_empty()
+    }
+
+    pub fn name(&self, column: usize) -> Option<&str> {
+        self.columns.get(column).map(|(name, _)| name.as_str())
+    }
+
+    pub fn column(&self, column: usize) -> Option<&Column> {
+        self.columns.get(column).map(|(_, column)| *column)
+    }
+
+    pub fn row(&self, index: usize) -> Row<'_> {
+        Row{columns: self,
+            index}
+    }
+
+    /// Every row of the section in order, including rows without a value in
+    /// any of these columns.
+    pub fn rows(&self) -> impl ExactSizeIterator<Item = Row<'_>> + DoubleEndedIterator + '_ {
+        (0..self.points).map(move |index| self.row(index))
+    }
+}
+
+#[derive(Debug)]
+pub struct Row<'a> {
+    columns: &'a IndexedColumns<'a>,
+    index: usize,
+}
+
+impl<'a> Row<'a> {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The value of the column at position `column` in this row.
+    pub fn get(&self, column: usize) -> Option<DataField> {
+        self.columns.column(column).and_then(|c| c.get(self.index))
+    }
+}
+
 pub struct Point<'a> {
     section: &'a Section,
     index: usize,
@@ -385,15 +1660,7 @@ impl<'a> Serialize for Point<'a> {
         let mut map = serializer.serialize_map(None)?;
         for field in self.section.flags.fields.keys() {
             if let Some(column) = self.section.columns.get(field) {
-                let maybe_data = match column {
-                    Column::Numbers(m) => m.get(&self.index).map(|v| DataField::Number(*v)),
-                    Column::LongFloat(m) => m.get(&self.index).map(|v| DataField::LongFloat(*v)),
-                    Column::ShortFloat(m) => m.get(&self.index).map(|v| DataField::ShortFloat(*v)),
-                    Column::Base64(m) => m.get(&self.index).map(|v| DataField::Base64(base64::encode(v))),
-                    Column::String(m) => m.get(&self.index).map(|v| DataField::String(v.to_string())),
-                    Column::Bool(m) => m.get(&self.index).map(|v| DataField::Bool(*v)),
-                    Column::IDs(m) => m.get(&self.index).map(|v| DataField::IDs(v.to_vec())),
-                };
+                let maybe_data = column.get(self.index);
 
                 if let Some(data) = maybe_data {
                     map.serialize_entry(field, &data)?;
@@ -418,8 +1685,74 @@ impl Serialize for Section {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use assert_matches::assert_matches;
     use crate::polyline::PointField;
 
+    #[test]
+    fn test_float_overflow_policy() {
+        let mut s = Section::new(SectionType::TrackPoints);
+        let long_max = *Column::long_float_range().end();
+        let short_max = *Column::short_float_range().end();
+        assert!(short_max > long_max);
+
+        // the default is to refuse
+        assert_matches!(s.add_long_float(0, "a", long_max * 2.0), Err(Error::FloatOutOfRange{index: 0, ..}));
+        assert_matches!(s.add_short_float(0, "b", f64::NAN), Err(Error::FloatOutOfRange{index: 0, ..}));
+        assert_eq!(s.add_short_float(0, "b", f64::NAN).unwrap_err().kind(), ErrorKind::Schema);
+        assert!(s.add_long_float(0, "a", -long_max).is_ok());
+        assert!(s.add_short_float(0, "b", short_max).is_ok());
+
+        s.set_float_overflow_policy("a", FloatOverflowPolicy::Saturate);
+        assert!(s.add_long_float(1, "a", f64::INFINITY).is_ok());
+        assert!(s.add_long_float(2, "a", f64::NAN).is_ok());
+
+        s.set_float_overflow_policy("b", FloatOverflowPolicy::Null);
+        assert!(s.add_short_float(1, "b", -short_max * 2.0).is_ok());
+        assert!(s.add_short_float(2, "b", 1.5).is_ok());
+
+        assert_matches!(s.columns().get("a"), Some(Column::LongFloat(m)) if m.len() == 2 && m[&1] == long_max);
+        assert_matches!(s.columns().get("b"), Some(Column::ShortFloat(m)) if m.len() == 2 && !m.contains_key(&1));
+
+        // the extremes still fit in the deltas
+        let mut buf = vec![];
+        assert!(s.write_data(&mut buf).is_ok());
+    }
+
+    #[test]
+    fn test_infer_float_type() {
+        assert_eq!(Column::infer_float_type(&[]), FieldType::ShortFloat);
+        assert_eq!(Column::infer_float_type(&[1.0, 25.125, -3.5]), FieldType::ShortFloat);
+        assert_eq!(Column::infer_float_type(&[1.0, 45.1234567]), FieldType::LongFloat);
+        assert_eq!(Column::infer_float_type(&[1.0, 0.1 + 0.2]), FieldType::XorFloat);
+        assert_eq!(Column::infer_float_type(&[f64::NAN]), FieldType::XorFloat);
+        assert_eq!(Column::infer_float_type(&[*Column::short_float_range().end() * 2.0]), FieldType::XorFloat);
+    }
+
+    #[test]
+    fn test_diff_columns() {
+        let mut a = Section::new(SectionType::TrackPoints);
+        assert!(a.add_number(0, "t", 0).is_ok());
+        assert!(a.add_long_float(0, "x", 0.0).is_ok());
+        assert!(a.add_bool(0, "paused", false).is_ok());
+
+        let mut b = Section::new(SectionType::TrackPoints);
+        assert!(b.add_number(5, "t", 5).is_ok());
+        assert!(b.add_packed_bool(0, "paused", false).is_ok());
+        assert!(b.add_number(0, "h", 100).is_ok());
+
+        assert!(a.diff_columns(&a).is_empty());
+        assert_eq!(a.diff_columns(&b),
+                   ColumnsDiff{added: vec!["h".into()],
+                               removed: vec!["x".into()],
+                               retyped: vec![("paused".into(), FieldType::Bool, FieldType::PackedBool)]});
+
+        assert!(!a.has_columns_of(&b));
+        assert!(b.has_columns_of(&Section::new(SectionType::TrackPoints)));
+        assert!(a.add_number(0, "h", 1).is_ok());
+        assert!(a.add_packed_bool(0, "paused2", true).is_ok());
+        assert!(!a.has_columns_of(&b));
+    }
+
     #[test]
     fn test_max() {
         let mut s = Section::new(SectionType::TrackPoints);
@@ -454,6 +1787,80 @@ mod tests {
         assert!(s.add_number(3, "foo", 0).is_ok());
     }
 
+    #[test]
+    fn test_map_values_are_scalar() {
+        let mut s = Section::new(SectionType::TrackPoints);
+        let mut m = BTreeMap::new();
+        m.insert("hr_zone".to_string(), DataField::Number(3));
+        assert!(s.add_map(0, "attrs", m.clone()).is_ok());
+        m.insert("ids".to_string(), DataField::IDs(vec![1]));
+        assert_matches!(s.add_map(1, "attrs", m), Err(Error::NonScalarMapValue{ref key, ..}) if key == "ids");
+        assert_eq!(s.columns()["attrs"].get(1), None);
+    }
+
+    #[test]
+    fn test_add_geo_point() {
+        let mut s = Section::new(SectionType::TrackPoints);
+        assert!(s.add_geo_point(0, "pos", 45.123456789, -122.123456789).is_ok());
+        s.set_geo_point_decimals("pos", 3);
+        assert!(s.add_geo_point(1, "pos", 45.123456789, -122.123456789).is_ok());
+        assert_eq!(s.columns()["pos"].get(0), Some(DataField::GeoPoint(45.123, -122.123)));
+        assert_eq!(s.columns()["pos"].get(1), Some(DataField::GeoPoint(45.123, -122.123)));
+        s.set_geo_point_decimals("pos", 1);
+        assert_eq!(s.columns()["pos"].get(1), Some(DataField::GeoPoint(45.1, -122.1)));
+
+        assert_matches!(s.add_geo_point(2, "pos", 91.0, 0.0), Err(Error::FloatOutOfRange{index: 2, ..}));
+        assert_matches!(s.add_geo_point(2, "pos", 0.0, f64::NAN), Err(Error::FloatOutOfRange{index: 2, ..}));
+        s.set_float_overflow_policy("pos", FloatOverflowPolicy::Saturate);
+        assert!(s.add_geo_point(2, "pos", 91.0, -200.0).is_ok());
+        assert_eq!(s.columns()["pos"].get(2), Some(DataField::GeoPoint(90.0, -180.0)));
+
+        s.set_geo_point_decimals("pos", 200);
+        assert_eq!(s.geo_point_decimals("pos"), MAX_GEO_POINT_DECIMALS);
+    }
+
+    #[test]
+    fn test_add_f64_array() {
+        let mut s = Section::new(SectionType::TrackPoints);
+        assert!(s.add_f64_array(0, "v", vec![1.23456, -2.0]).is_ok());
+        s.set_f64_array_decimals("v", 1);
+        assert!(s.add_f64_array(1, "v", vec![1.23456]).is_ok());
+        assert_eq!(s.columns()["v"].get(0), Some(DataField::F64Array(vec![1.2, -2.0])));
+        assert_eq!(s.columns()["v"].get(1), Some(DataField::F64Array(vec![1.2])));
+
+        assert_matches!(s.add_f64_array(2, "v", vec![0.0, f64::NAN]), Err(Error::FloatOutOfRange{index: 2, ..}));
+        s.set_float_overflow_policy("v", FloatOverflowPolicy::Null);
+        assert!(s.add_f64_array(2, "v", vec![0.0, f64::NAN]).is_ok());
+        assert_eq!(s.columns()["v"].get(2), None);
+    }
+
+    #[test]
+    fn test_set_timestamp_unit() {
+        let mut s = Section::new(SectionType::TrackPoints);
+        assert!(s.add_timestamp(0, "at", 1600).is_ok());
+        assert!(s.add_timestamp(1, "at", -1400).is_ok());
+        s.set_timestamp_unit("at", TimestampUnit::Seconds);
+        assert!(s.add_timestamp(2, "at", 2499).is_ok());
+        assert_eq!(s.columns()["at"].get(0), Some(DataField::Timestamp(2000)));
+        assert_eq!(s.columns()["at"].get(1), Some(DataField::Timestamp(-1000)));
+        assert_eq!(s.columns()["at"].get(2), Some(DataField::Timestamp(2000)));
+
+        // going back to millis can't restore what was rounded away
+        s.set_timestamp_unit("at", TimestampUnit::Millis);
+        assert_eq!(s.columns()["at"].get(0), Some(DataField::Timestamp(2000)));
+    }
+
+    #[test]
+    fn test_too_many_columns() {
+        let mut s = Section::new(SectionType::TrackPoints);
+        for c in 0..MAX_FIELDS {
+            assert!(s.add_number(0, &format!("c{}", c), c as i64).is_ok());
+        }
+        assert_matches!(s.add_number(0, "c4096", 0), Err(Error::TooManyColumns{max: 4096, ..}));
+        assert!(s.add_number(1, "c0", 0).is_ok());
+        assert_eq!(s.columns().len(), MAX_FIELDS);
+    }
+
     #[test]
     fn test_len() {
         let mut s = Section::new(SectionType::TrackPoints);
@@ -466,6 +1873,15 @@ mod tests {
         assert_eq!(s.len(), 501);
     }
 
+    #[test]
+    fn test_write_missing_column() {
+        let mut s = Section::new(SectionType::TrackPoints);
+        assert!(s.add_number(0, "a", 1).is_ok());
+        s.flags.set(0, "ghost");
+        assert_matches!(s.write_types_table(&mut vec![], &s.run_length_columns()), Err(Error::MissingColumn{ref name}) if name == "ghost");
+        assert_matches!(s.write_data(&mut vec![]), Err(Error::MissingColumn{ref name}) if name == "ghost");
+    }
+
     #[test]
     fn test_write_types_table() {
         let mut s = Section::new(SectionType::TrackPoints);
@@ -473,7 +1889,7 @@ mod tests {
         assert!(s.add_base64(1, "bazar", vec![0,1,2,3,4]).is_ok());
 
         let mut buf = vec![];
-        let written = s.write_types_table(&mut buf);
+        let written = s.write_types_table(&mut buf, &s.run_length_columns());
         assert!(written.is_ok());
         let expected = &[0x02, // 2 entries in the table
                          0x00, // column 1 type is Column::Numbers
@@ -504,7 +1920,7 @@ mod tests {
         assert!(s.add_number(500, "j10", 12).is_ok());
 
         let mut buf = vec![];
-        let written = s.write_types_table(&mut buf);
+        let written = s.write_types_table(&mut buf, &s.run_length_columns());
         assert!(written.is_ok());
         let expected = vec![0x0A, // 10 entries in the table
                             0x00, // column 1 type is Column::Numbers
@@ -559,7 +1975,7 @@ mod tests {
         assert!(s.add_number(1, "I♥NY", 5).is_ok());
 
         let mut buf = vec![];
-        let written = s.write_types_table(&mut buf);
+        let written = s.write_types_table(&mut buf, &s.run_length_columns());
         assert!(written.is_ok());
         let expected = &[0x01, // 1 entry in the table
                          0x00, // column 1 type is Column::Numbers
@@ -790,6 +2206,125 @@ mod tests {
         assert_eq!(written.unwrap(), expected.len());
     }
 
+    #[test]
+    fn test_write_data_packed_bool() {
+        let mut s = Section::new(SectionType::TrackPoints);
+        assert!(s.add_packed_bool(0, "a", true).is_ok());
+        assert!(s.add_packed_bool(1, "a", false).is_ok());
+        assert!(s.add_packed_bool(2, "a", true).is_ok());
+        assert!(s.add_packed_bool(9, "a", true).is_ok());
+
+        let mut buf = vec![];
+        let written = s.write_data(&mut buf);
+        assert!(written.is_ok());
+        let expected = &[0x01, // flags column
+                         0x01,
+                         0x01,
+                         0x00,
+                         0x00,
+                         0x00,
+                         0x00,
+                         0x00,
+                         0x00,
+                         0x01,
+                         0x05, // now the data column - rows 0 and 2 are true
+                         0x02, // row 9 is true
+                         0xA0, // 4-byte crc
+                         0xC1,
+                         0x87,
+                         0x5C];
+        assert_eq!(buf, expected);
+        assert_eq!(written.unwrap(), expected.len());
+    }
+
+    #[test]
+    fn test_write_run_length_numbers() {
+        let mut s = Section::new(SectionType::TrackPoints);
+        s.set_run_length_encoding(true);
+        for i in 0..6 {
+            assert!(s.add_number(i, "a", 3).is_ok());
+            assert!(s.add_number(i, "b", i as i64).is_ok());
+        }
+
+        let mut buf = vec![];
+        let written = s.write_types_table(&mut buf, &s.run_length_columns());
+        assert!(written.is_ok());
+        assert_eq!(&buf[..5], &[0x02, // 2 entries in the table
+                                0x08, // column 1 type is run-length encoded Column::Numbers
+                                0x01,
+                                b'a',
+                                0x00]); // column 2 type is Column::Numbers, RLE wouldn't help
+
+        let mut buf = vec![];
+        let written = s.write_data(&mut buf);
+        assert!(written.is_ok());
+        let expected = &[0x06, // flags column, a single run of 6 rows
+                         0x03, // with both columns
+                         0x06, // a is a single run of 6 rows
+                         0x03, // with value 3
+                         0x00, // b is delta encoded
+                         0x01,
+                         0x01,
+                         0x01,
+                         0x01,
+                         0x01,
+                         0xDB, // 4-byte crc
+                         0x12,
+                         0x9C,
+                         0xCA];
+        assert_eq!(buf, expected);
+        assert_eq!(written.unwrap(), expected.len());
+    }
+
+    #[test]
+    fn test_indexed_columns() {
+        let mut s = Section::new(SectionType::TrackPoints);
+        assert!(s.add_number(0, "b", 5).is_ok());
+        assert!(s.add_string(1, "a", "hi".to_string()).is_ok());
+        assert!(s.add_number(1, "b", 6).is_ok());
+
+        assert_eq!(s.column_index("b"), Some(0));
+        assert_eq!(s.column_index("a"), Some(1));
+        assert_eq!(s.column_index("c"), None);
+
+        let columns = s.indexed_columns();
+        assert_eq!(columns.len(), 2);
+        assert_eq!(columns.name(1), Some("a"));
+        assert_matches!(columns.column(0), Some(Column::Numbers(_)));
+        assert!(columns.column(2).is_none());
+
+        let row = columns.row(0);
+        assert_matches!(row.get(0), Some(DataField::Number(5)));
+        assert_matches!(row.get(1), None);
+
+        let row = columns.row(1);
+        assert_matches!(row.get(0), Some(DataField::Number(6)));
+        assert_matches!(row.get(1), Some(DataField::String(ref v)) if v == "hi");
+        assert_matches!(row.get(2), None);
+
+        let columns = s.indexed_columns_for(&[1]).unwrap();
+        assert_eq!(columns.len(), 1);
+        assert_eq!(columns.name(0), Some("a"));
+        assert_matches!(columns.row(1).get(0), Some(DataField::String(ref v)) if v == "hi");
+        assert_matches!(columns.row(1).get(1), None);
+
+        let columns = s.indexed_columns_for(&[1, 0]).unwrap();
+        assert_eq!(columns.name(0), Some("a"));
+        assert_matches!(columns.row(0).get(1), Some(DataField::Number(5)));
+        assert!(s.indexed_columns_for(&[]).unwrap().is_empty());
+        assert!(s.indexed_columns_for(&[0, 2]).is_none());
+
+        // rows past the last value of the chosen columns are included
+        assert!(s.add_number(3, "b", 7).is_ok());
+        let columns = s.indexed_columns_for(&[1]).unwrap();
+        assert_eq!(columns.rows().len(), 4);
+        assert_eq!(columns.rows().map(|row| row.index()).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+        let values = s.indexed_columns().rows().filter_map(|row| row.get(0)).collect::<Vec<_>>();
+        assert_eq!(values, vec![DataField::Number(5), DataField::Number(6), DataField::Number(7)]);
+        assert_eq!(s.indexed_columns().rows().next_back().map(|row| row.index()), Some(3));
+        assert_eq!(Section::new(SectionType::TrackPoints).indexed_columns().rows().count(), 0);
+    }
+
     #[test]
     fn test_simplify_empty_section() {
         let s = Section::new(SectionType::TrackPoints);
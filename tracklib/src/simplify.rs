@@ -1,17 +1,24 @@
+use crate::downsample::{select_rows};
 use crate::polyline::{polyline_encode, FieldEncodeOptions};
+use crate::rwtfile::{Result};
 use crate::surface::{RoadClassId, SurfaceMapping, SurfaceTypeId};
-use crate::{Column, Section};
+use crate::{fields, Column, Section};
 use itertools::Itertools;
 use std::collections::{BTreeMap, HashSet};
 
 fn haversine_distance(prev: &Point, x: f64, y: f64) -> f64 {
+    haversine(prev.x, prev.y, x, y)
+}
+
+// Meters between two positions, as (lng, lat) in degrees
+pub(crate) fn haversine(x1: f64, y1: f64, x: f64, y: f64) -> f64 {
     // lifted wholesale from https://github.com/georust/geo/blob/2cf153d59072d18054baf4da8bcaf3e0c088a7d8/geo/src/algorithm/haversine_distance.rs
     const MEAN_EARTH_RADIUS: f64 = 6_371_000.0;
 
-    let theta1 = prev.y.to_radians();
+    let theta1 = y1.to_radians();
     let theta2 = y.to_radians();
-    let delta_theta = (y - prev.y).to_radians();
-    let delta_lambda = (x - prev.x).to_radians();
+    let delta_theta = (y - y1).to_radians();
+    let delta_lambda = (x - x1).to_radians();
     let a = (delta_theta / 2.0).sin().powi(2) + theta1.cos() * theta2.cos() * (delta_lambda / 2.0).sin().powi(2);
     let c = 2.0 * a.sqrt().asin();
     MEAN_EARTH_RADIUS * c
@@ -180,27 +187,27 @@ impl<'a, 'b> Iterator for SurfaceGroupIter<'a, 'b> {
     }
 }
 
-fn simplify_points(points: &[Point], mapping: &SurfaceMapping, tolerance: f64) -> HashSet<usize> {
-    fn stack_rdp(points: &[Point], tolerance_sq: f64) -> HashSet<usize> {
-        let mut anchors = HashSet::new();
-        let mut stack = Vec::new();
-        stack.push(points);
+fn stack_rdp(points: &[Point], tolerance_sq: f64) -> HashSet<usize> {
+    let mut anchors = HashSet::new();
+    let mut stack = Vec::new();
+    stack.push(points);
 
-        while let Some(slice) = stack.pop() {
-            let (farthest_index, farthest_dist) = slice.farthest_point();
+    while let Some(slice) = stack.pop() {
+        let (farthest_index, farthest_dist) = slice.farthest_point();
 
-            if farthest_dist > tolerance_sq {
-                stack.push(&slice[..=farthest_index]);
-                stack.push(&slice[farthest_index..]);
-            } else {
-                anchors.insert(slice.first().unwrap().index);
-                anchors.insert(slice.last().unwrap().index);
-            }
+        if farthest_dist > tolerance_sq {
+            stack.push(&slice[..=farthest_index]);
+            stack.push(&slice[farthest_index..]);
+        } else {
+            anchors.insert(slice.first().unwrap().index);
+            anchors.insert(slice.last().unwrap().index);
         }
-
-        anchors
     }
 
+    anchors
+}
+
+fn simplify_points(points: &[Point], mapping: &SurfaceMapping, tolerance: f64) -> HashSet<usize> {
     let tolerance_sq = tolerance * tolerance;
     SurfaceGroupIter::new(points, mapping)
         .map(|points| stack_rdp(points, tolerance_sq))
@@ -214,7 +221,7 @@ fn section_to_points(section: &Section) -> Vec<Point> {
     let empty_base64_btree = BTreeMap::new();
 
     let columns = section.columns();
-    let x_map = if let Some(x_column) = columns.get("x") {
+    let x_map = if let Some(x_column) = columns.get(fields::LNG.name) {
         match x_column {
             Column::LongFloat(x) => x,
             _ => panic!("unexpected x column type"),
@@ -223,7 +230,7 @@ fn section_to_points(section: &Section) -> Vec<Point> {
         &empty_longfloat_btree
     };
 
-    let y_map = if let Some(y_column) = columns.get("y") {
+    let y_map = if let Some(y_column) = columns.get(fields::LAT.name) {
         match y_column {
             Column::LongFloat(y) => y,
             _ => panic!("unexpected y column type"),
@@ -232,7 +239,7 @@ fn section_to_points(section: &Section) -> Vec<Point> {
         &empty_longfloat_btree
     };
 
-    let e_map = if let Some(e_column) = columns.get("e") {
+    let e_map = if let Some(e_column) = columns.get(fields::ELEVATION.name) {
         match e_column {
             Column::LongFloat(e) => e,
             _ => panic!("unexpected e column type"),
@@ -241,7 +248,7 @@ fn section_to_points(section: &Section) -> Vec<Point> {
         &empty_longfloat_btree
     };
 
-    let s_map = if let Some(s_column) = columns.get("S") {
+    let s_map = if let Some(s_column) = columns.get(fields::SURFACE.name) {
         match s_column {
             Column::Numbers(s) => s,
             _ => panic!("unexpected S column type"),
@@ -250,7 +257,7 @@ fn section_to_points(section: &Section) -> Vec<Point> {
         &empty_numbers_btree
     };
 
-    let r_map = if let Some(r_column) = columns.get("R") {
+    let r_map = if let Some(r_column) = columns.get(fields::ROAD_CLASS.name) {
         match r_column {
             Column::Numbers(r) => r,
             _ => panic!("unexpected R column type"),
@@ -321,6 +328,31 @@ pub(crate) fn simplify_and_encode(
     polyline_encode(&simplified_points, fields)
 }
 
+/// Simplifies a section with Ramer-Douglas-Peucker on the `x` and `y`
+/// columns, keeping every point that's more than `tolerance`, in the units
+/// of those columns, off the simplified line. Unlike `simplify_and_encode`
+/// this gives back a section, of the rows kept with all of their columns,
+/// renumbered from 0, e.g. to write a simplified copy of a track. Rows
+/// without both an `x` and a `y` are dropped.
+pub fn simplify_section(section: &Section, tolerance: f64, x: &str, y: &str) -> Result<Section> {
+    let get = |name, row| section.columns().get(name).and_then(|column: &Column| column.get(row)).and_then(|v| v.as_f64());
+    let (rows, points): (Vec<usize>, Vec<Point>) = (0..section.len())
+        .filter_map(|row| match (get(x, row), get(y, row)) {
+            (Some(x), Some(y)) => Some((row, (x, y))),
+            _ => None,
+        })
+        .enumerate()
+        .map(|(index, (row, (x, y)))| (row, Point{index, x, y, d: 0.0, e: 0.0, s: None, r: None}))
+        .unzip();
+
+    let kept = if points.is_empty() {
+        vec![]
+    } else {
+        stack_rdp(&points, tolerance * tolerance).into_iter().sorted().map(|index| rows[index]).collect()
+    };
+    select_rows(section, &kept, None)
+}
+
 #[cfg(test)]
 mod tests {
     use assert_matches::assert_matches;
@@ -559,4 +591,27 @@ mod tests {
             assert!((381.0 - d).abs() < 1.0);
         });
     }
+
+    #[test]
+    fn test_simplify_section() {
+        let mut s = Section::new(SectionType::TrackPoints);
+        // an L with a wobble along the first leg
+        let positions = [(0.0, 0.0), (0.0, 1.0), (0.01, 2.0), (0.0, 3.0), (1.0, 3.0), (2.0, 3.0)];
+        for (i, (x, y)) in positions.iter().enumerate() {
+            assert!(s.add_long_float(i, "x", *x).is_ok());
+            assert!(s.add_long_float(i, "y", *y).is_ok());
+            assert!(s.add_number(i, "t", i as i64).is_ok());
+        }
+        // no position
+        assert!(s.add_number(6, "t", 6).is_ok());
+
+        let out = simplify_section(&s, 0.1, "x", "y").unwrap();
+        assert_eq!(out.len(), 3);
+        assert_matches!(out.columns().get("t"), Some(Column::Numbers(m)) if m.values().copied().collect::<Vec<_>>() == vec![0, 3, 5]);
+
+        let out = simplify_section(&s, 0.006, "x", "y").unwrap();
+        assert_matches!(out.columns().get("t"), Some(Column::Numbers(m)) if m.values().copied().collect::<Vec<_>>() == vec![0, 2, 3, 5]);
+
+        assert_eq!(simplify_section(&s, 0.1, "a", "b").unwrap().len(), 0);
+    }
 }
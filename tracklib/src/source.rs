@@ -0,0 +1,198 @@
+use std::fs::{File};
+use std::future::{Future, Ready};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::{Range};
+use std::pin::{Pin};
+use std::task::{Context, Poll, Waker};
+use crate::decode::{ParseOptions};
+use crate::fields::{FieldType};
+use crate::metadata::{RWTFMetadata};
+use crate::rangereader::{RangeReader, Result, SectionInfo};
+use crate::rwtfile::{RWTFHeader};
+use crate::section::{Section, SectionSizes, SectionType};
+
+/// Somewhere a file can be read from a piece at a time. Implement this for
+/// an object store or a cache to read from it with `TrackReader`.
+pub trait TrackSource {
+    /// `len` bytes from `offset`, or fewer at the end of the file.
+    fn read_at(&mut self, offset: usize, len: usize) -> io::Result<Vec<u8>>;
+}
+
+impl TrackSource for &[u8] {
+    fn read_at(&mut self, offset: usize, len: usize) -> io::Result<Vec<u8>> {
+        let start = offset.min(self.len());
+        Ok(self[start..offset.saturating_add(len).min(self.len())].to_vec())
+    }
+}
+
+impl TrackSource for File {
+    fn read_at(&mut self, offset: usize, len: usize) -> io::Result<Vec<u8>> {
+        self.seek(SeekFrom::Start(offset as u64))?;
+        let mut buf = Vec::with_capacity(len);
+        self.by_ref().take(len as u64).read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+type Fetch<'a> = Box<dyn FnMut(usize, usize) -> Ready<io::Result<Vec<u8>>> + 'a>;
+
+// Every fetch from a TrackSource is ready straight away, so the futures of
+// a RangeReader over one finish the first time they're polled
+fn now<T>(future: impl Future<Output = T>) -> T {
+    let mut future = Box::pin(future);
+    match Pin::as_mut(&mut future).poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(v) => v,
+        Poll::Pending => unreachable!("TrackSource reads are never pending"),
+    }
+}
+
+/// `RangeReader` for a `TrackSource`, without the async.
+pub struct TrackReader<'a> {
+    reader: RangeReader<Fetch<'a>>,
+}
+
+impl<'a> TrackReader<'a> {
+    pub fn open<S: TrackSource + 'a>(mut source: S, options: ParseOptions) -> Result<Self> {
+        let fetch: Fetch<'a> = Box::new(move |offset, len| std::future::ready(source.read_at(offset, len)));
+        Ok(TrackReader{reader: now(RangeReader::open(fetch, options))?})
+    }
+
+    pub fn header(&self) -> &RWTFHeader {
+        self.reader.header()
+    }
+
+    pub fn metadata(&self) -> &RWTFMetadata {
+        self.reader.metadata()
+    }
+
+    /// See `RangeReader::sections`.
+    pub fn sections(&self) -> &[SectionInfo] {
+        self.reader.sections()
+    }
+
+    /// See `RangeReader::columns`.
+    pub fn columns(&mut self, section: SectionInfo) -> Result<Vec<(String, FieldType)>> {
+        now(self.reader.columns(section))
+    }
+
+    /// See `RangeReader::section`.
+    pub fn section(&mut self, section: SectionInfo) -> Result<Section> {
+        now(self.reader.section(section))
+    }
+
+    /// See `RangeReader::section_sizes`.
+    pub fn section_sizes(&mut self, section: SectionInfo) -> Result<SectionSizes> {
+        now(self.reader.section_sizes(section))
+    }
+
+    /// See `RangeReader::verify_file_checksum`.
+    pub fn verify_file_checksum(&mut self) -> Result<bool> {
+        now(self.reader.verify_file_checksum())
+    }
+
+    /// See `RangeReader::section_columns`.
+    pub fn section_columns(&mut self, section: SectionInfo, names: &[&str]) -> Result<Section> {
+        now(self.reader.section_columns(section, names))
+    }
+
+    /// See `RangeReader::rows`.
+    pub fn rows(&mut self, section_type: SectionType, rows: Range<usize>) -> Result<Section> {
+        now(self.reader.rows(section_type, rows))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Write};
+    use crate::rwtfile::{DataField, RWTFile};
+    use crate::section::{SectionType};
+
+    fn test_file() -> Vec<u8> {
+        let mut f = RWTFile::new();
+        for i in 0..10 {
+            assert!(f.add_track_point(i, "t", 1000 + i as i64).is_ok());
+        }
+        assert!(f.add_course_point(0, "n", DataField::String("start".into())).is_ok());
+        f.to_bytes().unwrap()
+    }
+
+    fn check(reader: &mut TrackReader) {
+        assert_eq!(reader.sections().iter().map(|s| s.section_type).collect::<Vec<_>>(),
+                   vec![SectionType::TrackPoints, SectionType::CoursePoints]);
+        let track = reader.sections()[0];
+        assert_eq!(reader.columns(track).unwrap(), vec![("t".to_string(), FieldType::Numbers)]);
+        assert_eq!(reader.section(track).unwrap().len(), 10);
+    }
+
+    #[test]
+    fn test_slice_source() {
+        let buf = test_file();
+        let mut source = &buf[..];
+        assert_eq!(source.read_at(buf.len() - 2, 10).unwrap().len(), 2);
+        assert!(source.read_at(buf.len() + 10, 10).unwrap().is_empty());
+
+        let mut reader = TrackReader::open(&buf[..], ParseOptions::new()).unwrap();
+        check(&mut reader);
+
+        // the first time, 1000, takes two bytes
+        let track = reader.sections()[0];
+        let sizes = reader.section_sizes(track).unwrap();
+        assert_eq!(sizes.columns, vec![("t".to_string(), FieldType::Numbers, 11)]);
+        assert_eq!(sizes.total(), track.len);
+    }
+
+    #[test]
+    fn test_file_source() {
+        let path = std::env::temp_dir().join(format!("tracklib-source-{}.rwtf", std::process::id()));
+        assert!(File::create(&path).and_then(|mut file| file.write_all(&test_file())).is_ok());
+        let file = File::open(&path).unwrap();
+        let mut reader = TrackReader::open(file, ParseOptions::new()).unwrap();
+        check(&mut reader);
+        assert!(std::fs::remove_file(&path).is_ok());
+    }
+
+    #[test]
+    fn test_verify_file_checksum() {
+        let buf = test_file();
+        assert!(!TrackReader::open(&buf[..], ParseOptions::new()).unwrap().verify_file_checksum().unwrap());
+        // junk the size of a checksum isn't taken for one
+        let junk = [&buf[..], &[0x01, 0x02, 0x03, 0x04]].concat();
+        assert!(!TrackReader::open(&junk[..], ParseOptions::new()).unwrap().verify_file_checksum().unwrap());
+
+        let mut f = RWTFile::read(&buf).unwrap();
+        f.set_file_checksum(true);
+        let mut buf = f.to_bytes().unwrap();
+        assert!(TrackReader::open(&buf[..], ParseOptions::new()).unwrap().verify_file_checksum().unwrap());
+        let junk = [&buf[..], &[0x01, 0x02]].concat();
+        assert!(TrackReader::open(&junk[..], ParseOptions::new()).unwrap().verify_file_checksum().unwrap());
+
+        // a flipped bit in a value, which reading the section would catch too
+        let track = TrackReader::open(&buf[..], ParseOptions::new()).unwrap().sections()[0];
+        buf[track.offset + track.len - 5] ^= 0x01;
+        let mut reader = TrackReader::open(&buf[..], ParseOptions::new()).unwrap();
+        assert_eq!(reader.verify_file_checksum().unwrap_err().kind(), crate::error::ErrorKind::Corruption);
+    }
+
+    #[test]
+    fn test_custom_source() {
+        // e.g. an object store, counting requests
+        struct Counting {
+            buf: Vec<u8>,
+            reads: usize,
+        }
+
+        impl TrackSource for &mut Counting {
+            fn read_at(&mut self, offset: usize, len: usize) -> io::Result<Vec<u8>> {
+                self.reads += 1;
+                (&self.buf[..]).read_at(offset, len)
+            }
+        }
+
+        let mut source = Counting{buf: test_file(), reads: 0};
+        check(&mut TrackReader::open(&mut source, ParseOptions::new()).unwrap());
+        // the header, the metadata, three section headers, a types table and
+        // a section
+        assert_eq!(source.reads, 7);
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::{HashMap};
+use crate::trackpoint::{TrackPointReader};
+
+// Points are bucketed into cells this many degrees on a side, roughly 1km
+// north to south
+const CELL_SIZE: f64 = 0.01;
+
+fn cell(lat: f64, lng: f64) -> (i64, i64) {
+    ((lat / CELL_SIZE).floor() as i64, (lng / CELL_SIZE).floor() as i64)
+}
+
+/// Finds the rows of a track near a position without scanning the whole
+/// track, for map lookups. Build it once and query it as often as needed.
+/// Rows without both a latitude and a longitude aren't indexed.
+#[derive(Debug)]
+pub struct SpatialIndex {
+    // (row, lat, lng)
+    points: Vec<(usize, f64, f64)>,
+    // indexes into `points`, in row order
+    cells: HashMap<(i64, i64), Vec<usize>>,
+    // the smallest and largest cells with points in them
+    bounds: Option<((i64, i64), (i64, i64))>,
+}
+
+impl SpatialIndex {
+    pub fn new(reader: &TrackPointReader) -> Self {
+        let points = (0..reader.len())
+            .filter_map(|row| match (reader.lat(row), reader.lng(row)) {
+                (Some(lat), Some(lng)) if lat.is_finite() && lng.is_finite() => Some((row, lat, lng)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        let mut cells: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+        let mut bounds: Option<((i64, i64), (i64, i64))> = None;
+        for (i, (_, lat, lng)) in points.iter().enumerate() {
+            let (y, x) = cell(*lat, *lng);
+            cells.entry((y, x)).or_default().push(i);
+            bounds = Some(match bounds {
+                Some(((min_y, min_x), (max_y, max_x))) => ((min_y.min(y), min_x.min(x)), (max_y.max(y), max_x.max(x))),
+                None => ((y, x), (y, x)),
+            });
+        }
+
+        SpatialIndex{points, cells, bounds}
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// The row closest to `lat`, `lng`, measuring distance on a flat map
+    /// centered on that position, which is accurate for anything within a
+    /// few hundred kilometers. Ties go to the earlier row.
+    pub fn nearest_point(&self, lat: f64, lng: f64) -> Option<usize> {
+        let ((min_y, min_x), (max_y, max_x)) = self.bounds?;
+        let (y, x) = cell(lat, lng);
+        // degrees of longitude are shorter than degrees of latitude away
+        // from the equator
+        let scale = lat.to_radians().cos().abs().max(1e-6);
+        let distance = |i: usize| {
+            let (_, point_lat, point_lng) = self.points[i];
+            ((point_lat - lat).powi(2) + ((point_lng - lng) * scale).powi(2)).sqrt()
+        };
+
+        let consider = |best: &mut Option<(f64, usize)>, i: usize| {
+            let d = distance(i);
+            match best {
+                Some((best_distance, best_i)) if d > *best_distance || (d == *best_distance && i > *best_i) => {}
+                _ => *best = Some((d, i)),
+            }
+        };
+
+        let rings = [y - min_y, max_y - y, x - min_x, max_x - x].iter().map(|d| d.abs()).max().unwrap_or(0);
+        let mut best: Option<(f64, usize)> = None;
+        let mut visited = 0;
+        for ring in 0..=rings {
+            // every point in this ring is at least this far away
+            let closest = (ring - 1).max(0) as f64 * CELL_SIZE * scale.min(1.0);
+            if let Some((best_distance, _)) = best {
+                if best_distance < closest {
+                    break;
+                }
+            }
+
+            // far from the track it's quicker to check every point
+            visited += (8 * ring).max(1) as usize;
+            if visited > self.cells.len() * 4 {
+                best = None;
+                for i in 0..self.points.len() {
+                    consider(&mut best, i);
+                }
+                break;
+            }
+
+            // the cells around the edge of the square `ring` cells out
+            for dy in -ring..=ring {
+                let step = if dy.abs() == ring { 1 } else { (2 * ring) as usize };
+                for dx in (-ring..=ring).step_by(step.max(1)) {
+                    for i in self.cells.get(&(y + dy, x + dx)).into_iter().flatten() {
+                        consider(&mut best, *i);
+                    }
+                }
+            }
+        }
+
+        best.map(|(_, i)| self.points[i].0)
+    }
+
+    /// The rows within a bounding box, in order.
+    pub fn rows_in_bounds(&self, min_lat: f64, min_lng: f64, max_lat: f64, max_lng: f64) -> Vec<usize> {
+        let inside = |&(_, lat, lng): &(usize, f64, f64)| {
+            lat >= min_lat && lat <= max_lat && lng >= min_lng && lng <= max_lng
+        };
+
+        let ((min_y, min_x), (max_y, max_x)) = match self.bounds {
+            Some(bounds) => bounds,
+            None => return vec![],
+        };
+        let (low_y, low_x) = cell(min_lat, min_lng);
+        let (high_y, high_x) = cell(max_lat, max_lng);
+        let (low_y, low_x) = (low_y.max(min_y), low_x.max(min_x));
+        let (high_y, high_x) = (high_y.min(max_y), high_x.min(max_x));
+        if low_y > high_y || low_x > high_x {
+            return vec![];
+        }
+
+        // a box covering most of the track is quicker to check point by point
+        let cells = (high_y - low_y + 1).saturating_mul(high_x - low_x + 1);
+        let mut rows = if cells as usize > self.cells.len() {
+            self.points.iter().filter(|point| inside(point)).map(|(row, _, _)| *row).collect::<Vec<_>>()
+        } else {
+            (low_y..=high_y)
+                .flat_map(|y| (low_x..=high_x).map(move |x| (y, x)))
+                .filter_map(|key| self.cells.get(&key))
+                .flatten()
+                .map(|i| &self.points[*i])
+                .filter(|point| inside(point))
+                .map(|(row, _, _)| *row)
+                .collect::<Vec<_>>()
+        };
+        rows.sort_unstable();
+
+        rows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::section::{Section, SectionType};
+
+    fn section(points: &[(f64, f64)]) -> Section {
+        let mut s = Section::new(SectionType::TrackPoints);
+        for (i, (lat, lng)) in points.iter().enumerate() {
+            assert!(s.add_long_float(i, "y", *lat).is_ok());
+            assert!(s.add_long_float(i, "x", *lng).is_ok());
+        }
+        s
+    }
+
+    fn nearest_by_scan(points: &[(f64, f64)], lat: f64, lng: f64) -> Option<usize> {
+        let scale = lat.to_radians().cos().abs().max(1e-6);
+        let distance = |(point_lat, point_lng): &(f64, f64)| {
+            ((point_lat - lat).powi(2) + ((point_lng - lng) * scale).powi(2)).sqrt()
+        };
+        (0..points.len()).fold(None, |best: Option<usize>, i| match best {
+            Some(b) if distance(&points[b]) <= distance(&points[i]) => Some(b),
+            _ => Some(i),
+        })
+    }
+
+    #[test]
+    fn test_spatial_index() {
+        // a wiggly track heading north east from Portland
+        let points = (0..2000)
+            .map(|i| (45.5 + i as f64 * 0.0007, -122.6 + i as f64 * 0.0005 + (i as f64 / 30.0).sin() * 0.01))
+            .collect::<Vec<_>>();
+        let s = section(&points);
+        let spatial = SpatialIndex::new(&TrackPointReader::new(&s));
+        assert_eq!(spatial.len(), 2000);
+
+        for (lat, lng) in &[(45.5, -122.6), (46.0, -122.0), (45.9, -122.3), (40.0, -100.0), (45.51, -122.61)] {
+            assert_eq!(spatial.nearest_point(*lat, *lng), nearest_by_scan(&points, *lat, *lng));
+        }
+
+        let expected = points.iter()
+            .enumerate()
+            .filter(|(_, (lat, lng))| *lat >= 45.6 && *lat <= 45.7 && *lng >= -122.55 && *lng <= -122.5)
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        assert!(!expected.is_empty());
+        assert_eq!(spatial.rows_in_bounds(45.6, -122.55, 45.7, -122.5), expected);
+        assert_eq!(spatial.rows_in_bounds(-90.0, -180.0, 90.0, 180.0), (0..2000).collect::<Vec<_>>());
+        assert!(spatial.rows_in_bounds(0.0, 0.0, 1.0, 1.0).is_empty());
+    }
+
+    #[test]
+    fn test_missing_positions() {
+        let mut s = Section::new(SectionType::TrackPoints);
+        assert!(s.add_long_float(0, "y", 45.5).is_ok());
+        assert!(s.add_long_float(1, "y", 45.5).is_ok());
+        assert!(s.add_long_float(1, "x", -122.6).is_ok());
+
+        let spatial = SpatialIndex::new(&TrackPointReader::new(&s));
+        assert_eq!(spatial.len(), 1);
+        assert_eq!(spatial.nearest_point(45.5, -122.6), Some(1));
+
+        let empty = Section::new(SectionType::TrackPoints);
+        let spatial = SpatialIndex::new(&TrackPointReader::new(&empty));
+        assert!(spatial.is_empty());
+        assert_eq!(spatial.nearest_point(45.5, -122.6), None);
+        assert!(spatial.rows_in_bounds(-90.0, -180.0, 90.0, 180.0).is_empty());
+    }
+}
@@ -0,0 +1,123 @@
+use std::io::{Read};
+use snafu::{ResultExt};
+use crate::decode::{parse_data_offset, parse_section_info, parse_single_section, parse_start, ParseOptions};
+use crate::metadata::{RWTFMetadata};
+use crate::rangereader::{Fetch, Parse, Result, SectionInfo, HEADER_SIZE, SECTION_HEADER_SIZE};
+use crate::rwtfile::{RWTFHeader};
+use crate::section::{Section};
+
+/// Reads a file front to back from a `Read` which can't seek, e.g. a file
+/// piped from S3, one section at a time. Only the section being decoded is
+/// held in memory. Like `RangeReader`, each continuation section is read
+/// separately.
+pub struct StreamReader<R> {
+    input: R,
+    options: ParseOptions,
+    header: RWTFHeader,
+    metadata: RWTFMetadata,
+    offset: usize,
+    index: usize,
+    done: bool,
+}
+
+// `len` bytes from `offset`, or fewer at the end of the input
+fn read<R: Read>(input: &mut R, offset: usize, len: usize) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(len);
+    input.by_ref().take(len as u64).read_to_end(&mut buf).context(Fetch{offset, len})?;
+    Ok(buf)
+}
+
+impl<R: Read> StreamReader<R> {
+    /// Reads the header and the metadata.
+    pub fn open(mut input: R, options: ParseOptions) -> Result<Self> {
+        let mut start = read(&mut input, 0, HEADER_SIZE)?;
+        let data_offset = parse_data_offset(&start, &options).context(Parse)?;
+        if data_offset > start.len() {
+            start.extend(read(&mut input, start.len(), data_offset - start.len())?);
+        }
+        let (header, metadata) = parse_start(&start, &options).context(Parse)?;
+
+        Ok(StreamReader{input, options, header, metadata, offset: data_offset, index: 0, done: false})
+    }
+
+    pub fn header(&self) -> &RWTFHeader {
+        &self.header
+    }
+
+    pub fn metadata(&self) -> &RWTFMetadata {
+        &self.metadata
+    }
+
+    /// Reads and decodes the next section, or `None` once the trailer has
+    /// been read.
+    pub fn next_section(&mut self) -> Result<Option<(SectionInfo, Section)>> {
+        if self.done {
+            return Ok(None);
+        }
+
+        let offset = self.offset;
+        let mut bytes = read(&mut self.input, offset, SECTION_HEADER_SIZE)?;
+        match parse_section_info(&bytes, self.index, &self.options).map_err(|e| e.moved(offset)).context(Parse)? {
+            Some((section_type, points, len)) => {
+                bytes.extend(read(&mut self.input, offset + bytes.len(), len.saturating_sub(bytes.len()))?);
+                let section = parse_single_section(&bytes, self.index, &self.options)
+                    .map_err(|e| e.moved(offset))
+                    .context(Parse)?;
+                let info = SectionInfo{index: self.index, section_type, points, offset, len};
+                self.index += 1;
+                self.offset += len;
+                Ok(Some((info, section)))
+            }
+            None => {
+                self.done = true;
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rwtfile::{DataField, RWTFile};
+    use crate::section::{SectionType};
+
+    #[test]
+    fn test_stream_reader() {
+        let mut f = RWTFile::new();
+        for i in 0..10 {
+            assert!(f.add_track_point(i, "t", 1000 + i as i64).is_ok());
+        }
+        assert!(f.add_course_point(0, "n", DataField::String("start".into())).is_ok());
+        f.set_file_checksum(true);
+        let buf = f.to_bytes().unwrap();
+
+        // a Read which hands out a few bytes at a time
+        struct Trickle<'a>(&'a [u8]);
+
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let n = buf.len().min(self.0.len()).min(3);
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+
+        let mut reader = StreamReader::open(Trickle(&buf), ParseOptions::new()).unwrap();
+        assert_eq!(reader.metadata().track_type(), f.metadata().track_type());
+        let (info, section) = reader.next_section().unwrap().unwrap();
+        assert_eq!((info.index, info.section_type, info.points), (0, SectionType::TrackPoints, 10));
+        assert_eq!(section.columns()["t"].get(9), Some(DataField::Number(1009)));
+        let (info, section) = reader.next_section().unwrap().unwrap();
+        assert_eq!((info.index, info.section_type), (1, SectionType::CoursePoints));
+        assert_eq!(section.columns()["n"].get(0), Some(DataField::String("start".into())));
+        assert!(reader.next_section().unwrap().is_none());
+        assert!(reader.next_section().unwrap().is_none());
+
+        // cut off part way through the course points
+        let mut reader = StreamReader::open(&buf[..info.offset + 20], ParseOptions::new()).unwrap();
+        assert!(reader.next_section().is_ok());
+        assert!(reader.next_section().is_err());
+    }
+}
@@ -0,0 +1,286 @@
+use std::collections::{BTreeMap};
+use std::ops::{Range};
+use crate::fields::{self, Field};
+use crate::section::{Column, Section};
+
+/// The standard position and time fields of a single row.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TrackPoint {
+    pub lat: Option<f64>,
+    pub lng: Option<f64>,
+    pub elevation: Option<f64>,
+    /// Seconds since the unix epoch.
+    pub time: Option<i64>,
+}
+
+/// Reads the standard fields of a section row by row. A field that's
+/// missing from the section, or stored with the wrong column type, reads as
+/// `None` for every row.
+#[derive(Debug)]
+pub struct TrackPointReader<'a> {
+    len: usize,
+    lat: Option<&'a BTreeMap<usize, f64>>,
+    lng: Option<&'a BTreeMap<usize, f64>>,
+    elevation: Option<&'a BTreeMap<usize, f64>>,
+    time: Option<&'a BTreeMap<usize, i64>>,
+    segment: Option<&'a BTreeMap<usize, i64>>,
+}
+
+fn floats<'a>(section: &'a Section, field: &Field) -> Option<&'a BTreeMap<usize, f64>> {
+    match field.column(section) {
+        Some(Column::LongFloat(m)) => Some(m),
+        _ => None,
+    }
+}
+
+fn numbers<'a>(section: &'a Section, field: &Field) -> Option<&'a BTreeMap<usize, i64>> {
+    match field.column(section) {
+        Some(Column::Numbers(m)) => Some(m),
+        _ => None,
+    }
+}
+
+impl<'a> TrackPointReader<'a> {
+    pub fn new(section: &'a Section) -> Self {
+        TrackPointReader{len: section.len(),
+                         lat: floats(section, &fields::LAT),
+                         lng: floats(section, &fields::LNG),
+                         elevation: floats(section, &fields::ELEVATION),
+                         time: numbers(section, &fields::TIME),
+                         segment: numbers(section, &fields::SEGMENT)}
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn lat(&self, index: usize) -> Option<f64> {
+        self.lat.and_then(|m| m.get(&index)).copied()
+    }
+
+    pub fn lng(&self, index: usize) -> Option<f64> {
+        self.lng.and_then(|m| m.get(&index)).copied()
+    }
+
+    pub fn elevation(&self, index: usize) -> Option<f64> {
+        self.elevation.and_then(|m| m.get(&index)).copied()
+    }
+
+    pub fn time(&self, index: usize) -> Option<i64> {
+        self.time.and_then(|m| m.get(&index)).copied()
+    }
+
+    /// The segment id stored at this row, see `fields::SEGMENT`.
+    pub fn segment(&self, index: usize) -> Option<i64> {
+        self.segment.and_then(|m| m.get(&index)).copied()
+    }
+
+    /// The rows of each segment, in order. A section without segment ids is
+    /// a single segment.
+    pub fn segments(&self) -> Vec<Range<usize>> {
+        let mut segments = Vec::new();
+        let mut start = 0;
+        if let Some(m) = self.segment {
+            let mut current = None;
+            for (index, id) in m.range(..self.len) {
+                if current.is_some() && current != Some(id) {
+                    segments.push(start..*index);
+                    start = *index;
+                }
+                current = Some(id);
+            }
+        }
+        if start < self.len {
+            segments.push(start..self.len);
+        }
+
+        segments
+    }
+
+    pub fn point(&self, index: usize) -> TrackPoint {
+        TrackPoint{lat: self.lat(index),
+                   lng: self.lng(index),
+                   elevation: self.elevation(index),
+                   time: self.time(index)}
+    }
+
+    /// Every row in order, including rows without any of the standard fields.
+    pub fn points(&self) -> impl Iterator<Item = TrackPoint> + '_ {
+        (0..self.len).map(move |index| self.point(index))
+    }
+
+    // Splits the rows before each row with a time for which
+    // `f(first time of the piece, previous time, time)` is true. Rows
+    // without a time stay with the row before them.
+    fn split_where<F: FnMut(i64, i64, i64) -> bool>(&self, mut f: F) -> Vec<Range<usize>> {
+        let mut pieces = Vec::new();
+        let mut start = 0;
+        let mut times = None;
+        for row in 0..self.len {
+            if let Some(time) = self.time(row) {
+                times = match times {
+                    Some((first, prev)) if f(first, prev, time) => {
+                        pieces.push(start..row);
+                        start = row;
+                        Some((time, time))
+                    }
+                    Some((first, _)) => Some((first, time)),
+                    None => Some((time, time)),
+                };
+            }
+        }
+        if start < self.len {
+            pieces.push(start..self.len);
+        }
+
+        pieces
+    }
+
+    /// The rows between gaps of more than `min_gap` seconds, such as a
+    /// stop with the recording paused. See `RWTFile::crop` to make each into
+    /// a file of its own.
+    pub fn split_at_pauses(&self, min_gap: i64) -> Vec<Range<usize>> {
+        self.split_where(|_, prev, time| time - prev > min_gap)
+    }
+
+    /// The rows in pieces of up to `seconds` each, with each piece starting
+    /// at the first time after the piece before it.
+    pub fn split_by_duration(&self, seconds: i64) -> Vec<Range<usize>> {
+        self.split_where(|first, _, time| time - first >= seconds)
+    }
+}
+
+/// Finds the rows of a time range without scanning the whole track. Build
+/// it once and query it as often as needed. The track's times must be in
+/// order, as they are in a recording.
+#[derive(Debug)]
+pub struct TimeIndex {
+    // (time, row) for every row which has a time, in row order
+    entries: Vec<(i64, usize)>,
+}
+
+impl TimeIndex {
+    pub fn new(reader: &TrackPointReader) -> Self {
+        TimeIndex{entries: reader.time
+                      .map(|m| m.iter().map(|(row, time)| (*time, *row)).collect())
+                      .unwrap_or_default()}
+    }
+
+    /// The rows with times from `times.start` up to `times.end`. Rows
+    /// without a time go with the row after them.
+    pub fn rows(&self, times: Range<i64>) -> Range<usize> {
+        // the row after the last one with a time before `time`
+        let find = |time: i64| {
+            match self.entries.partition_point(|(t, _)| *t < time) {
+                0 => 0,
+                i => self.entries[i - 1].1 + 1,
+            }
+        };
+        let start = find(times.start);
+        start..find(times.end).max(start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::section::{SectionType};
+
+    #[test]
+    fn test_track_point_reader() {
+        let mut s = Section::new(SectionType::TrackPoints);
+        assert!(s.add_long_float(0, "y", 45.5).is_ok());
+        assert!(s.add_long_float(0, "x", -122.6).is_ok());
+        assert!(s.add_number(0, "t", 1000).is_ok());
+        assert!(s.add_long_float(2, "y", 45.6).is_ok());
+        assert!(s.add_long_float(2, "x", -122.7).is_ok());
+        assert!(s.add_long_float(2, "e", 12.0).is_ok());
+
+        let reader = TrackPointReader::new(&s);
+        assert_eq!(reader.len(), 3);
+        assert_eq!(reader.lat(0), Some(45.5));
+        assert_eq!(reader.time(2), None);
+        assert_eq!(reader.points().collect::<Vec<_>>(),
+                   vec![TrackPoint{lat: Some(45.5), lng: Some(-122.6), elevation: None, time: Some(1000)},
+                        TrackPoint{lat: None, lng: None, elevation: None, time: None},
+                        TrackPoint{lat: Some(45.6), lng: Some(-122.7), elevation: Some(12.0), time: None}]);
+    }
+
+    #[test]
+    fn test_segments() {
+        let mut s = Section::new(SectionType::TrackPoints);
+        assert!(TrackPointReader::new(&s).segments().is_empty());
+
+        assert!(s.add_number(0, "t", 0).is_ok());
+        assert!(s.add_number(4, "t", 4).is_ok());
+        assert_eq!(TrackPointReader::new(&s).segments(), vec![0..5]);
+
+        // rows 0 and 1 have no id and join the first segment that has one
+        assert!(s.add_number(2, "g", 7).is_ok());
+        assert!(s.add_number(3, "g", 8).is_ok());
+        let reader = TrackPointReader::new(&s);
+        assert_eq!(reader.segment(3), Some(8));
+        assert_eq!(reader.segment(4), None);
+        assert_eq!(reader.segments(), vec![0..3, 3..5]);
+    }
+
+    #[test]
+    fn test_split() {
+        let mut s = Section::new(SectionType::TrackPoints);
+        assert!(TrackPointReader::new(&s).split_at_pauses(60).is_empty());
+
+        // a ride with a 10 minute stop after row 4, and rows 0 and 7
+        // without times
+        for i in 1..10 {
+            if i != 7 {
+                let time = 1000 + i as i64 * 10 + if i >= 5 { 600 } else { 0 };
+                assert!(s.add_number(i, "t", time).is_ok());
+            }
+        }
+        assert!(s.add_long_float(0, "y", 45.5).is_ok());
+
+        let reader = TrackPointReader::new(&s);
+        assert_eq!(reader.split_at_pauses(60), vec![0..5, 5..10]);
+        assert_eq!(reader.split_at_pauses(610), vec![0..10]);
+        assert_eq!(reader.split_by_duration(30), vec![0..4, 4..5, 5..8, 8..10]);
+        assert_eq!(reader.split_by_duration(3600), vec![0..10]);
+    }
+
+    #[test]
+    fn test_time_index() {
+        let mut s = Section::new(SectionType::TrackPoints);
+        for i in 0..10 {
+            if i != 4 {
+                assert!(s.add_number(i, "t", 1000 + i as i64 * 10).is_ok());
+            }
+        }
+        assert!(s.add_long_float(10, "y", 45.5).is_ok());
+
+        let index = TimeIndex::new(&TrackPointReader::new(&s));
+        assert_eq!(index.rows(1020..1050), 2..4);
+        assert_eq!(index.rows(1015..1045), 2..4);
+        // row 4 has no time and goes with row 5
+        assert_eq!(index.rows(1045..1060), 4..6);
+        assert_eq!(index.rows(0..1000), 0..0);
+        assert_eq!(index.rows(1090..2000), 9..10);
+        assert_eq!(index.rows(3000..4000), 10..10);
+
+        let empty = Section::new(SectionType::TrackPoints);
+        assert_eq!(TimeIndex::new(&TrackPointReader::new(&empty)).rows(0..10), 0..0);
+    }
+
+    #[test]
+    fn test_wrong_column_type() {
+        let mut s = Section::new(SectionType::TrackPoints);
+        assert!(s.add_number(0, "y", 45).is_ok());
+        assert!(s.add_string(0, "t", "noon".into()).is_ok());
+
+        let reader = TrackPointReader::new(&s);
+        assert_eq!(reader.lat(0), None);
+        assert_eq!(reader.time(0), None);
+    }
+}
@@ -0,0 +1,331 @@
+use std::collections::{BTreeMap};
+use crate::rwtfile::{DataField, RWTFile, Result};
+use crate::fields::{FieldType};
+use crate::section::{Column, Section, SectionType};
+
+/// The values of a single row, by column name.
+pub type Values = BTreeMap<String, DataField>;
+
+type MapFn<'a> = Box<dyn FnMut(usize, &mut Values) + 'a>;
+type FilterFn<'a> = Box<dyn FnMut(usize, &Values) -> bool + 'a>;
+type DeriveFn<'a> = Box<dyn FnMut(usize, &Values) -> Option<DataField> + 'a>;
+
+enum Step<'a> {
+    Map(MapFn<'a>),
+    Filter(FilterFn<'a>),
+    Derive(String, DeriveFn<'a>),
+}
+
+/// A list of steps run over every row of a section in order, building a new
+/// section in a single pass. Each step is given the row's index in the
+/// input section and its values as left by the steps before it. Steps can
+/// keep state between rows, e.g. the previous point for a derived speed.
+pub struct Transform<'a> {
+    steps: Vec<Step<'a>>,
+}
+
+impl<'a> Transform<'a> {
+    pub fn new() -> Self {
+        Transform{steps: vec![]}
+    }
+
+    /// Change a row's values in place.
+    pub fn map<F: FnMut(usize, &mut Values) + 'a>(mut self, f: F) -> Self {
+        self.steps.push(Step::Map(Box::new(f)));
+        self
+    }
+
+    /// Keep only the rows `f` returns true for. Later steps don't see the
+    /// rows that were dropped.
+    pub fn filter<F: FnMut(usize, &Values) -> bool + 'a>(mut self, f: F) -> Self {
+        self.steps.push(Step::Filter(Box::new(f)));
+        self
+    }
+
+    /// Set the named column to what `f` returns, or remove it from the row
+    /// for `None`.
+    pub fn derive<F: FnMut(usize, &Values) -> Option<DataField> + 'a>(mut self, name: &str, f: F) -> Self {
+        self.steps.push(Step::Derive(name.into(), Box::new(f)));
+        self
+    }
+
+    /// Runs every step over `section`, returning a new section of the same
+    /// type and settings holding the rows that are left, renumbered from 0.
+    pub fn apply(&mut self, section: &Section) -> Result<Section> {
+        let mut out = Section::new(section.section_type);
+        out.run_length_encoding = section.run_length_encoding;
+        out.packed_ids = section.packed_ids;
+        out.float_overflow_policies = section.float_overflow_policies.clone();
+        out.decimals = section.decimals.clone();
+
+        let mut out_index = 0;
+        'rows: for index in 0..section.len() {
+            let mut values = section.columns()
+                .iter()
+                .filter_map(|(name, column)| column.get(index).map(|v| (name.clone(), v)))
+                .collect::<Values>();
+
+            for step in self.steps.iter_mut() {
+                match step {
+                    Step::Map(f) => f(index, &mut values),
+                    Step::Filter(f) => if !f(index, &values) {
+                        continue 'rows;
+                    },
+                    Step::Derive(name, f) => match f(index, &values) {
+                        Some(v) => { values.insert(name.clone(), v); }
+                        None => { values.remove(name); }
+                    },
+                }
+            }
+
+            for (name, v) in values {
+                RWTFile::add_point(&mut out, out_index, &name, v)?;
+            }
+            out_index += 1;
+        }
+
+        Ok(out)
+    }
+}
+
+impl<'a> Default for Transform<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A copy of `section` without the named columns, e.g. `fields::LAT` and
+/// `fields::LNG` for a shareable version of a private ride. Columns are
+/// copied as they are rather than row by row, and rows keep their indexes
+/// even if stripping leaves them empty. Names which aren't columns of
+/// `section` are ignored.
+pub fn strip_columns(section: &Section, names: &[&str]) -> Section {
+    let mut out = section.clone();
+    out.columns.retain(|name, _| !names.contains(&name.as_str()));
+    out.float_overflow_policies.retain(|name, _| !names.contains(&name.as_str()));
+    out.decimals.retain(|name, _| !names.contains(&name.as_str()));
+    out.flags.remove_fields(names);
+    out
+}
+
+/// A copy of `section` with the values of the named float, GeoPoint and
+/// F64Array columns rounded to `decimals` places, e.g. 3 for `fields::LAT` and
+/// `fields::LNG` to blur a ride to within about 100 meters. Columns keep
+/// their types, and other columns are copied as they are. Values which
+/// aren't finite are left as they are.
+pub fn reduce_precision(section: &Section, names: &[&str], decimals: i32) -> Section {
+    let scale = 10f64.powi(decimals);
+    let mut out = section.clone();
+    for (_name, column) in out.columns.iter_mut().filter(|(name, _)| names.contains(&name.as_str())) {
+        match column {
+            Column::LongFloat(m) | Column::ShortFloat(m) | Column::XorFloat(m) => {
+                for v in m.values_mut().filter(|v| v.is_finite()) {
+                    *v = (*v * scale).round() / scale;
+                }
+            }
+            Column::GeoPoint(m) => {
+                for (lat, lng) in m.values_mut() {
+                    *lat = (*lat * scale).round() / scale;
+                    *lng = (*lng * scale).round() / scale;
+                }
+            }
+            Column::F64Array(m) => {
+                for v in m.values_mut().flatten() {
+                    *v = (*v * scale).round() / scale;
+                }
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+// The type of a merged column which is `a` in one section and `b` in
+// another. Floats of different types are stored in the type which loses the
+// least. Other differences can't be reconciled and fail when merging.
+fn merged_type(a: FieldType, b: FieldType) -> FieldType {
+    match (a, b) {
+        (FieldType::XorFloat, FieldType::LongFloat) | (FieldType::XorFloat, FieldType::ShortFloat) |
+        (FieldType::LongFloat, FieldType::XorFloat) | (FieldType::ShortFloat, FieldType::XorFloat) => FieldType::XorFloat,
+        (FieldType::LongFloat, FieldType::ShortFloat) | (FieldType::ShortFloat, FieldType::LongFloat) => FieldType::LongFloat,
+        _ => a,
+    }
+}
+
+/// The rows of `sections` one after another as a single section, e.g. to
+/// combine recordings of the same ride from several devices. Columns are
+/// the union of every section's columns, so rows from a section without a
+/// column have no value for it. A float column stored as different float
+/// types in different sections is converted to the more precise one: a
+/// ShortFloat and LongFloat column merge into a LongFloat column, and
+/// either with a XorFloat column into a XorFloat column. Any other
+/// difference in type is an error.
+pub fn merge(section_type: SectionType, sections: &[&Section]) -> Result<Section> {
+    let mut types = BTreeMap::new();
+    for section in sections {
+        for (name, column) in section.columns() {
+            let field_type = column.field_type();
+            types.entry(name.as_str())
+                .and_modify(|merged| *merged = merged_type(*merged, field_type))
+                .or_insert(field_type);
+        }
+    }
+
+    let mut out = Section::new(section_type);
+    if let Some(first) = sections.first() {
+        out.run_length_encoding = first.run_length_encoding;
+        out.packed_ids = first.packed_ids;
+    }
+    let mut start = 0;
+    for section in sections {
+        for (name, policy) in section.float_overflow_policies.iter() {
+            out.float_overflow_policies.entry(name.clone()).or_insert(*policy);
+        }
+        for (name, decimals) in section.decimals.iter() {
+            out.decimals.entry(name.clone()).or_insert(*decimals);
+        }
+        for (name, column) in section.columns() {
+            let field_type = types[name.as_str()];
+            for index in 0..section.len() {
+                let v = match (column.get(index), field_type) {
+                    (Some(v), _) if v.field_type() == field_type => v,
+                    (Some(v), FieldType::LongFloat) => DataField::LongFloat(v.as_f64().unwrap_or_default()),
+                    (Some(v), FieldType::XorFloat) => DataField::XorFloat(v.as_f64().unwrap_or_default()),
+                    (Some(v), _) => v,
+                    (None, _) => continue,
+                };
+                RWTFile::add_point(&mut out, start + index, name, v)?;
+            }
+        }
+        start += section.len();
+        out.extend_to(start);
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    #[test]
+    fn test_transform() {
+        let mut s = Section::new(SectionType::TrackPoints);
+        for i in 0..10 {
+            assert!(s.add_number(i, "t", i as i64 * 10).is_ok());
+            assert!(s.add_short_float(i, "d", (i * i) as f64).is_ok());
+        }
+
+        let mut last = None;
+        let out = Transform::new()
+            // crop
+            .filter(|index, _| (2..8).contains(&index))
+            // start the clock at the new first point
+            .map(|_, values| if let Some(DataField::Number(t)) = values.get_mut("t") {
+                *t -= 20;
+            })
+            // speed from the previous point
+            .derive("s", |_, values| {
+                let (t, d) = match (values.get("t"), values.get("d")) {
+                    (Some(DataField::Number(t)), Some(DataField::ShortFloat(d))) => (*t, *d),
+                    _ => return None,
+                };
+                let speed = last.map(|(last_t, last_d)| DataField::ShortFloat((d - last_d) / (t - last_t) as f64));
+                last = Some((t, d));
+                speed
+            })
+            .apply(&s)
+            .unwrap();
+
+        assert_eq!(out.len(), 6);
+        assert_matches!(out.columns().get("t"), Some(Column::Numbers(m)) if m.values().cloned().collect::<Vec<_>>() == vec![0, 10, 20, 30, 40, 50]);
+        assert_matches!(out.columns().get("s"), Some(Column::ShortFloat(m))
+                        if m.iter().map(|(i, v)| (*i, *v)).collect::<Vec<_>>() == vec![(1, 0.5), (2, 0.7), (3, 0.9), (4, 1.1), (5, 1.3)]);
+    }
+
+    #[test]
+    fn test_strip_columns() {
+        let mut f = RWTFile::new();
+        for i in 0..5 {
+            assert!(f.add_track_point(i, "y", DataField::LongFloat(45.5)).is_ok());
+            assert!(f.add_track_point(i, "x", DataField::LongFloat(-122.6)).is_ok());
+            assert!(f.add_track_point(i, "t", i as i64).is_ok());
+        }
+        // only a position at the end
+        assert!(f.add_track_point(5, "y", DataField::LongFloat(45.6)).is_ok());
+
+        let stripped = strip_columns(f.track_points(), &["y", "x", "missing"]);
+        assert_eq!(stripped.len(), 6);
+        assert_eq!(stripped.columns().keys().collect::<Vec<_>>(), vec!["t"]);
+
+        let mut g = RWTFile::new();
+        g.track_points = stripped;
+        let g = RWTFile::read(&g.to_bytes().unwrap()).unwrap();
+        assert_eq!(g.track_points().len(), 6);
+        assert_matches!(g.track_points().columns().get("t"), Some(Column::Numbers(m)) if m.len() == 5);
+        assert_eq!(f.track_points().columns().len(), 3);
+    }
+
+    #[test]
+    fn test_reduce_precision() {
+        let mut s = Section::new(SectionType::TrackPoints);
+        assert!(s.add_long_float(0, "y", 45.5123456).is_ok());
+        assert!(s.add_long_float(1, "y", -45.5126).is_ok());
+        assert!(s.add_xor_float(0, "x", -122.6789).is_ok());
+        assert!(s.add_xor_float(1, "x", f64::NAN).is_ok());
+        assert!(s.add_short_float(0, "e", 12.345).is_ok());
+        assert!(s.add_number(0, "t", 1234).is_ok());
+
+        let out = reduce_precision(&s, &["y", "x", "t"], 3);
+        assert_matches!(out.columns().get("y"), Some(Column::LongFloat(m)) if m[&0] == 45.512 && m[&1] == -45.513);
+        assert_matches!(out.columns().get("x"), Some(Column::XorFloat(m)) if m[&0] == -122.679 && m[&1].is_nan());
+        assert_matches!(out.columns().get("e"), Some(Column::ShortFloat(m)) if m[&0] == 12.345);
+        assert_matches!(out.columns().get("t"), Some(Column::Numbers(m)) if m[&0] == 1234);
+        assert_eq!(out.len(), 2);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut a = Section::new(SectionType::TrackPoints);
+        for i in 0..3 {
+            assert!(a.add_number(i, "t", i as i64).is_ok());
+            assert!(a.add_short_float(i, "e", 100.5).is_ok());
+            assert!(a.add_long_float(i, "y", 45.5).is_ok());
+        }
+        // an empty row at the end
+        assert!(a.add_number(3, "t", 3).is_ok());
+        assert!(a.remove(3, "t").is_some());
+        let mut b = Section::new(SectionType::TrackPoints);
+        for i in 0..2 {
+            assert!(b.add_number(i, "t", 10 + i as i64).is_ok());
+            assert!(b.add_long_float(i, "e", 101.25).is_ok());
+            assert!(b.add_xor_float(i, "y", 45.123456789).is_ok());
+            assert!(b.add_number(i, "h", 120).is_ok());
+        }
+
+        let merged = merge(SectionType::TrackPoints, &[&a, &b]).unwrap();
+        assert_eq!(merged.len(), a.len() + b.len());
+        assert_matches!(merged.columns().get("t"), Some(Column::Numbers(m))
+                        if m.iter().map(|(i, v)| (*i, *v)).collect::<Vec<_>>() == vec![(0, 0), (1, 1), (2, 2), (4, 10), (5, 11)]);
+        assert_matches!(merged.columns().get("e"), Some(Column::LongFloat(m)) if m[&0] == 100.5 && m[&5] == 101.25);
+        assert_matches!(merged.columns().get("y"), Some(Column::XorFloat(m)) if m[&0] == 45.5 && m[&4] == 45.123456789);
+        assert_matches!(merged.columns().get("h"), Some(Column::Numbers(m)) if m.keys().cloned().collect::<Vec<_>>() == vec![4, 5]);
+
+        let mut c = Section::new(SectionType::TrackPoints);
+        assert!(c.add_string(0, "t", "noon".into()).is_ok());
+        assert!(merge(SectionType::TrackPoints, &[&a, &c]).is_err());
+        assert_eq!(merge(SectionType::CoursePoints, &[]).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_transform_errors() {
+        let mut s = Section::new(SectionType::TrackPoints);
+        assert!(s.add_number(0, "t", 0).is_ok());
+
+        let result = Transform::new()
+            .derive("t", |_, _| Some(DataField::Base64("not base64".into())))
+            .apply(&s);
+        assert!(result.is_err());
+    }
+}
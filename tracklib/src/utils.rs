@@ -4,3 +4,125 @@ pub(crate) fn write<W: Write>(out: &mut W, bytes: &[u8]) -> Result<usize> {
     out.write_all(bytes)?;
     Ok(bytes.len())
 }
+
+/// Passes writes straight through to `out` while keeping a running CRC32 of
+/// everything written, so large structures don't have to be buffered in
+/// full just to be checksummed.
+pub(crate) struct Crc32Writer<'a, W: Write> {
+    out: &'a mut W,
+    crc: u32,
+}
+
+impl<'a, W: Write> Crc32Writer<'a, W> {
+    pub(crate) fn new(out: &'a mut W) -> Self {
+        Self { out, crc: 0 }
+    }
+
+    /// Carries on from `crc`, the CRC of what was written to `out` before.
+    pub(crate) fn resume(out: &'a mut W, crc: u32) -> Self {
+        Self { out, crc }
+    }
+
+    pub(crate) fn crc(&self) -> u32 {
+        self.crc
+    }
+}
+
+impl<'a, W: Write> Write for Crc32Writer<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let written = self.out.write(buf)?;
+        self.crc = crc::crc32::update(self.crc, &crc::crc32::IEEE_TABLE, &buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.out.flush()
+    }
+}
+
+pub(crate) fn unsigned_leb128_len(mut v: u64) -> usize {
+    let mut len = 1;
+    while v >= 0x80 {
+        v >>= 7;
+        len += 1;
+    }
+    len
+}
+
+pub(crate) fn signed_leb128_len(mut v: i64) -> usize {
+    let mut len = 1;
+    while !(-0x40..0x40).contains(&v) {
+        v >>= 7;
+        len += 1;
+    }
+    len
+}
+
+// The year, month and day of a number of days since 1970-01-01 in the
+// proleptic Gregorian calendar
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// An RFC 3339 time in UTC, such as "2021-03-04T05:06:07Z", for
+/// milliseconds since the unix epoch. The milliseconds are only included
+/// when there are some.
+pub(crate) fn format_time(millis: i64) -> String {
+    let seconds = millis.div_euclid(1000);
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let time = seconds.rem_euclid(86400);
+    let mut s = format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}", year, month, day, time / 3600, time / 60 % 60, time % 60);
+    if millis.rem_euclid(1000) != 0 {
+        s.push_str(&format!(".{:03}", millis.rem_euclid(1000)));
+    }
+    s.push('Z');
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32_writer() {
+        let mut buf = vec![];
+        let mut out = Crc32Writer::new(&mut buf);
+        assert!(write(&mut out, b"hello, ").is_ok());
+        assert!(write(&mut out, b"world").is_ok());
+        let crc = out.crc();
+        assert_eq!(crc, crc::crc32::checksum_ieee(b"hello, world"));
+        assert_eq!(buf, b"hello, world");
+    }
+
+    #[test]
+    fn test_leb128_len() {
+        for v in &[0, 1, 63, 64, 127, 128, 16383, 16384, u64::MAX] {
+            let mut buf = vec![];
+            assert!(leb128::write::unsigned(&mut buf, *v).is_ok());
+            assert_eq!(unsigned_leb128_len(*v), buf.len());
+        }
+
+        for v in &[0, 1, -1, 63, -64, 64, -65, 8191, -8192, 8192, i64::MIN, i64::MAX] {
+            let mut buf = vec![];
+            assert!(leb128::write::signed(&mut buf, *v).is_ok());
+            assert_eq!(signed_leb128_len(*v), buf.len());
+        }
+    }
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_time(1614834367500), "2021-03-04T05:06:07.500Z");
+        assert_eq!(format_time(951782400000), "2000-02-29T00:00:00Z");
+        assert_eq!(format_time(-1), "1969-12-31T23:59:59.999Z");
+    }
+}
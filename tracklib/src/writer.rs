@@ -0,0 +1,223 @@
+use std::collections::{BTreeMap};
+use std::io::{Write};
+use snafu::{ResultExt};
+use crate::decode::{parse_column_types, parse_data_offset, parse_file_checksum_at, parse_raw_sections, parse_start, ParseOptions};
+use crate::fields::{FieldType};
+use crate::metadata::{RWTFMetadata};
+use crate::rwtfile::{DuplicateSection, Error, ReadExisting, Result, RWTFile, SetContinuation, WriteBytes, WriteSection, WriteTrailer, RWTFTRAILER, RWTF_FILE_VERSION};
+use crate::section::{Section, SectionType, Error as SectionError};
+use crate::utils::{write, Crc32Writer};
+
+/// Writes a file a section at a time, e.g. for a recorder to stream rows to
+/// disk as they arrive instead of holding the whole track. The header and
+/// metadata are written by `new`, each section by `write_section` and the
+/// trailer by `finish`.
+///
+/// A section of the same type as the one before it is written as a
+/// continuation section, so track points can be written in as many pieces
+/// as they arrive in, and readers join them back up. Those pieces can't
+/// change the type of a column. Other than that, each section type can only
+/// be written once, like in an `RWTFile`.
+pub struct TrackWriter<W> {
+    out: W,
+    // holds the header, the metadata and whether to write a file checksum
+    file: RWTFile,
+    crc: u32,
+    written: usize,
+    seen: Vec<SectionType>,
+    // the column types of the section being continued
+    columns: BTreeMap<String, FieldType>,
+}
+
+impl<W: Write> TrackWriter<W> {
+    /// Writes the header and `metadata` straight away. The header says
+    /// whether a file checksum follows the trailer, so that has to be
+    /// decided here, see `RWTFile::set_file_checksum`.
+    pub fn new(mut out: W, metadata: RWTFMetadata, file_checksum: bool) -> Result<Self> {
+        let mut file = RWTFile::new();
+        file.set_metadata(metadata);
+        file.set_file_checksum(file_checksum);
+
+        let mut crc_out = Crc32Writer::new(&mut out);
+        let written = file.write_start(&mut crc_out, RWTF_FILE_VERSION)?;
+        let crc = crc_out.crc();
+
+        Ok(TrackWriter{out, file, crc, written, seen: vec![], columns: BTreeMap::new()})
+    }
+
+    /// Starts writing a copy of the file in `existing` with more sections to
+    /// come, e.g. to add laps to a track which has already been written. The
+    /// header and metadata are written again and the existing sections are
+    /// copied without decoding their columns; only their types tables are
+    /// read, for the column types a continuation of the last one has to
+    /// keep. The file checksum is kept if the file had one.
+    pub fn append(mut out: W, existing: &[u8], options: &ParseOptions) -> Result<Self> {
+        let data_offset = parse_data_offset(existing, options).context(ReadExisting)?;
+        let (header, metadata) = parse_start(existing, options).context(ReadExisting)?;
+        let sections = parse_raw_sections(existing, options).context(ReadExisting)?;
+        let trailer_end = data_offset + sections.iter().map(|section| section.as_bytes().len()).sum::<usize>() + RWTFTRAILER.len();
+        parse_file_checksum_at(existing, trailer_end, &header, options).context(ReadExisting)?;
+
+        let mut file = RWTFile::new();
+        file.set_file_checksum(header.file_checksum);
+        file.header = header;
+        file.set_metadata(metadata);
+
+        let mut crc_out = Crc32Writer::new(&mut out);
+        let mut written = file.write_start(&mut crc_out, RWTF_FILE_VERSION)?;
+        let mut seen = vec![];
+        let mut columns = BTreeMap::new();
+        for (index, section) in sections.iter().enumerate() {
+            written += write(&mut crc_out, section.as_bytes()).context(WriteBytes)?;
+            if section.section_type() != SectionType::Continuation {
+                seen.push(section.section_type());
+                columns.clear();
+            }
+            columns.extend(parse_column_types(section.as_bytes(), index, options).context(ReadExisting)?);
+        }
+        let crc = crc_out.crc();
+
+        Ok(TrackWriter{out, file, crc, written, seen, columns})
+    }
+
+    /// The number of bytes written so far.
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    /// Writes `section` straight away, returning the number of bytes
+    /// written. Empty sections are skipped.
+    pub fn write_section(&mut self, mut section: Section) -> Result<usize> {
+        let section_type = section.section_type();
+        if section_type == SectionType::Continuation {
+            return SetContinuation{}.fail();
+        }
+        if section.len() == 0 {
+            return Ok(0);
+        }
+
+        if self.seen.last() == Some(&section_type) {
+            for (name, column) in section.columns() {
+                if self.columns.get(name).is_some_and(|field_type| *field_type != column.field_type()) {
+                    return Err(Error::WriteSection{source: SectionError::ColumnTypeChange{name: name.clone()}});
+                }
+            }
+            section.section_type = SectionType::Continuation;
+        } else if self.seen.contains(&section_type) {
+            return DuplicateSection{section_type}.fail();
+        } else {
+            self.seen.push(section_type);
+            self.columns.clear();
+        }
+        for (name, column) in section.columns() {
+            self.columns.insert(name.clone(), column.field_type());
+        }
+
+        let mut out = Crc32Writer::resume(&mut self.out, self.crc);
+        let written = section.write(&mut out).context(WriteSection)?;
+        self.crc = out.crc();
+        self.written += written;
+
+        Ok(written)
+    }
+
+    /// Writes the trailer, and the file checksum if it's set, handing back
+    /// the output.
+    pub fn finish(mut self) -> Result<W> {
+        let mut out = Crc32Writer::resume(&mut self.out, self.crc);
+        write(&mut out, &RWTFTRAILER).context(WriteTrailer)?;
+        self.file.write_file_checksum(&mut out)?;
+
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::{TrackType};
+    use crate::rwtfile::{DataField};
+    use assert_matches::assert_matches;
+
+    fn points(start: usize, len: usize) -> Section {
+        let mut s = Section::new(SectionType::TrackPoints);
+        for i in 0..len {
+            assert!(s.add(i, "t", (start + i) as i64).is_ok());
+        }
+        s
+    }
+
+    #[test]
+    fn test_track_writer() {
+        let mut writer = TrackWriter::new(vec![], RWTFMetadata::new(None, Some(TrackType::Route(7))), true).unwrap();
+        assert!(writer.write_section(points(0, 10)).unwrap() > 0);
+        assert!(writer.write_section(points(10, 5)).is_ok());
+        assert_eq!(writer.write_section(Section::new(SectionType::TrackPoints)).unwrap(), 0);
+        let mut course_points = Section::new(SectionType::CoursePoints);
+        assert!(course_points.add(0, "n", DataField::String("start".into())).is_ok());
+        assert!(writer.write_section(course_points).is_ok());
+        let written = writer.written();
+        let buf = writer.finish().unwrap();
+        assert_eq!(buf.len(), written + RWTFTRAILER.len() + 4);
+        // the header is written before the sections it would depend on
+        assert_eq!(buf[8], RWTF_FILE_VERSION);
+
+        let f = RWTFile::read(&buf).unwrap();
+        assert_eq!(f.metadata().track_type(), Some(TrackType::Route(7)));
+        assert!(f.file_checksum());
+        assert_eq!(f.track_points().len(), 15);
+        assert_eq!(f.track_points().columns()["t"].get(14), Some(DataField::Number(14)));
+        assert_eq!(f.course_points().len(), 1);
+    }
+
+    #[test]
+    fn test_track_writer_errors() {
+        let mut writer = TrackWriter::new(vec![], RWTFMetadata::new(None, None), false).unwrap();
+        assert!(writer.write_section(points(0, 10)).is_ok());
+        let mut floats = Section::new(SectionType::TrackPoints);
+        assert!(floats.add(0, "t", DataField::LongFloat(1.5)).is_ok());
+        assert_matches!(writer.write_section(floats), Err(Error::WriteSection{source: SectionError::ColumnTypeChange{..}}));
+
+        let mut course_points = Section::new(SectionType::CoursePoints);
+        assert!(course_points.add(0, "n", 1).is_ok());
+        assert!(writer.write_section(course_points).is_ok());
+        assert_matches!(writer.write_section(points(10, 5)), Err(Error::DuplicateSection{section_type: SectionType::TrackPoints}));
+        assert_matches!(writer.write_section(Section::new(SectionType::Continuation)), Err(Error::SetContinuation{}));
+
+        // nothing failed part way through a section
+        let buf = writer.finish().unwrap();
+        assert_eq!(RWTFile::read(&buf).unwrap().track_points().len(), 10);
+    }
+
+    #[test]
+    fn test_track_writer_append() {
+        let mut writer = TrackWriter::new(vec![], RWTFMetadata::new(None, Some(TrackType::Trip(3))), true).unwrap();
+        assert!(writer.write_section(points(0, 10)).is_ok());
+        assert!(writer.write_section(points(10, 5)).is_ok());
+        let existing = writer.finish().unwrap();
+
+        let mut writer = TrackWriter::append(vec![], &existing, &ParseOptions::new()).unwrap();
+        assert_eq!(writer.written(), existing.len() - RWTFTRAILER.len() - 4);
+        let mut floats = Section::new(SectionType::TrackPoints);
+        assert!(floats.add(0, "t", DataField::LongFloat(1.5)).is_ok());
+        assert_matches!(writer.write_section(floats), Err(Error::WriteSection{source: SectionError::ColumnTypeChange{..}}));
+        assert!(writer.write_section(points(15, 5)).is_ok());
+        let mut laps = Section::new(SectionType::Laps);
+        assert!(laps.add(0, "t", 0).is_ok());
+        assert!(writer.write_section(laps).is_ok());
+        let buf = writer.finish().unwrap();
+        // the existing sections are copied as they were
+        assert_eq!(buf[..existing.len() - RWTFTRAILER.len() - 4], existing[..existing.len() - RWTFTRAILER.len() - 4]);
+
+        let f = RWTFile::read(&buf).unwrap();
+        assert_eq!(f.metadata().track_type(), Some(TrackType::Trip(3)));
+        assert!(f.file_checksum());
+        assert_eq!(f.track_points().len(), 20);
+        assert_eq!(f.track_points().columns()["t"].get(19), Some(DataField::Number(19)));
+        assert_eq!(f.laps().len(), 1);
+
+        let mut writer = TrackWriter::append(vec![], &buf, &ParseOptions::new()).unwrap();
+        assert_matches!(writer.write_section(points(0, 1)), Err(Error::DuplicateSection{section_type: SectionType::TrackPoints}));
+        assert_matches!(TrackWriter::append(vec![], &buf[..buf.len() - 20], &ParseOptions::new()).err(), Some(Error::ReadExisting{..}));
+    }
+}
@@ -0,0 +1,197 @@
+// Gorilla-style XOR compression for f64 values, as described in "Gorilla: A
+// Fast, Scalable, In-Memory Time Series Database" (Pelkonen et al. 2015).
+//
+// The first value is stored verbatim. Every following value is XORed with the
+// one before it and stored as:
+//   '0'                          - the value is identical to the previous one
+//   '10' + meaningful bits       - the XOR fits in the previous bit window
+//   '11' + 5 bits leading zeros
+//        + 6 bits (length - 1)
+//        + meaningful bits       - a new bit window
+// The bit stream is padded with zeros to a whole number of bytes.
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    bit: u8,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self { bytes: Vec::new(), bit: 8 }
+    }
+
+    fn push(&mut self, value: u64, bits: u8) {
+        for shift in (0..bits).rev() {
+            if self.bit == 8 {
+                self.bytes.push(0);
+                self.bit = 0;
+            }
+            if (value >> shift) & 1 == 1 {
+                if let Some(last) = self.bytes.last_mut() {
+                    *last |= 0x80 >> self.bit;
+                }
+            }
+            self.bit += 1;
+        }
+    }
+}
+
+/// Why `decode` couldn't decode its input.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum DecodeError {
+    /// The input ends before the last value does.
+    Truncated,
+    /// A new bit window doesn't fit in 64 bits, so the input isn't XOR
+    /// compressed floats.
+    InvalidWindow,
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    fn take(&mut self, bits: u8) -> Result<u64, DecodeError> {
+        let mut value = 0;
+        for _ in 0..bits {
+            let byte = self.bytes.get(self.position / 8).ok_or(DecodeError::Truncated)?;
+            let bit = (byte >> (7 - (self.position % 8))) & 1;
+            value = (value << 1) | u64::from(bit);
+            self.position += 1;
+        }
+        Ok(value)
+    }
+
+    fn bytes_consumed(&self) -> usize {
+        self.position.div_ceil(8)
+    }
+}
+
+pub(crate) fn encode<I: IntoIterator<Item = f64>>(values: I) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    let mut previous: Option<u64> = None;
+    let mut window: Option<(u32, u32)> = None; // (leading zeros, trailing zeros)
+
+    for value in values {
+        let bits = value.to_bits();
+        match previous {
+            None => writer.push(bits, 64),
+            Some(prev) => {
+                let xor = bits ^ prev;
+                if xor == 0 {
+                    writer.push(0, 1);
+                } else {
+                    let leading = xor.leading_zeros().min(31);
+                    let trailing = xor.trailing_zeros();
+
+                    match window {
+                        Some((window_leading, window_trailing)) if leading >= window_leading && trailing >= window_trailing => {
+                            writer.push(0b10, 2);
+                            writer.push(xor >> window_trailing, (64 - window_leading - window_trailing) as u8);
+                        }
+                        _ => {
+                            let meaningful = 64 - leading - trailing;
+                            writer.push(0b11, 2);
+                            writer.push(u64::from(leading), 5);
+                            writer.push(u64::from(meaningful - 1), 6);
+                            writer.push(xor >> trailing, meaningful as u8);
+                            window = Some((leading, trailing));
+                        }
+                    }
+                }
+            }
+        }
+        previous = Some(bits);
+    }
+
+    writer.bytes
+}
+
+/// Decodes `count` values, returning them along with the number of bytes of
+/// `i` they occupied.
+pub(crate) fn decode(i: &[u8], count: usize) -> Result<(Vec<f64>, usize), DecodeError> {
+    let mut reader = BitReader::new(i);
+    let mut values = Vec::with_capacity(count.min(i.len() * 8));
+    let mut previous: Option<u64> = None;
+    let mut window = (0, 0); // (leading zeros, trailing zeros)
+
+    for _ in 0..count {
+        let bits = match previous {
+            None => reader.take(64)?,
+            Some(prev) => {
+                if reader.take(1)? == 0 {
+                    prev
+                } else {
+                    if reader.take(1)? == 1 {
+                        let leading = reader.take(5)? as u32;
+                        let meaningful = reader.take(6)? as u32 + 1;
+                        if leading + meaningful > 64 {
+                            return Err(DecodeError::InvalidWindow);
+                        }
+                        window = (leading, 64 - leading - meaningful);
+                    }
+                    let (leading, trailing) = window;
+                    prev ^ (reader.take((64 - leading - trailing) as u8)? << trailing)
+                }
+            }
+        };
+        values.push(f64::from_bits(bits));
+        previous = Some(bits);
+    }
+
+    Ok((values, reader.bytes_consumed()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(values: &[f64]) -> usize {
+        let bytes = encode(values.iter().cloned());
+        let (decoded, consumed) = decode(&bytes, values.len()).unwrap();
+        assert_eq!(consumed, bytes.len());
+        assert_eq!(decoded.iter().map(|v| v.to_bits()).collect::<Vec<_>>(),
+                   values.iter().map(|v| v.to_bits()).collect::<Vec<_>>());
+        bytes.len()
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(roundtrip(&[]), 0);
+    }
+
+    #[test]
+    fn test_repeated_values() {
+        // 8 bytes for the first value, then a single bit for each repeat
+        assert_eq!(roundtrip(&[21.5; 9]), 9);
+    }
+
+    #[test]
+    fn test_noisy_values() {
+        roundtrip(&[22.31, 22.29, 22.35, 22.3, -0.0, 0.0, 1e300, -1e-300, f64::MAX, f64::MIN_POSITIVE, f64::INFINITY]);
+        roundtrip(&[0.49, 0.51, 0.5, 0.52, 0.47, 0.53, 0.5]);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let bytes = encode(vec![1.0, 2.0, 3.0]);
+        assert_eq!(decode(&bytes[..bytes.len() - 1], 3), Err(DecodeError::Truncated));
+    }
+
+    #[test]
+    fn test_invalid_window() {
+        // a new window of 31 leading zeros and 64 meaningful bits
+        let mut writer = BitWriter::new();
+        writer.push(0, 64);
+        writer.push(0b11, 2);
+        writer.push(31, 5);
+        writer.push(63, 6);
+        writer.push(0, 64);
+        assert_eq!(decode(&writer.bytes, 2), Err(DecodeError::InvalidWindow));
+    }
+}